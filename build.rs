@@ -0,0 +1,28 @@
+use std::env;
+use std::process::Command;
+
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8(output.stdout).ok()?.trim().to_string())
+}
+
+fn main() {
+    // `GIT_SHA` takes precedence, for builds outside of a git checkout such as Docker images.
+    let git_sha = env::var("GIT_SHA")
+        .ok()
+        .or_else(|| command_output("git", &["rev-parse", "--short", "HEAD"]))
+        .unwrap_or_else(|| "unknown".to_string());
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let rustc_version =
+        command_output(&rustc, &["--version"]).unwrap_or_else(|| "unknown".to_string());
+
+    println!("cargo:rustc-env=GRAPHGATE_GIT_SHA={}", git_sha);
+    println!("cargo:rustc-env=GRAPHGATE_RUSTC_VERSION={}", rustc_version);
+    println!(
+        "cargo:rustc-env=GRAPHGATE_TARGET={}",
+        env::var("TARGET").unwrap_or_default()
+    );
+}
@@ -0,0 +1,193 @@
+use std::collections::{BTreeSet, HashMap};
+
+use anyhow::{Context, Result};
+use graphgate_planner::{Request, Response};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+/// Read-only mode state.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ReadOnlyMode {
+    pub enabled: bool,
+    #[serde(default)]
+    pub message: Option<String>,
+}
+
+/// Maintenance mode state.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MaintenanceMode {
+    pub enabled: bool,
+
+    #[serde(default)]
+    pub message: Option<String>,
+
+    /// Extensions of the returned error.
+    #[serde(default)]
+    pub extensions: HashMap<String, serde_json::Value>,
+
+    /// Value of the `Retry-After` header in seconds.
+    #[serde(default)]
+    pub retry_after: Option<u64>,
+}
+
+/// Schema coordinates and services that are disabled.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DisabledTargets {
+    /// Schema coordinates such as `Query.topProducts`.
+    #[serde(default)]
+    pub fields: BTreeSet<String>,
+
+    /// Service names.
+    #[serde(default)]
+    pub services: BTreeSet<String>,
+}
+
+/// Route and health of a service.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServiceState {
+    pub name: String,
+    pub addr: String,
+    pub tls: bool,
+    pub query_path: Option<String>,
+    pub subscribe_path: Option<String>,
+    pub http_version: String,
+    pub subscription_callback: bool,
+
+    /// `None` if the SDL of the service has not been fetched yet.
+    pub healthy: Option<bool>,
+    pub error: Option<String>,
+    pub checked_at: Option<String>,
+
+    /// The SDL of the last successful fetch.
+    pub sdl: Option<String>,
+}
+
+const SERVICES_QUERY: &str = r#"{
+    services {
+        name addr tls queryPath subscribePath httpVersion subscriptionCallback
+        healthy error checkedAt sdl
+    }
+}"#;
+
+/// Client of the admin API, served on the `admin.bind` address.
+#[derive(Clone)]
+pub struct AdminClient {
+    client: reqwest::Client,
+    url: String,
+}
+
+impl AdminClient {
+    /// Creates a client of the admin API at `url`, such as `http://127.0.0.1:8001`.
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            url: url.into().trim_end_matches('/').to_string(),
+        }
+    }
+
+    pub async fn read_only(&self) -> Result<ReadOnlyMode> {
+        self.get("read-only").await
+    }
+
+    pub async fn set_read_only(&self, read_only: &ReadOnlyMode) -> Result<ReadOnlyMode> {
+        self.put("read-only", read_only).await
+    }
+
+    pub async fn disabled(&self) -> Result<DisabledTargets> {
+        self.get("disabled").await
+    }
+
+    pub async fn set_disabled(&self, disabled: &DisabledTargets) -> Result<DisabledTargets> {
+        self.put("disabled", disabled).await
+    }
+
+    pub async fn maintenance(&self) -> Result<MaintenanceMode> {
+        self.get("maintenance").await
+    }
+
+    pub async fn set_maintenance(&self, maintenance: &MaintenanceMode) -> Result<MaintenanceMode> {
+        self.put("maintenance", maintenance).await
+    }
+
+    /// Returns the version of the composed schema, `None` if it is not ready.
+    pub async fn schema_version(&self) -> Result<Option<u64>> {
+        #[derive(Deserialize)]
+        struct Data {
+            schema: Option<SchemaState>,
+        }
+
+        #[derive(Deserialize)]
+        struct SchemaState {
+            version: u64,
+        }
+
+        let data: Data = self.query("{ schema { version } }").await?;
+        Ok(data.schema.map(|schema| schema.version))
+    }
+
+    /// Returns the routes and health of the services.
+    pub async fn services(&self) -> Result<Vec<ServiceState>> {
+        #[derive(Deserialize)]
+        struct Data {
+            services: Vec<ServiceState>,
+        }
+
+        let data: Data = self.query(SERVICES_QUERY).await?;
+        Ok(data.services)
+    }
+
+    /// Returns the SDL of a service, `None` if it has not been fetched.
+    pub async fn service_sdl(&self, service: &str) -> Result<Option<String>> {
+        Ok(self
+            .services()
+            .await?
+            .into_iter()
+            .find(|state| state.name == service)
+            .and_then(|state| state.sdl))
+    }
+
+    /// Executes a query of the admin schema and deserializes its data.
+    pub async fn query<T: DeserializeOwned>(&self, query: &str) -> Result<T> {
+        let resp: Response = self
+            .client
+            .post(format!("{}/admin/graphql", self.url))
+            .json(&Request::new(query))
+            .send()
+            .await
+            .context("Failed to send the admin query.")?
+            .json()
+            .await
+            .context("Failed to parse the admin response.")?;
+        if let Some(err) = resp.errors.first() {
+            anyhow::bail!("Admin query failed: {}", err.message);
+        }
+        serde_json::from_value(serde_json::to_value(resp.data)?)
+            .context("Failed to parse the admin response.")
+    }
+
+    async fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T> {
+        self.client
+            .get(format!("{}/admin/{}", self.url, path))
+            .send()
+            .await
+            .with_context(|| format!("Failed to request '/admin/{}'.", path))?
+            .error_for_status()?
+            .json()
+            .await
+            .with_context(|| format!("Failed to parse the response of '/admin/{}'.", path))
+    }
+
+    async fn put<B: Serialize, T: DeserializeOwned>(&self, path: &str, body: &B) -> Result<T> {
+        self.client
+            .put(format!("{}/admin/{}", self.url, path))
+            .json(body)
+            .send()
+            .await
+            .with_context(|| format!("Failed to request '/admin/{}'.", path))?
+            .error_for_status()?
+            .json()
+            .await
+            .with_context(|| format!("Failed to parse the response of '/admin/{}'.", path))
+    }
+}
@@ -0,0 +1,53 @@
+use anyhow::{Context, Result};
+use graphgate_planner::{ErrorCode, Request, Response};
+
+/// Client of the public address of the gateway.
+#[derive(Clone)]
+pub struct GatewayClient {
+    client: reqwest::Client,
+    url: String,
+}
+
+impl GatewayClient {
+    /// Creates a client of the gateway at `url`, such as `http://127.0.0.1:8000`.
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            url: url.into().trim_end_matches('/').to_string(),
+        }
+    }
+
+    /// Returns `true` if the gateway process is serving requests.
+    pub async fn health(&self) -> Result<bool> {
+        let resp = self
+            .client
+            .get(format!("{}/health", self.url))
+            .send()
+            .await
+            .context("Failed to request the health endpoint.")?;
+        Ok(resp.status().is_success())
+    }
+
+    /// Returns `true` if the schema is composed and the gateway can execute
+    /// operations.
+    pub async fn ready(&self) -> Result<bool> {
+        let resp = self.execute(Request::new("{ __typename }")).await?;
+        Ok(!resp
+            .errors
+            .iter()
+            .any(|err| err.code() == Some(ErrorCode::NotReady.as_str())))
+    }
+
+    /// Executes an operation on the gateway.
+    pub async fn execute(&self, request: Request) -> Result<Response> {
+        self.client
+            .post(&self.url)
+            .json(&request)
+            .send()
+            .await
+            .context("Failed to send the request.")?
+            .json()
+            .await
+            .context("Failed to parse the response.")
+    }
+}
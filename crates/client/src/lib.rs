@@ -0,0 +1,9 @@
+#![forbid(unsafe_code)]
+
+//! Typed clients for the health endpoint and the admin API of GraphGate.
+
+mod admin;
+mod gateway;
+
+pub use admin::{AdminClient, DisabledTargets, MaintenanceMode, ReadOnlyMode, ServiceState};
+pub use gateway::GatewayClient;
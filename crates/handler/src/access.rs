@@ -0,0 +1,219 @@
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::str::FromStr;
+
+use anyhow::{Context, Result};
+use http::HeaderMap;
+
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+
+/// An IP network in CIDR notation, such as `10.0.0.0/8`.
+///
+/// A single address is treated as a network containing only that address.
+/// The IPv4-mapped IPv6 addresses, such as `::ffff:10.0.0.1`, are matched as
+/// IPv4 addresses.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct IpNetwork {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl FromStr for IpNetwork {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (addr, prefix_len) = match s.split_once('/') {
+            Some((addr, prefix_len)) => (addr, Some(prefix_len)),
+            None => (s, None),
+        };
+        let addr = IpAddr::from_str(addr.trim())
+            .with_context(|| format!("Invalid IP address '{}'.", addr))?;
+        let max_prefix_len = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => prefix_len
+                .trim()
+                .parse::<u8>()
+                .ok()
+                .filter(|prefix_len| *prefix_len <= max_prefix_len)
+                .with_context(|| format!("Invalid prefix length '{}'.", prefix_len))?,
+            None => max_prefix_len,
+        };
+        match normalize_ip(addr) {
+            IpAddr::V4(ipv4) if addr.is_ipv6() && prefix_len >= 96 => Ok(Self {
+                addr: IpAddr::V4(ipv4),
+                prefix_len: prefix_len - 96,
+            }),
+            _ => Ok(Self { addr, prefix_len }),
+        }
+    }
+}
+
+impl IpNetwork {
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match (self.addr, &normalize_ip(*ip)) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u32::from(network) & mask == u32::from(*ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u128::from(network) & mask == u128::from(*ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Returns the IPv4 address of an IPv4-mapped IPv6 address, such as the peers
+/// of a dual-stack socket, or else the address.
+fn normalize_ip(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(ipv6) => match ipv6.octets() {
+            [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, a, b, c, d] => {
+                IpAddr::V4(Ipv4Addr::new(a, b, c, d))
+            }
+            _ => ip,
+        },
+        IpAddr::V4(_) => ip,
+    }
+}
+
+/// Source IP filtering options.
+#[derive(Debug, Clone, Default)]
+pub struct AccessOptions {
+    /// If not empty, only the clients in these networks are allowed.
+    pub allow: Vec<IpNetwork>,
+
+    /// The clients in these networks are denied, even if they are allowed.
+    pub deny: Vec<IpNetwork>,
+
+    /// Proxies whose `X-Forwarded-For` header is trusted.
+    pub trusted_proxies: Vec<IpNetwork>,
+}
+
+impl AccessOptions {
+    fn is_trusted_proxy(&self, ip: &IpAddr) -> bool {
+        self.trusted_proxies
+            .iter()
+            .any(|network| network.contains(ip))
+    }
+
+    /// Returns the IP address of the client.
+    ///
+    /// The `X-Forwarded-For` header is only used if the peer is a trusted proxy,
+    /// in which case the rightmost address that is not a trusted proxy is returned.
+    /// The addresses are walked from the right, and the client is unknown if an
+    /// address that is not valid is reached first, so that it is denied by the
+    /// filters instead of being mistaken for the proxy. The IPv4-mapped IPv6
+    /// addresses are returned as IPv4 addresses.
+    pub fn client_ip(
+        &self,
+        header_map: &HeaderMap,
+        remote_addr: Option<SocketAddr>,
+    ) -> Option<IpAddr> {
+        let peer_ip = normalize_ip(remote_addr?.ip());
+        if !self.is_trusted_proxy(&peer_ip) {
+            return Some(peer_ip);
+        }
+
+        let forwarded = header_map
+            .get_all(X_FORWARDED_FOR)
+            .iter()
+            .flat_map(|value| match value.to_str() {
+                Ok(value) => value
+                    .split(',')
+                    .map(|ip| IpAddr::from_str(ip.trim()).ok().map(normalize_ip))
+                    .collect(),
+                Err(_) => vec![None],
+            })
+            .collect::<Vec<_>>();
+
+        let mut client_ip = peer_ip;
+        for ip in forwarded.into_iter().rev() {
+            client_ip = ip?;
+            if !self.is_trusted_proxy(&client_ip) {
+                break;
+            }
+        }
+        Some(client_ip)
+    }
+
+    /// Returns `true` if the client is allowed to access the gateway.
+    pub fn is_allowed(&self, client_ip: Option<IpAddr>) -> bool {
+        if self.allow.is_empty() && self.deny.is_empty() {
+            return true;
+        }
+        let client_ip = match client_ip {
+            Some(client_ip) => client_ip,
+            None => return false,
+        };
+        if self.deny.iter().any(|network| network.contains(&client_ip)) {
+            return false;
+        }
+        self.allow.is_empty()
+            || self
+                .allow
+                .iter()
+                .any(|network| network.contains(&client_ip))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client_ip(forwarded_for: &str, peer: &str) -> Option<IpAddr> {
+        let options = AccessOptions {
+            trusted_proxies: vec!["10.0.0.0/8".parse().unwrap()],
+            ..Default::default()
+        };
+        let mut header_map = HeaderMap::new();
+        header_map.insert(X_FORWARDED_FOR, forwarded_for.parse().unwrap());
+        options.client_ip(
+            &header_map,
+            Some(SocketAddr::new(peer.parse().unwrap(), 80)),
+        )
+    }
+
+    #[test]
+    fn forwarded_for() {
+        let ip = |ip: &str| Some(ip.parse::<IpAddr>().unwrap());
+        assert_eq!(client_ip("1.2.3.4", "5.6.7.8"), ip("5.6.7.8"));
+        assert_eq!(client_ip("1.2.3.4, 10.0.0.2", "10.0.0.1"), ip("1.2.3.4"));
+        assert_eq!(client_ip("9.9.9.9, 1.2.3.4", "10.0.0.1"), ip("1.2.3.4"));
+        assert_eq!(client_ip("10.0.0.3, 10.0.0.2", "10.0.0.1"), ip("10.0.0.3"));
+        assert_eq!(client_ip("garbage, 1.2.3.4", "10.0.0.1"), ip("1.2.3.4"));
+        assert_eq!(client_ip("1.2.3.4, garbage", "10.0.0.1"), None);
+        assert_eq!(client_ip("1.2.3.4, garbage, 10.0.0.2", "10.0.0.1"), None);
+
+        // IPv4-mapped IPv6 addresses, such as the peers of a dual-stack socket.
+        assert_eq!(client_ip("1.2.3.4", "::ffff:10.0.0.1"), ip("1.2.3.4"));
+        assert_eq!(
+            client_ip("1.2.3.4, ::ffff:10.0.0.2", "::ffff:10.0.0.1"),
+            ip("1.2.3.4")
+        );
+        assert_eq!(client_ip("1.2.3.4", "::ffff:5.6.7.8"), ip("5.6.7.8"));
+        assert_eq!(client_ip("1.2.3.4", "::1"), ip("::1"));
+    }
+
+    #[test]
+    fn mapped_networks() {
+        let network = "::ffff:10.0.0.0/104".parse::<IpNetwork>().unwrap();
+        assert!(network.contains(&"10.1.2.3".parse().unwrap()));
+        assert!(network.contains(&"::ffff:10.1.2.3".parse().unwrap()));
+        assert!(!network.contains(&"11.0.0.1".parse().unwrap()));
+
+        let options = AccessOptions {
+            deny: vec!["192.168.0.0/16".parse().unwrap()],
+            ..Default::default()
+        };
+        assert!(!options.is_allowed(Some("::ffff:192.168.1.1".parse().unwrap())));
+        assert!(options.is_allowed(Some("::ffff:10.0.0.1".parse().unwrap())));
+    }
+}
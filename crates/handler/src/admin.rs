@@ -0,0 +1,95 @@
+use graphgate_planner::Request;
+use warp::{Filter, Rejection, Reply};
+
+use crate::admin_schema;
+use crate::switches::{DisabledTargets, MaintenanceMode, ReadOnlyMode, RuntimeSwitches};
+use crate::SharedRouteTable;
+
+/// Routes for changing the runtime switches of the gateway, and the GraphQL
+/// endpoint for querying its runtime state.
+///
+/// These routes are not authenticated and should only be served on a private address.
+pub fn admin(
+    switches: RuntimeSwitches,
+    shared_route_table: SharedRouteTable,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path("admin").and(
+        read_only(switches.clone())
+            .or(disabled(switches.clone()))
+            .or(maintenance(switches))
+            .or(graphql(shared_route_table)),
+    )
+}
+
+fn read_only(
+    switches: RuntimeSwitches,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let get = warp::path!("read-only").and(warp::get()).map({
+        let switches = switches.clone();
+        move || warp::reply::json(&switches.read_only())
+    });
+    let set = warp::path!("read-only")
+        .and(warp::put())
+        .and(warp::body::json())
+        .map(move |read_only: ReadOnlyMode| {
+            tracing::info!(enabled = read_only.enabled, "Read-only mode changed.");
+            switches.set_read_only(read_only);
+            warp::reply::json(&switches.read_only())
+        });
+    get.or(set)
+}
+
+fn disabled(
+    switches: RuntimeSwitches,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let get = warp::path!("disabled").and(warp::get()).map({
+        let switches = switches.clone();
+        move || warp::reply::json(&switches.disabled())
+    });
+    let set = warp::path!("disabled")
+        .and(warp::put())
+        .and(warp::body::json())
+        .map(move |disabled: DisabledTargets| {
+            tracing::info!(
+                fields = ?disabled.fields,
+                services = ?disabled.services,
+                "Disabled fields and services changed."
+            );
+            switches.set_disabled(disabled);
+            warp::reply::json(&switches.disabled())
+        });
+    get.or(set)
+}
+
+fn maintenance(
+    switches: RuntimeSwitches,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let get = warp::path!("maintenance").and(warp::get()).map({
+        let switches = switches.clone();
+        move || warp::reply::json(&switches.maintenance())
+    });
+    let set = warp::path!("maintenance")
+        .and(warp::put())
+        .and(warp::body::json())
+        .map(move |maintenance: MaintenanceMode| {
+            tracing::info!(enabled = maintenance.enabled, "Maintenance mode changed.");
+            switches.set_maintenance(maintenance);
+            warp::reply::json(&switches.maintenance())
+        });
+    get.or(set)
+}
+
+fn graphql(
+    shared_route_table: SharedRouteTable,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("graphql")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and_then(move |request: Request| {
+            let shared_route_table = shared_route_table.clone();
+            async move {
+                let response = admin_schema::execute(&shared_route_table, request).await;
+                Ok::<_, Rejection>(warp::reply::json(&response))
+            }
+        })
+}
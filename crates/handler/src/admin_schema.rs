@@ -0,0 +1,225 @@
+use std::collections::HashMap;
+use std::sync::atomic::Ordering;
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use graphgate_planner::{ErrorCode, PlanBuilder, Request, Response, ServerError};
+use graphgate_schema::ComposedSchema;
+use once_cell::sync::Lazy;
+use parser::types::DocumentOperations;
+use serde_json::json;
+use value::ConstValue;
+
+use crate::executor::Executor;
+use crate::fetcher::Fetcher;
+use crate::shaping::project;
+use crate::websocket::{ACTIVE_CONNECTIONS, ACTIVE_SUBSCRIPTIONS};
+use crate::SharedRouteTable;
+
+const ADMIN_SERVICE: &str = "admin";
+
+const ADMIN_SDL: &str = r#"
+type Query {
+    "The composed schema, null if it is not ready."
+    schema: SchemaState @resolve(service: "admin")
+    services: [ServiceState!]! @resolve(service: "admin")
+    subscriptions: SubscriptionState! @resolve(service: "admin")
+    config: ConfigState! @resolve(service: "admin")
+}
+
+type SchemaState {
+    "Incremented whenever the SDL of any service changes."
+    version: Int!
+    updatedAt: String!
+    "True if the schema is loaded from a supergraph file."
+    static: Boolean!
+    types: Int!
+}
+
+type ServiceState {
+    name: String!
+    addr: String!
+    tls: Boolean!
+    queryPath: String
+    subscribePath: String
+    httpVersion: String!
+    subscriptionCallback: Boolean!
+    "Null if the SDL of the service has not been fetched yet."
+    healthy: Boolean
+    error: String
+    checkedAt: String
+    "The SDL of the last successful fetch."
+    sdl: String
+    "True if the service is in a planned maintenance window, which is not a failure."
+    maintenance: Boolean!
+    "End of the current maintenance window."
+    maintenanceUntil: String
+}
+
+type SubscriptionState {
+    connections: Int!
+    active: Int!
+}
+
+type ConfigState {
+    receiveHeaders: [String!]!
+    planLimits: PlanLimitsState!
+    readOnly: Boolean!
+    maintenance: Boolean!
+    disabledFields: [String!]!
+    disabledServices: [String!]!
+    audit: Boolean!
+    record: Boolean!
+    slo: Boolean!
+}
+
+type PlanLimitsState {
+    maxFetchNodes: Int
+    maxFlattenRounds: Int
+    maxServices: Int
+    maxAliasesPerField: Int
+    maxRepeatedSelections: Int
+}
+"#;
+
+static ADMIN_SCHEMA: Lazy<ComposedSchema> =
+    Lazy::new(|| ComposedSchema::parse(ADMIN_SDL).expect("Invalid admin schema."));
+
+/// Executes a query of the admin schema, which exposes the runtime state of the gateway.
+pub(crate) async fn execute(shared_route_table: &SharedRouteTable, request: Request) -> Response {
+    let document = match parser::parse_query(&request.query) {
+        Ok(document) => document,
+        Err(err) => {
+            return Response::from_errors(vec![
+                ServerError::new(err.to_string()).with_code(ErrorCode::ParseFailed)
+            ])
+        }
+    };
+
+    let mut plan_builder = PlanBuilder::new(&ADMIN_SCHEMA, document).variables(request.variables);
+    if let Some(operation) = request.operation {
+        plan_builder = plan_builder.operation_name(operation);
+    }
+    let plan = match plan_builder.plan() {
+        Ok(plan) => plan,
+        Err(response) => return response,
+    };
+
+    let fetcher = AdminFetcher {
+        state: state(shared_route_table).await,
+    };
+    Executor::new(&ADMIN_SCHEMA)
+        .execute_query(&fetcher, &plan)
+        .await
+}
+
+async fn state(shared_route_table: &SharedRouteTable) -> ConstValue {
+    let schema = shared_route_table.get().await.map(|(schema, _)| schema);
+    let (schema_version, static_schema) = shared_route_table.schema_version().await;
+    let health = shared_route_table.health().await;
+    let route_table = shared_route_table.route_table().await;
+    let now = Utc::now();
+    let switches = shared_route_table.switches();
+    let plan_limits = shared_route_table.plan_limits();
+
+    let schema = schema.map(|schema| {
+        json!({
+            "__typename": "SchemaState",
+            "version": schema_version.version,
+            "updatedAt": schema_version.updated_at.map(|time| time.to_rfc3339()),
+            "static": static_schema,
+            "types": schema.types.len(),
+        })
+    });
+
+    let mut services = route_table
+        .iter()
+        .flat_map(|route_table| route_table.iter())
+        .map(|(name, route)| {
+            let health = health.get(name);
+            let maintenance_until = route.maintenance(now).map(|(_, end)| end);
+            json!({
+                "__typename": "ServiceState",
+                "name": name,
+                "addr": route.addr,
+                "tls": route.tls,
+                "queryPath": route.query_path,
+                "subscribePath": route.subscribe_path,
+                "httpVersion": format!("{:?}", route.http_version).to_lowercase(),
+                "subscriptionCallback": route.subscription_callback,
+                "healthy": health.map(|health| health.error.is_none()),
+                "error": health.and_then(|health| health.error.clone()),
+                "checkedAt": health.map(|health| health.checked_at.to_rfc3339()),
+                "sdl": health.and_then(|health| health.sdl.clone()),
+                "maintenance": maintenance_until.is_some(),
+                "maintenanceUntil": maintenance_until.map(|end| end.to_rfc3339()),
+            })
+        })
+        .collect::<Vec<_>>();
+    services.sort_by(|a, b| a["name"].as_str().cmp(&b["name"].as_str()));
+
+    let disabled = switches.disabled();
+    let state = json!({
+        "schema": schema,
+        "services": services,
+        "subscriptions": {
+            "__typename": "SubscriptionState",
+            "connections": ACTIVE_CONNECTIONS.load(Ordering::Relaxed),
+            "active": ACTIVE_SUBSCRIPTIONS.load(Ordering::Relaxed),
+        },
+        "config": {
+            "__typename": "ConfigState",
+            "receiveHeaders": shared_route_table.receive_headers(),
+            "planLimits": {
+                "__typename": "PlanLimitsState",
+                "maxFetchNodes": plan_limits.max_fetch_nodes,
+                "maxFlattenRounds": plan_limits.max_flatten_rounds,
+                "maxServices": plan_limits.max_services,
+                "maxAliasesPerField": plan_limits.validation.max_aliases_per_field,
+                "maxRepeatedSelections": plan_limits.validation.max_repeated_selections,
+            },
+            "readOnly": switches.read_only().enabled,
+            "maintenance": switches.maintenance().enabled,
+            "disabledFields": disabled.fields,
+            "disabledServices": disabled.services,
+            "audit": shared_route_table.audit_enabled(),
+            "record": shared_route_table.record_enabled(),
+            "slo": shared_route_table.slo_enabled(),
+        },
+    });
+    ConstValue::from_json(state).unwrap_or_default()
+}
+
+/// Resolves the requests planned for the admin service from a snapshot of the state.
+struct AdminFetcher {
+    state: ConstValue,
+}
+
+#[async_trait::async_trait]
+impl Fetcher for AdminFetcher {
+    async fn query(&self, service: &str, request: Request) -> Result<Response> {
+        anyhow::ensure!(service == ADMIN_SERVICE, "Unknown service '{}'.", service);
+        let document = parser::parse_query(&request.query)?;
+        let operation = match &document.operations {
+            DocumentOperations::Single(operation) => Some(operation),
+            DocumentOperations::Multiple(operations) => operations.values().next(),
+        }
+        .context("Expected an operation.")?;
+        let fragments = document
+            .fragments
+            .iter()
+            .map(|(name, fragment)| (name.clone(), fragment.node.selection_set.clone()))
+            .collect::<HashMap<_, _>>();
+        Ok(Response {
+            data: project(
+                self.state.clone(),
+                &operation.node.selection_set,
+                &fragments,
+            ),
+            errors: Vec::new(),
+            extensions: Default::default(),
+            headers: Default::default(),
+            body_sizes: None,
+        })
+    }
+}
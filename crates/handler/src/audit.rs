@@ -0,0 +1,199 @@
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use graphgate_planner::{BodySizes, Request, Response};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+
+use crate::fetcher::Fetcher;
+
+static HTTP_CLIENT: Lazy<reqwest::Client> = Lazy::new(Default::default);
+
+/// Execution audit options.
+#[derive(Debug, Clone, Default)]
+pub struct AuditOptions {
+    /// If set, the execution reports are also posted to this URL.
+    pub webhook: Option<String>,
+}
+
+/// A request sent to a service while executing a query plan.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FetchEvent {
+    pub service: String,
+    pub start_time: DateTime<Utc>,
+    pub end_time: DateTime<Utc>,
+    pub request_bytes: usize,
+    pub response_bytes: usize,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<String>,
+}
+
+/// The services contacted for a single GraphQL request.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExecutionReport {
+    pub operation: Option<String>,
+    /// The operation with the literals replaced by placeholders.
+    pub signature: Option<String>,
+    pub start_time: DateTime<Utc>,
+    pub end_time: DateTime<Utc>,
+    pub fetches: Vec<FetchEvent>,
+}
+
+impl ExecutionReport {
+    /// Emits this report as a tracing event, and posts it to the webhook if configured.
+    pub fn emit(self, options: &AuditOptions) {
+        let report = match serde_json::to_string(&self) {
+            Ok(report) => report,
+            Err(err) => {
+                tracing::error!(error = %err, "Failed to serialize execution report.");
+                return;
+            }
+        };
+        tracing::info!(target: "graphgate::audit", report = %report, "Execution report.");
+
+        if let Some(webhook) = options.webhook.clone() {
+            tokio::spawn(async move {
+                let res = HTTP_CLIENT
+                    .post(&webhook)
+                    .header("content-type", "application/json")
+                    .body(report)
+                    .send()
+                    .await
+                    .and_then(|res| res.error_for_status());
+                if let Err(err) = res {
+                    tracing::error!(
+                        webhook = %webhook,
+                        error = %err,
+                        "Failed to post execution report."
+                    );
+                }
+            });
+        }
+    }
+}
+
+/// A fetcher that measures the requests sent by another fetcher, for the
+/// execution report of the operation.
+pub struct AuditFetcher<F> {
+    inner: F,
+    start_time: DateTime<Utc>,
+    events: Option<Mutex<Vec<FetchEvent>>>,
+}
+
+impl<F: Fetcher> AuditFetcher<F> {
+    pub fn new(inner: F, enabled: bool) -> Self {
+        Self {
+            inner,
+            start_time: Utc::now(),
+            events: if enabled {
+                Some(Default::default())
+            } else {
+                None
+            },
+        }
+    }
+
+    pub fn inner(&self) -> &F {
+        &self.inner
+    }
+
+    /// Returns the execution report, or `None` if auditing is disabled.
+    pub fn into_report(
+        self,
+        operation: Option<String>,
+        signature: Option<String>,
+    ) -> Option<ExecutionReport> {
+        let events = self.events?;
+        Some(ExecutionReport {
+            operation,
+            signature,
+            start_time: self.start_time,
+            end_time: Utc::now(),
+            fetches: events.into_inner().unwrap_or_default(),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl<F: Fetcher> Fetcher for AuditFetcher<F> {
+    async fn query(&self, service: &str, request: Request) -> Result<Response> {
+        let events = match &self.events {
+            Some(events) => events,
+            None => return self.inner.query(service, request).await,
+        };
+
+        let start_time = Utc::now();
+        let res = self.inner.query(service, request).await;
+        let end_time = Utc::now();
+
+        // The responses that were not received over HTTP, such as the
+        // maintenance responses, have no size.
+        let (body_sizes, errors) = match &res {
+            Ok(resp) => (
+                resp.body_sizes.unwrap_or_default(),
+                resp.errors.iter().map(|err| err.message.clone()).collect(),
+            ),
+            Err(err) => (BodySizes::default(), vec![err.to_string()]),
+        };
+        if let Ok(mut events) = events.lock() {
+            events.push(FetchEvent {
+                service: service.to_string(),
+                start_time,
+                end_time,
+                request_bytes: body_sizes.request,
+                response_bytes: body_sizes.response,
+                errors,
+            });
+        }
+        res
+    }
+}
+
+/// The execution report of an operation whose requests are sent over
+/// WebSocket, such as a subscription.
+///
+/// The WebSocket controller adds a fetch event when an upstream subscription
+/// completes, with the sizes of the messages sent and received. The report is
+/// emitted once the operation and all its upstream subscriptions are done.
+pub(crate) struct StreamAudit {
+    report: Mutex<Option<ExecutionReport>>,
+    options: AuditOptions,
+}
+
+impl StreamAudit {
+    pub(crate) fn new(
+        options: AuditOptions,
+        operation: Option<String>,
+        signature: Option<String>,
+    ) -> Arc<Self> {
+        let now = Utc::now();
+        Arc::new(Self {
+            report: Mutex::new(Some(ExecutionReport {
+                operation,
+                signature,
+                start_time: now,
+                end_time: now,
+                fetches: Vec::new(),
+            })),
+            options,
+        })
+    }
+
+    pub(crate) fn push(&self, event: FetchEvent) {
+        if let Some(report) = self.report.lock().unwrap().as_mut() {
+            report.fetches.push(event);
+        }
+    }
+}
+
+impl Drop for StreamAudit {
+    fn drop(&mut self) {
+        if let Some(mut report) = self.report.get_mut().ok().and_then(Option::take) {
+            report.end_time = Utc::now();
+            report.emit(&self.options);
+        }
+    }
+}
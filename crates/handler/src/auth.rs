@@ -0,0 +1,427 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use http::header::{HeaderName, AUTHORIZATION};
+use http::{HeaderMap, HeaderValue};
+use once_cell::sync::Lazy;
+use ring::{hmac, signature};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use tokio::sync::Notify;
+
+static HTTP_CLIENT: Lazy<reqwest::Client> = Lazy::new(Default::default);
+
+/// Minimum delay between two fetches of the JWKS, when tokens signed with
+/// unknown keys ask for it to be fetched again.
+const MIN_JWKS_REFRESH_DELAY: Duration = Duration::from_secs(10);
+
+/// Claims of a verified token.
+pub type Claims = Map<String, Value>;
+
+/// Verification of the JWT in the bearer `Authorization` header of the client
+/// requests.
+///
+/// The `HS256`, `HS384` and `HS512` tokens are verified with the secret, the
+/// `RS256`, `RS384` and `RS512` tokens with the RSA keys of the JWKS. An
+/// algorithm is only accepted if its key is configured.
+#[derive(Debug, Clone)]
+pub struct AuthOptions {
+    /// Secret of the HMAC tokens.
+    pub secret: Option<String>,
+
+    /// URL of the JWKS with the keys of the RSA tokens.
+    pub jwks_url: Option<String>,
+
+    /// Interval between the fetches of the JWKS.
+    pub jwks_refresh_interval: Duration,
+
+    /// Expected `iss` claim.
+    pub issuer: Option<String>,
+
+    /// Expected `aud` claim, or one of the items of the claim if it is a list.
+    pub audience: Option<String>,
+
+    /// If `true`, the requests without a token are rejected, otherwise they
+    /// are forwarded without claims.
+    pub required: bool,
+
+    /// Tolerance of the `exp` and `nbf` claims.
+    pub leeway: Duration,
+
+    /// Claims forwarded to the services, as `(claim, header)`. The headers
+    /// with the same names sent by the client are removed.
+    pub claim_headers: Vec<(String, String)>,
+}
+
+impl Default for AuthOptions {
+    fn default() -> Self {
+        Self {
+            secret: None,
+            jwks_url: None,
+            jwks_refresh_interval: Duration::from_secs(600),
+            issuer: None,
+            audience: None,
+            required: true,
+            leeway: Duration::from_secs(60),
+            claim_headers: Vec::new(),
+        }
+    }
+}
+
+struct RsaKey {
+    n: Vec<u8>,
+    e: Vec<u8>,
+}
+
+#[derive(Deserialize)]
+struct Jwks {
+    keys: Vec<Jwk>,
+}
+
+#[derive(Deserialize)]
+struct Jwk {
+    kty: String,
+    kid: Option<String>,
+    n: Option<String>,
+    e: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct JwtHeader {
+    alg: String,
+    kid: Option<String>,
+}
+
+/// Verifies the tokens of the client requests.
+pub struct Authenticator {
+    options: AuthOptions,
+    secret: RwLock<Option<String>>,
+    keys: RwLock<HashMap<Option<String>, RsaKey>>,
+    refresh_jwks: Arc<Notify>,
+}
+
+impl Authenticator {
+    /// Creates an authenticator, and fetches the JWKS periodically if it is
+    /// configured.
+    pub fn new(options: AuthOptions) -> Arc<Self> {
+        let authenticator = Arc::new(Self {
+            secret: RwLock::new(options.secret.clone()),
+            options,
+            keys: Default::default(),
+            refresh_jwks: Default::default(),
+        });
+        if authenticator.options.jwks_url.is_some() {
+            let refresh_jwks = authenticator.refresh_jwks.clone();
+            let authenticator = Arc::downgrade(&authenticator);
+            tokio::spawn(async move {
+                loop {
+                    let interval = match authenticator.upgrade() {
+                        Some(authenticator) => {
+                            if let Err(err) = authenticator.fetch_jwks().await {
+                                tracing::error!(error = %err, "Failed to fetch JWKS.");
+                            }
+                            authenticator.options.jwks_refresh_interval
+                        }
+                        None => return,
+                    };
+                    tokio::time::sleep(MIN_JWKS_REFRESH_DELAY).await;
+                    tokio::select! {
+                        _ = tokio::time::sleep(interval.saturating_sub(MIN_JWKS_REFRESH_DELAY)) => {}
+                        _ = refresh_jwks.notified() => {}
+                    }
+                }
+            });
+        }
+        authenticator
+    }
+
+    /// Replaces the secret of the HMAC tokens, such as when the secret is
+    /// rotated.
+    pub fn set_secret(&self, secret: Option<String>) {
+        *self.secret.write().unwrap() = secret;
+    }
+
+    async fn fetch_jwks(&self) -> Result<()> {
+        let jwks_url = match &self.options.jwks_url {
+            Some(jwks_url) => jwks_url,
+            None => return Ok(()),
+        };
+        let jwks: Jwks = HTTP_CLIENT
+            .get(jwks_url)
+            .timeout(Duration::from_secs(10))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+            .context("Invalid JWKS.")?;
+        let keys = jwks
+            .keys
+            .into_iter()
+            .filter(|jwk| jwk.kty == "RSA")
+            .filter_map(|jwk| {
+                let decode =
+                    |value: &str| base64::decode_config(value, base64::URL_SAFE_NO_PAD).ok();
+                let key = RsaKey {
+                    n: decode(jwk.n.as_deref()?)?,
+                    e: decode(jwk.e.as_deref()?)?,
+                };
+                Some((jwk.kid, key))
+            })
+            .collect::<HashMap<_, _>>();
+        tracing::debug!(url = %jwks_url, keys = keys.len(), "JWKS fetched.");
+        *self.keys.write().unwrap() = keys;
+        Ok(())
+    }
+
+    /// Verifies the token of a request, returns its claims or `None` if there
+    /// is no token and it is not required.
+    pub(crate) fn authenticate(&self, header_map: &HeaderMap) -> Result<Option<Claims>> {
+        let token = header_map
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        match token {
+            Some(token) => self.verify(token.trim()).map(Some),
+            None if self.options.required => anyhow::bail!("Missing bearer token."),
+            None => Ok(None),
+        }
+    }
+
+    /// Replaces the claim headers of the requests sent to the services with
+    /// the claims of the token.
+    pub(crate) fn forward_claims(&self, claims: Option<&Claims>, forward_headers: &mut HeaderMap) {
+        for (claim, header) in &self.options.claim_headers {
+            let name = match HeaderName::from_str(header) {
+                Ok(name) => name,
+                Err(_) => continue,
+            };
+            forward_headers.remove(&name);
+            let value = match claims.and_then(|claims| claims.get(claim)) {
+                Some(Value::String(value)) => HeaderValue::from_str(value),
+                Some(value) => HeaderValue::from_str(&value.to_string()),
+                None => continue,
+            };
+            if let Ok(value) = value {
+                forward_headers.insert(name, value);
+            }
+        }
+    }
+
+    fn verify(&self, token: &str) -> Result<Claims> {
+        let decode = |part: &str| {
+            base64::decode_config(part, base64::URL_SAFE_NO_PAD).context("Invalid token.")
+        };
+        let mut parts = token.split('.');
+        let (header, payload, sig) = match (parts.next(), parts.next(), parts.next(), parts.next())
+        {
+            (Some(header), Some(payload), Some(sig), None) => (header, payload, sig),
+            _ => anyhow::bail!("Invalid token."),
+        };
+        let message = &token[..header.len() + 1 + payload.len()];
+        let jwt_header: JwtHeader =
+            serde_json::from_slice(&decode(header)?).context("Invalid token header.")?;
+        let sig = decode(sig)?;
+
+        let verified = match jwt_header.alg.as_str() {
+            "HS256" => self.verify_hmac(hmac::HMAC_SHA256, message, &sig),
+            "HS384" => self.verify_hmac(hmac::HMAC_SHA384, message, &sig),
+            "HS512" => self.verify_hmac(hmac::HMAC_SHA512, message, &sig),
+            "RS256" => self.verify_rsa(
+                &signature::RSA_PKCS1_2048_8192_SHA256,
+                jwt_header.kid,
+                message,
+                &sig,
+            ),
+            "RS384" => self.verify_rsa(
+                &signature::RSA_PKCS1_2048_8192_SHA384,
+                jwt_header.kid,
+                message,
+                &sig,
+            ),
+            "RS512" => self.verify_rsa(
+                &signature::RSA_PKCS1_2048_8192_SHA512,
+                jwt_header.kid,
+                message,
+                &sig,
+            ),
+            alg => anyhow::bail!("Unsupported token algorithm '{}'.", alg),
+        };
+        anyhow::ensure!(verified, "Invalid token signature.");
+
+        let claims: Claims =
+            serde_json::from_slice(&decode(payload)?).context("Invalid token claims.")?;
+        self.check_claims(&claims)?;
+        Ok(claims)
+    }
+
+    fn verify_hmac(&self, algorithm: hmac::Algorithm, message: &str, sig: &[u8]) -> bool {
+        match &*self.secret.read().unwrap() {
+            Some(secret) => {
+                let key = hmac::Key::new(algorithm, secret.as_bytes());
+                hmac::verify(&key, message.as_bytes(), sig).is_ok()
+            }
+            None => false,
+        }
+    }
+
+    fn verify_rsa(
+        &self,
+        parameters: &'static signature::RsaParameters,
+        kid: Option<String>,
+        message: &str,
+        sig: &[u8],
+    ) -> bool {
+        let keys = self.keys.read().unwrap();
+        let verify = |key: &RsaKey| {
+            signature::RsaPublicKeyComponents {
+                n: &key.n,
+                e: &key.e,
+            }
+            .verify(parameters, message.as_bytes(), sig)
+            .is_ok()
+        };
+        match keys.get(&kid) {
+            Some(key) => verify(key),
+            None if kid.is_none() => keys.values().any(verify),
+            None => {
+                // The keys may have been rotated.
+                self.refresh_jwks.notify_one();
+                false
+            }
+        }
+    }
+
+    fn check_claims(&self, claims: &Claims) -> Result<()> {
+        let now = Utc::now().timestamp();
+        let leeway = self.options.leeway.as_secs() as i64;
+        if let Some(exp) = claims.get("exp").and_then(Value::as_i64) {
+            anyhow::ensure!(exp + leeway > now, "Token expired.");
+        }
+        if let Some(nbf) = claims.get("nbf").and_then(Value::as_i64) {
+            anyhow::ensure!(nbf - leeway <= now, "Token not yet valid.");
+        }
+        if let Some(issuer) = &self.options.issuer {
+            anyhow::ensure!(
+                claims.get("iss").and_then(Value::as_str) == Some(issuer),
+                "Invalid token issuer."
+            );
+        }
+        if let Some(audience) = &self.options.audience {
+            let valid = match claims.get("aud") {
+                Some(Value::String(aud)) => aud == audience,
+                Some(Value::Array(aud)) => aud.iter().any(|aud| aud.as_str() == Some(audience)),
+                _ => false,
+            };
+            anyhow::ensure!(valid, "Invalid token audience.");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(claims: Value) -> String {
+        let encode =
+            |value: &Value| base64::encode_config(value.to_string(), base64::URL_SAFE_NO_PAD);
+        let message = format!(
+            "{}.{}",
+            encode(&serde_json::json!({ "alg": "HS256", "typ": "JWT" })),
+            encode(&claims)
+        );
+        let key = hmac::Key::new(hmac::HMAC_SHA256, b"secret");
+        let sig = hmac::sign(&key, message.as_bytes());
+        format!(
+            "{}.{}",
+            message,
+            base64::encode_config(sig.as_ref(), base64::URL_SAFE_NO_PAD)
+        )
+    }
+
+    fn header_map(token: &str) -> HeaderMap {
+        let mut header_map = HeaderMap::new();
+        header_map.insert(AUTHORIZATION, format!("Bearer {}", token).parse().unwrap());
+        header_map
+    }
+
+    #[test]
+    fn hmac_token() {
+        let authenticator = Authenticator::new(AuthOptions {
+            secret: Some("secret".to_string()),
+            issuer: Some("https://issuer".to_string()),
+            claim_headers: vec![
+                ("sub".to_string(), "x-user-id".to_string()),
+                ("roles".to_string(), "x-user-roles".to_string()),
+            ],
+            ..Default::default()
+        });
+        let now = Utc::now().timestamp();
+
+        let claims = authenticator
+            .authenticate(&header_map(&token(serde_json::json!({
+                "sub": "1234",
+                "roles": ["admin"],
+                "iss": "https://issuer",
+                "exp": now + 60,
+            }))))
+            .unwrap();
+        let mut forward_headers = HeaderMap::new();
+        forward_headers.insert("x-user-id", "spoofed".parse().unwrap());
+        authenticator.forward_claims(claims.as_ref(), &mut forward_headers);
+        assert_eq!(forward_headers["x-user-id"], "1234");
+        assert_eq!(forward_headers["x-user-roles"], r#"["admin"]"#);
+
+        assert!(authenticator
+            .authenticate(&header_map(&token(serde_json::json!({
+                "iss": "https://issuer",
+                "exp": now - 120,
+            }))))
+            .is_err());
+        assert!(authenticator
+            .authenticate(&header_map(&token(serde_json::json!({
+                "iss": "https://other",
+            }))))
+            .is_err());
+        let mut tampered = token(serde_json::json!({ "iss": "https://issuer" }));
+        tampered.push('A');
+        assert!(authenticator.authenticate(&header_map(&tampered)).is_err());
+        assert!(authenticator.authenticate(&HeaderMap::new()).is_err());
+
+        // The tokens signed with the previous secret are rejected once it is
+        // rotated.
+        let signed = token(serde_json::json!({ "iss": "https://issuer" }));
+        assert!(authenticator.authenticate(&header_map(&signed)).is_ok());
+        authenticator.set_secret(Some("rotated".to_string()));
+        assert!(authenticator.authenticate(&header_map(&signed)).is_err());
+    }
+
+    #[test]
+    fn optional_token() {
+        let authenticator = Authenticator::new(AuthOptions {
+            jwks_url: None,
+            required: false,
+            claim_headers: vec![("sub".to_string(), "x-user-id".to_string())],
+            ..Default::default()
+        });
+        assert!(authenticator
+            .authenticate(&HeaderMap::new())
+            .unwrap()
+            .is_none());
+
+        // Without a secret, the HMAC tokens are rejected.
+        assert!(authenticator
+            .authenticate(&header_map(&token(serde_json::json!({ "sub": "1" }))))
+            .is_err());
+
+        let mut forward_headers = HeaderMap::new();
+        forward_headers.insert("x-user-id", "spoofed".parse().unwrap());
+        authenticator.forward_claims(None, &mut forward_headers);
+        assert!(forward_headers.is_empty());
+    }
+}
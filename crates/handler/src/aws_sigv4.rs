@@ -0,0 +1,372 @@
+use std::fmt::Write;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use http::header::{HeaderName, HeaderValue, ACCEPT, AUTHORIZATION, CONTENT_TYPE, HOST};
+use http::HeaderMap;
+use once_cell::sync::Lazy;
+use reqwest::Url;
+use ring::{digest, hmac};
+use serde_json::Value;
+
+static HTTP_CLIENT: Lazy<reqwest::Client> = Lazy::new(Default::default);
+
+/// Credentials of the web identity role, and their expiration.
+static WEB_IDENTITY_CREDENTIALS: Lazy<tokio::sync::Mutex<Option<(AwsCredentials, DateTime<Utc>)>>> =
+    Lazy::new(Default::default);
+
+/// AWS credentials used to sign requests.
+#[derive(Debug, Clone)]
+pub struct AwsCredentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    pub session_token: Option<String>,
+}
+
+impl AwsCredentials {
+    /// Reads the credentials from the `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY`
+    /// and `AWS_SESSION_TOKEN` environment variables.
+    pub fn from_env() -> Option<Self> {
+        Some(Self {
+            access_key_id: std::env::var("AWS_ACCESS_KEY_ID").ok()?,
+            secret_access_key: std::env::var("AWS_SECRET_ACCESS_KEY").ok()?,
+            session_token: std::env::var("AWS_SESSION_TOKEN").ok(),
+        })
+    }
+
+    /// Returns the credentials of the environment variables, or else the
+    /// credentials of the role assumed with a web identity token, such as with
+    /// IAM roles for service accounts on EKS.
+    ///
+    /// The role is read from the `AWS_ROLE_ARN` environment variable and the
+    /// token from the `AWS_WEB_IDENTITY_TOKEN_FILE` file, the credentials are
+    /// cached until a few minutes before they expire.
+    pub async fn load() -> Result<Self> {
+        if let Some(credentials) = Self::from_env() {
+            return Ok(credentials);
+        }
+
+        let mut cached = WEB_IDENTITY_CREDENTIALS.lock().await;
+        if let Some((credentials, expiration)) = &*cached {
+            if *expiration > Utc::now() + Duration::minutes(5) {
+                return Ok(credentials.clone());
+            }
+        }
+        let (credentials, expiration) = assume_role_with_web_identity()
+            .await
+            .context("Failed to assume role with web identity.")?;
+        *cached = Some((credentials.clone(), expiration));
+        Ok(credentials)
+    }
+}
+
+async fn assume_role_with_web_identity() -> Result<(AwsCredentials, DateTime<Utc>)> {
+    let role_arn = std::env::var("AWS_ROLE_ARN")
+        .context("No AWS credentials, neither 'AWS_ACCESS_KEY_ID' nor 'AWS_ROLE_ARN' is set.")?;
+    let token_file = std::env::var("AWS_WEB_IDENTITY_TOKEN_FILE")
+        .context("'AWS_WEB_IDENTITY_TOKEN_FILE' is not set.")?;
+    let token = tokio::fs::read_to_string(&token_file)
+        .await
+        .with_context(|| format!("Failed to read web identity token '{}'.", token_file))?;
+    let url = match std::env::var("AWS_REGION") {
+        Ok(region) => format!("https://sts.{}.amazonaws.com/", region),
+        Err(_) => "https://sts.amazonaws.com/".to_string(),
+    };
+    let session_name =
+        std::env::var("AWS_ROLE_SESSION_NAME").unwrap_or_else(|_| "graphgate".to_string());
+
+    let resp = HTTP_CLIENT
+        .get(url)
+        .header(ACCEPT, "application/json")
+        .query(&[
+            ("Action", "AssumeRoleWithWebIdentity"),
+            ("Version", "2011-06-15"),
+            ("RoleArn", role_arn.as_str()),
+            ("RoleSessionName", session_name.as_str()),
+            ("WebIdentityToken", token.trim()),
+        ])
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<Value>()
+        .await?;
+    let credentials = &resp["AssumeRoleWithWebIdentityResponse"]["AssumeRoleWithWebIdentityResult"]
+        ["Credentials"];
+    let field = |name: &str| {
+        credentials[name]
+            .as_str()
+            .map(ToString::to_string)
+            .with_context(|| format!("Missing '{}' in the STS response.", name))
+    };
+    let expiration = match &credentials["Expiration"] {
+        Value::Number(seconds) => seconds
+            .as_f64()
+            .map(|seconds| Utc.timestamp(seconds as i64, 0)),
+        Value::String(expiration) => DateTime::parse_from_rfc3339(expiration)
+            .ok()
+            .map(|expiration| expiration.with_timezone(&Utc)),
+        _ => None,
+    }
+    .context("Missing 'Expiration' in the STS response.")?;
+
+    Ok((
+        AwsCredentials {
+            access_key_id: field("AccessKeyId")?,
+            secret_access_key: field("SecretAccessKey")?,
+            session_token: Some(field("SessionToken")?),
+        },
+        expiration,
+    ))
+}
+
+/// Signing of the HTTP requests sent to a service hosted by AWS, such as
+/// AppSync or API Gateway with IAM authorization.
+///
+/// The credentials are loaded with [`AwsCredentials::load`].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct AwsSigV4Options {
+    /// Region of the service, such as `eu-west-1`.
+    pub region: String,
+
+    /// Signing name of the service, such as `appsync` or `execute-api`.
+    pub service: String,
+}
+
+impl AwsSigV4Options {
+    /// Returns the headers signing a `POST` request, to add to the request.
+    pub(crate) async fn sign(
+        &self,
+        url: &str,
+        content_type: &str,
+        body: &[u8],
+    ) -> Result<HeaderMap> {
+        let credentials = AwsCredentials::load().await?;
+        let url = Url::parse(url)?;
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_str(content_type)?);
+        sign_request(
+            &credentials,
+            &self.region,
+            &self.service,
+            "POST",
+            &url,
+            &mut headers,
+            body,
+            Utc::now(),
+        );
+        // Both are signed, and set by the request with the same values.
+        headers.remove(CONTENT_TYPE);
+        headers.remove(HOST);
+        Ok(headers)
+    }
+}
+
+/// Signs a request with [AWS Signature Version 4](https://docs.aws.amazon.com/general/latest/gr/signature-version-4.html).
+///
+/// The `Host`, `X-Amz-Date`, `X-Amz-Security-Token` and `Authorization` headers
+/// are added to `headers`, all headers are signed.
+#[allow(clippy::too_many_arguments)]
+pub fn sign_request(
+    credentials: &AwsCredentials,
+    region: &str,
+    service: &str,
+    method: &str,
+    url: &Url,
+    headers: &mut HeaderMap,
+    body: &[u8],
+    now: DateTime<Utc>,
+) {
+    let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+    let date = now.format("%Y%m%d").to_string();
+
+    if let Some(host) = url
+        .host_str()
+        .map(|host| match url.port() {
+            Some(port) => format!("{}:{}", host, port),
+            None => host.to_string(),
+        })
+        .and_then(|host| HeaderValue::from_str(&host).ok())
+    {
+        headers.insert(HOST, host);
+    }
+    headers.insert(
+        HeaderName::from_static("x-amz-date"),
+        HeaderValue::from_str(&amz_date).unwrap(),
+    );
+    if let Some(session_token) = credentials
+        .session_token
+        .as_deref()
+        .and_then(|token| HeaderValue::from_str(token).ok())
+    {
+        headers.insert(
+            HeaderName::from_static("x-amz-security-token"),
+            session_token,
+        );
+    }
+    headers.remove(AUTHORIZATION);
+
+    let mut signed_headers = headers
+        .iter()
+        .filter_map(|(name, value)| Some((name.as_str(), value.to_str().ok()?.trim())))
+        .collect::<Vec<_>>();
+    signed_headers.sort_by(|a, b| a.0.cmp(b.0));
+    let mut canonical_headers = String::new();
+    for (name, value) in &signed_headers {
+        writeln!(canonical_headers, "{}:{}", name, value).unwrap();
+    }
+    let signed_header_names = signed_headers
+        .iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>()
+        .join(";");
+
+    let mut query = url
+        .query_pairs()
+        .map(|(name, value)| (uri_encode(&name), uri_encode(&value)))
+        .collect::<Vec<_>>();
+    query.sort();
+    let canonical_query = query
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect::<Vec<_>>()
+        .join("&");
+
+    let canonical_request = format!(
+        "{}\n{}\n{}\n{}\n{}\n{}",
+        method,
+        if url.path().is_empty() {
+            "/"
+        } else {
+            url.path()
+        },
+        canonical_query,
+        canonical_headers,
+        signed_header_names,
+        hex_digest(body),
+    );
+
+    let scope = format!("{}/{}/{}/aws4_request", date, region, service);
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        amz_date,
+        scope,
+        hex_digest(canonical_request.as_bytes())
+    );
+
+    let signing_key = [region, service, "aws4_request"].iter().fold(
+        hmac_sha256(
+            format!("AWS4{}", credentials.secret_access_key).as_bytes(),
+            date.as_bytes(),
+        ),
+        |key, data| hmac_sha256(&key, data.as_bytes()),
+    );
+    let signature = hex(&hmac_sha256(&signing_key, string_to_sign.as_bytes()));
+
+    let authorization = format!(
+        "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+        credentials.access_key_id, scope, signed_header_names, signature
+    );
+    if let Ok(authorization) = HeaderValue::from_str(&authorization) {
+        headers.insert(AUTHORIZATION, authorization);
+    }
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, key);
+    hmac::sign(&key, data).as_ref().to_vec()
+}
+
+fn hex_digest(data: &[u8]) -> String {
+    hex(digest::digest(&digest::SHA256, data).as_ref())
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().fold(String::new(), |mut s, b| {
+        write!(s, "{:02x}", b).unwrap();
+        s
+    })
+}
+
+fn uri_encode(s: &str) -> String {
+    s.bytes().fold(String::new(), |mut encoded, b| {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(b as char)
+            }
+            _ => write!(encoded, "%{:02X}", b).unwrap(),
+        }
+        encoded
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Signs a request without body with the credentials and the date of the
+    /// AWS Signature Version 4 test suite.
+    fn sign(method: &str, url: &str, session_token: Option<&str>) -> HeaderMap {
+        let credentials = AwsCredentials {
+            access_key_id: "AKIDEXAMPLE".to_string(),
+            secret_access_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_string(),
+            session_token: session_token.map(ToString::to_string),
+        };
+        let mut headers = HeaderMap::new();
+        sign_request(
+            &credentials,
+            "us-east-1",
+            "service",
+            method,
+            &Url::parse(url).unwrap(),
+            &mut headers,
+            b"",
+            Utc.ymd(2015, 8, 30).and_hms(12, 36, 0),
+        );
+        headers
+    }
+
+    #[test]
+    fn post_vanilla() {
+        let headers = sign("POST", "https://example.amazonaws.com/", None);
+        assert_eq!(headers[HOST], "example.amazonaws.com");
+        assert_eq!(headers["x-amz-date"], "20150830T123600Z");
+        assert_eq!(
+            headers[AUTHORIZATION],
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, \
+             SignedHeaders=host;x-amz-date, \
+             Signature=5da7c1a2acd57cee7505fc6676e4e544621c30862966e37dddb68e92efbe5d6b"
+        );
+    }
+
+    #[test]
+    fn get_vanilla_query_order() {
+        let headers = sign(
+            "GET",
+            "https://example.amazonaws.com/?Param2=value2&Param1=value1",
+            None,
+        );
+        assert_eq!(
+            headers[AUTHORIZATION],
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, \
+             SignedHeaders=host;x-amz-date, \
+             Signature=b97d918cfa904a5beff61c982a1b6f458b799221646efd99d3219ec94cdf2500"
+        );
+    }
+
+    #[test]
+    fn post_sts_header_before() {
+        let session_token = "AQoDYXdzEPT//////////wEXAMPLEtc764bNrC9SAPBSM22wDOk4x4HIZ8j4FZTwdQWLWsKWHGBuFqwAeMicRXmxfpSPfIeoIYRqTflfKD8YUuwthAx7mSEI/qkPpKPi/kMcGdQrmGdeehM4IC1NtBmUpp2wUE8phUZampKsburEDy0KPkyQDYwT7WZ0wq5VSXDvp75YU9HFvlRd8Tx6q6fE8YQcHNVXAkiY9q6d+xo0rKwT38xVqr7ZD0u0iPPkUL64lIZbqBAz+scqKmlzm8FDrypNC9Yjc8fPOLn9FX9KSYvKTr4rvx3iSIlTJabIQwj2ICCR/oLxBA==";
+        let headers = sign(
+            "POST",
+            "https://example.amazonaws.com/",
+            Some(session_token),
+        );
+        assert_eq!(headers["x-amz-security-token"], session_token);
+        assert_eq!(
+            headers[AUTHORIZATION],
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, \
+             SignedHeaders=host;x-amz-date;x-amz-security-token, \
+             Signature=85d96828115b5dc0cfc3bd16ad9e210dd772bbebba041836c64533a82be05ead"
+        );
+    }
+}
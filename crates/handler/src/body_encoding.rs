@@ -0,0 +1,119 @@
+use std::str::FromStr;
+
+use anyhow::Result;
+use graphgate_planner::{Request, Response};
+use http::HeaderValue;
+use serde::{Deserialize, Serialize};
+
+/// Encoding of the bodies exchanged with a service.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BodyEncoding {
+    /// JSON, supported by every service.
+    Json,
+    /// MessagePack, with the struct fields encoded as maps.
+    Msgpack,
+    /// CBOR.
+    Cbor,
+}
+
+impl Default for BodyEncoding {
+    fn default() -> Self {
+        BodyEncoding::Json
+    }
+}
+
+impl FromStr for BodyEncoding {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(BodyEncoding::Json),
+            "msgpack" => Ok(BodyEncoding::Msgpack),
+            "cbor" => Ok(BodyEncoding::Cbor),
+            _ => anyhow::bail!("Unknown body encoding '{}'.", s),
+        }
+    }
+}
+
+impl BodyEncoding {
+    pub(crate) fn content_type(&self) -> &'static str {
+        match self {
+            BodyEncoding::Json => "application/json",
+            BodyEncoding::Msgpack => "application/msgpack",
+            BodyEncoding::Cbor => "application/cbor",
+        }
+    }
+
+    /// Value of the `Accept` header, the service may always answer with JSON.
+    pub(crate) fn accept(&self) -> String {
+        match self {
+            BodyEncoding::Json => self.content_type().to_string(),
+            _ => format!("{}, application/json;q=0.9", self.content_type()),
+        }
+    }
+
+    /// Returns the encoding of a `Content-Type` header, JSON if it is missing
+    /// or unknown.
+    pub(crate) fn from_content_type(content_type: Option<&HeaderValue>) -> Self {
+        let mime = content_type
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(';').next())
+            .map(|value| value.trim().to_ascii_lowercase());
+        match mime.as_deref() {
+            Some("application/msgpack") | Some("application/x-msgpack") => BodyEncoding::Msgpack,
+            Some("application/cbor") => BodyEncoding::Cbor,
+            _ => BodyEncoding::Json,
+        }
+    }
+
+    pub(crate) fn encode(&self, request: &Request) -> Result<Vec<u8>> {
+        Ok(match self {
+            BodyEncoding::Json => serde_json::to_vec(request)?,
+            BodyEncoding::Msgpack => rmp_serde::to_vec_named(request)?,
+            BodyEncoding::Cbor => serde_cbor::to_vec(request)?,
+        })
+    }
+
+    pub(crate) fn decode(&self, body: &[u8]) -> Result<Response> {
+        Ok(match self {
+            BodyEncoding::Json => serde_json::from_slice(body)?,
+            BodyEncoding::Msgpack => rmp_serde::from_slice(body)?,
+            BodyEncoding::Cbor => serde_cbor::from_slice(body)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let response: Response =
+            serde_json::from_str(r#"{"data":{"a":[1,"b",{"c":true}]},"errors":[{"message":"x"}]}"#)
+                .unwrap();
+        for encoding in [
+            BodyEncoding::Json,
+            BodyEncoding::Msgpack,
+            BodyEncoding::Cbor,
+        ] {
+            let body = match encoding {
+                BodyEncoding::Json => serde_json::to_vec(&response).unwrap(),
+                BodyEncoding::Msgpack => rmp_serde::to_vec_named(&response).unwrap(),
+                BodyEncoding::Cbor => serde_cbor::to_vec(&response).unwrap(),
+            };
+            let decoded = encoding.decode(&body).unwrap();
+            assert_eq!(decoded.data, response.data);
+            assert_eq!(decoded.errors.len(), 1);
+            assert_eq!(decoded.errors[0].message, "x");
+
+            let content_type = HeaderValue::from_static(encoding.content_type());
+            assert_eq!(
+                BodyEncoding::from_content_type(Some(&content_type)),
+                encoding
+            );
+        }
+        assert_eq!(BodyEncoding::from_content_type(None), BodyEncoding::Json);
+    }
+}
@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use graphgate_planner::{Response, ServerError};
+use ring::constant_time::verify_slices_are_equal;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::Deserialize;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+use value::ConstValue;
+use warp::http::StatusCode;
+
+/// Header returned to the services that post subscription events.
+pub(crate) const SUBSCRIPTION_PROTOCOL_HEADER: &str = "subscription-protocol";
+pub(crate) const SUBSCRIPTION_PROTOCOL: &str = "callback/1.0";
+
+/// Subscription callback options.
+#[derive(Debug, Clone)]
+pub struct CallbackOptions {
+    /// URL of the callback endpoint as seen by the services, such as
+    /// `http://graphgate:8000/callback`.
+    pub public_url: String,
+
+    /// Interval of the heartbeats sent by the services, a subscription is
+    /// terminated if no heartbeat is received for twice this interval.
+    pub heartbeat_interval: Duration,
+}
+
+impl Default for CallbackOptions {
+    fn default() -> Self {
+        Self {
+            public_url: "http://127.0.0.1:8000/callback".to_string(),
+            heartbeat_interval: Duration::from_secs(5),
+        }
+    }
+}
+
+struct Subscription {
+    verifier: String,
+    tx: mpsc::UnboundedSender<Response>,
+    last_heartbeat: Instant,
+}
+
+/// Subscriptions whose events are posted by the services to the callback endpoint.
+///
+/// This implements the subscription callback protocol of Apollo Federation.
+pub struct CallbackRegistry {
+    options: CallbackOptions,
+    subscriptions: Mutex<HashMap<String, Subscription>>,
+}
+
+impl CallbackRegistry {
+    pub fn new(options: CallbackOptions) -> Arc<Self> {
+        let registry = Arc::new(Self {
+            options,
+            subscriptions: Default::default(),
+        });
+        tokio::spawn({
+            let registry = Arc::downgrade(&registry);
+            async move {
+                loop {
+                    let interval = match registry.upgrade() {
+                        Some(registry) => registry.expire(),
+                        None => return,
+                    };
+                    tokio::time::sleep(interval).await;
+                }
+            }
+        });
+        registry
+    }
+
+    /// Registers a subscription, and returns the `subscription` extension of the
+    /// request sent to the service.
+    pub(crate) fn register(&self, tx: mpsc::UnboundedSender<Response>) -> (String, ConstValue) {
+        let id = random_token();
+        let verifier = random_token();
+        let extension = ConstValue::from_json(serde_json::json!({
+            "callbackUrl": format!("{}/{}", self.options.public_url.trim_end_matches('/'), id),
+            "subscriptionId": id,
+            "verifier": verifier,
+            "heartbeatIntervalMs": self.options.heartbeat_interval.as_millis() as u64,
+        }))
+        .unwrap();
+        self.subscriptions.lock().unwrap().insert(
+            id.clone(),
+            Subscription {
+                verifier,
+                tx,
+                last_heartbeat: Instant::now(),
+            },
+        );
+        (id, extension)
+    }
+
+    /// Removes a subscription, the next message of the service is answered with
+    /// `404 Not Found` which terminates the subscription in the service.
+    pub(crate) fn unregister(&self, id: &str) {
+        self.subscriptions.lock().unwrap().remove(id);
+    }
+
+    /// Terminates the subscriptions without a recent heartbeat, and returns the
+    /// delay until the next check.
+    fn expire(&self) -> Duration {
+        let timeout = self.options.heartbeat_interval * 2;
+        self.subscriptions
+            .lock()
+            .unwrap()
+            .retain(|id, subscription| {
+                if subscription.last_heartbeat.elapsed() <= timeout {
+                    return true;
+                }
+                tracing::debug!(id = %id, "Subscription callback heartbeat timeout.");
+                subscription
+                    .tx
+                    .send(error_response("Subscription heartbeat timeout."))
+                    .ok();
+                false
+            });
+        self.options.heartbeat_interval
+    }
+
+    pub(crate) fn handle(&self, id: &str, message: CallbackMessage) -> StatusCode {
+        let mut subscriptions = self.subscriptions.lock().unwrap();
+        let subscription = match subscriptions.get_mut(id) {
+            // The verifier is compared in constant time, so that it can't be
+            // guessed from the response times.
+            Some(subscription)
+                if verify_slices_are_equal(
+                    subscription.verifier.as_bytes(),
+                    message.verifier.as_bytes(),
+                )
+                .is_ok() =>
+            {
+                subscription
+            }
+            Some(_) => return StatusCode::BAD_REQUEST,
+            None => return StatusCode::NOT_FOUND,
+        };
+        subscription.last_heartbeat = Instant::now();
+
+        match message.action {
+            CallbackAction::Check => StatusCode::NO_CONTENT,
+            CallbackAction::Next => {
+                let response = message.payload.unwrap_or_default();
+                if subscription.tx.send(response).is_err() {
+                    subscriptions.remove(id);
+                    return StatusCode::NOT_FOUND;
+                }
+                StatusCode::OK
+            }
+            CallbackAction::Complete => {
+                if let Some(subscription) = subscriptions.remove(id) {
+                    if !message.errors.is_empty() {
+                        subscription
+                            .tx
+                            .send(Response::from_errors(message.errors))
+                            .ok();
+                    }
+                }
+                StatusCode::ACCEPTED
+            }
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum CallbackAction {
+    Check,
+    Next,
+    Complete,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct CallbackMessage {
+    action: CallbackAction,
+    verifier: String,
+    #[serde(default)]
+    payload: Option<Response>,
+    #[serde(default)]
+    errors: Vec<ServerError>,
+}
+
+pub(crate) fn random_token() -> String {
+    let mut data = [0u8; 16];
+    SystemRandom::new().fill(&mut data).unwrap();
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn error_response(message: &str) -> Response {
+    Response::from_errors(vec![ServerError::new(message)])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(verifier: &str) -> CallbackMessage {
+        serde_json::from_value(serde_json::json!({
+            "action": "check",
+            "verifier": verifier,
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn heartbeat_timeout() {
+        let registry = CallbackRegistry::new(CallbackOptions {
+            heartbeat_interval: Duration::from_secs(5),
+            ..Default::default()
+        });
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let (id, extension) = registry.register(tx);
+        let extension = extension.into_json().unwrap();
+        let verifier = extension["verifier"].as_str().unwrap();
+
+        tokio::time::advance(Duration::from_secs(8)).await;
+        assert_eq!(
+            registry.handle(&id, check(verifier)),
+            StatusCode::NO_CONTENT
+        );
+        assert_eq!(
+            registry.handle(&id, check("wrong")),
+            StatusCode::BAD_REQUEST
+        );
+
+        tokio::time::advance(Duration::from_secs(9)).await;
+        assert!(rx.try_recv().is_err());
+
+        let response = rx.recv().await.unwrap();
+        assert_eq!(
+            response.errors[0].message,
+            "Subscription heartbeat timeout."
+        );
+        assert_eq!(registry.handle(&id, check(verifier)), StatusCode::NOT_FOUND);
+    }
+}
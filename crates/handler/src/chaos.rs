@@ -0,0 +1,143 @@
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{Context, Error, Result};
+use graphgate_planner::{Request, Response};
+use serde::{Deserialize, Serialize};
+
+use crate::fetcher::Fetcher;
+
+/// Header that specifies additional fault injection rules for a single request.
+///
+/// Rules are separated by `,`, and the options of a rule by `;`, for example
+/// `service=accounts;percentage=50;latency=200;fault=error`.
+pub const CHAOS_HEADER: &str = "x-graphgate-chaos";
+
+/// The fault returned instead of the response of a service.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChaosFault {
+    /// The request fails as if the service is unreachable.
+    Error,
+    /// The service returns a response that is not valid JSON.
+    MalformedJson,
+}
+
+/// A fault injection rule.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChaosRule {
+    /// Names of the affected services, all services are affected if empty.
+    #[serde(default)]
+    pub services: Vec<String>,
+
+    /// Percentage of the affected requests, from `0` to `100`.
+    #[serde(default = "default_percentage")]
+    pub percentage: f64,
+
+    /// Latency in milliseconds added before the request.
+    #[serde(default)]
+    pub latency: Option<u64>,
+
+    #[serde(default)]
+    pub fault: Option<ChaosFault>,
+}
+
+fn default_percentage() -> f64 {
+    100.0
+}
+
+impl FromStr for ChaosRule {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut rule = ChaosRule {
+            services: Vec::new(),
+            percentage: default_percentage(),
+            latency: None,
+            fault: None,
+        };
+        for option in s.split(';').map(str::trim).filter(|s| !s.is_empty()) {
+            let (name, value) = option
+                .split_once('=')
+                .with_context(|| format!("Invalid chaos option '{}'.", option))?;
+            match name.trim() {
+                "service" => rule.services.push(value.trim().to_string()),
+                "percentage" => {
+                    rule.percentage = value.trim().parse().context("Invalid percentage.")?
+                }
+                "latency" => rule.latency = Some(value.trim().parse().context("Invalid latency.")?),
+                "fault" => {
+                    rule.fault = Some(match value.trim() {
+                        "error" => ChaosFault::Error,
+                        "malformed_json" => ChaosFault::MalformedJson,
+                        fault => anyhow::bail!("Unknown chaos fault '{}'.", fault),
+                    })
+                }
+                name => anyhow::bail!("Unknown chaos option '{}'.", name),
+            }
+        }
+        Ok(rule)
+    }
+}
+
+impl ChaosRule {
+    /// Parses the rules of the chaos header.
+    pub fn parse_header(value: &str) -> Result<Vec<Self>> {
+        value
+            .split(',')
+            .filter(|s| !s.trim().is_empty())
+            .map(str::parse)
+            .collect()
+    }
+
+    fn matches(&self, service: &str) -> bool {
+        self.services.is_empty() || self.services.iter().any(|name| name == service)
+    }
+}
+
+/// Fault injection options.
+#[derive(Debug, Clone, Default)]
+pub struct ChaosOptions {
+    pub rules: Vec<ChaosRule>,
+
+    /// If `true`, clients can add rules with the chaos header.
+    pub allow_header: bool,
+}
+
+/// A fetcher that injects latency and faults into the requests of another fetcher.
+pub struct ChaosFetcher<F> {
+    inner: F,
+    rules: Vec<ChaosRule>,
+}
+
+impl<F: Fetcher> ChaosFetcher<F> {
+    pub fn new(inner: F, rules: Vec<ChaosRule>) -> Self {
+        Self { inner, rules }
+    }
+}
+
+#[async_trait::async_trait]
+impl<F: Fetcher> Fetcher for ChaosFetcher<F> {
+    async fn query(&self, service: &str, request: Request) -> Result<Response> {
+        let rule = self
+            .rules
+            .iter()
+            .find(|rule| rule.matches(service) && fastrand::f64() * 100.0 < rule.percentage);
+        let rule = match rule {
+            Some(rule) => rule,
+            None => return self.inner.query(service, request).await,
+        };
+
+        tracing::debug!(service = %service, rule = ?rule, "Inject fault.");
+        if let Some(latency) = rule.latency {
+            tokio::time::sleep(Duration::from_millis(latency)).await;
+        }
+        match rule.fault {
+            Some(ChaosFault::Error) => {
+                anyhow::bail!("Injected fault for service '{}'.", service)
+            }
+            Some(ChaosFault::MalformedJson) => Ok(serde_json::from_str::<Response>("{\"data\":{")?),
+            None => self.inner.query(service, request).await,
+        }
+    }
+}
@@ -0,0 +1,181 @@
+use http::header::{ACCEPT_LANGUAGE, AUTHORIZATION};
+use http::HeaderMap;
+use value::{ConstValue, Name, Variables};
+
+/// Where the value of a computed variable comes from.
+#[derive(Debug, Clone)]
+pub enum VariableSource {
+    /// A fixed value.
+    Value(ConstValue),
+
+    /// The value of a header of the client request.
+    Header(String),
+
+    /// The preferred language of the `Accept-Language` header.
+    AcceptLanguage,
+
+    /// A claim of the JWT in the bearer token of the `Authorization` header.
+    ///
+    /// The signature of the token is not verified, it must be verified by a
+    /// proxy in front of the gateway.
+    JwtClaim(String),
+}
+
+/// A variable computed by the gateway for the operations of the clients.
+///
+/// The value is validated like a value sent by the client, against the
+/// definition of the variable in the operation.
+#[derive(Debug, Clone)]
+pub struct ComputedVariable {
+    pub name: String,
+    pub source: VariableSource,
+
+    /// Replace the value sent by the client, instead of only providing a
+    /// default value.
+    pub override_client: bool,
+}
+
+/// The values of the computed variables of a client request.
+#[derive(Debug, Clone, Default)]
+pub struct ComputedVariables {
+    defaults: Variables,
+    overrides: Variables,
+}
+
+impl ComputedVariables {
+    /// Computes the variables from the headers of the client request, the
+    /// variables whose source is missing are skipped.
+    pub fn new(variables: &[ComputedVariable], header_map: &HeaderMap) -> Self {
+        let mut computed = ComputedVariables::default();
+        for variable in variables {
+            let value = match &variable.source {
+                VariableSource::Value(value) => Some(value.clone()),
+                VariableSource::Header(name) => header_map
+                    .get(name.as_str())
+                    .and_then(|value| value.to_str().ok())
+                    .map(|value| ConstValue::String(value.to_string())),
+                VariableSource::AcceptLanguage => header_map
+                    .get(ACCEPT_LANGUAGE)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(preferred_language)
+                    .map(ConstValue::String),
+                VariableSource::JwtClaim(claim) => header_map
+                    .get(AUTHORIZATION)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|value| jwt_claim(value, claim)),
+            };
+            if let Some(value) = value {
+                let target = if variable.override_client {
+                    &mut computed.overrides
+                } else {
+                    &mut computed.defaults
+                };
+                target.insert(Name::new(&variable.name), value);
+            }
+        }
+        computed
+    }
+
+    /// Sets the computed variables of an operation.
+    pub fn apply(&self, variables: &mut Variables) {
+        for (name, value) in self.defaults.iter() {
+            if !variables.contains_key(name) {
+                variables.insert(name.clone(), value.clone());
+            }
+        }
+        for (name, value) in self.overrides.iter() {
+            variables.insert(name.clone(), value.clone());
+        }
+    }
+}
+
+/// Returns the language with the highest quality in an `Accept-Language` header.
+fn preferred_language(header: &str) -> Option<String> {
+    let mut preferred: Option<(&str, f32)> = None;
+    for item in header.split(',') {
+        let mut parts = item.split(';');
+        let language = parts.next().unwrap_or_default().trim();
+        if language.is_empty() || language == "*" {
+            continue;
+        }
+        let quality = parts
+            .find_map(|param| param.trim().strip_prefix("q="))
+            .and_then(|quality| quality.parse::<f32>().ok())
+            .unwrap_or(1.0);
+        if preferred.map_or(true, |(_, current)| quality > current) {
+            preferred = Some((language, quality));
+        }
+    }
+    preferred.map(|(language, _)| language.to_string())
+}
+
+/// Returns a claim of the JWT in a bearer `Authorization` header.
+fn jwt_claim(authorization: &str, claim: &str) -> Option<ConstValue> {
+    let token = authorization.strip_prefix("Bearer ")?.trim();
+    let payload = token.split('.').nth(1)?;
+    let payload = base64::decode_config(payload, base64::URL_SAFE_NO_PAD).ok()?;
+    let mut claims = serde_json::from_slice::<serde_json::Value>(&payload).ok()?;
+    ConstValue::from_json(claims.get_mut(claim)?.take()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compute() {
+        let claims =
+            base64::encode_config(r#"{"sub":"1234","admin":true}"#, base64::URL_SAFE_NO_PAD);
+        let mut header_map = HeaderMap::new();
+        header_map.insert(
+            AUTHORIZATION,
+            format!("Bearer eyJhbGciOiJIUzI1NiJ9.{}.c2ln", claims)
+                .parse()
+                .unwrap(),
+        );
+        header_map.insert(
+            ACCEPT_LANGUAGE,
+            "fr-CH;q=0.8, de;q=0.9, *;q=1".parse().unwrap(),
+        );
+
+        let computed = ComputedVariables::new(
+            &[
+                ComputedVariable {
+                    name: "userId".to_string(),
+                    source: VariableSource::JwtClaim("sub".to_string()),
+                    override_client: true,
+                },
+                ComputedVariable {
+                    name: "locale".to_string(),
+                    source: VariableSource::AcceptLanguage,
+                    override_client: false,
+                },
+                ComputedVariable {
+                    name: "tenant".to_string(),
+                    source: VariableSource::Header("x-tenant".to_string()),
+                    override_client: false,
+                },
+            ],
+            &header_map,
+        );
+
+        let mut variables = Variables::default();
+        variables.insert(Name::new("userId"), ConstValue::String("5678".to_string()));
+        variables.insert(Name::new("locale"), ConstValue::String("en".to_string()));
+        computed.apply(&mut variables);
+
+        assert_eq!(
+            variables.get("userId"),
+            Some(&ConstValue::String("1234".to_string()))
+        );
+        assert_eq!(
+            variables.get("locale"),
+            Some(&ConstValue::String("en".to_string()))
+        );
+        assert!(variables.get("tenant").is_none());
+        assert_eq!(
+            preferred_language("fr-CH;q=0.8, de;q=0.9, *;q=1").as_deref(),
+            Some("de")
+        );
+    }
+}
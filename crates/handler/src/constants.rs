@@ -1,4 +1,7 @@
-use opentelemetry::Key;
+use std::io;
+
+use opentelemetry::{Key, KeyValue};
+use serde::Serialize;
 
 pub const KEY_SERVICE: Key = Key::from_static_str("graphgate.service");
 pub const KEY_QUERY: Key = Key::from_static_str("graphgate.query");
@@ -8,3 +11,41 @@ pub const KEY_RETURN_TYPE: Key = Key::from_static_str("graphgate.returnType");
 pub const KEY_FIELD_NAME: Key = Key::from_static_str("graphgate.fieldName");
 pub const KEY_VARIABLES: Key = Key::from_static_str("graphgate.variables");
 pub const KEY_ERROR: Key = Key::from_static_str("graphgate.error");
+pub const KEY_CLIENT_IP: Key = Key::from_static_str("graphgate.clientIp");
+pub const KEY_HTTP_VERSION: Key = Key::from_static_str("graphgate.httpVersion");
+pub const KEY_SCHEMA_VERSION: Key = Key::from_static_str("graphgate.schemaVersion");
+
+/// Maximum length in bytes of the `graphgate.variables` attribute.
+pub const MAX_VARIABLES_ATTRIBUTE_LEN: usize = 4096;
+
+/// Returns the `graphgate.variables` attribute, truncated to
+/// [`MAX_VARIABLES_ATTRIBUTE_LEN`] bytes.
+///
+/// The serialization stops at the limit, so large values such as uploads are
+/// not copied into every span.
+pub fn variables_attribute(variables: &impl Serialize) -> KeyValue {
+    struct Truncated(Vec<u8>);
+
+    impl io::Write for Truncated {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let len = buf.len().min(MAX_VARIABLES_ATTRIBUTE_LEN - self.0.len());
+            if len == 0 && !buf.is_empty() {
+                return Err(io::ErrorKind::WriteZero.into());
+            }
+            self.0.extend_from_slice(&buf[..len]);
+            Ok(len)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let mut writer = Truncated(Vec::new());
+    let truncated = serde_json::to_writer(&mut writer, variables).is_err();
+    let mut value = String::from_utf8_lossy(&writer.0).into_owned();
+    if truncated {
+        value.push_str("...");
+    }
+    KEY_VARIABLES.string(value)
+}
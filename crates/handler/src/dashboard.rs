@@ -0,0 +1,160 @@
+use serde_json::{json, Value};
+
+use crate::metrics::{MetricDefinition, MetricKind, METRIC_DEFINITIONS};
+
+const PANEL_WIDTH: u64 = 12;
+const PANEL_HEIGHT: u64 = 8;
+
+fn by_labels(definition: &MetricDefinition, extra: &[&str]) -> String {
+    let labels = definition
+        .labels
+        .iter()
+        .chain(extra)
+        .copied()
+        .collect::<Vec<_>>();
+    if labels.is_empty() {
+        String::new()
+    } else {
+        format!(" by ({})", labels.join(", "))
+    }
+}
+
+fn legend(definition: &MetricDefinition, prefix: &str) -> String {
+    let labels = definition
+        .labels
+        .iter()
+        .map(|label| format!("{{{{{}}}}}", label))
+        .collect::<Vec<_>>()
+        .join(" ");
+    match (prefix.is_empty(), labels.is_empty()) {
+        (true, true) => definition.prometheus_name(),
+        (true, false) => labels,
+        (false, true) => prefix.to_string(),
+        (false, false) => format!("{} {}", prefix, labels),
+    }
+}
+
+fn targets(definition: &MetricDefinition) -> Vec<Value> {
+    let name = definition.prometheus_name();
+    match definition.kind {
+        MetricKind::Counter => vec![json!({
+            "refId": "A",
+            "expr": format!(
+                "sum{}(rate({}[$__rate_interval]))",
+                by_labels(definition, &[]),
+                name
+            ),
+            "legendFormat": legend(definition, ""),
+        })],
+        MetricKind::Histogram => [
+            ("A", "0.5", "p50"),
+            ("B", "0.95", "p95"),
+            ("C", "0.99", "p99"),
+        ]
+        .iter()
+        .map(|(ref_id, quantile, percentile)| {
+            json!({
+                "refId": ref_id,
+                "expr": format!(
+                    "histogram_quantile({}, sum{}(rate({}_bucket[$__rate_interval])))",
+                    quantile,
+                    by_labels(definition, &["le"]),
+                    name
+                ),
+                "legendFormat": legend(definition, percentile),
+                "exemplar": true,
+            })
+        })
+        .collect(),
+        MetricKind::Gauge => vec![json!({
+            "refId": "A",
+            "expr": name,
+            "legendFormat": legend(definition, ""),
+        })],
+    }
+}
+
+/// Returns a Grafana dashboard with a panel for each metric of the gateway,
+/// the latency histograms show their exemplars.
+///
+/// The dashboard has a `datasource` variable selecting the Prometheus data
+/// source.
+pub fn grafana_dashboard() -> Value {
+    let panels = METRIC_DEFINITIONS
+        .iter()
+        .enumerate()
+        .map(|(idx, definition)| {
+            let unit = match definition.kind {
+                MetricKind::Counter => "ops",
+                MetricKind::Histogram => "s",
+                MetricKind::Gauge if definition.name.ends_with("_seconds") => "s",
+                MetricKind::Gauge => "none",
+            };
+            json!({
+                "id": idx + 1,
+                "type": "timeseries",
+                "title": definition.prometheus_name(),
+                "description": definition.description,
+                "datasource": { "type": "prometheus", "uid": "${datasource}" },
+                "gridPos": {
+                    "x": (idx as u64 % 2) * PANEL_WIDTH,
+                    "y": (idx as u64 / 2) * PANEL_HEIGHT,
+                    "w": PANEL_WIDTH,
+                    "h": PANEL_HEIGHT,
+                },
+                "fieldConfig": { "defaults": { "unit": unit }, "overrides": [] },
+                "targets": targets(definition),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    json!({
+        "title": "GraphGate",
+        "uid": "graphgate",
+        "tags": ["graphgate", "graphql"],
+        "schemaVersion": 30,
+        "time": { "from": "now-1h", "to": "now" },
+        "refresh": "30s",
+        "templating": {
+            "list": [{
+                "name": "datasource",
+                "label": "Data source",
+                "type": "datasource",
+                "query": "prometheus",
+            }],
+        },
+        "panels": panels,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dashboard() {
+        let dashboard = grafana_dashboard();
+        let panels = dashboard["panels"].as_array().unwrap();
+        assert_eq!(panels.len(), METRIC_DEFINITIONS.len());
+
+        let panel = panels
+            .iter()
+            .find(|panel| panel["title"] == "graphgate_query_duration_seconds")
+            .unwrap();
+        assert_eq!(panel["targets"].as_array().unwrap().len(), 3);
+        assert_eq!(
+            panel["targets"][1]["expr"],
+            "histogram_quantile(0.95, sum by (tenant, le)(rate(graphgate_query_duration_seconds_bucket[$__rate_interval])))"
+        );
+        assert_eq!(panel["targets"][1]["exemplar"], true);
+
+        let panel = panels
+            .iter()
+            .find(|panel| panel["title"] == "graphgate_recovered_panics_total")
+            .unwrap();
+        assert_eq!(
+            panel["targets"][0]["expr"],
+            "sum(rate(graphgate_recovered_panics_total[$__rate_interval]))"
+        );
+    }
+}
@@ -0,0 +1,270 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use graphgate_planner::{PlanBuilder, Request, Response};
+use graphgate_schema::ComposedSchema;
+use http::HeaderMap;
+use opentelemetry::Key;
+use value::{ConstValue, Variables};
+
+use crate::executor::Executor;
+use crate::fetcher::HttpFetcher;
+use crate::metrics::METRICS;
+use crate::ServiceRouteTable;
+
+const KEY_OUTCOME: Key = Key::from_static_str("outcome");
+
+/// Differential execution of a sample of the queries against a candidate
+/// composition, such as a supergraph composed by a newer federation version
+/// or a new implementation of a service.
+///
+/// The query is executed again in the background once the client received
+/// its response, and the divergences between both responses are logged with
+/// the `graphgate::differential` target and counted in
+/// `graphgate.differential_comparisons_total`. The mutations and
+/// subscriptions are never executed twice.
+#[derive(Clone)]
+pub struct DifferentialOptions {
+    /// Composed schema of the candidate, the current schema if not set.
+    pub schema: Option<Arc<ComposedSchema>>,
+
+    /// Addresses of the candidate services by name, replacing the address of
+    /// the service in the current routing table.
+    pub services: HashMap<String, String>,
+
+    /// Percentage of the queries executed against the candidate, from 0 to
+    /// 100.
+    pub percentage: f64,
+
+    /// Maximum number of divergent paths logged for a query.
+    pub max_reported_paths: usize,
+}
+
+impl Default for DifferentialOptions {
+    fn default() -> Self {
+        Self {
+            schema: None,
+            services: HashMap::new(),
+            percentage: 0.0,
+            max_reported_paths: 10,
+        }
+    }
+}
+
+/// Request of a query executed against the candidate.
+pub(crate) struct Comparison {
+    pub(crate) request: Request,
+    pub(crate) header_map: HeaderMap,
+    pub(crate) injected_variables: Variables,
+    pub(crate) signature: Option<String>,
+}
+
+impl DifferentialOptions {
+    /// Returns `true` if a query is executed against the candidate.
+    pub(crate) fn sample(&self) -> bool {
+        fastrand::f64() * 100.0 < self.percentage
+    }
+
+    fn route_table(&self, current: &ServiceRouteTable) -> ServiceRouteTable {
+        let mut route_table = current.clone();
+        for (service, addr) in &self.services {
+            if let Some(route) = route_table.get_mut(service) {
+                route.addr = addr.clone();
+            }
+        }
+        route_table
+    }
+
+    /// Executes a query against the candidate in the background, and reports
+    /// the divergences from the current response.
+    pub(crate) fn compare(
+        &self,
+        current_schema: Arc<ComposedSchema>,
+        current_route_table: &ServiceRouteTable,
+        comparison: Comparison,
+        current: Response,
+    ) {
+        let schema = self.schema.clone().unwrap_or(current_schema);
+        let route_table = self.route_table(current_route_table);
+        let max_reported_paths = self.max_reported_paths;
+
+        tokio::spawn(async move {
+            let Comparison {
+                request,
+                header_map,
+                injected_variables,
+                signature,
+            } = comparison;
+            let candidate = match parser::parse_query(&request.query) {
+                Ok(document) => {
+                    let mut builder =
+                        PlanBuilder::new(&schema, document).variables(request.variables.clone());
+                    if let Some(operation) = request.operation.clone() {
+                        builder = builder.operation_name(operation);
+                    }
+                    match builder.plan() {
+                        Ok(plan) => {
+                            let extensions = HashMap::new();
+                            let fetcher = HttpFetcher::new(
+                                &route_table,
+                                &header_map,
+                                &injected_variables,
+                                &extensions,
+                            );
+                            Executor::new(&schema).execute_query(&fetcher, &plan).await
+                        }
+                        Err(resp) => resp,
+                    }
+                }
+                Err(_) => return,
+            };
+            report(
+                request.operation.as_deref(),
+                signature.as_deref(),
+                &current,
+                &candidate,
+                max_reported_paths,
+            );
+        });
+    }
+}
+
+fn report(
+    operation: Option<&str>,
+    signature: Option<&str>,
+    current: &Response,
+    candidate: &Response,
+    max_reported_paths: usize,
+) {
+    let mut paths = Vec::new();
+    divergences(
+        &current.data,
+        &candidate.data,
+        &mut Vec::new(),
+        &mut paths,
+        max_reported_paths,
+    );
+    let errors_diverged = error_codes(current) != error_codes(candidate);
+
+    let outcome = if paths.is_empty() && !errors_diverged {
+        "match"
+    } else {
+        tracing::warn!(
+            target: "graphgate::differential",
+            operation = operation.unwrap_or_default(),
+            signature = signature.unwrap_or_default(),
+            paths = %paths.join(", "),
+            current_errors = current.errors.len(),
+            candidate_errors = candidate.errors.len(),
+            "The candidate response diverges from the current response."
+        );
+        "diverged"
+    };
+    METRICS
+        .differential_comparison_counter
+        .add(1, &[KEY_OUTCOME.string(outcome)]);
+}
+
+fn error_codes(resp: &Response) -> Vec<Option<&str>> {
+    let mut codes = resp
+        .errors
+        .iter()
+        .map(|error| error.code())
+        .collect::<Vec<_>>();
+    codes.sort_unstable();
+    codes
+}
+
+/// Collects the paths where two values differ, the order of the fields of the
+/// objects is ignored.
+fn divergences(
+    current: &ConstValue,
+    candidate: &ConstValue,
+    path: &mut Vec<String>,
+    paths: &mut Vec<String>,
+    max_paths: usize,
+) {
+    if paths.len() >= max_paths || current == candidate {
+        return;
+    }
+    match (current, candidate) {
+        (ConstValue::Object(current), ConstValue::Object(candidate)) => {
+            for (name, value) in current {
+                path.push(name.to_string());
+                divergences(
+                    value,
+                    candidate.get(name).unwrap_or(&ConstValue::Null),
+                    path,
+                    paths,
+                    max_paths,
+                );
+                path.pop();
+            }
+            for (name, value) in candidate {
+                if !current.contains_key(name) {
+                    path.push(name.to_string());
+                    divergences(&ConstValue::Null, value, path, paths, max_paths);
+                    path.pop();
+                }
+            }
+        }
+        (ConstValue::List(current), ConstValue::List(candidate))
+            if current.len() == candidate.len() =>
+        {
+            for (idx, (current, candidate)) in current.iter().zip(candidate).enumerate() {
+                path.push(idx.to_string());
+                divergences(current, candidate, path, paths, max_paths);
+                path.pop();
+            }
+        }
+        _ => paths.push(if path.is_empty() {
+            "data".to_string()
+        } else {
+            path.join(".")
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn paths(current: serde_json::Value, candidate: serde_json::Value) -> Vec<String> {
+        let mut paths = Vec::new();
+        divergences(
+            &ConstValue::from_json(current).unwrap(),
+            &ConstValue::from_json(candidate).unwrap(),
+            &mut Vec::new(),
+            &mut paths,
+            10,
+        );
+        paths
+    }
+
+    #[test]
+    fn compare() {
+        assert!(paths(
+            json!({ "me": { "id": "1", "username": "a" } }),
+            json!({ "me": { "username": "a", "id": "1" } }),
+        )
+        .is_empty());
+
+        assert_eq!(
+            paths(
+                json!({ "me": { "id": "1", "reviews": [{ "body": "a" }, { "body": "b" }] } }),
+                json!({ "me": { "id": "2", "reviews": [{ "body": "a" }, { "body": "c" }] } }),
+            ),
+            vec!["me.id", "me.reviews.1.body"]
+        );
+
+        assert_eq!(
+            paths(
+                json!({ "topProducts": [{ "upc": "1" }] }),
+                json!({ "topProducts": [], "extra": 1 }),
+            ),
+            vec!["topProducts", "extra"]
+        );
+    }
+}
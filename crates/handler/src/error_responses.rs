@@ -0,0 +1,153 @@
+use std::collections::HashMap;
+
+use graphgate_planner::{ErrorCode, Response};
+use http::header::CONTENT_TYPE;
+use serde::{Deserialize, Serialize};
+use value::ConstValue;
+use warp::http::{Response as HttpResponse, StatusCode};
+
+/// Failures of the gateway whose HTTP response can be replaced, so that the
+/// load balancers and CDNs in front of it can react to them.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureCategory {
+    /// The schema has not been composed yet.
+    NotReady,
+
+    /// The schema has not been composed because the last update failed.
+    CompositionFailed,
+
+    /// No data was returned because the requests to the services failed.
+    UpstreamUnavailable,
+
+    /// The client address is not allowed, or the token of the request is
+    /// missing or invalid.
+    Unauthorized,
+}
+
+/// The status code and body returned for a failure category.
+#[derive(Debug, Clone)]
+pub struct ErrorResponse {
+    pub status: StatusCode,
+
+    /// JSON body replacing the GraphQL response, if set.
+    pub body: Option<String>,
+}
+
+/// HTTP responses of the failure categories, the other failures keep the
+/// default response.
+#[derive(Debug, Clone, Default)]
+pub struct ErrorResponses(HashMap<FailureCategory, ErrorResponse>);
+
+impl ErrorResponses {
+    pub fn new(responses: HashMap<FailureCategory, ErrorResponse>) -> Self {
+        Self(responses)
+    }
+
+    pub(crate) fn contains(&self, category: FailureCategory) -> bool {
+        self.0.contains_key(&category)
+    }
+
+    /// Returns the HTTP response of a failure, the default status and body if
+    /// no response is configured for its category.
+    pub(crate) fn response(
+        &self,
+        category: FailureCategory,
+        status: StatusCode,
+        body: String,
+    ) -> HttpResponse<String> {
+        match self.0.get(&category) {
+            Some(ErrorResponse {
+                status,
+                body: Some(body),
+            }) => HttpResponse::builder()
+                .status(*status)
+                .header(CONTENT_TYPE, "application/json")
+                .body(body.clone())
+                .unwrap(),
+            Some(ErrorResponse { status, body: None }) => {
+                HttpResponse::builder().status(*status).body(body).unwrap()
+            }
+            None => HttpResponse::builder().status(status).body(body).unwrap(),
+        }
+    }
+}
+
+/// Returns `true` if a response has no data because the requests to the
+/// services failed.
+pub(crate) fn is_upstream_unavailable(response: &Response) -> bool {
+    response.data == ConstValue::Null
+        && response
+            .errors
+            .iter()
+            .any(|error| error.code() == Some(ErrorCode::FetchError.as_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use graphgate_planner::ServerError;
+
+    use super::*;
+
+    #[test]
+    fn response() {
+        let responses = ErrorResponses::new(
+            vec![
+                (
+                    FailureCategory::NotReady,
+                    ErrorResponse {
+                        status: StatusCode::SERVICE_UNAVAILABLE,
+                        body: Some(r#"{"status":"starting"}"#.to_string()),
+                    },
+                ),
+                (
+                    FailureCategory::Unauthorized,
+                    ErrorResponse {
+                        status: StatusCode::UNAUTHORIZED,
+                        body: None,
+                    },
+                ),
+            ]
+            .into_iter()
+            .collect(),
+        );
+
+        let resp = responses.response(
+            FailureCategory::NotReady,
+            StatusCode::BAD_REQUEST,
+            "Not ready.".to_string(),
+        );
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(resp.headers()[CONTENT_TYPE], "application/json");
+        assert_eq!(resp.body(), r#"{"status":"starting"}"#);
+
+        let resp = responses.response(
+            FailureCategory::Unauthorized,
+            StatusCode::FORBIDDEN,
+            "Forbidden.".to_string(),
+        );
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(resp.body(), "Forbidden.");
+
+        let resp = responses.response(
+            FailureCategory::UpstreamUnavailable,
+            StatusCode::OK,
+            "{}".to_string(),
+        );
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn upstream_unavailable() {
+        let mut response = Response::from_errors(vec![
+            ServerError::new("Connection refused.").with_code(ErrorCode::FetchError)
+        ]);
+        assert!(is_upstream_unavailable(&response));
+
+        response.data = ConstValue::Object(Default::default());
+        assert!(!is_upstream_unavailable(&response));
+
+        let response = Response::from_errors(vec![ServerError::new("Not found.")]);
+        assert!(!is_upstream_unavailable(&response));
+    }
+}
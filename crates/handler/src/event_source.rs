@@ -0,0 +1,221 @@
+use std::collections::HashMap;
+use std::ops::Deref;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use futures_util::stream::BoxStream;
+use futures_util::StreamExt;
+use graphgate_planner::{Request, Response};
+use indexmap::IndexMap;
+use parser::types::{
+    DocumentOperations, ExecutableDocument, Field, OperationType, Selection, SelectionSet,
+};
+use parser::Positioned;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use value::{ConstValue, Name, Variables};
+
+use crate::shaping::project;
+
+/// Maps a subscription root field to a broker subject.
+#[derive(Debug, Clone)]
+pub struct EventSourceField {
+    /// Name of the subscription root field.
+    pub field: String,
+
+    /// Subject of the messages.
+    ///
+    /// Placeholders such as `{id}` are replaced with the arguments of the field.
+    pub subject: String,
+
+    /// JSON pointer to the value in a message, the whole message is used if not set.
+    pub path: Option<String>,
+}
+
+/// A service whose subscription root fields are backed by a message broker.
+///
+/// Messages must be JSON, the selection set of the subscription is applied to
+/// them as if they were returned by a resolver.
+#[derive(Debug, Clone)]
+pub struct EventSource {
+    /// URL of the NATS server, such as `nats://127.0.0.1:4222`.
+    pub url: String,
+
+    /// SDL of the service, it is composed with the schemas of the other services.
+    pub sdl: String,
+
+    pub fields: Vec<EventSourceField>,
+}
+
+impl EventSource {
+    /// Subscribes to the subject of the root field selected by the request.
+    pub async fn subscribe(&self, request: &Request) -> Result<BoxStream<'static, Response>> {
+        let document = parser::parse_query(&request.query)?;
+        let (field, fragments) = root_field(&document)?;
+        let mapping = self
+            .fields
+            .iter()
+            .find(|mapping| mapping.field == field.node.name.node.as_str())
+            .with_context(|| {
+                format!(
+                    "Field '{}' is not backed by an event source.",
+                    field.node.name.node
+                )
+            })?;
+
+        let subject = subject(&mapping.subject, &field.node, &request.variables)?;
+        let mut messages = nats_subscribe(&self.url, &subject).await?;
+
+        let response_key = field.node.response_key().node.clone();
+        let selection_set = field.node.selection_set.clone();
+        let path = mapping.path.clone();
+        Ok(Box::pin(async_stream::stream! {
+            while let Some(message) = messages.next().await {
+                let value = message.and_then(|payload| {
+                    let value: serde_json::Value = serde_json::from_slice(&payload)?;
+                    let value = match &path {
+                        Some(path) => value
+                            .pointer(path)
+                            .cloned()
+                            .with_context(|| format!("No value at '{}' in the message.", path))?,
+                        None => value,
+                    };
+                    Ok(ConstValue::from_json(value)?)
+                });
+                match value {
+                    Ok(value) => {
+                        let mut data = IndexMap::new();
+                        data.insert(response_key.clone(), project(value, &selection_set, &fragments));
+                        yield Response {
+                            data: ConstValue::Object(data),
+                            errors: Vec::new(),
+                            extensions: Default::default(),
+                            headers: Default::default(),
+                            body_sizes: None,
+                        };
+                    }
+                    Err(err) => {
+                        tracing::error!(subject = %subject, error = %err, "Invalid event.");
+                    }
+                }
+            }
+        }))
+    }
+}
+
+/// Event sources, the key is the service name.
+#[derive(Debug, Clone, Default)]
+pub struct EventSources(HashMap<String, Arc<EventSource>>);
+
+impl Deref for EventSources {
+    type Target = HashMap<String, Arc<EventSource>>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl EventSources {
+    pub fn insert(&mut self, name: impl Into<String>, source: EventSource) {
+        self.0.insert(name.into(), Arc::new(source));
+    }
+}
+
+fn root_field(
+    document: &ExecutableDocument,
+) -> Result<(&Positioned<Field>, HashMap<Name, Positioned<SelectionSet>>)> {
+    let operation = match &document.operations {
+        DocumentOperations::Single(operation) => operation,
+        DocumentOperations::Multiple(operations) if operations.len() == 1 => {
+            operations.values().next().unwrap()
+        }
+        DocumentOperations::Multiple(_) => anyhow::bail!("Expected a single operation."),
+    };
+    anyhow::ensure!(
+        operation.node.ty == OperationType::Subscription,
+        "Only subscriptions are supported by event sources."
+    );
+    let field = operation
+        .node
+        .selection_set
+        .node
+        .items
+        .iter()
+        .find_map(|selection| match &selection.node {
+            Selection::Field(field) => Some(field),
+            _ => None,
+        })
+        .context("Expected a root field.")?;
+    let fragments = document
+        .fragments
+        .iter()
+        .map(|(name, fragment)| (name.clone(), fragment.node.selection_set.clone()))
+        .collect();
+    Ok((field, fragments))
+}
+
+fn subject(template: &str, field: &Field, variables: &Variables) -> Result<String> {
+    let mut subject = template.to_string();
+    for (name, value) in &field.arguments {
+        let value = value.node.clone().into_const_with(|name| {
+            variables
+                .get(&name)
+                .cloned()
+                .with_context(|| format!("Variable '{}' is not defined.", name))
+        })?;
+        let value = match value {
+            ConstValue::String(s) => s,
+            ConstValue::Enum(s) => s.to_string(),
+            value => value.to_string(),
+        };
+        subject = subject.replace(&format!("{{{}}}", name.node), &value);
+    }
+    Ok(subject)
+}
+
+/// Subscribes to a subject of a NATS server with the client protocol.
+async fn nats_subscribe(url: &str, subject: &str) -> Result<BoxStream<'static, Result<Vec<u8>>>> {
+    let addr = url.strip_prefix("nats://").unwrap_or(url).to_string();
+    let stream = TcpStream::connect(&addr)
+        .await
+        .with_context(|| format!("Failed to connect to NATS server '{}'.", addr))?;
+    let (reader, mut writer) = stream.into_split();
+    writer
+        .write_all(
+            format!(
+                "CONNECT {{\"verbose\":false,\"pedantic\":false,\"name\":\"graphgate\"}}\r\nSUB {} 1\r\n",
+                subject
+            )
+            .as_bytes(),
+        )
+        .await?;
+    tracing::debug!(addr = %addr, subject = %subject, "Subscribed to NATS subject.");
+
+    let mut reader = BufReader::new(reader);
+    Ok(Box::pin(async_stream::try_stream! {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if reader.read_line(&mut line).await? == 0 {
+                break;
+            }
+            let command = line.trim_end();
+            if command.starts_with("MSG ") {
+                // MSG <subject> <sid> [reply-to] <#bytes>
+                let size: usize = command
+                    .rsplit(' ')
+                    .next()
+                    .and_then(|size| size.parse().ok())
+                    .context("Invalid NATS message.")?;
+                let mut payload = vec![0; size + 2];
+                reader.read_exact(&mut payload).await?;
+                payload.truncate(size);
+                yield payload;
+            } else if command == "PING" {
+                writer.write_all(b"PONG\r\n").await?;
+            } else if let Some(err) = command.strip_prefix("-ERR") {
+                Err(anyhow::anyhow!("NATS error:{}", err))?;
+            }
+        }
+    }))
+}
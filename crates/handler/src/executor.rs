@@ -5,10 +5,10 @@ use futures_util::future::BoxFuture;
 use futures_util::stream::BoxStream;
 use futures_util::StreamExt;
 use graphgate_planner::{
-    FetchNode, FlattenNode, IntrospectionNode, ParallelNode, PathSegment, PlanNode, ResponsePath,
+    DeferNode, FetchNode, FlattenNode, IntrospectionNode, ParallelNode, PathSegment, PlanNode,
     RootNode, SequenceNode, SubscribeNode,
 };
-use graphgate_planner::{Request, Response, ServerError};
+use graphgate_planner::{ErrorCode, Request, Response, ServerError};
 use graphgate_schema::ComposedSchema;
 use indexmap::IndexMap;
 use opentelemetry::trace::{FutureExt, TraceContextExt, Tracer};
@@ -20,12 +20,18 @@ use value::{ConstValue, Name, Variables};
 use crate::constants::*;
 use crate::fetcher::{Fetcher, WebSocketFetcher};
 use crate::introspection::{IntrospectionRoot, Resolver};
+use crate::response_headers::ResponseHeaderOptions;
+use crate::shaping;
 use crate::websocket::WebSocketController;
 
+/// Headers returned by the fetches, in the order of the plan.
+type FetchHeaders = Vec<HashMap<String, Vec<String>>>;
+
 /// Query plan executor
 pub struct Executor<'e> {
     schema: &'e ComposedSchema,
     resp: Mutex<Response>,
+    response_headers: Option<&'e ResponseHeaderOptions>,
 }
 
 impl<'e> Executor<'e> {
@@ -33,6 +39,15 @@ impl<'e> Executor<'e> {
         Executor {
             schema,
             resp: Mutex::new(Response::default()),
+            response_headers: None,
+        }
+    }
+
+    /// Sets the merge policies of the headers returned by the services.
+    pub fn response_headers(self, options: &'e ResponseHeaderOptions) -> Self {
+        Self {
+            response_headers: Some(options),
+            ..self
         }
     }
 
@@ -42,23 +57,67 @@ impl<'e> Executor<'e> {
     pub async fn execute_query(self, fetcher: &impl Fetcher, node: &RootNode<'_>) -> Response {
         match node {
             RootNode::Query(node) => {
-                self.execute_node(fetcher, node).await;
-                self.resp.into_inner()
+                let fetch_headers = self.execute_node(fetcher, node).await;
+                let mut resp = self.resp.into_inner();
+                shaping::resolve_gateway_fields(&mut resp.data, &self.schema.gateway_fields);
+                if !fetch_headers.is_empty() {
+                    let default_options = ResponseHeaderOptions::default();
+                    let options = self.response_headers.unwrap_or(&default_options);
+                    resp.headers = Some(options.merge(fetch_headers));
+                }
+                resp
+            }
+            RootNode::Subscribe(_) => {
+                Response::from_errors(vec![ServerError::new("Not supported")])
             }
-            RootNode::Subscribe(_) => Response {
-                data: ConstValue::Null,
-                errors: vec![ServerError {
-                    message: "Not supported".to_string(),
-                    path: Default::default(),
-                    locations: Default::default(),
-                    extensions: Default::default(),
-                }],
-                extensions: Default::default(),
-                headers: Default::default(),
-            },
         }
     }
 
+    /// Executes a query plan with deferred fragments, and returns the response
+    /// once the primary node is executed, then after each deferred node.
+    ///
+    /// The responses are complete, the key aliases of the entities not
+    /// fetched yet are removed.
+    pub fn execute_incremental<'a>(
+        self,
+        fetcher: &'a impl Fetcher,
+        node: &'a RootNode<'_>,
+    ) -> BoxStream<'a, Response> {
+        Box::pin(async_stream::stream! {
+            let defer = match node {
+                RootNode::Query(PlanNode::Defer(defer)) => defer,
+                _ => {
+                    yield self.execute_query(fetcher, node).await;
+                    return;
+                }
+            };
+
+            let mut fetch_headers = self.execute_node(fetcher, &defer.primary).await;
+            yield self.snapshot(&fetch_headers).await;
+            for deferred in &defer.deferred {
+                fetch_headers.extend(self.execute_node(fetcher, &deferred.node).await);
+                yield self.snapshot(&fetch_headers).await;
+            }
+        })
+    }
+
+    /// Returns a copy of the current response, with the gateway fields
+    /// resolved and without the keys of the entities.
+    async fn snapshot(&self, fetch_headers: &FetchHeaders) -> Response {
+        let mut resp = self.resp.lock().await.clone();
+        if resp.data == ConstValue::Null {
+            resp.data = ConstValue::Object(Default::default());
+        }
+        remove_key_aliases(&mut resp.data);
+        shaping::resolve_gateway_fields(&mut resp.data, &self.schema.gateway_fields);
+        if !fetch_headers.is_empty() {
+            let default_options = ResponseHeaderOptions::default();
+            let options = self.response_headers.unwrap_or(&default_options);
+            resp.headers = Some(options.merge(fetch_headers.clone()));
+        }
+        resp
+    }
+
     /// Execute a subscription plan and return a stream.
     pub async fn execute_stream<'a>(
         self,
@@ -70,7 +129,9 @@ impl<'e> Executor<'e> {
         match node {
             RootNode::Query(node) => Box::pin(async_stream::stream! {
                 self.execute_node(&fetcher, node).await;
-                yield self.resp.into_inner();
+                let mut resp = self.resp.into_inner();
+                shaping::resolve_gateway_fields(&mut resp.data, &self.schema.gateway_fields);
+                yield resp;
             }),
             RootNode::Subscribe(SubscribeNode {
                 subscribe_nodes,
@@ -89,9 +150,8 @@ impl<'e> Executor<'e> {
                             let tracer = global::tracer("graphql");
                             let attributes = vec![
                                 KEY_SERVICE.string(node.service.to_string()),
-                                KEY_QUERY.string(node.query.to_string()),
-                                KEY_VARIABLES
-                                    .string(serde_json::to_string(&node.variables).unwrap()),
+                                KEY_QUERY.string(node.query.signature()),
+                                variables_attribute(&node.variables),
                             ];
                             let span = tracer
                                 .span_builder(format!("subscribe [{}]", node.service))
@@ -115,17 +175,9 @@ impl<'e> Executor<'e> {
                                 "Failed to subscribe".to_string(),
                                 vec![KEY_ERROR.string(err.to_string())],
                             );
-                            Response {
-                                data: ConstValue::Null,
-                                errors: vec![ServerError {
-                                    message: err.to_string(),
-                                    path: Default::default(),
-                                    locations: Default::default(),
-                                    extensions: Default::default(),
-                                }],
-                                extensions: Default::default(),
-                                headers: Default::default(),
-                            }
+                            Response::from_errors(vec![
+                                ServerError::new(err.to_string()).with_code(ErrorCode::FetchError)
+                            ])
                         })
                     }
                     .with_context(cx.clone())
@@ -134,17 +186,17 @@ impl<'e> Executor<'e> {
 
                 match res {
                     Ok(mut stream) => Box::pin(async_stream::stream! {
-                        while let Some(response) = stream.recv().await {
+                        while let Some(mut response) = stream.recv().await {
                             if let Some(flatten_node) = flatten_node {
                                 *self.resp.lock().await = response;
 
                                 let cx = Context::current_with_span(tracer.span_builder("push").start(&tracer));
                                 self.execute_node(&fetcher, flatten_node).with_context(cx).await;
 
-                                yield std::mem::take(&mut *self.resp.lock().await);
-                            } else {
-                                yield response;
+                                response = std::mem::take(&mut *self.resp.lock().await);
                             }
+                            shaping::resolve_gateway_fields(&mut response.data, &self.schema.gateway_fields);
+                            yield response;
                         }
                     }.with_context(cx)),
                     Err(response) => {
@@ -160,7 +212,7 @@ impl<'e> Executor<'e> {
         &'a self,
         fetcher: &'a impl Fetcher,
         node: &'a PlanNode<'_>,
-    ) -> BoxFuture<'a, ()> {
+    ) -> BoxFuture<'a, FetchHeaders> {
         Box::pin(async move {
             match node {
                 PlanNode::Sequence(sequence) => self.execute_sequence_node(fetcher, sequence).await,
@@ -169,28 +221,66 @@ impl<'e> Executor<'e> {
                     let tracer = global::tracer("graphql");
                     self.execute_introspection_node(introspection)
                         .with_context(Context::current_with_span(tracer.start("introspection")))
-                        .await
+                        .await;
+                    Vec::new()
+                }
+                PlanNode::Fetch(fetch) => self
+                    .execute_fetch_node(fetcher, fetch)
+                    .await
+                    .into_iter()
+                    .collect(),
+                PlanNode::Flatten(flatten) => {
+                    self.execute_flatten_node(fetcher, flatten).await;
+                    Vec::new()
                 }
-                PlanNode::Fetch(fetch) => self.execute_fetch_node(fetcher, fetch).await,
-                PlanNode::Flatten(flatten) => self.execute_flatten_node(fetcher, flatten).await,
+                PlanNode::Defer(defer) => self.execute_defer_node(fetcher, defer).await,
             }
         })
     }
 
-    async fn execute_sequence_node(&self, fetcher: &impl Fetcher, sequence: &SequenceNode<'_>) {
+    /// Executes the primary node, then the deferred nodes in order, for the
+    /// clients receiving a single response.
+    async fn execute_defer_node(
+        &self,
+        fetcher: &impl Fetcher,
+        defer: &DeferNode<'_>,
+    ) -> FetchHeaders {
+        let mut fetch_headers = self.execute_node(fetcher, &defer.primary).await;
+        for deferred in &defer.deferred {
+            fetch_headers.extend(self.execute_node(fetcher, &deferred.node).await);
+        }
+        fetch_headers
+    }
+
+    async fn execute_sequence_node(
+        &self,
+        fetcher: &impl Fetcher,
+        sequence: &SequenceNode<'_>,
+    ) -> FetchHeaders {
+        let mut fetch_headers = Vec::new();
         for node in &sequence.nodes {
-            self.execute_node(fetcher, node).await;
+            fetch_headers.extend(self.execute_node(fetcher, node).await);
         }
+        fetch_headers
     }
 
-    async fn execute_parallel_node(&self, fetcher: &impl Fetcher, parallel: &ParallelNode<'_>) {
+    /// Executes the nodes concurrently, the headers are returned in the order
+    /// of the nodes regardless of the order in which they complete.
+    async fn execute_parallel_node(
+        &self,
+        fetcher: &impl Fetcher,
+        parallel: &ParallelNode<'_>,
+    ) -> FetchHeaders {
         futures_util::future::join_all(
             parallel
                 .nodes
                 .iter()
                 .map(|node| async move { self.execute_node(fetcher, node).await }),
         )
-        .await;
+        .await
+        .into_iter()
+        .flatten()
+        .collect()
     }
 
     async fn execute_introspection_node(&self, introspection: &IntrospectionNode) {
@@ -199,7 +289,11 @@ impl<'e> Executor<'e> {
         merge_data(&mut current_resp.data, value);
     }
 
-    async fn execute_fetch_node(&self, fetcher: &impl Fetcher, fetch: &FetchNode<'_>) {
+    async fn execute_fetch_node(
+        &self,
+        fetcher: &impl Fetcher,
+        fetch: &FetchNode<'_>,
+    ) -> Option<HashMap<String, Vec<String>>> {
         let request = fetch.to_request();
 
         let tracer = global::tracer("graphql");
@@ -207,8 +301,8 @@ impl<'e> Executor<'e> {
             .span_builder(format!("fetch [{}]", fetch.service))
             .with_attributes(vec![
                 KEY_SERVICE.string(fetch.service.to_string()),
-                KEY_QUERY.string(fetch.query.to_string()),
-                KEY_VARIABLES.string(serde_json::to_string(&request.variables).unwrap()),
+                KEY_QUERY.string(fetch.query.signature()),
+                variables_attribute(&request.variables),
             ])
             .start(&tracer);
         let cx = Context::current_with_span(span);
@@ -221,18 +315,17 @@ impl<'e> Executor<'e> {
                 Ok(mut resp) => {
                     if resp.errors.is_empty() {
                         add_tracing_spans(&mut resp);
-                        current_resp.headers = resp.headers;
                         merge_data(&mut current_resp.data, resp.data);
+                        resp.headers
                     } else {
                         rewrite_errors(None, &mut current_resp.errors, resp.errors);
+                        None
                     }
                 }
-                Err(err) => current_resp.errors.push(ServerError {
-                    message: err.to_string(),
-                    path: Default::default(),
-                    locations: Default::default(),
-                    extensions: Default::default(),
-                }),
+                Err(err) => {
+                    current_resp.errors.push(fetch_error(fetch.service, err));
+                    None
+                }
             }
         }
         .with_context(cx)
@@ -240,160 +333,7 @@ impl<'e> Executor<'e> {
     }
 
     async fn execute_flatten_node(&self, fetcher: &impl Fetcher, flatten: &FlattenNode<'_>) {
-        enum Representation {
-            Keys(ConstValue),
-            Skip,
-        }
-
-        fn extract_keys(
-            from: &mut IndexMap<Name, ConstValue>,
-            prefix: usize,
-            possible_type: Option<&str>,
-        ) -> Representation {
-            let prefix = format!("__key{}_", prefix);
-            if let Some(possible_type) = possible_type {
-                match from.get(format!("{}__typename", prefix).as_str()) {
-                    Some(ConstValue::String(typename)) if typename == possible_type => {}
-                    _ => return Representation::Skip,
-                }
-            }
-
-            let mut res = IndexMap::new();
-            let mut keys = Vec::new();
-            for key in from.keys() {
-                if key.as_str().starts_with(&prefix) {
-                    keys.push(key.clone());
-                }
-            }
-            for key in keys {
-                if let Some(value) = from.remove(&key) {
-                    let name = Name::new(&key[prefix.len()..]);
-                    res.insert(name, value);
-                }
-            }
-            Representation::Keys(ConstValue::Object(res))
-        }
-
-        fn get_representations(
-            representations: &mut Vec<Representation>,
-            value: &mut ConstValue,
-            path: &[PathSegment<'_>],
-            prefix: usize,
-        ) {
-            let segment = match path.get(0) {
-                Some(segment) => segment,
-                None => return,
-            };
-            let is_last = path.len() == 1;
-
-            if is_last {
-                match value {
-                    ConstValue::Object(object) if !segment.is_list => {
-                        if let Some(ConstValue::Object(key_object)) = object.get_mut(segment.name) {
-                            representations.push(extract_keys(
-                                key_object,
-                                prefix,
-                                segment.possible_type,
-                            ));
-                        } else {
-                            representations.push(Representation::Skip);
-                        }
-                    }
-                    ConstValue::Object(object) if segment.is_list => {
-                        if let Some(ConstValue::List(array)) = object.get_mut(segment.name) {
-                            for element in array {
-                                if let ConstValue::Object(element_obj) = element {
-                                    representations.push(extract_keys(
-                                        element_obj,
-                                        prefix,
-                                        segment.possible_type,
-                                    ));
-                                } else {
-                                    representations.push(Representation::Skip);
-                                }
-                            }
-                        }
-                    }
-                    _ => {}
-                }
-            } else {
-                match value {
-                    ConstValue::Object(object) if !segment.is_list => {
-                        if let Some(next_value) = object.get_mut(segment.name) {
-                            get_representations(representations, next_value, &path[1..], prefix);
-                        } else {
-                            representations.push(Representation::Skip);
-                        }
-                    }
-                    ConstValue::Object(object) if segment.is_list => {
-                        if let Some(ConstValue::List(array)) = object.get_mut(segment.name) {
-                            for element in array {
-                                get_representations(representations, element, &path[1..], prefix);
-                            }
-                        } else {
-                            representations.push(Representation::Skip);
-                        }
-                    }
-                    _ => {}
-                }
-            }
-        }
-
-        fn flatten_values(
-            target: &mut ConstValue,
-            path: &[PathSegment<'_>],
-            values: &mut impl Iterator<Item = ConstValue>,
-            flags: &mut impl Iterator<Item = bool>,
-        ) {
-            let segment = match path.get(0) {
-                Some(segment) => segment,
-                None => return,
-            };
-            let is_last = path.len() == 1;
-            if is_last {
-                match target {
-                    ConstValue::Object(object) if !segment.is_list => {
-                        if let Some(target) = object.get_mut(segment.name) {
-                            if let Some(true) = flags.next() {
-                                if let Some(value) = values.next() {
-                                    merge_data(target, value);
-                                }
-                            }
-                        }
-                    }
-                    ConstValue::Object(object) if segment.is_list => {
-                        if let Some(ConstValue::List(array)) = object.get_mut(segment.name) {
-                            for element in array {
-                                if let Some(true) = flags.next() {
-                                    if let Some(value) = values.next() {
-                                        merge_data(element, value);
-                                    }
-                                }
-                            }
-                        }
-                    }
-                    _ => {}
-                }
-            } else {
-                match target {
-                    ConstValue::Object(object) if !segment.is_list => {
-                        if let Some(next_value) = object.get_mut(segment.name) {
-                            flatten_values(next_value, &path[1..], values, flags);
-                        }
-                    }
-                    ConstValue::Object(object) if segment.is_list => {
-                        if let Some(ConstValue::List(array)) = object.get_mut(segment.name) {
-                            for element in array {
-                                flatten_values(element, &path[1..], values, flags);
-                            }
-                        }
-                    }
-                    _ => {}
-                }
-            }
-        }
-
-        let (representations, flags) = {
+        let (representations, flags, entity_paths) = {
             let mut representations = Vec::new();
             let mut resp = self.resp.lock().await;
             get_representations(
@@ -401,6 +341,7 @@ impl<'e> Executor<'e> {
                 &mut resp.data,
                 &flatten.path,
                 flatten.prefix,
+                &mut Vec::new(),
             );
             if representations.is_empty() {
                 return;
@@ -408,11 +349,13 @@ impl<'e> Executor<'e> {
 
             let mut flags = Vec::with_capacity(representations.len());
             let mut values = Vec::with_capacity(representations.len());
+            let mut entity_paths = Vec::with_capacity(representations.len());
 
             for representation in representations {
                 match representation {
-                    Representation::Keys(value) => {
+                    Representation::Keys(value, path) => {
                         values.push(value);
+                        entity_paths.push(path);
                         flags.push(true);
                     }
                     Representation::Skip => flags.push(false),
@@ -421,7 +364,7 @@ impl<'e> Executor<'e> {
 
             let mut variables = Variables::default();
             variables.insert(Name::new("representations"), ConstValue::List(values));
-            (variables, flags)
+            (variables, flags, entity_paths)
         };
         let request = flatten.to_request(representations);
 
@@ -430,8 +373,8 @@ impl<'e> Executor<'e> {
             .span_builder(format!("flatten [{}]", flatten.service))
             .with_attributes(vec![
                 KEY_SERVICE.string(flatten.service.to_string()),
-                KEY_QUERY.string(flatten.query.to_string()),
-                KEY_VARIABLES.string(serde_json::to_string(&request.variables).unwrap()),
+                KEY_QUERY.string(flatten.query.signature()),
+                variables_attribute(&request.variables),
                 KEY_PATH.string(flatten.path.to_string()),
             ])
             .start(&tracer);
@@ -456,16 +399,15 @@ impl<'e> Executor<'e> {
                             }
                         }
                     } else {
-                        rewrite_errors(Some(&flatten.path), &mut current_resp.errors, resp.errors);
+                        rewrite_errors(
+                            Some(&entity_paths[..]),
+                            &mut current_resp.errors,
+                            resp.errors,
+                        );
                     }
                 }
                 Err(err) => {
-                    current_resp.errors.push(ServerError {
-                        message: err.to_string(),
-                        path: Default::default(),
-                        locations: Default::default(),
-                        extensions: Default::default(),
-                    });
+                    current_resp.errors.push(fetch_error(flatten.service, err));
                 }
             }
         }
@@ -474,7 +416,178 @@ impl<'e> Executor<'e> {
     }
 }
 
-fn merge_data(target: &mut ConstValue, value: ConstValue) {
+enum Representation {
+    /// The keys of an entity, and its path in the response.
+    Keys(ConstValue, Vec<ConstValue>),
+    Skip,
+}
+
+fn extract_keys(
+    from: &mut IndexMap<Name, ConstValue>,
+    prefix: usize,
+    possible_type: Option<&str>,
+    response_path: &[ConstValue],
+) -> Representation {
+    let prefix = format!("__key{}_", prefix);
+    if let Some(possible_type) = possible_type {
+        match from.get(format!("{}__typename", prefix).as_str()) {
+            Some(ConstValue::String(typename)) if typename == possible_type => {}
+            _ => return Representation::Skip,
+        }
+    }
+
+    let mut res = IndexMap::new();
+    let mut keys = Vec::new();
+    for key in from.keys() {
+        if key.as_str().starts_with(&prefix) {
+            keys.push(key.clone());
+        }
+    }
+    for key in keys {
+        if let Some(value) = from.remove(&key) {
+            let name = Name::new(&key[prefix.len()..]);
+            res.insert(name, value);
+        }
+    }
+    Representation::Keys(ConstValue::Object(res), response_path.to_vec())
+}
+
+/// Extracts the keys of the entities at the path.
+///
+/// The segments of the path are response keys, so the fields aliased by the
+/// client are looked up by their alias. `response_path` is the path of the
+/// current value, used for the errors of the entities.
+fn get_representations(
+    representations: &mut Vec<Representation>,
+    value: &mut ConstValue,
+    path: &[PathSegment<'_>],
+    prefix: usize,
+    response_path: &mut Vec<ConstValue>,
+) {
+    let (segment, rest) = match path.split_first() {
+        Some(res) => res,
+        None => return,
+    };
+    let object = match value {
+        ConstValue::Object(object) => object,
+        _ => return,
+    };
+    if let Some(value) = object.get_mut(segment.name) {
+        response_path.push(ConstValue::String(segment.name.to_string()));
+        get_element_representations(
+            representations,
+            value,
+            segment,
+            segment.list_depth,
+            rest,
+            prefix,
+            response_path,
+        );
+        response_path.pop();
+    }
+}
+
+/// Extracts the keys of the entities in the value of a field, after
+/// traversing `depth` levels of nested lists.
+fn get_element_representations(
+    representations: &mut Vec<Representation>,
+    value: &mut ConstValue,
+    segment: &PathSegment<'_>,
+    depth: usize,
+    rest: &[PathSegment<'_>],
+    prefix: usize,
+    response_path: &mut Vec<ConstValue>,
+) {
+    if depth > 0 {
+        if let ConstValue::List(array) = value {
+            for (idx, element) in array.iter_mut().enumerate() {
+                response_path.push(ConstValue::Number(idx.into()));
+                get_element_representations(
+                    representations,
+                    element,
+                    segment,
+                    depth - 1,
+                    rest,
+                    prefix,
+                    response_path,
+                );
+                response_path.pop();
+            }
+        }
+    } else if !rest.is_empty() {
+        get_representations(representations, value, rest, prefix, response_path);
+    } else if let ConstValue::Object(object) = value {
+        representations.push(extract_keys(
+            object,
+            prefix,
+            segment.possible_type,
+            response_path,
+        ));
+    } else {
+        representations.push(Representation::Skip);
+    }
+}
+
+/// Merges the fetched entities into the response.
+///
+/// It must walk the response exactly like `get_representations`, taking one
+/// flag for every representation, so that a skipped element doesn't shift the
+/// values of the following elements.
+fn flatten_values(
+    target: &mut ConstValue,
+    path: &[PathSegment<'_>],
+    values: &mut impl Iterator<Item = ConstValue>,
+    flags: &mut impl Iterator<Item = bool>,
+) {
+    let (segment, rest) = match path.split_first() {
+        Some(res) => res,
+        None => return,
+    };
+    if let ConstValue::Object(object) = target {
+        if let Some(target) = object.get_mut(segment.name) {
+            flatten_element_values(target, segment.list_depth, rest, values, flags);
+        }
+    }
+}
+
+/// Merges the fetched entities into the value of a field, after traversing
+/// `depth` levels of nested lists.
+fn flatten_element_values(
+    target: &mut ConstValue,
+    depth: usize,
+    rest: &[PathSegment<'_>],
+    values: &mut impl Iterator<Item = ConstValue>,
+    flags: &mut impl Iterator<Item = bool>,
+) {
+    if depth > 0 {
+        if let ConstValue::List(array) = target {
+            for element in array {
+                flatten_element_values(element, depth - 1, rest, values, flags);
+            }
+        }
+    } else if !rest.is_empty() {
+        flatten_values(target, rest, values, flags);
+    } else if let Some(true) = flags.next() {
+        if let Some(value) = values.next() {
+            merge_data(target, value);
+        }
+    }
+}
+
+/// Removes the keys of the entities fetched by the deferred nodes, which are
+/// selected with the `__key` aliases.
+fn remove_key_aliases(value: &mut ConstValue) {
+    match value {
+        ConstValue::List(list) => list.iter_mut().for_each(remove_key_aliases),
+        ConstValue::Object(object) => {
+            object.retain(|key, _| !key.starts_with("__key"));
+            object.values_mut().for_each(remove_key_aliases);
+        }
+        _ => {}
+    }
+}
+
+pub(crate) fn merge_data(target: &mut ConstValue, value: ConstValue) {
     match (target, value) {
         (target @ ConstValue::Null, fragment) => *target = fragment,
         (ConstValue::Object(object), ConstValue::Object(fragment_object)) => {
@@ -498,40 +611,39 @@ fn merge_data(target: &mut ConstValue, value: ConstValue) {
     }
 }
 
+/// Adds the errors returned by a service to the response.
+///
+/// The errors of an entity fetch have paths starting with `_entities` and the
+/// index of the representation, which are replaced with the path of the entity
+/// in the response. Other errors of an entity fetch cannot be located and have
+/// no path.
 fn rewrite_errors(
-    prefix_path: Option<&ResponsePath<'_>>,
+    entity_paths: Option<&[Vec<ConstValue>]>,
     target: &mut Vec<ServerError>,
     errors: Vec<ServerError>,
 ) {
     for mut err in errors {
-        let mut path = Vec::new();
-
-        if let Some(prefix_path) = prefix_path {
-            for segment in prefix_path.iter() {
-                path.push(ConstValue::String(segment.name.to_string()));
-                if segment.is_list {
-                    path.push(ConstValue::Number(0.into()));
+        if let Some(entity_paths) = entity_paths {
+            let entity_path = match (err.path.get(0), err.path.get(1)) {
+                (Some(ConstValue::String(name)), Some(ConstValue::Number(index)))
+                    if name == "_entities" =>
+                {
+                    index
+                        .as_u64()
+                        .and_then(|index| entity_paths.get(index as usize))
                 }
-            }
-        }
-
-        if matches!(err.path.first(), Some(ConstValue::String(s)) if s=="_entities") {
-            path.extend(err.path.drain(1..));
-        }
-
-        for subpath in err.path.iter() {
-            match subpath {
-                ConstValue::String(x) => path.push(ConstValue::String(x.to_string())),
-                _ => {}
-            }
+                _ => None,
+            };
+            err.path = match entity_path {
+                Some(entity_path) => entity_path
+                    .iter()
+                    .cloned()
+                    .chain(err.path.drain(2..))
+                    .collect(),
+                None => Vec::new(),
+            };
         }
-
-        target.push(ServerError {
-            message: err.message,
-            path,
-            locations: err.locations,
-            extensions: err.extensions,
-        })
+        target.push(err);
     }
 }
 
@@ -668,3 +780,396 @@ fn add_tracing_spans(response: &mut Response) {
         );
     }
 }
+
+/// Returns the error of a request that could not be sent to a service, or
+/// whose response could not be read.
+fn fetch_error(service: &str, err: anyhow::Error) -> ServerError {
+    ServerError::new(err.to_string())
+        .with_code(ErrorCode::FetchError)
+        .with_service_name(service)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::testing::{self, MockFetcher};
+
+    fn schema() -> ComposedSchema {
+        ComposedSchema::parse(
+            r#"
+            type Query {
+                me: User @resolve(service: "accounts")
+            }
+
+            type User
+            @owner(service: "accounts")
+            @key(fields: "id" service: "accounts")
+            @key(fields: "id" service: "reviews")
+            {
+                id: ID!
+                username: String!
+                reviews: [Review!]! @resolve(service: "reviews")
+            }
+
+            type Review @owner(service: "reviews") {
+                body: String!
+            }
+            "#,
+        )
+        .unwrap()
+    }
+
+    fn segment(
+        name: &str,
+        list_depth: usize,
+        possible_type: Option<&'static str>,
+    ) -> PathSegment<'_> {
+        PathSegment {
+            name,
+            list_depth,
+            possible_type,
+        }
+    }
+
+    /// Flattens the entities returned by a fake service, which resolves the
+    /// `width` field of every entity to its `id`.
+    fn flatten(data: &mut ConstValue, path: &[PathSegment<'_>]) {
+        let mut representations = Vec::new();
+        get_representations(&mut representations, data, path, 1, &mut Vec::new());
+
+        let mut flags = Vec::new();
+        let mut values = Vec::new();
+        for representation in representations {
+            match representation {
+                Representation::Keys(ConstValue::Object(mut keys), _) => {
+                    let mut entity = IndexMap::new();
+                    entity.insert(Name::new("width"), keys.remove("id").unwrap());
+                    values.push(ConstValue::Object(entity));
+                    flags.push(true);
+                }
+                Representation::Keys(..) => unreachable!(),
+                Representation::Skip => flags.push(false),
+            }
+        }
+
+        flatten_values(
+            data,
+            path,
+            &mut values.into_iter().fuse(),
+            &mut flags.into_iter().fuse(),
+        );
+    }
+
+    #[test]
+    fn flatten_mixed_type_list() {
+        let mut data = ConstValue::from_json(json!({
+            "me": {
+                "reviews": [
+                    { "attachment": { "__key1___typename": "Image", "__key1_id": 1 } },
+                    { "attachment": { "__key1___typename": "Audio", "__key1_id": 2 } },
+                    { "body": "no attachment" },
+                    { "attachment": { "__key1___typename": "Image", "__key1_id": 4 } },
+                ]
+            }
+        }))
+        .unwrap();
+        let path = [
+            segment("me", 0, None),
+            segment("reviews", 1, None),
+            segment("attachment", 0, Some("Image")),
+        ];
+        flatten(&mut data, &path);
+
+        assert_eq!(
+            data,
+            ConstValue::from_json(json!({
+                "me": {
+                    "reviews": [
+                        { "attachment": { "width": 1 } },
+                        { "attachment": { "__key1___typename": "Audio", "__key1_id": 2 } },
+                        { "body": "no attachment" },
+                        { "attachment": { "width": 4 } },
+                    ]
+                }
+            }))
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn flatten_list_with_missing_objects() {
+        let mut data = ConstValue::from_json(json!({
+            "items": [
+                { "owner": { "product": { "__key1_id": 1 } } },
+                {},
+                { "owner": { "product": { "__key1_id": 3 } } },
+            ]
+        }))
+        .unwrap();
+        let path = [
+            segment("items", 1, None),
+            segment("owner", 0, None),
+            segment("product", 0, None),
+        ];
+        flatten(&mut data, &path);
+
+        assert_eq!(
+            data,
+            ConstValue::from_json(json!({
+                "items": [
+                    { "owner": { "product": { "width": 1 } } },
+                    {},
+                    { "owner": { "product": { "width": 3 } } },
+                ]
+            }))
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn flatten_list_of_lists() {
+        let mut data = ConstValue::from_json(json!({
+            "grid": [
+                [{ "__key1_id": 1 }, null, { "__key1_id": 3 }],
+                null,
+                [],
+                [{ "__key1_id": 4 }],
+            ]
+        }))
+        .unwrap();
+        let path = [segment("grid", 2, None)];
+
+        let mut representations = Vec::new();
+        get_representations(
+            &mut representations,
+            &mut data.clone(),
+            &path,
+            1,
+            &mut Vec::new(),
+        );
+        let entity_paths = representations
+            .into_iter()
+            .filter_map(|representation| match representation {
+                Representation::Keys(_, path) => Some(path),
+                Representation::Skip => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            entity_paths,
+            vec![
+                vec![
+                    ConstValue::String("grid".to_string()),
+                    ConstValue::Number(0.into()),
+                    ConstValue::Number(0.into()),
+                ],
+                vec![
+                    ConstValue::String("grid".to_string()),
+                    ConstValue::Number(0.into()),
+                    ConstValue::Number(2.into()),
+                ],
+                vec![
+                    ConstValue::String("grid".to_string()),
+                    ConstValue::Number(3.into()),
+                    ConstValue::Number(0.into()),
+                ],
+            ]
+        );
+
+        flatten(&mut data, &path);
+        assert_eq!(
+            data,
+            ConstValue::from_json(json!({
+                "grid": [
+                    [{ "width": 1 }, null, { "width": 3 }],
+                    null,
+                    [],
+                    [{ "width": 4 }],
+                ]
+            }))
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn flatten_nested_list_of_lists() {
+        let mut data = ConstValue::from_json(json!({
+            "shelves": [
+                { "rows": [[{ "product": { "__key1_id": 1 } }], [{}, { "product": { "__key1_id": 2 } }]] },
+                { "rows": null },
+            ]
+        }))
+        .unwrap();
+        let path = [
+            segment("shelves", 1, None),
+            segment("rows", 2, None),
+            segment("product", 0, None),
+        ];
+        flatten(&mut data, &path);
+
+        assert_eq!(
+            data,
+            ConstValue::from_json(json!({
+                "shelves": [
+                    { "rows": [[{ "product": { "width": 1 } }], [{}, { "product": { "width": 2 } }]] },
+                    { "rows": null },
+                ]
+            }))
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn flatten_aliased_nested_list() {
+        let mut data = ConstValue::from_json(json!({
+            "m": {
+                "rs": [
+                    { "a": { "__key1___typename": "User", "__key1_id": 1 }, "author": 9 },
+                    { "a": null },
+                    { "a": { "__key1___typename": "User", "__key1_id": 3 } },
+                ]
+            }
+        }))
+        .unwrap();
+        let path = [
+            segment("m", 0, None),
+            segment("rs", 1, None),
+            segment("a", 0, None),
+        ];
+
+        let mut representations = Vec::new();
+        get_representations(
+            &mut representations,
+            &mut data.clone(),
+            &path,
+            1,
+            &mut Vec::new(),
+        );
+        let entity_paths = representations
+            .into_iter()
+            .filter_map(|representation| match representation {
+                Representation::Keys(_, path) => Some(path),
+                Representation::Skip => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            entity_paths,
+            vec![
+                vec![
+                    ConstValue::String("m".to_string()),
+                    ConstValue::String("rs".to_string()),
+                    ConstValue::Number(0.into()),
+                    ConstValue::String("a".to_string()),
+                ],
+                vec![
+                    ConstValue::String("m".to_string()),
+                    ConstValue::String("rs".to_string()),
+                    ConstValue::Number(2.into()),
+                    ConstValue::String("a".to_string()),
+                ],
+            ]
+        );
+
+        let mut errors = Vec::new();
+        rewrite_errors(
+            Some(&entity_paths[..]),
+            &mut errors,
+            vec![
+                ServerError::new("a").with_path(vec![
+                    ConstValue::String("_entities".to_string()),
+                    ConstValue::Number(1.into()),
+                    ConstValue::String("n".to_string()),
+                ]),
+                ServerError::new("b"),
+            ],
+        );
+        assert_eq!(
+            ConstValue::List(errors[0].path.clone()),
+            ConstValue::from_json(json!(["m", "rs", 2, "a", "n"])).unwrap()
+        );
+        assert!(errors[1].path.is_empty());
+
+        flatten(&mut data, &path);
+        assert_eq!(
+            data,
+            ConstValue::from_json(json!({
+                "m": {
+                    "rs": [
+                        { "a": { "width": 1 }, "author": 9 },
+                        { "a": null },
+                        { "a": { "width": 3 } },
+                    ]
+                }
+            }))
+            .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn merge_entities() {
+        let schema = schema();
+        let fetcher = MockFetcher::new()
+            .data(
+                "accounts",
+                json!({ "me": { "__typename": "User", "id": "1", "username": "a" } }),
+            )
+            .data(
+                "reviews",
+                json!({ "_entities": [{ "reviews": [{ "body": "x" }, { "body": "y" }] }] }),
+            );
+        let response = testing::execute(
+            &schema,
+            &fetcher,
+            "{ me { username reviews { b: body } } }",
+            json!({}),
+        )
+        .await;
+
+        testing::assert_data(
+            &response,
+            json!({
+                "me": {
+                    "username": "a",
+                    "reviews": [{ "b": "x" }, { "b": "y" }],
+                },
+            }),
+        );
+        let requests = fetcher.requests();
+        assert_eq!(requests[1].0, "reviews");
+        assert_eq!(
+            requests[1]
+                .1
+                .variables
+                .clone()
+                .into_value()
+                .into_json()
+                .unwrap(),
+            json!({ "representations": [{ "__typename": "User", "id": "1" }] })
+        );
+    }
+
+    #[tokio::test]
+    async fn entity_fetch_error() {
+        let schema = schema();
+        let fetcher = MockFetcher::new()
+            .data(
+                "accounts",
+                json!({ "me": { "__typename": "User", "id": "1", "username": "a" } }),
+            )
+            .error("reviews", "Connection refused.");
+        let response = testing::execute(
+            &schema,
+            &fetcher,
+            "{ me { username reviews { body } } }",
+            json!({}),
+        )
+        .await;
+
+        testing::assert_errors(
+            &response,
+            json!({ "me": { "username": "a" } }),
+            &["Connection refused."],
+        );
+    }
+}
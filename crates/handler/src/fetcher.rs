@@ -1,9 +1,11 @@
+use std::collections::HashMap;
 use std::sync::atomic::{AtomicU64, Ordering};
 
 use anyhow::Result;
 use graphgate_planner::{Request, Response};
 use http::HeaderMap;
 use tokio::sync::mpsc;
+use value::{ConstValue, Variables};
 
 use crate::websocket::WebSocketController;
 use crate::ServiceRouteTable;
@@ -16,20 +18,39 @@ pub trait Fetcher: Send + Sync {
 pub struct HttpFetcher<'a> {
     router_table: &'a ServiceRouteTable,
     header_map: &'a HeaderMap,
+
+    /// Injected variables, sent along with the variables of the request
+    /// without being declared by the fetched operation.
+    variables: &'a Variables,
+
+    extensions: &'a HashMap<String, ConstValue>,
 }
 
 impl<'a> HttpFetcher<'a> {
-    pub fn new(router_table: &'a ServiceRouteTable, header_map: &'a HeaderMap) -> Self {
+    pub fn new(
+        router_table: &'a ServiceRouteTable,
+        header_map: &'a HeaderMap,
+        variables: &'a Variables,
+        extensions: &'a HashMap<String, ConstValue>,
+    ) -> Self {
         Self {
             router_table,
             header_map,
+            variables,
+            extensions,
         }
     }
 }
 
 #[async_trait::async_trait]
 impl<'a> Fetcher for HttpFetcher<'a> {
-    async fn query(&self, service: &str, request: Request) -> Result<Response> {
+    async fn query(&self, service: &str, mut request: Request) -> Result<Response> {
+        if !self.variables.is_empty() {
+            request = request.extend_variables(self.variables.clone());
+        }
+        for (name, value) in self.extensions {
+            request = request.extension(name.clone(), value.clone());
+        }
         self.router_table
             .query(service, request, Some(self.header_map), None)
             .await
@@ -5,22 +5,98 @@ use std::sync::Arc;
 
 use graphgate_planner::Request;
 use http::header::HeaderName;
-use http::HeaderMap;
+use http::{HeaderMap, Version};
 use opentelemetry::trace::{FutureExt, TraceContextExt, Tracer};
 use opentelemetry::{global, Context};
-use warp::http::Response as HttpResponse;
+use warp::http::{Response as HttpResponse, StatusCode};
+use warp::hyper::Body;
 use warp::ws::Ws;
 use warp::{Filter, Rejection, Reply};
 
+use crate::callback::{
+    CallbackMessage, CallbackRegistry, SUBSCRIPTION_PROTOCOL, SUBSCRIPTION_PROTOCOL_HEADER,
+};
+#[cfg(feature = "chaos")]
+use crate::chaos::{ChaosRule, CHAOS_HEADER};
+use crate::computed_variables::ComputedVariables;
 use crate::constants::*;
-use crate::metrics::METRICS;
-use crate::{websocket, SharedRouteTable};
+use crate::error_responses::FailureCategory;
+use crate::inject::apply_inject_rules;
+use crate::metrics::{Metrics, METRICS};
+use crate::query_plan::query_plan_requested;
+use crate::record::RECORD_HEADER;
+use crate::shaping::{normalize_requested, remove_nulls_requested};
+use crate::shared_route_table::QueryOptions;
+use crate::sse;
+use crate::{
+    websocket, AccessOptions, Authenticator, IncrementalFormat, ProxyHeaderOptions,
+    SharedRouteTable, TenantRouteTables,
+};
 use std::time::Instant;
 
+/// The connection of a client request.
+///
+/// The filters can't read the HTTP version of a request, so the server adds
+/// the connection to the extensions of each request. It replaces the address
+/// that `warp::serve` would provide.
+#[derive(Debug, Copy, Clone)]
+pub struct ClientConnection {
+    pub remote_addr: Option<SocketAddr>,
+    pub version: Version,
+}
+
+/// Returns the address of the client, from the connection added by the
+/// server if any.
+fn remote_addr() -> impl Filter<Extract = (Option<SocketAddr>,), Error = Infallible> + Clone {
+    warp::addr::remote()
+        .and(warp::ext::optional::<ClientConnection>())
+        .map(|remote_addr, connection: Option<ClientConnection>| {
+            connection.map_or(remote_addr, |connection| connection.remote_addr)
+        })
+}
+
+/// Returns the negotiated HTTP version of the request, HTTP/1.1 if the server
+/// didn't add the connection.
+///
+/// Over HTTP/2 the payloads of a streamed response are sent in their own data
+/// frames, instead of the chunks of an HTTP/1.1 response that some proxies
+/// buffer.
+pub fn http_version() -> impl Filter<Extract = (Version,), Error = Infallible> + Clone {
+    warp::ext::optional::<ClientConnection>().map(|connection: Option<ClientConnection>| {
+        connection.map_or(Version::HTTP_11, |connection| connection.version)
+    })
+}
+
 #[derive(Clone)]
 pub struct HandlerConfig {
     pub shared_route_table: SharedRouteTable,
     pub forward_headers: Arc<Vec<String>>,
+    pub proxy_headers: Arc<ProxyHeaderOptions>,
+    pub access: Arc<AccessOptions>,
+
+    /// If set, the requests are authenticated with the JWT of their
+    /// `Authorization` header, and its claims are forwarded to the services.
+    pub auth: Option<Arc<Authenticator>>,
+
+    /// If set, the route table is selected by the tenant header instead of
+    /// using `shared_route_table`.
+    pub tenants: Option<Arc<TenantRouteTables>>,
+}
+
+impl HandlerConfig {
+    /// Returns the tenant and the route table for the request.
+    fn select_route_table<'a>(
+        &'a self,
+        header_map: &HeaderMap,
+    ) -> Result<(Option<&'a str>, &'a SharedRouteTable), String> {
+        match &self.tenants {
+            Some(tenants) => tenants
+                .select(header_map)
+                .map(|(tenant, route_table)| (Some(tenant), route_table))
+                .ok_or_else(|| format!("Unknown tenant in header '{}'.", tenants.header())),
+            None => Ok((None, &self.shared_route_table)),
+        }
+    }
 }
 
 fn do_forward_headers<T: AsRef<str>>(
@@ -44,44 +120,141 @@ fn do_forward_headers<T: AsRef<str>>(
     new_header_map
 }
 
+#[cfg(feature = "chaos")]
+fn chaos_header_rules(header_map: &HeaderMap) -> Vec<ChaosRule> {
+    let value = match header_map
+        .get(CHAOS_HEADER)
+        .and_then(|value| value.to_str().ok())
+    {
+        Some(value) => value,
+        None => return Vec::new(),
+    };
+    ChaosRule::parse_header(value).unwrap_or_else(|err| {
+        tracing::warn!(error = %err, "Invalid chaos header.");
+        Vec::new()
+    })
+}
+
 pub fn graphql_request(
     config: HandlerConfig,
 ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
     warp::post()
         .and(warp::body::json())
         .and(warp::header::headers_cloned())
-        .and(warp::addr::remote())
+        .and(remote_addr())
+        .and(http_version())
         .and_then({
-            move |request: Request, header_map: HeaderMap, remote_addr: Option<SocketAddr>| {
+            move |mut request: Request,
+                  header_map: HeaderMap,
+                  remote_addr: Option<SocketAddr>,
+                  version: Version| {
                 let config = config.clone();
                 async move {
+                    let client_ip = config.access.client_ip(&header_map, remote_addr);
+                    if !config.access.is_allowed(client_ip) {
+                        return Ok::<_, Infallible>(
+                            config
+                                .shared_route_table
+                                .error_responses()
+                                .response(
+                                    FailureCategory::Unauthorized,
+                                    StatusCode::FORBIDDEN,
+                                    "Forbidden.".to_string(),
+                                )
+                                .map(Body::from),
+                        );
+                    }
+                    let claims = match config
+                        .auth
+                        .as_ref()
+                        .map(|auth| auth.authenticate(&header_map))
+                    {
+                        Some(Ok(claims)) => claims,
+                        Some(Err(err)) => {
+                            return Ok::<_, Infallible>(
+                                config
+                                    .shared_route_table
+                                    .error_responses()
+                                    .response(
+                                        FailureCategory::Unauthorized,
+                                        StatusCode::UNAUTHORIZED,
+                                        err.to_string(),
+                                    )
+                                    .map(Body::from),
+                            );
+                        }
+                        None => None,
+                    };
+
                     let tracer = global::tracer("graphql");
 
+                    // The query is recorded as its signature once it is parsed.
+                    let mut attributes = vec![variables_attribute(&request.variables)];
+                    if let Some(client_ip) = client_ip {
+                        attributes.push(KEY_CLIENT_IP.string(client_ip.to_string()));
+                    }
                     let query = Context::current_with_span(
                         tracer
                             .span_builder("query")
-                            .with_attributes(vec![
-                                KEY_QUERY.string(request.query.clone()),
-                                KEY_VARIABLES
-                                    .string(serde_json::to_string(&request.variables).unwrap()),
-                            ])
+                            .with_attributes(attributes)
                             .start(&tracer),
                     );
 
+                    let (tenant, shared_route_table) = match config.select_route_table(&header_map)
+                    {
+                        Ok(res) => res,
+                        Err(err) => {
+                            return Ok::<_, Infallible>(
+                                HttpResponse::builder()
+                                    .status(StatusCode::BAD_REQUEST)
+                                    .body(Body::from(err))
+                                    .unwrap(),
+                            );
+                        }
+                    };
+
+                    let mut forward_headers =
+                        do_forward_headers(&config.forward_headers, &header_map, remote_addr);
+                    config
+                        .proxy_headers
+                        .apply(&header_map, remote_addr, &mut forward_headers);
+                    if let Some(auth) = &config.auth {
+                        auth.forward_claims(claims.as_ref(), &mut forward_headers);
+                    }
+                    let injected_variables = apply_inject_rules(
+                        &shared_route_table.inject_rules(),
+                        &header_map,
+                        &mut forward_headers,
+                    );
+                    ComputedVariables::new(shared_route_table.computed_variables(), &header_map)
+                        .apply(&mut request.variables);
+
+                    let options = QueryOptions {
+                        remove_nulls: remove_nulls_requested(&request, &header_map),
+                        normalize: normalize_requested(&request, &header_map),
+                        record: header_map.contains_key(RECORD_HEADER),
+                        #[cfg(feature = "chaos")]
+                        chaos: chaos_header_rules(&header_map),
+                        authenticated: shared_route_table
+                            .is_authenticated(claims.as_ref(), client_ip),
+                        client_ip,
+                        incremental: IncrementalFormat::from_accept(&header_map, version),
+                        event_stream: sse::accepts_event_stream(&header_map),
+                        query_plan: query_plan_requested(&request),
+                    };
                     let start_time = Instant::now();
-                    let resp = config
-                        .shared_route_table
-                        .query(
-                            request,
-                            do_forward_headers(&config.forward_headers, &header_map, remote_addr),
-                        )
-                        .with_context(query)
+                    let resp = shared_route_table
+                        .query(request, forward_headers, injected_variables, options)
+                        .with_context(query.clone())
                         .await;
 
-                    METRICS
-                        .query_histogram
-                        .record((Instant::now() - start_time).as_secs_f64());
-                    METRICS.query_counter.add(1);
+                    let labels = Metrics::labels(tenant);
+                    METRICS.query_histogram.record(
+                        &query,
+                        (Instant::now() - start_time).as_secs_f64(),
+                        &labels,
+                    );
+                    METRICS.query_counter.add(1, &labels);
 
                     Ok::<_, Infallible>(resp)
                 }
@@ -97,30 +270,91 @@ pub fn graphql_websocket(
         .and(warp::header::exact_ignore_case("upgrade", "websocket"))
         .and(warp::header::optional::<String>("sec-websocket-protocol"))
         .and(warp::header::headers_cloned())
-        .and(warp::addr::remote())
+        .and(remote_addr())
         .map({
             move |ws: Ws, protocols: Option<String>, header_map, remote_addr: Option<SocketAddr>| {
                 let config = config.clone();
-                let protocol = protocols
-                    .and_then(|protocols| {
-                        protocols
-                            .split(',')
-                            .find_map(|p| websocket::Protocols::from_str(p.trim()).ok())
-                    })
-                    .unwrap_or(websocket::Protocols::SubscriptionsTransportWS);
-                let header_map =
+                let client_ip = config.access.client_ip(&header_map, remote_addr);
+                if !config.access.is_allowed(client_ip) {
+                    return config
+                        .shared_route_table
+                        .error_responses()
+                        .response(
+                            FailureCategory::Unauthorized,
+                            StatusCode::FORBIDDEN,
+                            String::new(),
+                        )
+                        .into_response();
+                }
+                let claims = match config
+                    .auth
+                    .as_ref()
+                    .map(|auth| auth.authenticate(&header_map))
+                {
+                    Some(Ok(claims)) => claims,
+                    Some(Err(_)) => {
+                        return config
+                            .shared_route_table
+                            .error_responses()
+                            .response(
+                                FailureCategory::Unauthorized,
+                                StatusCode::UNAUTHORIZED,
+                                String::new(),
+                            )
+                            .into_response();
+                    }
+                    None => None,
+                };
+
+                // An unknown tenant is rejected before the upgrade, like the
+                // HTTP requests.
+                let shared_route_table = match config.select_route_table(&header_map) {
+                    Ok((_, shared_route_table)) => shared_route_table.clone(),
+                    Err(err) => {
+                        return HttpResponse::builder()
+                            .status(StatusCode::BAD_REQUEST)
+                            .body(Body::from(err))
+                            .unwrap();
+                    }
+                };
+                let protocol = match websocket::Protocols::negotiate(
+                    protocols.as_deref(),
+                    shared_route_table.websocket_protocols(),
+                ) {
+                    Some(protocol) => protocol,
+                    None => return StatusCode::BAD_REQUEST.into_response(),
+                };
+                let authenticated = shared_route_table.is_authenticated(claims.as_ref(), client_ip);
+                let mut forward_headers =
                     do_forward_headers(&config.forward_headers, &header_map, remote_addr);
+                config
+                    .proxy_headers
+                    .apply(&header_map, remote_addr, &mut forward_headers);
+                if let Some(auth) = &config.auth {
+                    auth.forward_claims(claims.as_ref(), &mut forward_headers);
+                }
+                let injected_variables = apply_inject_rules(
+                    &shared_route_table.inject_rules(),
+                    &header_map,
+                    &mut forward_headers,
+                );
+                let computed_variables =
+                    ComputedVariables::new(shared_route_table.computed_variables(), &header_map);
 
                 let reply = ws.on_upgrade(move |websocket| async move {
-                    if let Some((composed_schema, route_table)) =
-                        config.shared_route_table.get().await
-                    {
+                    if let Some((composed_schema, route_table)) = shared_route_table.get().await {
+                        let composed_schema =
+                            shared_route_table.select_schema(composed_schema, authenticated);
                         websocket::server(
                             composed_schema,
                             route_table,
                             websocket,
                             protocol,
-                            header_map,
+                            forward_headers,
+                            injected_variables,
+                            computed_variables,
+                            authenticated,
+                            shared_route_table,
                         )
                         .await;
                     }
@@ -131,6 +365,32 @@ pub fn graphql_websocket(
                     "Sec-WebSocket-Protocol",
                     protocol.sec_websocket_protocol(),
                 )
+                .into_response()
+            }
+        })
+}
+
+/// Receives the subscription events posted by the services with the callback protocol.
+///
+/// Requests are rejected if subscription callbacks are not enabled.
+pub fn graphql_callback(
+    callbacks: Option<Arc<CallbackRegistry>>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("callback" / String)
+        .and(warp::post())
+        .and(warp::body::json())
+        .and_then(move |id: String, message: CallbackMessage| {
+            let callbacks = callbacks.clone();
+            async move {
+                let callbacks = callbacks.ok_or_else(warp::reject::not_found)?;
+                let status = callbacks.handle(&id, message);
+                Ok::<_, Rejection>(
+                    HttpResponse::builder()
+                        .status(status)
+                        .header(SUBSCRIPTION_PROTOCOL_HEADER, SUBSCRIPTION_PROTOCOL)
+                        .body(String::new())
+                        .unwrap(),
+                )
             }
         })
 }
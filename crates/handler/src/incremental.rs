@@ -0,0 +1,507 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+
+use futures_util::StreamExt;
+use graphgate_planner::{DeferNode, PathSegment, Response, ServerError};
+use http::header::CONTENT_TYPE;
+use http::{HeaderMap, Version};
+use serde::Serialize;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use value::ConstValue;
+use warp::http::{Response as HttpResponse, StatusCode};
+use warp::hyper::Body;
+
+/// Format of the incremental responses, negotiated with the `Accept` header
+/// and the HTTP version of the request.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum IncrementalFormat {
+    /// `multipart/mixed`, a part for each payload.
+    Multipart,
+
+    /// `application/graphql-response+json`, a line for each payload sent as
+    /// a chunk of the response.
+    JsonLines,
+}
+
+impl IncrementalFormat {
+    /// Returns the format accepted by the client.
+    ///
+    /// Over HTTP/2 each payload is sent in its own data frames, so the JSON
+    /// lines are preferred, without the multipart boundaries that some
+    /// proxies buffer. Over HTTP/1.x, `multipart/mixed` is preferred.
+    pub fn from_accept(header_map: &HeaderMap, version: Version) -> Option<Self> {
+        let accept = header_map
+            .get_all(http::header::ACCEPT)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .collect::<Vec<_>>()
+            .join(",");
+        let multipart = accept.contains("multipart/mixed");
+        let json_lines = accept.contains("application/graphql-response+json");
+        match (multipart, json_lines) {
+            (_, true) if version >= Version::HTTP_2 => Some(IncrementalFormat::JsonLines),
+            (true, _) => Some(IncrementalFormat::Multipart),
+            (false, true) => Some(IncrementalFormat::JsonLines),
+            (false, false) => None,
+        }
+    }
+
+    fn content_type(&self) -> &'static str {
+        match self {
+            IncrementalFormat::Multipart => "multipart/mixed; boundary=\"-\"; deferSpec=20220824",
+            IncrementalFormat::JsonLines => "application/graphql-response+json; charset=utf-8",
+        }
+    }
+
+    fn encode(&self, payload: &Payload) -> String {
+        let json = serde_json::to_string(payload).unwrap();
+        match self {
+            IncrementalFormat::Multipart => format!(
+                "\r\n---\r\nContent-Type: application/json; charset=utf-8\r\n\r\n{}",
+                json
+            ),
+            IncrementalFormat::JsonLines => format!("{}\n", json),
+        }
+    }
+
+    fn end(&self) -> &'static str {
+        match self {
+            IncrementalFormat::Multipart => "\r\n-----\r\n",
+            IncrementalFormat::JsonLines => "",
+        }
+    }
+}
+
+/// A payload of an incremental response.
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Payload {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<ConstValue>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    incremental: Vec<IncrementalItem>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    errors: Vec<ServerError>,
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    extensions: HashMap<String, ConstValue>,
+    has_next: bool,
+
+    /// Headers of the HTTP response, set on the initial payload.
+    #[serde(skip)]
+    pub(crate) headers: HeaderMap,
+}
+
+/// The data of a deferred fragment, or the items of a streamed list.
+#[derive(Debug, Serialize)]
+struct IncrementalItem {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<ConstValue>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    items: Option<Vec<ConstValue>>,
+    path: Vec<ConstValue>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    label: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    errors: Vec<ServerError>,
+}
+
+/// Converts the responses of an incremental execution into payloads.
+///
+/// The first response gives the initial payload, with the streamed lists cut
+/// to their initial count, followed by the remaining items of the lists. Each
+/// following response gives the data added by a deferred node, compared with
+/// the previous response.
+pub(crate) struct IncrementalDelivery<'p, 'a> {
+    defer: &'p DeferNode<'a>,
+    step: usize,
+    previous: Option<Response>,
+}
+
+impl<'p, 'a> IncrementalDelivery<'p, 'a> {
+    pub(crate) fn new(defer: &'p DeferNode<'a>) -> Self {
+        Self {
+            defer,
+            step: 0,
+            previous: None,
+        }
+    }
+
+    /// Returns `true` if the initial payload is not returned yet.
+    pub(crate) fn is_initial(&self) -> bool {
+        self.step == 0
+    }
+
+    /// Returns `true` until the payloads of all the deferred nodes are
+    /// returned.
+    pub(crate) fn has_next(&self) -> bool {
+        self.step <= self.defer.deferred.len()
+    }
+
+    /// Returns the payloads of the next response.
+    pub(crate) fn payloads(&mut self, resp: &Response) -> Vec<Payload> {
+        let mut payloads = match &self.previous {
+            None => self.initial_payloads(resp),
+            Some(previous) => {
+                let deferred = &self.defer.deferred[self.step - 1];
+                let mut items = Vec::new();
+                let mut locations = Vec::new();
+                find_locations(
+                    &resp.data,
+                    &deferred.path,
+                    true,
+                    &mut Vec::new(),
+                    &mut locations,
+                );
+                for (path, value) in locations {
+                    let previous = get_path(&previous.data, &path).unwrap_or(&ConstValue::Null);
+                    if let Some(data) = diff(previous, value) {
+                        items.push(IncrementalItem {
+                            data: Some(data),
+                            items: None,
+                            path,
+                            label: deferred.label.clone(),
+                            errors: Vec::new(),
+                        });
+                    }
+                }
+
+                let errors = resp
+                    .errors
+                    .iter()
+                    .skip(previous.errors.len())
+                    .cloned()
+                    .collect::<Vec<_>>();
+                if !errors.is_empty() {
+                    match items.first_mut() {
+                        Some(item) => item.errors = errors,
+                        None => items.push(IncrementalItem {
+                            data: Some(ConstValue::Object(Default::default())),
+                            items: None,
+                            path: Vec::new(),
+                            label: deferred.label.clone(),
+                            errors,
+                        }),
+                    }
+                }
+                vec![Payload {
+                    incremental: items,
+                    ..Default::default()
+                }]
+            }
+        };
+
+        self.step += 1;
+        self.previous = Some(resp.clone());
+        let has_next = self.has_next();
+        let last = payloads.len() - 1;
+        for (idx, payload) in payloads.iter_mut().enumerate() {
+            payload.has_next = idx < last || has_next;
+        }
+        payloads
+    }
+
+    fn initial_payloads(&self, resp: &Response) -> Vec<Payload> {
+        let mut data = resp.data.clone();
+        let mut payloads = Vec::new();
+        for stream in &self.defer.streams {
+            let mut locations = Vec::new();
+            find_locations(
+                &resp.data,
+                &stream.path,
+                false,
+                &mut Vec::new(),
+                &mut locations,
+            );
+            for (mut path, _) in locations {
+                // The lists of the items beyond the initial count of an outer
+                // stream are delivered with its items.
+                if let Some(ConstValue::List(list)) = get_path_mut(&mut data, &path) {
+                    if list.len() > stream.initial_count {
+                        let items = list.split_off(stream.initial_count);
+                        path.push(ConstValue::Number(stream.initial_count.into()));
+                        payloads.push(Payload {
+                            incremental: vec![IncrementalItem {
+                                data: None,
+                                items: Some(items),
+                                path,
+                                label: stream.label.clone(),
+                                errors: Vec::new(),
+                            }],
+                            ..Default::default()
+                        });
+                    }
+                }
+            }
+        }
+
+        payloads.insert(
+            0,
+            Payload {
+                data: Some(data),
+                errors: resp.errors.clone(),
+                extensions: resp.extensions.clone(),
+                ..Default::default()
+            },
+        );
+        payloads
+    }
+
+    /// Returns the last payload of an execution interrupted before all the
+    /// deferred nodes are executed, such as by a timeout.
+    pub(crate) fn finish(&mut self, errors: Vec<ServerError>) -> Payload {
+        self.step = self.defer.deferred.len() + 1;
+        Payload {
+            errors,
+            ..Default::default()
+        }
+    }
+}
+
+/// Finds the values at a path, the lists are traversed with their indices.
+/// The last list is traversed only if `expand_last` is `true`.
+fn find_locations<'v>(
+    value: &'v ConstValue,
+    path: &[PathSegment<'_>],
+    expand_last: bool,
+    current: &mut Vec<ConstValue>,
+    locations: &mut Vec<(Vec<ConstValue>, &'v ConstValue)>,
+) {
+    let (segment, rest) = match path.split_first() {
+        Some(res) => res,
+        None => {
+            locations.push((current.clone(), value));
+            return;
+        }
+    };
+    let value = match value {
+        ConstValue::Object(object) => match object.get(segment.name) {
+            Some(value) => value,
+            None => return,
+        },
+        _ => return,
+    };
+    current.push(ConstValue::String(segment.name.to_string()));
+    find_element_locations(
+        value,
+        segment.list_depth,
+        rest,
+        expand_last,
+        current,
+        locations,
+    );
+    current.pop();
+}
+
+/// Finds the values at a path in the value of a field, after traversing
+/// `depth` levels of nested lists.
+fn find_element_locations<'v>(
+    value: &'v ConstValue,
+    depth: usize,
+    rest: &[PathSegment<'_>],
+    expand_last: bool,
+    current: &mut Vec<ConstValue>,
+    locations: &mut Vec<(Vec<ConstValue>, &'v ConstValue)>,
+) {
+    match value {
+        ConstValue::List(list) if depth > 0 && (expand_last || !rest.is_empty()) => {
+            for (idx, element) in list.iter().enumerate() {
+                current.push(ConstValue::Number(idx.into()));
+                find_element_locations(element, depth - 1, rest, expand_last, current, locations);
+                current.pop();
+            }
+        }
+        _ => find_locations(value, rest, expand_last, current, locations),
+    }
+}
+
+fn get_path<'v>(value: &'v ConstValue, path: &[ConstValue]) -> Option<&'v ConstValue> {
+    path.iter()
+        .try_fold(value, |value, segment| match (value, segment) {
+            (ConstValue::Object(object), ConstValue::String(name)) => object.get(name.as_str()),
+            (ConstValue::List(list), ConstValue::Number(idx)) => {
+                list.get(idx.as_u64().unwrap_or(u64::MAX) as usize)
+            }
+            _ => None,
+        })
+}
+
+fn get_path_mut<'v>(value: &'v mut ConstValue, path: &[ConstValue]) -> Option<&'v mut ConstValue> {
+    path.iter()
+        .try_fold(value, |value, segment| match (value, segment) {
+            (ConstValue::Object(object), ConstValue::String(name)) => object.get_mut(name.as_str()),
+            (ConstValue::List(list), ConstValue::Number(idx)) => {
+                list.get_mut(idx.as_u64().unwrap_or(u64::MAX) as usize)
+            }
+            _ => None,
+        })
+}
+
+/// Returns the fields of an object that are new or changed, the other values
+/// are returned whole if they changed.
+fn diff(previous: &ConstValue, value: &ConstValue) -> Option<ConstValue> {
+    if previous == value {
+        return None;
+    }
+    match (previous, value) {
+        (ConstValue::Object(previous), ConstValue::Object(object)) => Some(ConstValue::Object(
+            object
+                .iter()
+                .filter_map(|(name, value)| {
+                    let changed = match previous.get(name) {
+                        Some(previous) => diff(previous, value)?,
+                        None => value.clone(),
+                    };
+                    Some((name.clone(), changed))
+                })
+                .collect(),
+        )),
+        _ => Some(value.clone()),
+    }
+}
+
+/// Returns the HTTP response streaming the payloads, starting with the
+/// initial payload.
+pub(crate) fn http_response(
+    format: IncrementalFormat,
+    mut initial: Payload,
+    payloads: mpsc::UnboundedReceiver<Payload>,
+) -> HttpResponse<Body> {
+    let headers = std::mem::take(&mut initial.headers);
+    let stream = futures_util::stream::once(async move { initial })
+        .chain(UnboundedReceiverStream::new(payloads))
+        .map(move |payload| format.encode(&payload))
+        .chain(futures_util::stream::once(async move {
+            format.end().to_string()
+        }))
+        .map(Ok::<_, Infallible>);
+
+    let mut builder = HttpResponse::builder()
+        .status(StatusCode::OK)
+        .header(CONTENT_TYPE, format.content_type());
+    if let Some(header_map) = builder.headers_mut() {
+        header_map.extend(headers);
+    }
+    builder.body(Body::wrap_stream(stream)).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use graphgate_planner::{PlanBuilder, PlanNode, RootNode};
+    use graphgate_schema::ComposedSchema;
+    use serde_json::json;
+
+    use super::*;
+
+    fn response(data: serde_json::Value) -> Response {
+        Response {
+            data: ConstValue::from_json(data).unwrap(),
+            ..Default::default()
+        }
+    }
+
+    fn payloads_json(payloads: Vec<Payload>) -> Vec<serde_json::Value> {
+        payloads
+            .iter()
+            .map(|payload| serde_json::to_value(payload).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn negotiate_format() {
+        let accept = |value: &str| {
+            let mut header_map = HeaderMap::new();
+            header_map.insert(http::header::ACCEPT, value.parse().unwrap());
+            header_map
+        };
+        let both = accept("multipart/mixed, application/graphql-response+json");
+        assert_eq!(
+            IncrementalFormat::from_accept(&both, Version::HTTP_11),
+            Some(IncrementalFormat::Multipart)
+        );
+        assert_eq!(
+            IncrementalFormat::from_accept(&both, Version::HTTP_2),
+            Some(IncrementalFormat::JsonLines)
+        );
+        assert_eq!(
+            IncrementalFormat::from_accept(&accept("multipart/mixed"), Version::HTTP_2),
+            Some(IncrementalFormat::Multipart)
+        );
+        assert_eq!(
+            IncrementalFormat::from_accept(
+                &accept("application/graphql-response+json"),
+                Version::HTTP_11
+            ),
+            Some(IncrementalFormat::JsonLines)
+        );
+        assert_eq!(
+            IncrementalFormat::from_accept(&accept("application/json"), Version::HTTP_2),
+            None
+        );
+    }
+
+    #[test]
+    fn deliver() {
+        let schema = ComposedSchema::parse(
+            r#"
+            type Query {
+                me: User @resolve(service: "accounts")
+            }
+
+            type User @owner(service: "accounts") @key(fields: "id" service: "accounts") {
+                id: ID!
+                username: String!
+                friends: [User!]!
+            }
+            "#,
+        )
+        .unwrap();
+        let document = parser::parse_query(
+            r#"{
+                me {
+                    username
+                    friends @stream(initialCount: 1) { id }
+                    ... @defer(label: "details") { id }
+                }
+            }"#,
+        )
+        .unwrap();
+        let builder = PlanBuilder::new(&schema, document);
+        let plan = builder.plan().unwrap();
+        let defer = match &plan {
+            RootNode::Query(PlanNode::Defer(defer)) => defer,
+            _ => panic!("Not a deferred plan."),
+        };
+        let mut delivery = IncrementalDelivery::new(defer);
+
+        let payloads = delivery.payloads(&response(json!({
+            "me": { "username": "a", "friends": [{ "id": "2" }, { "id": "3" }] }
+        })));
+        assert_eq!(
+            payloads_json(payloads),
+            vec![
+                json!({
+                    "data": { "me": { "username": "a", "friends": [{ "id": "2" }] } },
+                    "hasNext": true,
+                }),
+                json!({
+                    "incremental": [{ "items": [{ "id": "3" }], "path": ["me", "friends", 1] }],
+                    "hasNext": true,
+                }),
+            ]
+        );
+        assert!(delivery.has_next());
+
+        let payloads = delivery.payloads(&response(json!({
+            "me": { "username": "a", "friends": [{ "id": "2" }, { "id": "3" }], "id": "1" }
+        })));
+        assert_eq!(
+            payloads_json(payloads),
+            vec![json!({
+                "incremental": [{ "data": { "id": "1" }, "path": ["me"], "label": "details" }],
+                "hasNext": false,
+            })]
+        );
+        assert!(!delivery.has_next());
+    }
+}
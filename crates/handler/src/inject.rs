@@ -0,0 +1,74 @@
+use std::str::FromStr;
+
+use http::header::HeaderName;
+use http::{HeaderMap, HeaderValue};
+use value::{ConstValue, Name, Variables};
+
+/// Where the injected value comes from.
+#[derive(Debug, Clone)]
+pub enum InjectSource {
+    /// A fixed value.
+    Value(String),
+
+    /// The value of a header of the client request.
+    Header(String),
+}
+
+/// Where the value is injected into the requests sent to the services.
+#[derive(Debug, Clone)]
+pub enum InjectTarget {
+    /// A GraphQL variable.
+    ///
+    /// The variable is only added to the `variables` of the requests, it is
+    /// not declared by their operations, so the services read it out-of-band,
+    /// such as from the request context, instead of from an argument.
+    Variable(String),
+
+    /// An HTTP header.
+    Header(String),
+}
+
+/// A rule that injects a value into all requests sent to the services.
+#[derive(Debug, Clone)]
+pub struct InjectRule {
+    pub source: InjectSource,
+    pub target: InjectTarget,
+}
+
+/// Applies the inject rules, the injected headers are appended to
+/// `forward_headers` and the injected variables are returned.
+pub fn apply_inject_rules(
+    rules: &[InjectRule],
+    header_map: &HeaderMap,
+    forward_headers: &mut HeaderMap,
+) -> Variables {
+    let mut variables = Variables::default();
+
+    for rule in rules {
+        let value = match &rule.source {
+            InjectSource::Value(value) => value.as_str(),
+            InjectSource::Header(name) => {
+                match header_map.get(name).and_then(|value| value.to_str().ok()) {
+                    Some(value) => value,
+                    None => continue,
+                }
+            }
+        };
+
+        match &rule.target {
+            InjectTarget::Variable(name) => {
+                variables.insert(Name::new(name), ConstValue::String(value.to_string()));
+            }
+            InjectTarget::Header(name) => {
+                if let Some((name, value)) = HeaderName::from_str(name)
+                    .ok()
+                    .zip(HeaderValue::from_str(value).ok())
+                {
+                    forward_headers.insert(name, value);
+                }
+            }
+        }
+    }
+
+    variables
+}
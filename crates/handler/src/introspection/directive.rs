@@ -0,0 +1,67 @@
+use graphgate_planner::IntrospectionSelectionSet;
+use graphgate_schema::{ComposedSchema, MetaDirective};
+use parser::types::DirectiveLocation;
+use value::{ConstValue, Name};
+
+use super::input_value::IntrospectionInputValue;
+use super::resolver::{resolve_obj, Resolver};
+
+pub struct IntrospectionDirective<'a>(pub &'a MetaDirective);
+
+impl<'a> Resolver for IntrospectionDirective<'a> {
+    fn resolve(
+        &self,
+        selection_set: &IntrospectionSelectionSet,
+        schema: &ComposedSchema,
+    ) -> ConstValue {
+        resolve_obj(selection_set, |name, field| match name {
+            "__typename" => ConstValue::String("__Directive".to_string()),
+            "name" => ConstValue::String(self.0.name.to_string()),
+            "description" => self
+                .0
+                .description
+                .as_ref()
+                .map(|description| ConstValue::String(description.clone()))
+                .unwrap_or_default(),
+            "locations" => ConstValue::List(
+                self.0
+                    .locations
+                    .iter()
+                    .map(|location| ConstValue::Enum(Name::new(location_name(*location))))
+                    .collect(),
+            ),
+            "args" => ConstValue::List(
+                self.0
+                    .arguments
+                    .values()
+                    .map(|arg| IntrospectionInputValue(arg).resolve(&field.selection_set, schema))
+                    .collect(),
+            ),
+            _ => ConstValue::Null,
+        })
+    }
+}
+
+fn location_name(location: DirectiveLocation) -> &'static str {
+    match location {
+        DirectiveLocation::Query => "QUERY",
+        DirectiveLocation::Mutation => "MUTATION",
+        DirectiveLocation::Subscription => "SUBSCRIPTION",
+        DirectiveLocation::Field => "FIELD",
+        DirectiveLocation::FragmentDefinition => "FRAGMENT_DEFINITION",
+        DirectiveLocation::FragmentSpread => "FRAGMENT_SPREAD",
+        DirectiveLocation::InlineFragment => "INLINE_FRAGMENT",
+        DirectiveLocation::VariableDefinition => "VARIABLE_DEFINITION",
+        DirectiveLocation::Schema => "SCHEMA",
+        DirectiveLocation::Scalar => "SCALAR",
+        DirectiveLocation::Object => "OBJECT",
+        DirectiveLocation::FieldDefinition => "FIELD_DEFINITION",
+        DirectiveLocation::ArgumentDefinition => "ARGUMENT_DEFINITION",
+        DirectiveLocation::Interface => "INTERFACE",
+        DirectiveLocation::Union => "UNION",
+        DirectiveLocation::Enum => "ENUM",
+        DirectiveLocation::EnumValue => "ENUM_VALUE",
+        DirectiveLocation::InputObject => "INPUT_OBJECT",
+        DirectiveLocation::InputFieldDefinition => "INPUT_FIELD_DEFINITION",
+    }
+}
@@ -13,6 +13,7 @@ impl<'a> Resolver for IntrospectionEnumValue<'a> {
         _schema: &ComposedSchema,
     ) -> ConstValue {
         resolve_obj(selection_set, |name, _field| match name {
+            "__typename" => ConstValue::String("__EnumValue".to_string()),
             "name" => ConstValue::String(self.0.value.to_string()),
             "description" => self
                 .0
@@ -15,6 +15,7 @@ impl<'a> Resolver for IntrospectionField<'a> {
         schema: &ComposedSchema,
     ) -> ConstValue {
         resolve_obj(selection_set, |name, field| match name {
+            "__typename" => ConstValue::String("__Field".to_string()),
             "name" => ConstValue::String(self.0.name.to_string()),
             "description" => self
                 .0
@@ -3,6 +3,8 @@ use graphgate_schema::ComposedSchema;
 use indexmap::IndexMap;
 use value::{ConstValue, Name};
 
+use crate::executor::merge_data;
+
 pub trait Resolver {
     fn resolve(
         &self,
@@ -11,6 +13,12 @@ pub trait Resolver {
     ) -> ConstValue;
 }
 
+/// Resolves the fields of an object keyed by their response keys, in the
+/// requested order.
+///
+/// The fields with the same response key are merged like in graphql-js, the
+/// value is at the position of the first one and contains the fields selected
+/// by all of them.
 pub fn resolve_obj(
     selection_set: &IntrospectionSelectionSet,
     resolve_fn: impl Fn(&str, &IntrospectionField) -> ConstValue,
@@ -20,32 +28,31 @@ pub fn resolve_obj(
         if is_skip(&field.directives) {
             continue;
         }
-        let key = field
-            .alias
-            .as_ref()
-            .cloned()
-            .unwrap_or_else(|| field.name.clone());
-        obj.insert(key, resolve_fn(field.name.as_str(), field));
+        let key = field.alias.as_ref().unwrap_or(&field.name);
+        let value = resolve_fn(field.name.as_str(), field);
+        match obj.get_mut(key) {
+            Some(target) => merge_data(target, value),
+            None => {
+                obj.insert(key.clone(), value);
+            }
+        }
     }
     ConstValue::Object(obj)
 }
 
+/// Returns `true` if the `@skip` or `@include` directives exclude a field.
 fn is_skip(directives: &[IntrospectionDirective]) -> bool {
-    for directive in directives {
-        let include = match &*directive.name.as_str() {
-            "skip" => false,
-            "include" => true,
-            _ => continue,
-        };
-
-        let condition_input = directive.arguments.get("if").unwrap();
-        let value = match condition_input {
-            ConstValue::Boolean(value) => *value,
+    directives.iter().any(|directive| {
+        let condition = matches!(
+            directive.arguments.get("if"),
+            Some(ConstValue::Boolean(true))
+        );
+        match directive.name.as_str() {
+            "skip" => condition,
+            "include" => !condition,
             _ => false,
-        };
-        return include != value;
-    }
-    false
+        }
+    })
 }
 
 pub fn is_include_deprecated(arguments: &IndexMap<Name, ConstValue>) -> bool {
@@ -55,3 +62,145 @@ pub fn is_include_deprecated(arguments: &IndexMap<Name, ConstValue>) -> bool {
         false
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use graphgate_planner::{PlanBuilder, PlanNode, RootNode};
+    use value::Variables;
+
+    use super::super::IntrospectionRoot;
+    use super::*;
+
+    fn resolve(query: &str, variables: serde_json::Value) -> serde_json::Value {
+        let schema = ComposedSchema::parse(
+            r#"
+            type Query {
+                me: User @resolve(service: "accounts")
+            }
+
+            type User @owner(service: "accounts") {
+                id: ID!
+                name: String
+            }
+
+            "Restricts a field to a role."
+            directive @auth(role: String!) on FIELD_DEFINITION | OBJECT
+            "#,
+        )
+        .unwrap();
+        let document = parser::parse_query(query).unwrap();
+        let builder =
+            PlanBuilder::new(&schema, document).variables(Variables::from_json(variables));
+        let nodes = match builder.plan().unwrap() {
+            RootNode::Query(PlanNode::Sequence(node)) => node.nodes,
+            _ => panic!("Not a query plan."),
+        };
+        match nodes.first() {
+            Some(PlanNode::Introspection(node)) => IntrospectionRoot
+                .resolve(&node.selection_set, &schema)
+                .into_json()
+                .unwrap(),
+            _ => panic!("Not an introspection plan."),
+        }
+    }
+
+    #[test]
+    fn aliases() {
+        assert_eq!(
+            resolve(
+                r#"{ __type(name: "User") { n: name name kind k2: kind } }"#,
+                serde_json::json!({}),
+            ),
+            serde_json::json!({
+                "__type": { "n": "User", "name": "User", "kind": "OBJECT", "k2": "OBJECT" },
+            })
+        );
+    }
+
+    #[test]
+    fn merge_fields() {
+        let value = resolve(
+            r#"
+            {
+                __type(name: "User") {
+                    fields { name }
+                    ...TypeFields
+                }
+            }
+
+            fragment TypeFields on __Type {
+                fields { type { name } }
+            }
+            "#,
+            serde_json::json!({}),
+        );
+        assert_eq!(
+            value,
+            serde_json::json!({
+                "__type": {
+                    "fields": [
+                        { "name": "id", "type": { "name": null } },
+                        { "name": "name", "type": { "name": "String" } },
+                    ],
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn skip_include() {
+        let query = r#"
+            query($skip: Boolean!) {
+                __type(name: "User") {
+                    name @skip(if: $skip)
+                    kind @include(if: $skip)
+                    ... on __Type @include(if: $skip) { description }
+                }
+            }
+        "#;
+        assert_eq!(
+            resolve(query, serde_json::json!({ "skip": true })),
+            serde_json::json!({
+                "__type": { "kind": "OBJECT", "description": null },
+            })
+        );
+        assert_eq!(
+            resolve(query, serde_json::json!({ "skip": false })),
+            serde_json::json!({ "__type": { "name": "User" } })
+        );
+    }
+
+    #[test]
+    fn typename() {
+        assert_eq!(
+            resolve(
+                "{ __schema { __typename queryType { __typename name } } }",
+                serde_json::json!({}),
+            ),
+            serde_json::json!({
+                "__schema": {
+                    "__typename": "__Schema",
+                    "queryType": { "__typename": "__Type", "name": "Query" },
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn directives() {
+        let value = resolve(
+            "{ __schema { directives { name description locations args { name } } } }",
+            serde_json::json!({}),
+        );
+        let directives = value["__schema"]["directives"].as_array().unwrap();
+        assert!(directives.contains(&serde_json::json!({
+            "name": "auth",
+            "description": "Restricts a field to a role.",
+            "locations": ["FIELD_DEFINITION", "OBJECT"],
+            "args": [{ "name": "role" }],
+        })));
+        assert!(directives
+            .iter()
+            .any(|directive| directive["name"] == "include"));
+    }
+}
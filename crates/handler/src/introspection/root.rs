@@ -18,7 +18,7 @@ impl Resolver for IntrospectionRoot {
             "__schema" => IntrospectionSchema.resolve(&field.selection_set, schema),
             "__type" => {
                 if let Some(ConstValue::String(name)) = field.arguments.get("name") {
-                    if let Some(ty) = schema.types.get(name.as_str()) {
+                    if let Some(ty) = schema.get_type_by_name(name.as_str()) {
                         return IntrospectionType::Named(ty).resolve(&field.selection_set, schema);
                     }
                 }
@@ -2,6 +2,7 @@ use graphgate_planner::IntrospectionSelectionSet;
 use graphgate_schema::ComposedSchema;
 use value::ConstValue;
 
+use super::directive::IntrospectionDirective;
 use super::r#type::IntrospectionType;
 use super::resolver::{resolve_obj, Resolver};
 
@@ -14,6 +15,7 @@ impl Resolver for IntrospectionSchema {
         schema: &ComposedSchema,
     ) -> ConstValue {
         resolve_obj(selection_set, |name, field| match name {
+            "__typename" => ConstValue::String("__Schema".to_string()),
             "types" => ConstValue::List(
                 schema
                     .types
@@ -24,8 +26,7 @@ impl Resolver for IntrospectionSchema {
             ),
             "queryType" => {
                 let query_type = schema
-                    .types
-                    .get(schema.query_type())
+                    .get_type_by_name(schema.query_type())
                     .expect("The query validator should find this error.");
                 IntrospectionType::Named(query_type).resolve(&field.selection_set, schema)
             }
@@ -33,7 +34,7 @@ impl Resolver for IntrospectionSchema {
                 let mutation_type = schema
                     .mutation_type
                     .as_ref()
-                    .and_then(|name| schema.types.get(name));
+                    .and_then(|name| schema.get_type_by_name(name));
                 match mutation_type {
                     Some(ty) => IntrospectionType::Named(ty).resolve(&field.selection_set, schema),
                     None => ConstValue::Null,
@@ -43,12 +44,21 @@ impl Resolver for IntrospectionSchema {
                 let subscription_type = schema
                     .subscription_type
                     .as_ref()
-                    .and_then(|name| schema.types.get(name));
+                    .and_then(|name| schema.get_type_by_name(name));
                 match subscription_type {
                     Some(ty) => IntrospectionType::Named(ty).resolve(&field.selection_set, schema),
                     None => ConstValue::Null,
                 }
             }
+            "directives" => ConstValue::List(
+                schema
+                    .directives
+                    .values()
+                    .map(|directive| {
+                        IntrospectionDirective(directive).resolve(&field.selection_set, schema)
+                    })
+                    .collect(),
+            ),
             _ => ConstValue::Null,
         })
     }
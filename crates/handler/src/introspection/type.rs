@@ -37,8 +37,7 @@ impl<'a> IntrospectionType<'a> {
         match ty {
             BaseType::Named(name) => IntrospectionType::Named(
                 schema
-                    .types
-                    .get(name)
+                    .get_type_by_name(name)
                     .expect("The query validator should find this error."),
             ),
             BaseType::List(ty) => IntrospectionType::List(Box::new(Self::new(ty, schema))),
@@ -53,6 +52,7 @@ impl<'a> Resolver for IntrospectionType<'a> {
         schema: &ComposedSchema,
     ) -> ConstValue {
         resolve_obj(selection_set, |name, field| match name {
+            "__typename" => ConstValue::String("__Type".to_string()),
             "kind" => match self {
                 Self::Named(ty) => match ty.kind {
                     TypeKind::Scalar => ConstValue::Enum(SCALAR.clone()),
@@ -105,8 +105,7 @@ impl<'a> Resolver for IntrospectionType<'a> {
                         .map(|name| {
                             IntrospectionType::Named(
                                 schema
-                                    .types
-                                    .get(name)
+                                    .get_type_by_name(name)
                                     .expect("The query validator should find this error."),
                             )
                             .resolve(&field.selection_set, schema)
@@ -123,8 +122,7 @@ impl<'a> Resolver for IntrospectionType<'a> {
                             .map(|name| {
                                 IntrospectionType::Named(
                                     schema
-                                        .types
-                                        .get(name)
+                                        .get_type_by_name(name)
                                         .expect("The query validator should find this error."),
                                 )
                                 .resolve(&field.selection_set, schema)
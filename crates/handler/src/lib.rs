@@ -1,15 +1,106 @@
 #![forbid(unsafe_code)]
 
-pub use service_route::{ServiceRoute, ServiceRouteTable};
-pub use shared_route_table::SharedRouteTable;
+pub use access::{AccessOptions, IpNetwork};
+pub use audit::{AuditOptions, ExecutionReport, FetchEvent};
+pub use auth::{AuthOptions, Authenticator, Claims};
+pub use aws_sigv4::{sign_request, AwsCredentials, AwsSigV4Options};
+pub use body_encoding::BodyEncoding;
+pub use callback::{CallbackOptions, CallbackRegistry};
+#[cfg(feature = "chaos")]
+pub use chaos::{ChaosFault, ChaosOptions, ChaosRule, CHAOS_HEADER};
+pub use computed_variables::{ComputedVariable, ComputedVariables, VariableSource};
+pub use dashboard::grafana_dashboard;
+pub use differential::DifferentialOptions;
+pub use error_responses::{ErrorResponse, ErrorResponses, FailureCategory};
+pub use event_source::{EventSource, EventSourceField, EventSources};
+pub use graphgate_planner::{
+    GraphFormat, InlineVariables, PlanBuilder, PlanLimits, ScalarValidator, ValidationLimits,
+};
+pub use graphgate_schema::{ComposedSchema, ShapingDirective};
+pub use incremental::IncrementalFormat;
+pub use inject::{InjectRule, InjectSource, InjectTarget};
+pub use maintenance_window::{CronSchedule, MaintenanceWindow};
+pub use metrics::{
+    exemplar, Exemplar, MetricDefinition, MetricKind, LATENCY_BUCKETS, METRIC_DEFINITIONS,
+};
+pub use mirror::MirrorOptions;
+pub use oauth2::OAuth2Options;
+pub use operation_policy::OperationPolicy;
+pub use proxy_headers::{ProxyHeaderOptions, DEFAULT_USER_AGENT};
+pub use public_schema::PublicSchemaOptions;
+pub use query_plan::QUERY_PLAN_EXTENSION;
+pub use record::{replay, RecordOptions, RecordedFetch, Recording, RECORD_HEADER};
+pub use response_headers::{HeaderMergePolicy, ResponseHeaderOptions};
+pub use response_limits::ResponseLimits;
+pub use response_rules::{ResponseAction, ResponseRule};
+pub use secret::{
+    secret_references, AwsSecretsManagerResolver, EnvSecretResolver, FileSecretResolver,
+    SecretResolver, SecretResolvers, VaultSecretResolver,
+};
+pub use service_route::{HttpVersion, RouteTableDiff, ServiceRoute, ServiceRouteTable};
+pub use shared_route_table::{QueryOptions, SchemaUpdateOptions, SharedRouteTable};
+pub use shutdown::{begin_shutdown, drain, is_shutting_down, shutdown_started};
+pub use slo::{SloObjective, SloOptions};
+pub use slow_query_log::SlowQueryLogOptions;
+pub use switches::{DisabledTargets, MaintenanceMode, ReadOnlyMode, RuntimeSwitches};
+pub use tenant::TenantRouteTables;
+pub use token_refresh::TokenRefreshOptions;
+pub use upstream_pool::UpstreamPoolOptions;
+pub use upstream_tls::UpstreamTlsOptions;
+pub use validation_cache::ValidationCacheOptions;
+pub use websocket::Protocols;
 
+mod access;
+mod admin_schema;
+mod audit;
+mod auth;
+mod aws_sigv4;
+mod body_encoding;
+mod callback;
+#[cfg(feature = "chaos")]
+mod chaos;
+mod computed_variables;
 mod constants;
+mod dashboard;
+mod differential;
+mod error_responses;
+mod event_source;
 mod executor;
 mod fetcher;
+mod incremental;
+mod inject;
 mod introspection;
+mod maintenance_window;
 mod metrics;
+mod mirror;
+mod oauth2;
+mod operation_policy;
+mod proxy_headers;
+mod public_schema;
+mod query_plan;
+mod record;
+mod recover;
+mod response_headers;
+mod response_limits;
+mod response_rules;
+mod secret;
 mod service_route;
+mod shaping;
 mod shared_route_table;
+mod shutdown;
+mod slo;
+mod slow_query_log;
+mod sse;
+mod switches;
+mod tenant;
+mod token_refresh;
+mod upstream_pool;
+mod upstream_tls;
+mod validation_cache;
+mod version_check;
 mod websocket;
 
+pub mod admin;
 pub mod handler;
+#[cfg(any(test, feature = "test-util"))]
+pub mod testing;
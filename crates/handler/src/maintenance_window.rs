@@ -0,0 +1,271 @@
+use std::convert::TryFrom;
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+use anyhow::{Context, Error, Result};
+use chrono::{DateTime, Datelike, Duration, Timelike, Utc};
+use graphgate_planner::{ErrorCode, Response, ServerError};
+use serde::{Deserialize, Serialize};
+use value::ConstValue;
+
+/// Maximum duration of a maintenance window, the schedule is searched minute
+/// by minute for the start of an active window.
+const MAX_DURATION: u64 = 7 * 24 * 60 * 60;
+
+/// A cron expression with five fields, `minute hour day-of-month month
+/// day-of-week`, evaluated in UTC.
+///
+/// The fields accept `*`, values, ranges and steps separated by commas, such
+/// as `0 2 * * 0` or `*/15 8-18 * * 1-5`. The day of week is `0` to `7`, both
+/// `0` and `7` are Sunday. As in cron, a day matches either of the day fields
+/// if both are restricted.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct CronSchedule {
+    expr: String,
+    minutes: u64,
+    hours: u64,
+    days_of_month: u64,
+    months: u64,
+    days_of_week: u64,
+    any_day_of_month: bool,
+    any_day_of_week: bool,
+}
+
+fn parse_field(field: &str, min: u32, max: u32) -> Result<u64> {
+    let mut bits = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().context("Invalid step.")?),
+            None => (part, 1),
+        };
+        anyhow::ensure!(step > 0, "Step must be greater than 0.");
+        let (start, end) = match range {
+            "*" => (min, max),
+            range => match range.split_once('-') {
+                Some((start, end)) => (start.parse()?, end.parse()?),
+                None => {
+                    let value = range.parse()?;
+                    (value, if part.contains('/') { max } else { value })
+                }
+            },
+        };
+        anyhow::ensure!(
+            min <= start && start <= end && end <= max,
+            "'{}' is not in the range {}-{}.",
+            part,
+            min,
+            max
+        );
+        for value in (start..=end).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+impl FromStr for CronSchedule {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields = s.split_whitespace().collect::<Vec<_>>();
+        anyhow::ensure!(
+            fields.len() == 5,
+            "Schedule '{}' must have 5 fields: minute hour day-of-month month day-of-week.",
+            s
+        );
+        let parse = |idx: usize, min, max| {
+            parse_field(fields[idx], min, max)
+                .with_context(|| format!("Invalid field '{}' of schedule '{}'.", fields[idx], s))
+        };
+        let mut days_of_week = parse(4, 0, 7)?;
+        if days_of_week & (1 << 7) != 0 {
+            days_of_week |= 1;
+        }
+        Ok(Self {
+            expr: s.to_string(),
+            minutes: parse(0, 0, 59)?,
+            hours: parse(1, 0, 23)?,
+            days_of_month: parse(2, 1, 31)?,
+            months: parse(3, 1, 12)?,
+            days_of_week,
+            any_day_of_month: fields[2] == "*",
+            any_day_of_week: fields[4] == "*",
+        })
+    }
+}
+
+impl TryFrom<String> for CronSchedule {
+    type Error = Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<CronSchedule> for String {
+    fn from(schedule: CronSchedule) -> Self {
+        schedule.expr
+    }
+}
+
+impl Display for CronSchedule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.expr)
+    }
+}
+
+impl CronSchedule {
+    /// Returns `true` if the schedule matches the minute of a time.
+    pub fn matches(&self, time: DateTime<Utc>) -> bool {
+        let bit = |bits: u64, value: u32| bits & (1 << value) != 0;
+        let day_of_month = bit(self.days_of_month, time.day());
+        let day_of_week = bit(self.days_of_week, time.weekday().num_days_from_sunday());
+        let day = match (self.any_day_of_month, self.any_day_of_week) {
+            (false, false) => day_of_month || day_of_week,
+            _ => day_of_month && day_of_week,
+        };
+        bit(self.minutes, time.minute())
+            && bit(self.hours, time.hour())
+            && bit(self.months, time.month())
+            && day
+    }
+}
+
+/// A planned maintenance window of a service.
+///
+/// During a window, the fields resolved by the service fail immediately with
+/// a `MAINTENANCE` error instead of being fetched, and the rest of the
+/// operation is executed as usual.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct MaintenanceWindow {
+    /// Start times of the window.
+    pub schedule: CronSchedule,
+
+    /// Duration of the window in seconds, at most 7 days.
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub duration: u64,
+
+    /// Message of the errors returned during the window.
+    #[serde(default)]
+    pub message: Option<String>,
+}
+
+fn deserialize_duration<'de, D>(deserializer: D) -> Result<u64, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let duration = u64::deserialize(deserializer)?;
+    if duration == 0 || duration > MAX_DURATION {
+        return Err(serde::de::Error::custom(format!(
+            "Duration must be between 1 and {} seconds.",
+            MAX_DURATION
+        )));
+    }
+    Ok(duration)
+}
+
+impl MaintenanceWindow {
+    /// Returns the end of the window if it is active at a time.
+    pub fn active_until(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let duration = Duration::seconds(self.duration.min(MAX_DURATION) as i64);
+        let mut start = now.with_second(0)?.with_nanosecond(0)?;
+        while start > now - duration {
+            if self.schedule.matches(start) {
+                return Some(start + duration);
+            }
+            start -= Duration::minutes(1);
+        }
+        None
+    }
+}
+
+/// Returns the active window ending last, and its end.
+pub(crate) fn active_window(
+    windows: &[MaintenanceWindow],
+    now: DateTime<Utc>,
+) -> Option<(&MaintenanceWindow, DateTime<Utc>)> {
+    windows
+        .iter()
+        .filter_map(|window| window.active_until(now).map(|end| (window, end)))
+        .max_by_key(|(_, end)| *end)
+}
+
+/// Returns the response of a fetch sent to a service during a window.
+pub(crate) fn maintenance_response(
+    service: &str,
+    window: &MaintenanceWindow,
+    end: DateTime<Utc>,
+) -> Response {
+    let message = match &window.message {
+        Some(message) => message.clone(),
+        None => format!(
+            "Service '{}' is under planned maintenance until {}.",
+            service,
+            end.to_rfc3339()
+        ),
+    };
+    let mut error = ServerError::new(message)
+        .with_code(ErrorCode::Maintenance)
+        .with_service_name(service);
+    error.extensions.insert(
+        "maintenanceEndsAt".to_string(),
+        ConstValue::String(end.to_rfc3339()),
+    );
+    Response::from_errors(vec![error])
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn schedule() {
+        let schedule: CronSchedule = "*/15 8-18 * * 1-5".parse().unwrap();
+        // 2021-06-07 is a Monday.
+        assert!(schedule.matches(Utc.ymd(2021, 6, 7).and_hms(8, 45, 0)));
+        assert!(!schedule.matches(Utc.ymd(2021, 6, 7).and_hms(8, 46, 0)));
+        assert!(!schedule.matches(Utc.ymd(2021, 6, 7).and_hms(19, 0, 0)));
+        assert!(!schedule.matches(Utc.ymd(2021, 6, 6).and_hms(8, 45, 0)));
+
+        let schedule: CronSchedule = "0 2 1 * 7".parse().unwrap();
+        assert!(schedule.matches(Utc.ymd(2021, 6, 1).and_hms(2, 0, 0)));
+        assert!(schedule.matches(Utc.ymd(2021, 6, 6).and_hms(2, 0, 0)));
+        assert!(!schedule.matches(Utc.ymd(2021, 6, 7).and_hms(2, 0, 0)));
+
+        assert!("0 2 * *".parse::<CronSchedule>().is_err());
+        assert!("60 * * * *".parse::<CronSchedule>().is_err());
+        assert!("*/0 * * * *".parse::<CronSchedule>().is_err());
+    }
+
+    #[test]
+    fn window() {
+        let window = MaintenanceWindow {
+            schedule: "30 2 * * *".parse().unwrap(),
+            duration: 3600,
+            message: None,
+        };
+        let end = Utc.ymd(2021, 6, 7).and_hms(3, 30, 0);
+        assert_eq!(
+            window.active_until(Utc.ymd(2021, 6, 7).and_hms(2, 30, 0)),
+            Some(end)
+        );
+        assert_eq!(
+            window.active_until(Utc.ymd(2021, 6, 7).and_hms(3, 29, 59)),
+            Some(end)
+        );
+        assert_eq!(
+            window.active_until(Utc.ymd(2021, 6, 7).and_hms(3, 30, 0)),
+            None
+        );
+        assert_eq!(
+            window.active_until(Utc.ymd(2021, 6, 7).and_hms(2, 29, 0)),
+            None
+        );
+
+        let response = maintenance_response("accounts", &window, end);
+        assert_eq!(response.errors[0].code(), Some("MAINTENANCE"));
+    }
+}
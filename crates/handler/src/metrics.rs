@@ -1,26 +1,375 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use graphgate_planner::{ErrorCode, Response};
 use once_cell::sync::Lazy;
-use opentelemetry::global;
-use opentelemetry::metrics::{BoundCounter, BoundValueRecorder};
+use opentelemetry::metrics::{Counter, Meter, ValueObserver, ValueRecorder};
+use opentelemetry::trace::TraceContextExt;
+use opentelemetry::{global, Context, Key, KeyValue};
+use value::ConstValue;
+
+use crate::slo;
+use crate::upstream_pool;
+
+const KEY_TENANT: Key = Key::from_static_str("tenant");
+const KEY_SERVICE: Key = Key::from_static_str("service");
+const KEY_HTTP_VERSION: Key = Key::from_static_str("http_version");
+const KEY_PHASE: Key = Key::from_static_str("phase");
+
+/// Upper bounds in seconds of the buckets of the latency histograms.
+pub const LATENCY_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Type of a metric, as exported to Prometheus.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum MetricKind {
+    Counter,
+    Histogram,
+    Gauge,
+}
+
+/// Definition of a metric of the gateway.
+///
+/// The names follow the Prometheus conventions once the dots are replaced
+/// with underscores: the counters end with `_total` and the durations are in
+/// seconds.
+#[derive(Debug, Copy, Clone)]
+pub struct MetricDefinition {
+    pub name: &'static str,
+    pub kind: MetricKind,
+    pub description: &'static str,
+    pub labels: &'static [&'static str],
+}
+
+impl MetricDefinition {
+    /// Returns the name of the metric in Prometheus, such as
+    /// `graphgate_queries_total`.
+    pub fn prometheus_name(&self) -> String {
+        self.name.replace('.', "_")
+    }
+}
+
+const QUERIES: MetricDefinition = MetricDefinition {
+    name: "graphgate.queries_total",
+    kind: MetricKind::Counter,
+    description: "Total number of GraphQL queries executed.",
+    labels: &["tenant"],
+};
+
+const QUERY_DURATION: MetricDefinition = MetricDefinition {
+    name: "graphgate.query_duration_seconds",
+    kind: MetricKind::Histogram,
+    description: "Latencies of the GraphQL queries in seconds.",
+    labels: &["tenant"],
+};
+
+const UPSTREAM_REQUESTS: MetricDefinition = MetricDefinition {
+    name: "graphgate.upstream_requests_total",
+    kind: MetricKind::Counter,
+    description: "Total number of requests sent to the services.",
+    labels: &["service", "http_version"],
+};
+
+const UPSTREAM_REQUEST_DURATION: MetricDefinition = MetricDefinition {
+    name: "graphgate.upstream_request_duration_seconds",
+    kind: MetricKind::Histogram,
+    description: "Latencies of the requests sent to the services in seconds, until the response headers are received.",
+    labels: &["service", "http_version"],
+};
+
+const UPSTREAM_IN_FLIGHT_REQUESTS: MetricDefinition = MetricDefinition {
+    name: "graphgate.upstream_in_flight_requests",
+    kind: MetricKind::Gauge,
+    description: "Number of requests to the services waiting for their response, each one holds a pooled connection.",
+    labels: &["service"],
+};
+
+const SLO_VIOLATIONS: MetricDefinition = MetricDefinition {
+    name: "graphgate.slo_violations_total",
+    kind: MetricKind::Counter,
+    description: "Total number of requests to the services that violated the objectives.",
+    labels: &["service", "operation", "reason"],
+};
+
+const RECOVERED_PANICS: MetricDefinition = MetricDefinition {
+    name: "graphgate.recovered_panics_total",
+    kind: MetricKind::Counter,
+    description: "Total number of panics recovered while executing requests.",
+    labels: &[],
+};
+
+const SCHEMA_VERSION_MISMATCHES: MetricDefinition = MetricDefinition {
+    name: "graphgate.schema_version_mismatches_total",
+    kind: MetricKind::Counter,
+    description:
+        "Total number of responses served by a different schema version than the composed schema.",
+    labels: &["service"],
+};
+
+const SUBSCRIPTIONS_OVER_HTTP: MetricDefinition = MetricDefinition {
+    name: "graphgate.subscriptions_over_http_total",
+    kind: MetricKind::Counter,
+    description: "Total number of subscriptions rejected because they were sent over HTTP.",
+    labels: &[],
+};
+
+const VALIDATION_FAILURES: MetricDefinition = MetricDefinition {
+    name: "graphgate.validation_failures_total",
+    kind: MetricKind::Counter,
+    description: "Total number of operations rejected by the validation.",
+    labels: &[],
+};
+
+const BUDGET_ABORTS: MetricDefinition = MetricDefinition {
+    name: "graphgate.budget_aborts_total",
+    kind: MetricKind::Counter,
+    description: "Total number of operations aborted because parsing, validating or planning them exceeded its time budget.",
+    labels: &["phase"],
+};
+
+const MIRRORED_REQUESTS: MetricDefinition = MetricDefinition {
+    name: "graphgate.mirrored_requests_total",
+    kind: MetricKind::Counter,
+    description: "Total number of requests mirrored to the shadow graph.",
+    labels: &["target", "outcome"],
+};
+
+const DIFFERENTIAL_COMPARISONS: MetricDefinition = MetricDefinition {
+    name: "graphgate.differential_comparisons_total",
+    kind: MetricKind::Counter,
+    description: "Total number of queries executed against the candidate composition, by outcome of the comparison.",
+    labels: &["outcome"],
+};
+
+const SLI_SUCCESS_RATIO: MetricDefinition = MetricDefinition {
+    name: "graphgate.sli_success_ratio",
+    kind: MetricKind::Gauge,
+    description: "Ratio of the successful requests to the services.",
+    labels: &["service", "operation"],
+};
+
+const SLI_LATENCY: MetricDefinition = MetricDefinition {
+    name: "graphgate.sli_latency_seconds",
+    kind: MetricKind::Gauge,
+    description: "Latency quantiles in seconds of the requests to the services.",
+    labels: &["service", "operation", "quantile"],
+};
+
+const SLI_APDEX: MetricDefinition = MetricDefinition {
+    name: "graphgate.sli_apdex",
+    kind: MetricKind::Gauge,
+    description: "Apdex score of the requests to the services.",
+    labels: &["service", "operation"],
+};
+
+/// Definitions of all the metrics of the gateway.
+pub const METRIC_DEFINITIONS: &[MetricDefinition] = &[
+    QUERIES,
+    QUERY_DURATION,
+    UPSTREAM_REQUESTS,
+    UPSTREAM_REQUEST_DURATION,
+    UPSTREAM_IN_FLIGHT_REQUESTS,
+    SLO_VIOLATIONS,
+    RECOVERED_PANICS,
+    SCHEMA_VERSION_MISMATCHES,
+    SUBSCRIPTIONS_OVER_HTTP,
+    VALIDATION_FAILURES,
+    BUDGET_ABORTS,
+    MIRRORED_REQUESTS,
+    DIFFERENTIAL_COMPARISONS,
+    SLI_SUCCESS_RATIO,
+    SLI_LATENCY,
+    SLI_APDEX,
+];
+
+/// A sample of a histogram bucket, linking the metric to the trace that
+/// recorded it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Exemplar {
+    pub trace_id: String,
+    pub value: f64,
+
+    /// Seconds since the Unix epoch.
+    pub timestamp: f64,
+}
+
+type ExemplarKey = (String, Vec<(String, String)>, usize);
+
+/// The last sampled exemplar of each bucket of the latency histograms.
+static EXEMPLARS: Lazy<Mutex<HashMap<ExemplarKey, Exemplar>>> = Lazy::new(Default::default);
+
+/// Returns the exemplar of a histogram bucket, the labels are the labels of
+/// the histogram and its upper bound is the `le` label.
+pub fn exemplar(name: &str, labels: &[(String, String)], upper_bound: f64) -> Option<Exemplar> {
+    let bucket = LATENCY_BUCKETS
+        .iter()
+        .position(|bound| *bound == upper_bound)
+        .unwrap_or(LATENCY_BUCKETS.len());
+    let mut labels = labels.to_vec();
+    labels.sort();
+    EXEMPLARS
+        .lock()
+        .unwrap()
+        .get(&(name.to_string(), labels, bucket))
+        .cloned()
+}
+
+/// A latency histogram keeping the exemplars of its buckets.
+pub struct LatencyHistogram {
+    recorder: ValueRecorder<f64>,
+    name: String,
+}
+
+impl LatencyHistogram {
+    fn new(meter: &Meter, definition: &MetricDefinition) -> Self {
+        Self {
+            recorder: meter
+                .f64_value_recorder(definition.name)
+                .with_description(definition.description)
+                .init(),
+            name: definition.prometheus_name(),
+        }
+    }
+
+    /// Records a latency in seconds, the sampled trace of the context becomes
+    /// the exemplar of its bucket.
+    pub fn record(&self, cx: &Context, value: f64, labels: &[KeyValue]) {
+        self.recorder.record(value, labels);
+
+        let span = cx.span();
+        let span_context = span.span_context();
+        if !span_context.is_valid() || !span_context.is_sampled() {
+            return;
+        }
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|bound| value <= *bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        let mut labels = labels
+            .iter()
+            .map(|kv| (kv.key.as_str().to_string(), kv.value.as_str().into_owned()))
+            .collect::<Vec<_>>();
+        labels.sort();
+        let exemplar = Exemplar {
+            trace_id: format!("{:032x}", span_context.trace_id().to_u128()),
+            value,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_secs_f64())
+                .unwrap_or_default(),
+        };
+        EXEMPLARS
+            .lock()
+            .unwrap()
+            .insert((self.name.clone(), labels, bucket), exemplar);
+    }
+}
 
 pub struct Metrics {
-    pub query_counter: BoundCounter<'static, u64>,
-    pub query_histogram: BoundValueRecorder<'static, f64>,
+    pub query_counter: Counter<u64>,
+    pub query_histogram: LatencyHistogram,
+    pub upstream_request_counter: Counter<u64>,
+    pub upstream_request_histogram: LatencyHistogram,
+    pub slo_violation_counter: Counter<u64>,
+    pub recovered_panic_counter: Counter<u64>,
+    pub schema_version_mismatch_counter: Counter<u64>,
+    pub subscription_over_http_counter: Counter<u64>,
+    pub validation_failure_counter: Counter<u64>,
+    pub budget_abort_counter: Counter<u64>,
+    pub mirrored_request_counter: Counter<u64>,
+    pub differential_comparison_counter: Counter<u64>,
+    _sli_observers: [ValueObserver<f64>; 3],
+    _upstream_in_flight_observer: ValueObserver<u64>,
+}
+
+impl Metrics {
+    pub fn labels(tenant: Option<&str>) -> Vec<KeyValue> {
+        tenant
+            .map(|tenant| KEY_TENANT.string(tenant.to_string()))
+            .into_iter()
+            .collect()
+    }
+
+    pub fn service_labels(service: &str) -> Vec<KeyValue> {
+        vec![KEY_SERVICE.string(service.to_string())]
+    }
+
+    pub fn upstream_labels(service: &str, http_version: String) -> Vec<KeyValue> {
+        vec![
+            KEY_SERVICE.string(service.to_string()),
+            KEY_HTTP_VERSION.string(http_version),
+        ]
+    }
+}
+
+fn counter(meter: &Meter, definition: &MetricDefinition) -> Counter<u64> {
+    meter
+        .u64_counter(definition.name)
+        .with_description(definition.description)
+        .init()
 }
 
 pub static METRICS: Lazy<Metrics> = Lazy::new(|| {
     let meter = global::meter("graphgate");
-    let query_counter = meter
-        .u64_counter("graphgate.queries_total")
-        .with_description("Total number of GraphQL queries executed")
-        .init()
-        .bind(&[]);
-    let query_histogram = meter
-        .f64_value_recorder("graphgate.graphql_query_duration_seconds")
-        .with_description("The GraphQL query latencies in seconds.")
-        .init()
-        .bind(&[]);
+    let sli_success_ratio = meter
+        .f64_value_observer(SLI_SUCCESS_RATIO.name, slo::observe_success_ratio)
+        .with_description(SLI_SUCCESS_RATIO.description)
+        .init();
+    let sli_latency = meter
+        .f64_value_observer(SLI_LATENCY.name, slo::observe_latency)
+        .with_description(SLI_LATENCY.description)
+        .init();
+    let sli_apdex = meter
+        .f64_value_observer(SLI_APDEX.name, slo::observe_apdex)
+        .with_description(SLI_APDEX.description)
+        .init();
+    let upstream_in_flight = meter
+        .u64_value_observer(
+            UPSTREAM_IN_FLIGHT_REQUESTS.name,
+            upstream_pool::observe_in_flight,
+        )
+        .with_description(UPSTREAM_IN_FLIGHT_REQUESTS.description)
+        .init();
     Metrics {
-        query_counter,
-        query_histogram,
+        query_counter: counter(&meter, &QUERIES),
+        query_histogram: LatencyHistogram::new(&meter, &QUERY_DURATION),
+        upstream_request_counter: counter(&meter, &UPSTREAM_REQUESTS),
+        upstream_request_histogram: LatencyHistogram::new(&meter, &UPSTREAM_REQUEST_DURATION),
+        slo_violation_counter: counter(&meter, &SLO_VIOLATIONS),
+        recovered_panic_counter: counter(&meter, &RECOVERED_PANICS),
+        schema_version_mismatch_counter: counter(&meter, &SCHEMA_VERSION_MISMATCHES),
+        subscription_over_http_counter: counter(&meter, &SUBSCRIPTIONS_OVER_HTTP),
+        validation_failure_counter: counter(&meter, &VALIDATION_FAILURES),
+        budget_abort_counter: counter(&meter, &BUDGET_ABORTS),
+        mirrored_request_counter: counter(&meter, &MIRRORED_REQUESTS),
+        differential_comparison_counter: counter(&meter, &DIFFERENTIAL_COMPARISONS),
+        _sli_observers: [sli_success_ratio, sli_latency, sli_apdex],
+        _upstream_in_flight_observer: upstream_in_flight,
     }
 });
+
+/// Counts an operation that failed before its execution, the budget aborts
+/// separately from the validation failures.
+pub(crate) fn count_plan_failure(response: &Response) {
+    let error = match response.errors.first() {
+        Some(error) => error,
+        None => return,
+    };
+    match error.code() {
+        Some(code) if code == ErrorCode::BudgetExceeded.as_str() => {
+            let phase = match error.extensions.get("phase") {
+                Some(ConstValue::String(phase)) => phase.clone(),
+                _ => String::new(),
+            };
+            METRICS
+                .budget_abort_counter
+                .add(1, &[KEY_PHASE.string(phase)]);
+        }
+        Some(code) if code == ErrorCode::ValidationFailed.as_str() => {
+            METRICS.validation_failure_counter.add(1, &[]);
+        }
+        _ => {}
+    }
+}
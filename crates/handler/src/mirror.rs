@@ -0,0 +1,188 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use anyhow::Result;
+use graphgate_planner::{Request, Response};
+use http::HeaderMap;
+use opentelemetry::Key;
+
+use crate::fetcher::Fetcher;
+use crate::metrics::METRICS;
+use crate::service_route::HttpVersion;
+use crate::upstream_tls;
+
+const KEY_TARGET: Key = Key::from_static_str("target");
+const KEY_OUTCOME: Key = Key::from_static_str("outcome");
+
+/// Target of the requests mirrored to the shadow gateway, in the metrics.
+const GATEWAY_TARGET: &str = "gateway";
+
+/// Mirroring of a sample of the queries to a shadow graph, to validate
+/// changes of the planner or the services against real traffic.
+///
+/// The mirrored requests are sent in the background and their responses are
+/// ignored. The mutations and subscriptions are never mirrored.
+#[derive(Debug, Clone)]
+pub struct MirrorOptions {
+    /// URL of a shadow gateway receiving the client requests.
+    pub url: Option<String>,
+
+    /// URLs of shadow services by name, receiving the requests sent to the
+    /// services.
+    pub services: HashMap<String, String>,
+
+    /// Percentage of the queries mirrored, from 0 to 100.
+    pub percentage: f64,
+
+    /// Headers of the client request sent to the shadow graph, the other
+    /// headers are removed.
+    pub forward_headers: Vec<String>,
+
+    pub timeout: Duration,
+}
+
+impl Default for MirrorOptions {
+    fn default() -> Self {
+        Self {
+            url: None,
+            services: HashMap::new(),
+            percentage: 0.0,
+            forward_headers: Vec::new(),
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+impl MirrorOptions {
+    /// Returns `true` if a query is mirrored.
+    pub(crate) fn sample(&self) -> bool {
+        fastrand::f64() * 100.0 < self.percentage
+    }
+
+    /// Returns the headers of the client request sent to the shadow graph.
+    fn scrub_headers(&self, header_map: &HeaderMap) -> HeaderMap {
+        header_map
+            .iter()
+            .filter(|(name, _)| {
+                self.forward_headers
+                    .iter()
+                    .any(|forward| name.as_str().eq_ignore_ascii_case(forward))
+            })
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect()
+    }
+
+    /// Sends the client request to the shadow gateway, if any.
+    pub(crate) fn mirror_request(&self, request: &Request, header_map: &HeaderMap) {
+        if let Some(url) = &self.url {
+            tokio::spawn(send(
+                GATEWAY_TARGET.to_string(),
+                url.clone(),
+                request.clone(),
+                self.scrub_headers(header_map),
+                self.timeout,
+            ));
+        }
+    }
+}
+
+async fn send(
+    target: String,
+    url: String,
+    request: Request,
+    header_map: HeaderMap,
+    timeout: Duration,
+) {
+    let res = upstream_tls::http_client(HttpVersion::Auto)
+        .post(&url)
+        .headers(header_map)
+        .json(&request)
+        .timeout(timeout)
+        .send()
+        .await
+        .and_then(|resp| resp.error_for_status());
+    let outcome = match res {
+        Ok(_) => "success",
+        Err(err) => {
+            tracing::debug!(mirror = %target, error = %err, "Failed to mirror a request.");
+            "error"
+        }
+    };
+    METRICS
+        .mirrored_request_counter
+        .add(1, &[KEY_TARGET.string(target), KEY_OUTCOME.string(outcome)]);
+}
+
+/// Mirrors the requests sent to the services that have a shadow service, for
+/// the sampled queries.
+pub struct MirrorFetcher<'a, F> {
+    inner: F,
+    options: Option<&'a MirrorOptions>,
+    header_map: HeaderMap,
+}
+
+impl<'a, F: Fetcher> MirrorFetcher<'a, F> {
+    /// The options are `None` if the query is not mirrored.
+    pub fn new(inner: F, options: Option<&'a MirrorOptions>, header_map: &HeaderMap) -> Self {
+        Self {
+            inner,
+            options,
+            header_map: options
+                .map(|options| options.scrub_headers(header_map))
+                .unwrap_or_default(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<'a, F: Fetcher> Fetcher for MirrorFetcher<'a, F> {
+    async fn query(&self, service: &str, request: Request) -> Result<Response> {
+        if let Some(options) = self.options {
+            if let Some(url) = options.services.get(service) {
+                tokio::spawn(send(
+                    service.to_string(),
+                    url.clone(),
+                    request.clone(),
+                    self.header_map.clone(),
+                    options.timeout,
+                ));
+            }
+        }
+        self.inner.query(service, request).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::HeaderValue;
+
+    use super::*;
+
+    #[test]
+    fn scrub_headers() {
+        let options = MirrorOptions {
+            forward_headers: vec!["X-Tenant".to_string()],
+            ..Default::default()
+        };
+        let mut header_map = HeaderMap::new();
+        header_map.insert("authorization", HeaderValue::from_static("Bearer secret"));
+        header_map.insert("cookie", HeaderValue::from_static("session=secret"));
+        header_map.insert("x-tenant", HeaderValue::from_static("acme"));
+
+        let scrubbed = options.scrub_headers(&header_map);
+        assert_eq!(scrubbed.len(), 1);
+        assert_eq!(scrubbed["x-tenant"], "acme");
+    }
+
+    #[test]
+    fn sample() {
+        let options = MirrorOptions::default();
+        assert!((0..100).all(|_| !options.sample()));
+
+        let options = MirrorOptions {
+            percentage: 100.0,
+            ..Default::default()
+        };
+        assert!((0..100).all(|_| options.sample()));
+    }
+}
@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+use std::fmt::{self, Debug, Formatter};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use http::header::AUTHORIZATION;
+use http::{HeaderMap, HeaderValue};
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use tokio::time::Instant;
+
+static HTTP_CLIENT: Lazy<reqwest::Client> = Lazy::new(Default::default);
+
+/// Tokens of the client credentials, shared by the routing tables so that they
+/// are kept when the routing table changes.
+static TOKENS: Lazy<Mutex<HashMap<OAuth2Options, Arc<tokio::sync::Mutex<Option<Token>>>>>> =
+    Lazy::new(Default::default);
+
+/// A token is refreshed when it expires in less than this duration.
+const REFRESH_BEFORE_EXPIRY: Duration = Duration::from_secs(30);
+
+/// Lifetime of the tokens returned without `expires_in`.
+const DEFAULT_LIFETIME: Duration = Duration::from_secs(300);
+
+/// OAuth2 client credentials used to authenticate the requests sent to a
+/// service with a `Bearer` token.
+///
+/// The token is fetched from the token endpoint with the `client_credentials`
+/// grant, cached until shortly before it expires, and fetched again when the
+/// service answers `401 Unauthorized`.
+#[derive(Clone, Eq, PartialEq, Hash)]
+pub struct OAuth2Options {
+    pub token_url: String,
+    pub client_id: String,
+    pub client_secret: String,
+    pub scopes: Vec<String>,
+}
+
+impl Debug for OAuth2Options {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OAuth2Options")
+            .field("token_url", &self.token_url)
+            .field("client_id", &self.client_id)
+            .field("client_secret", &"<redacted>")
+            .field("scopes", &self.scopes)
+            .finish()
+    }
+}
+
+struct Token {
+    access_token: String,
+    expires_at: Instant,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: Option<u64>,
+}
+
+impl OAuth2Options {
+    fn token_slot(&self) -> Arc<tokio::sync::Mutex<Option<Token>>> {
+        TOKENS
+            .lock()
+            .unwrap()
+            .entry(self.clone())
+            .or_default()
+            .clone()
+    }
+
+    /// Returns the access token, fetching a new one if there is none or it is
+    /// about to expire.
+    ///
+    /// The concurrent requests wait for the same fetch.
+    pub(crate) async fn access_token(&self) -> Result<String> {
+        let slot = self.token_slot();
+        let mut token = slot.lock().await;
+        if let Some(token) = &*token {
+            if token.expires_at > Instant::now() + REFRESH_BEFORE_EXPIRY {
+                return Ok(token.access_token.clone());
+            }
+        }
+
+        let new_token = self
+            .fetch_token()
+            .await
+            .with_context(|| format!("Failed to fetch OAuth2 token from '{}'.", self.token_url))?;
+        let access_token = new_token.access_token.clone();
+        *token = Some(new_token);
+        Ok(access_token)
+    }
+
+    /// Adds the `Authorization` header with the access token to a header map.
+    pub(crate) async fn authorize(&self, header_map: &mut HeaderMap) -> Result<()> {
+        let value = HeaderValue::from_str(&format!("Bearer {}", self.access_token().await?))
+            .context("Invalid OAuth2 access token.")?;
+        header_map.insert(AUTHORIZATION, value);
+        Ok(())
+    }
+
+    /// Drops the cached token, such as after it is rejected by the service.
+    pub(crate) async fn invalidate(&self) {
+        *self.token_slot().lock().await = None;
+    }
+
+    async fn fetch_token(&self) -> Result<Token> {
+        let mut form = vec![("grant_type", "client_credentials".to_string())];
+        if !self.scopes.is_empty() {
+            form.push(("scope", self.scopes.join(" ")));
+        }
+        let resp: TokenResponse = HTTP_CLIENT
+            .post(&self.token_url)
+            .basic_auth(&self.client_id, Some(&self.client_secret))
+            .form(&form)
+            .timeout(Duration::from_secs(10))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        tracing::debug!(token_url = %self.token_url, "OAuth2 token fetched.");
+        Ok(Token {
+            access_token: resp.access_token,
+            expires_at: Instant::now()
+                + resp
+                    .expires_in
+                    .map(Duration::from_secs)
+                    .unwrap_or(DEFAULT_LIFETIME),
+        })
+    }
+}
@@ -0,0 +1,69 @@
+use std::time::Duration;
+
+use graphgate_planner::PlanLimits;
+use parser::types::{DocumentOperations, ExecutableDocument};
+
+/// Settings of the operations with a specific name, replacing the settings of
+/// the gateway.
+#[derive(Debug, Clone, Default)]
+pub struct OperationPolicy {
+    /// Name of the operation.
+    pub name: String,
+
+    /// Maximum execution time, the operation fails with a `TIMEOUT` error
+    /// when it is exceeded.
+    pub timeout: Option<Duration>,
+
+    /// Plan limits used instead of the plan limits of the gateway.
+    pub plan_limits: Option<PlanLimits>,
+
+    /// `max-age` of the `Cache-Control` header of the responses without errors,
+    /// replacing the header returned by the services.
+    pub cache_max_age: Option<Duration>,
+}
+
+impl OperationPolicy {
+    pub(crate) fn cache_control(&self) -> Option<String> {
+        self.cache_max_age
+            .map(|max_age| format!("max-age={}", max_age.as_secs()))
+    }
+}
+
+/// Returns the name of the executed operation, the name of the only operation
+/// of the document if the request does not specify it.
+pub(crate) fn operation_name<'a>(
+    document: &'a ExecutableDocument,
+    operation_name: Option<&'a str>,
+) -> Option<&'a str> {
+    match (&document.operations, operation_name) {
+        (_, Some(operation_name)) => Some(operation_name),
+        (DocumentOperations::Multiple(operations), None) if operations.len() == 1 => {
+            operations.keys().next().map(|name| name.as_str())
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn name() {
+        let document = parser::parse_query("query TopProducts { a }").unwrap();
+        assert_eq!(operation_name(&document, None), Some("TopProducts"));
+        assert_eq!(operation_name(&document, Some("Other")), Some("Other"));
+
+        let document = parser::parse_query("{ a }").unwrap();
+        assert_eq!(operation_name(&document, None), None);
+
+        let document = parser::parse_query("query A { a } query B { b }").unwrap();
+        assert_eq!(operation_name(&document, None), None);
+
+        let policy = OperationPolicy {
+            cache_max_age: Some(Duration::from_secs(60)),
+            ..Default::default()
+        };
+        assert_eq!(policy.cache_control().as_deref(), Some("max-age=60"));
+    }
+}
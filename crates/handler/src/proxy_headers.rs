@@ -0,0 +1,109 @@
+use std::net::SocketAddr;
+
+use http::header::{
+    HeaderName, CONNECTION, HOST, PROXY_AUTHENTICATE, PROXY_AUTHORIZATION, TE, TRAILER,
+    TRANSFER_ENCODING, UPGRADE, USER_AGENT,
+};
+use http::{HeaderMap, HeaderValue};
+
+/// User agent of the requests sent to the services.
+pub const DEFAULT_USER_AGENT: &str = concat!("graphgate/", env!("CARGO_PKG_VERSION"));
+
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+const X_FORWARDED_HOST: &str = "x-forwarded-host";
+
+/// Options of the headers sent to the services.
+#[derive(Debug, Clone)]
+pub struct ProxyHeaderOptions {
+    /// User agent of the requests, it is not overwritten if the `User-Agent`
+    /// header of the client is forwarded.
+    pub user_agent: String,
+
+    /// Remove the hop-by-hop headers from the forwarded headers.
+    pub strip_hop_by_hop: bool,
+
+    /// Append the address of the client to the `X-Forwarded-For` header.
+    pub x_forwarded_for: bool,
+
+    /// Set the `X-Forwarded-Host` header to the `Host` header of the client.
+    pub x_forwarded_host: bool,
+}
+
+impl Default for ProxyHeaderOptions {
+    fn default() -> Self {
+        Self {
+            user_agent: DEFAULT_USER_AGENT.to_string(),
+            strip_hop_by_hop: true,
+            x_forwarded_for: false,
+            x_forwarded_host: false,
+        }
+    }
+}
+
+impl ProxyHeaderOptions {
+    /// Applies the options to the headers forwarded to the services.
+    pub fn apply(
+        &self,
+        header_map: &HeaderMap,
+        remote_addr: Option<SocketAddr>,
+        forward_headers: &mut HeaderMap,
+    ) {
+        if self.strip_hop_by_hop {
+            strip_hop_by_hop_headers(forward_headers);
+        }
+
+        if self.x_forwarded_for {
+            if let Some(remote_addr) = remote_addr {
+                let value = match header_map
+                    .get(X_FORWARDED_FOR)
+                    .and_then(|value| value.to_str().ok())
+                {
+                    Some(value) => format!("{}, {}", value, remote_addr.ip()),
+                    None => remote_addr.ip().to_string(),
+                };
+                if let Ok(value) = HeaderValue::from_str(&value) {
+                    forward_headers.insert(HeaderName::from_static(X_FORWARDED_FOR), value);
+                }
+            }
+        }
+
+        if self.x_forwarded_host {
+            if let Some(host) = header_map.get(HOST) {
+                forward_headers.insert(HeaderName::from_static(X_FORWARDED_HOST), host.clone());
+            }
+        }
+
+        if !forward_headers.contains_key(USER_AGENT) {
+            if let Ok(value) = HeaderValue::from_str(&self.user_agent) {
+                forward_headers.insert(USER_AGENT, value);
+            }
+        }
+    }
+}
+
+/// Removes the hop-by-hop headers, including the headers listed in `Connection`.
+fn strip_hop_by_hop_headers(header_map: &mut HeaderMap) {
+    let listed = header_map
+        .get_all(CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|name| HeaderName::from_bytes(name.trim().as_bytes()).ok())
+        .collect::<Vec<_>>();
+    for name in listed {
+        header_map.remove(name);
+    }
+
+    for name in &[
+        CONNECTION,
+        PROXY_AUTHENTICATE,
+        PROXY_AUTHORIZATION,
+        TE,
+        TRAILER,
+        TRANSFER_ENCODING,
+        UPGRADE,
+    ] {
+        header_map.remove(name);
+    }
+    header_map.remove("keep-alive");
+}
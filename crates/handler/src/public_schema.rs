@@ -0,0 +1,127 @@
+use std::collections::HashSet;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+
+use graphgate_schema::ComposedSchema;
+
+use crate::{Claims, IpNetwork};
+
+/// Options of the public schema, which is used to validate and introspect the
+/// operations of the unauthenticated callers.
+///
+/// A caller is authenticated if its token was verified by the
+/// [`Authenticator`](crate::Authenticator), so the full schema is only
+/// available to the callers of the internal networks if the authentication is
+/// not configured.
+#[derive(Debug, Clone, Default)]
+pub struct PublicSchemaOptions {
+    /// Types removed from the public schema.
+    pub hidden_types: HashSet<String>,
+
+    /// Fields removed from the public schema, in the form `Type.field`.
+    pub hidden_fields: HashSet<String>,
+
+    /// The callers in these networks see the full schema.
+    pub internal_networks: Vec<IpNetwork>,
+}
+
+impl PublicSchemaOptions {
+    pub fn is_authenticated(&self, claims: Option<&Claims>, client_ip: Option<IpAddr>) -> bool {
+        claims.is_some()
+            || client_ip.map_or(false, |ip| {
+                self.internal_networks
+                    .iter()
+                    .any(|network| network.contains(&ip))
+            })
+    }
+}
+
+/// The public schema, filtered again whenever the composed schema changes.
+#[derive(Default)]
+pub(crate) struct PublicSchema {
+    options: PublicSchemaOptions,
+    cache: Mutex<Option<(Arc<ComposedSchema>, Arc<ComposedSchema>)>>,
+}
+
+impl PublicSchema {
+    pub(crate) fn new(options: PublicSchemaOptions) -> Self {
+        Self {
+            options,
+            cache: Default::default(),
+        }
+    }
+
+    pub(crate) fn options(&self) -> &PublicSchemaOptions {
+        &self.options
+    }
+
+    pub(crate) fn get(&self, schema: &Arc<ComposedSchema>) -> Arc<ComposedSchema> {
+        let mut cache = self.cache.lock().unwrap();
+        match &*cache {
+            Some((full, public)) if Arc::ptr_eq(full, schema) => public.clone(),
+            _ => {
+                let public = Arc::new(
+                    schema.without(&self.options.hidden_types, &self.options.hidden_fields),
+                );
+                *cache = Some((schema.clone(), public.clone()));
+                public
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filter() {
+        let schema = Arc::new(
+            ComposedSchema::parse(
+                r#"
+                type Query {
+                    me: User @resolve(service: "accounts")
+                    audit: AuditLog @resolve(service: "audit")
+                }
+
+                type User @owner(service: "accounts") {
+                    id: ID!
+                    email: String!
+                }
+
+                type AuditLog @owner(service: "audit") {
+                    id: ID!
+                }
+                "#,
+            )
+            .unwrap(),
+        );
+        let public_schema = PublicSchema::new(PublicSchemaOptions {
+            hidden_types: vec!["AuditLog".to_string()].into_iter().collect(),
+            hidden_fields: vec!["User.email".to_string()].into_iter().collect(),
+            ..Default::default()
+        });
+
+        let public = public_schema.get(&schema);
+        assert!(public.get_type_by_name("AuditLog").is_none());
+        assert!(public.types["Query"].fields.get("audit").is_none());
+        assert!(public.types["Query"].fields.get("me").is_some());
+        assert!(public.types["User"].fields.get("email").is_none());
+        assert!(schema.types["User"].fields.get("email").is_some());
+        assert!(Arc::ptr_eq(&public, &public_schema.get(&schema)));
+    }
+
+    #[test]
+    fn authenticated() {
+        let options = PublicSchemaOptions {
+            internal_networks: vec!["10.0.0.0/8".parse().unwrap()],
+            ..Default::default()
+        };
+        let internal = "10.1.2.3".parse().ok();
+        let external = "1.2.3.4".parse().ok();
+        assert!(options.is_authenticated(Some(&Claims::new()), external));
+        assert!(options.is_authenticated(None, internal));
+        assert!(!options.is_authenticated(None, external));
+        assert!(!options.is_authenticated(None, None));
+    }
+}
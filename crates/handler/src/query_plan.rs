@@ -0,0 +1,46 @@
+use graphgate_planner::{GraphFormat, Request};
+use value::ConstValue;
+
+/// Request extension that asks the gateway for the graph of the query plan,
+/// in the `dot` or `mermaid` format.
+///
+/// The graph is returned in the response extension of the same name, if the
+/// extension is enabled and the caller uses the full schema.
+pub const QUERY_PLAN_EXTENSION: &str = "queryPlan";
+
+/// Returns the format of the query plan graph asked by the client, if any.
+pub fn query_plan_requested(request: &Request) -> Option<GraphFormat> {
+    match request.extensions.get(QUERY_PLAN_EXTENSION) {
+        Some(ConstValue::String(format)) => format.parse().ok(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requested() {
+        let request = |format: ConstValue| {
+            let mut request = Request::new("{ me { id } }");
+            request
+                .extensions
+                .insert(QUERY_PLAN_EXTENSION.to_string(), format);
+            request
+        };
+        assert_eq!(
+            query_plan_requested(&request(ConstValue::String("dot".to_string()))),
+            Some(GraphFormat::Dot)
+        );
+        assert_eq!(
+            query_plan_requested(&request(ConstValue::String("svg".to_string()))),
+            None
+        );
+        assert_eq!(
+            query_plan_requested(&request(ConstValue::Boolean(true))),
+            None
+        );
+        assert_eq!(query_plan_requested(&Request::new("{ me { id } }")), None);
+    }
+}
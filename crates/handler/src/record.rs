@@ -0,0 +1,200 @@
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use graphgate_planner::{PlanBuilder, Request, Response, ServerError};
+use graphgate_schema::ComposedSchema;
+use serde::{Deserialize, Serialize};
+use value::{ConstValue, Variables};
+
+use crate::executor::Executor;
+use crate::fetcher::Fetcher;
+
+/// Header that asks the gateway to record the requests sent to the services.
+pub const RECORD_HEADER: &str = "x-graphgate-record";
+
+const REDACTED: &str = "[REDACTED]";
+
+/// Options for recording the requests sent to the services.
+#[derive(Debug, Clone)]
+pub struct RecordOptions {
+    /// Directory of the recording files.
+    pub dir: PathBuf,
+
+    /// Names of the variables whose values are replaced before writing a recording.
+    pub redact_variables: Vec<String>,
+}
+
+/// A request sent to a service and its response.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RecordedFetch {
+    pub service: String,
+    pub request: Request,
+    pub response: Result<Response, String>,
+}
+
+/// The services traffic of a client request.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Recording {
+    pub request: Request,
+    pub fetches: Vec<RecordedFetch>,
+}
+
+impl Recording {
+    /// Reads a recording file.
+    pub async fn load(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let data = tokio::fs::read(&path)
+            .await
+            .with_context(|| format!("Failed to read recording '{}'.", path.display()))?;
+        serde_json::from_slice(&data)
+            .with_context(|| format!("Failed to parse recording '{}'.", path.display()))
+    }
+
+    /// Writes this recording to a new file in the directory of the options,
+    /// after redacting the variables.
+    pub async fn save(mut self, options: &RecordOptions) -> Result<PathBuf> {
+        redact_variables(&mut self.request.variables, &options.redact_variables);
+        for fetch in &mut self.fetches {
+            redact_variables(&mut fetch.request.variables, &options.redact_variables);
+        }
+
+        let path = options.dir.join(format!(
+            "{}-{}.json",
+            Utc::now().format("%Y%m%dT%H%M%S%.3fZ"),
+            self.request.operation.as_deref().unwrap_or("anonymous")
+        ));
+        tokio::fs::create_dir_all(&options.dir).await?;
+        tokio::fs::write(&path, serde_json::to_vec_pretty(&self)?).await?;
+        Ok(path)
+    }
+}
+
+fn redact_variables(variables: &mut Variables, names: &[String]) {
+    for name in names {
+        if let Some(value) = variables.get_mut(name.as_str()) {
+            *value = ConstValue::String(REDACTED.to_string());
+        }
+    }
+}
+
+/// A fetcher that keeps a copy of the requests sent by another fetcher and of
+/// their responses, so that the client request can be replayed offline
+/// against a new schema without the services.
+///
+/// Unlike the `AuditFetcher`, which only keeps the timing, size and errors of
+/// each request, the whole requests and responses are cloned, so it is only
+/// enabled for the requests asking to be recorded.
+pub struct RecordingFetcher<F> {
+    inner: F,
+    fetches: Option<Mutex<Vec<RecordedFetch>>>,
+}
+
+impl<F: Fetcher> RecordingFetcher<F> {
+    pub fn new(inner: F, enabled: bool) -> Self {
+        Self {
+            inner,
+            fetches: if enabled {
+                Some(Default::default())
+            } else {
+                None
+            },
+        }
+    }
+
+    /// Takes the recorded requests, or returns `None` if recording is disabled.
+    pub fn take_recording(&self, request: Request) -> Option<Recording> {
+        let fetches = self.fetches.as_ref()?;
+        Some(Recording {
+            request,
+            fetches: std::mem::take(&mut *fetches.lock().unwrap()),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl<F: Fetcher> Fetcher for RecordingFetcher<F> {
+    async fn query(&self, service: &str, request: Request) -> Result<Response> {
+        let fetches = match &self.fetches {
+            Some(fetches) => fetches,
+            None => return self.inner.query(service, request).await,
+        };
+
+        let recorded_request = request.clone();
+        let res = self.inner.query(service, request).await;
+        fetches.lock().unwrap().push(RecordedFetch {
+            service: service.to_string(),
+            request: recorded_request,
+            response: match &res {
+                Ok(resp) => Ok(resp.clone()),
+                Err(err) => Err(err.to_string()),
+            },
+        });
+        res
+    }
+}
+
+/// A fetcher that serves the responses of a recording.
+///
+/// Every request is answered by the first unused recorded fetch with the same
+/// service and query.
+pub struct ReplayFetcher {
+    fetches: Mutex<Vec<Option<RecordedFetch>>>,
+}
+
+impl ReplayFetcher {
+    pub fn new(fetches: Vec<RecordedFetch>) -> Self {
+        Self {
+            fetches: Mutex::new(fetches.into_iter().map(Some).collect()),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Fetcher for ReplayFetcher {
+    async fn query(&self, service: &str, request: Request) -> Result<Response> {
+        let fetch = self
+            .fetches
+            .lock()
+            .unwrap()
+            .iter_mut()
+            .find(|fetch| {
+                fetch.as_ref().map_or(false, |fetch| {
+                    fetch.service == service && fetch.request.query == request.query
+                })
+            })
+            .and_then(Option::take)
+            .with_context(|| format!("No recorded response of service '{}'.", service))?;
+        fetch.response.map_err(anyhow::Error::msg)
+    }
+}
+
+/// Plans and executes the client request of a recording, the responses of
+/// the services are served from the recording.
+pub async fn replay(schema: &ComposedSchema, recording: Recording) -> Response {
+    let document = match parser::parse_query(&recording.request.query) {
+        Ok(document) => document,
+        Err(err) => {
+            return Response {
+                data: ConstValue::Null,
+                errors: vec![ServerError::new(err.to_string())],
+                extensions: Default::default(),
+                headers: Default::default(),
+                body_sizes: None,
+            }
+        }
+    };
+    let mut plan_builder =
+        PlanBuilder::new(schema, document).variables(recording.request.variables);
+    if let Some(operation) = recording.request.operation {
+        plan_builder = plan_builder.operation_name(operation);
+    }
+    let plan = match plan_builder.plan() {
+        Ok(plan) => plan,
+        Err(response) => return response,
+    };
+
+    let fetcher = ReplayFetcher::new(recording.fetches);
+    Executor::new(schema).execute_query(&fetcher, &plan).await
+}
@@ -0,0 +1,27 @@
+use std::any::Any;
+
+use graphgate_planner::{ErrorCode, Response, ServerError};
+use value::ConstValue;
+
+use crate::callback::random_token;
+use crate::metrics::METRICS;
+
+/// Returns the response for a panic caught while executing a request.
+///
+/// The panic message is only logged, the client receives a reference id
+/// that can be used to find it in the logs.
+pub(crate) fn panic_response(panic: Box<dyn Any + Send>) -> Response {
+    let reference = random_token();
+    let message = panic
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown panic");
+    tracing::error!(reference = %reference, panic = %message, "Recovered from a panic.");
+    METRICS.recovered_panic_counter.add(1, &[]);
+
+    Response::from_errors(vec![ServerError::new("Internal server error.")
+        .with_code(ErrorCode::InternalServerError)
+        .with_http_status(500)
+        .with_extension("reference", ConstValue::String(reference))])
+}
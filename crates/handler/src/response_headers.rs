@@ -0,0 +1,108 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+/// How the values of a header returned by several services are merged.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HeaderMergePolicy {
+    /// The values of all services.
+    Append,
+    /// The values of the first service in the order of the plan.
+    First,
+    /// The values of the last service in the order of the plan.
+    Last,
+}
+
+impl Default for HeaderMergePolicy {
+    fn default() -> Self {
+        HeaderMergePolicy::Append
+    }
+}
+
+/// Merge policies of the headers returned by the services.
+///
+/// The headers are merged in the order of the fetches in the query plan, not
+/// the order in which they complete, so the response headers don't depend on
+/// the latency of the services.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ResponseHeaderOptions {
+    /// Policy of the headers not listed in `headers`.
+    #[serde(default)]
+    pub default: HeaderMergePolicy,
+
+    /// Policies of specific headers, the names are case-insensitive.
+    #[serde(default)]
+    pub headers: HashMap<String, HeaderMergePolicy>,
+}
+
+impl ResponseHeaderOptions {
+    fn policy(&self, name: &str) -> HeaderMergePolicy {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, policy)| *policy)
+            .unwrap_or(self.default)
+    }
+
+    /// Merges the headers of the fetches, in the order of the plan.
+    pub(crate) fn merge(
+        &self,
+        fetch_headers: Vec<HashMap<String, Vec<String>>>,
+    ) -> HashMap<String, Vec<String>> {
+        let mut headers: HashMap<String, Vec<String>> = HashMap::new();
+        for (name, values) in fetch_headers.into_iter().flatten() {
+            match headers.get_mut(&name) {
+                Some(current) => match self.policy(&name) {
+                    HeaderMergePolicy::Append => current.extend(values),
+                    HeaderMergePolicy::First => {}
+                    HeaderMergePolicy::Last => *current = values,
+                },
+                None => {
+                    headers.insert(name, values);
+                }
+            }
+        }
+        headers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(values: &[(&str, &str)]) -> HashMap<String, Vec<String>> {
+        let mut headers: HashMap<String, Vec<String>> = HashMap::new();
+        for (name, value) in values {
+            headers
+                .entry(name.to_string())
+                .or_default()
+                .push(value.to_string());
+        }
+        headers
+    }
+
+    #[test]
+    fn merge() {
+        let mut options = ResponseHeaderOptions::default();
+        options
+            .headers
+            .insert("Cache-Control".to_string(), HeaderMergePolicy::First);
+        options
+            .headers
+            .insert("x-version".to_string(), HeaderMergePolicy::Last);
+
+        let merged = options.merge(vec![
+            headers(&[("set-cookie", "a=1"), ("cache-control", "no-cache")]),
+            headers(&[("x-version", "1")]),
+            headers(&[
+                ("set-cookie", "b=2"),
+                ("cache-control", "max-age=60"),
+                ("x-version", "2"),
+            ]),
+        ]);
+        assert_eq!(merged["set-cookie"], vec!["a=1", "b=2"]);
+        assert_eq!(merged["cache-control"], vec!["no-cache"]);
+        assert_eq!(merged["x-version"], vec!["2"]);
+    }
+}
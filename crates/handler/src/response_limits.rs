@@ -0,0 +1,150 @@
+use anyhow::Result;
+use graphgate_planner::Response;
+use http::header::CONTENT_TYPE;
+use serde::{Deserialize, Serialize};
+
+use crate::body_encoding::BodyEncoding;
+
+/// Limits of the responses returned by a service.
+///
+/// A response exceeding any of the limits is rejected before it is
+/// deserialized, and the fetch fails with a `FETCH_ERROR`. The depth and
+/// string length limits only apply to the JSON responses.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ResponseLimits {
+    /// Maximum size in bytes of the decompressed response body.
+    pub max_size: Option<usize>,
+
+    /// Maximum nesting depth of the JSON objects and arrays.
+    pub max_depth: Option<usize>,
+
+    /// Maximum length in bytes of a JSON string.
+    pub max_string_length: Option<usize>,
+}
+
+impl ResponseLimits {
+    fn is_empty(&self) -> bool {
+        self.max_size.is_none() && self.max_depth.is_none() && self.max_string_length.is_none()
+    }
+}
+
+/// Reads and deserializes the response of a service, in the encoding of its
+/// `Content-Type` header, enforcing the limits. Returns the response and the
+/// size in bytes of its body.
+pub(crate) async fn read_response(
+    service: &str,
+    mut raw_resp: reqwest::Response,
+    limits: &ResponseLimits,
+) -> Result<(Response, usize)> {
+    let encoding = BodyEncoding::from_content_type(raw_resp.headers().get(CONTENT_TYPE));
+    if encoding == BodyEncoding::Json && limits.is_empty() {
+        let body = raw_resp.bytes().await?;
+        return Ok((serde_json::from_slice(&body)?, body.len()));
+    }
+
+    let mut body = Vec::new();
+    if let Some(max_size) = limits.max_size {
+        if raw_resp.content_length().unwrap_or_default() > max_size as u64 {
+            anyhow::bail!(
+                "Response of service '{}' exceeds the maximum size of {} bytes.",
+                service,
+                max_size
+            );
+        }
+    }
+    while let Some(chunk) = raw_resp.chunk().await? {
+        if let Some(max_size) = limits.max_size {
+            if body.len() + chunk.len() > max_size {
+                anyhow::bail!(
+                    "Response of service '{}' exceeds the maximum size of {} bytes.",
+                    service,
+                    max_size
+                );
+            }
+        }
+        body.extend_from_slice(&chunk);
+    }
+
+    if encoding == BodyEncoding::Json {
+        check_json(&body, limits)
+            .map_err(|err| anyhow::anyhow!("Response of service '{}' {}.", service, err))?;
+    }
+    Ok((encoding.decode(&body)?, body.len()))
+}
+
+/// Checks the nesting depth and the string lengths of a JSON document without
+/// deserializing it.
+fn check_json(data: &[u8], limits: &ResponseLimits) -> Result<(), String> {
+    let mut depth: usize = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut string_length = 0;
+
+    for &b in data {
+        if in_string {
+            if escaped {
+                escaped = false;
+            } else if b == b'\\' {
+                escaped = true;
+            } else if b == b'"' {
+                in_string = false;
+                continue;
+            }
+            string_length += 1;
+            if let Some(max_string_length) = limits.max_string_length {
+                if string_length > max_string_length {
+                    return Err(format!(
+                        "contains a string longer than {} bytes",
+                        max_string_length
+                    ));
+                }
+            }
+            continue;
+        }
+
+        match b {
+            b'"' => {
+                in_string = true;
+                string_length = 0;
+            }
+            b'{' | b'[' => {
+                depth += 1;
+                if let Some(max_depth) = limits.max_depth {
+                    if depth > max_depth {
+                        return Err(format!("exceeds the maximum depth of {}", max_depth));
+                    }
+                }
+            }
+            b'}' | b']' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits(max_depth: Option<usize>, max_string_length: Option<usize>) -> ResponseLimits {
+        ResponseLimits {
+            max_size: None,
+            max_depth,
+            max_string_length,
+        }
+    }
+
+    #[test]
+    fn depth() {
+        let data = br#"{"data":{"a":[{"b":"[[[{{{"}]}}"#;
+        assert!(check_json(data, &limits(Some(4), None)).is_ok());
+        assert!(check_json(data, &limits(Some(3), None)).is_err());
+    }
+
+    #[test]
+    fn string_length() {
+        let data = br#"{"data":{"a":"abc\"de"}}"#;
+        assert!(check_json(data, &limits(None, Some(7))).is_ok());
+        assert!(check_json(data, &limits(None, Some(6))).is_err());
+    }
+}
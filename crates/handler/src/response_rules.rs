@@ -0,0 +1,295 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use parser::types::{ExecutableDocument, FragmentDefinition, Selection, SelectionSet};
+use parser::Positioned;
+use value::{ConstValue, Name};
+
+/// What a response rule does to the matched fields.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ResponseAction {
+    /// Replaces the non-null leaf values with a string, such as `"***"`.
+    Mask(String),
+
+    /// Removes the field from the response.
+    Drop,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum PathSegment {
+    Field(String),
+    AnyField,
+    AnyDepth,
+}
+
+/// A rule transforming the data of the responses before they are returned to
+/// the clients, such as masking the emails or removing the debug fields.
+///
+/// The path is a list of field names separated by dots, where `*` matches any
+/// field and `**` matches any number of fields, such as `users.email` or
+/// `**.debug`. The names are matched against the field names of the
+/// operation, not the aliases, and the lists are traversed transparently.
+#[derive(Debug, Clone)]
+pub struct ResponseRule {
+    path: Vec<PathSegment>,
+    pub action: ResponseAction,
+
+    /// If `true`, the rule only applies to the unauthenticated callers of the
+    /// public schema.
+    pub unauthenticated_only: bool,
+}
+
+impl ResponseRule {
+    pub fn new(path: &str, action: ResponseAction, unauthenticated_only: bool) -> Result<Self> {
+        let path = path
+            .split('.')
+            .map(|segment| match segment {
+                "" => anyhow::bail!("Path '{}' contains an empty field name.", path),
+                "*" => Ok(PathSegment::AnyField),
+                "**" => Ok(PathSegment::AnyDepth),
+                name => Ok(PathSegment::Field(name.to_string())),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        anyhow::ensure!(
+            path.last() != Some(&PathSegment::AnyDepth),
+            "Path must not end with '**'."
+        );
+        Ok(Self {
+            path,
+            action,
+            unauthenticated_only,
+        })
+    }
+
+    /// Adds the position of the path to the matching states, and the
+    /// positions following the `**` segments that can match no field.
+    fn push_state(&self, states: &mut Vec<(usize, usize)>, rule: usize, pos: usize) {
+        if states.contains(&(rule, pos)) {
+            return;
+        }
+        states.push((rule, pos));
+        if self.path.get(pos) == Some(&PathSegment::AnyDepth) {
+            self.push_state(states, rule, pos + 1);
+        }
+    }
+}
+
+/// Applies the response rules to the data of a response, in order, the first
+/// rule matching a field is used.
+pub(crate) fn apply_response_rules(
+    data: &mut ConstValue,
+    rules: &[ResponseRule],
+    authenticated: bool,
+    document: &ExecutableDocument,
+    operation_name: Option<&str>,
+) {
+    let rules = rules
+        .iter()
+        .filter(|rule| !(authenticated && rule.unauthenticated_only))
+        .collect::<Vec<_>>();
+    if rules.is_empty() {
+        return;
+    }
+    let operation = document
+        .operations
+        .iter()
+        .find(|(name, _)| operation_name.is_none() || name.map(Name::as_str) == operation_name);
+    let selection_sets = operation
+        .iter()
+        .map(|(_, operation)| &operation.node.selection_set.node)
+        .collect::<Vec<_>>();
+
+    let mut states = Vec::new();
+    for (idx, rule) in rules.iter().enumerate() {
+        rule.push_state(&mut states, idx, 0);
+    }
+    apply_rec(data, &selection_sets, &document.fragments, &rules, &states);
+}
+
+fn apply_rec(
+    value: &mut ConstValue,
+    selection_sets: &[&SelectionSet],
+    fragments: &HashMap<Name, Positioned<FragmentDefinition>>,
+    rules: &[&ResponseRule],
+    states: &[(usize, usize)],
+) {
+    fn collect<'a>(
+        selection_set: &'a SelectionSet,
+        fragments: &'a HashMap<Name, Positioned<FragmentDefinition>>,
+        selected: &mut HashMap<&'a Name, (&'a Name, Vec<&'a SelectionSet>)>,
+    ) {
+        for selection in &selection_set.items {
+            match &selection.node {
+                Selection::Field(field) => selected
+                    .entry(&field.node.response_key().node)
+                    .or_insert_with(|| (&field.node.name.node, Vec::new()))
+                    .1
+                    .push(&field.node.selection_set.node),
+                Selection::FragmentSpread(fragment_spread) => {
+                    if let Some(fragment) = fragments.get(&fragment_spread.node.fragment_name.node)
+                    {
+                        collect(&fragment.node.selection_set.node, fragments, selected);
+                    }
+                }
+                Selection::InlineFragment(inline_fragment) => collect(
+                    &inline_fragment.node.selection_set.node,
+                    fragments,
+                    selected,
+                ),
+            }
+        }
+    }
+
+    match value {
+        ConstValue::List(list) => list
+            .iter_mut()
+            .for_each(|value| apply_rec(value, selection_sets, fragments, rules, states)),
+        ConstValue::Object(object) => {
+            let mut selected = HashMap::new();
+            for selection_set in selection_sets {
+                collect(selection_set, fragments, &mut selected);
+            }
+            object.retain(|key, value| {
+                let (name, selection_sets) = match selected.get(key) {
+                    Some((name, selection_sets)) => (name.as_str(), selection_sets.as_slice()),
+                    None => (key.as_str(), &[][..]),
+                };
+
+                let mut next_states = Vec::new();
+                let mut action = None;
+                for &(idx, pos) in states {
+                    let rule = rules[idx];
+                    match &rule.path[pos] {
+                        PathSegment::AnyDepth => rule.push_state(&mut next_states, idx, pos),
+                        PathSegment::Field(field) if field != name => {}
+                        PathSegment::Field(_) | PathSegment::AnyField => {
+                            if pos + 1 < rule.path.len() {
+                                rule.push_state(&mut next_states, idx, pos + 1);
+                            } else if action.map_or(true, |(first, _)| idx < first) {
+                                action = Some((idx, &rule.action));
+                            }
+                        }
+                    }
+                }
+
+                match action {
+                    Some((_, ResponseAction::Drop)) => false,
+                    Some((_, ResponseAction::Mask(replacement))) => {
+                        mask(value, replacement);
+                        true
+                    }
+                    None => {
+                        if !next_states.is_empty() {
+                            apply_rec(value, selection_sets, fragments, rules, &next_states);
+                        }
+                        true
+                    }
+                }
+            });
+        }
+        _ => {}
+    }
+}
+
+fn mask(value: &mut ConstValue, replacement: &str) {
+    match value {
+        ConstValue::Null => {}
+        ConstValue::List(list) => list.iter_mut().for_each(|value| mask(value, replacement)),
+        ConstValue::Object(object) => object
+            .values_mut()
+            .for_each(|value| mask(value, replacement)),
+        _ => *value = ConstValue::String(replacement.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(
+        rules: &[ResponseRule],
+        authenticated: bool,
+        query: &str,
+        data: serde_json::Value,
+    ) -> serde_json::Value {
+        let document = parser::parse_query(query).unwrap();
+        let mut data = ConstValue::from_json(data).unwrap();
+        apply_response_rules(&mut data, rules, authenticated, &document, None);
+        data.into_json().unwrap()
+    }
+
+    #[test]
+    fn path() {
+        assert!(ResponseRule::new("users.email", ResponseAction::Drop, false).is_ok());
+        assert!(ResponseRule::new("users..email", ResponseAction::Drop, false).is_err());
+        assert!(ResponseRule::new("users.**", ResponseAction::Drop, false).is_err());
+    }
+
+    #[test]
+    fn lists() {
+        let rules = vec![
+            ResponseRule::new(
+                "users.email",
+                ResponseAction::Mask("***".to_string()),
+                false,
+            )
+            .unwrap(),
+            ResponseRule::new("**.debug", ResponseAction::Drop, true).unwrap(),
+        ];
+        let query = "{ users { email contact: email debug } debug }";
+        let data = serde_json::json!({
+            "users": [
+                { "email": "a@example.com", "contact": "a@example.com", "debug": "x" },
+                { "email": null, "contact": null, "debug": "y" },
+            ],
+            "debug": "z",
+        });
+
+        assert_eq!(
+            apply(&rules, false, query, data.clone()),
+            serde_json::json!({
+                "users": [
+                    { "email": "***", "contact": "***" },
+                    { "email": null, "contact": null },
+                ],
+            })
+        );
+        assert_eq!(
+            apply(&rules, true, query, data),
+            serde_json::json!({
+                "users": [
+                    { "email": "***", "contact": "***", "debug": "x" },
+                    { "email": null, "contact": null, "debug": "y" },
+                ],
+                "debug": "z",
+            })
+        );
+    }
+
+    #[test]
+    fn abstract_types() {
+        let rules = vec![ResponseRule::new("search.*.email", ResponseAction::Drop, false).unwrap()];
+        let query = r#"{
+            search {
+                ... on User { email name }
+                ... on Organization { owner { email } }
+            }
+        }"#;
+        let data = serde_json::json!({
+            "search": [
+                { "email": "a@example.com", "name": "a" },
+                { "owner": { "email": "b@example.com" } },
+            ],
+        });
+
+        assert_eq!(
+            apply(&rules, false, query, data),
+            serde_json::json!({
+                "search": [
+                    { "email": "a@example.com", "name": "a" },
+                    { "owner": {} },
+                ],
+            })
+        );
+    }
+}
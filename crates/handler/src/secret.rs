@@ -0,0 +1,400 @@
+use std::collections::HashMap;
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use futures_util::future::BoxFuture;
+use http::header::{HeaderName, HeaderValue, CONTENT_TYPE};
+use http::HeaderMap;
+use once_cell::sync::Lazy;
+use reqwest::Url;
+use serde_json::Value;
+
+use crate::aws_sigv4::{sign_request, AwsCredentials};
+
+static HTTP_CLIENT: Lazy<reqwest::Client> = Lazy::new(Default::default);
+
+/// Resolves the value of a secret from its reference.
+pub trait SecretResolver: Send + Sync {
+    fn resolve<'a>(&'a self, reference: &'a str) -> BoxFuture<'a, Result<String>>;
+}
+
+/// Reads the secret from an environment variable.
+///
+/// The reference is the name of the variable.
+pub struct EnvSecretResolver;
+
+impl SecretResolver for EnvSecretResolver {
+    fn resolve<'a>(&'a self, reference: &'a str) -> BoxFuture<'a, Result<String>> {
+        Box::pin(async move {
+            std::env::var(reference)
+                .with_context(|| format!("Environment variable '{}' is not set.", reference))
+        })
+    }
+}
+
+/// Reads the secret from a file, the trailing newline is removed.
+///
+/// The reference is the path of the file.
+pub struct FileSecretResolver;
+
+impl SecretResolver for FileSecretResolver {
+    fn resolve<'a>(&'a self, reference: &'a str) -> BoxFuture<'a, Result<String>> {
+        Box::pin(async move {
+            let data = tokio::fs::read_to_string(reference)
+                .await
+                .with_context(|| format!("Failed to read secret file '{}'.", reference))?;
+            Ok(data.trim_end_matches(&['\r', '\n'][..]).to_string())
+        })
+    }
+}
+
+/// Reads the secret from the HashiCorp Vault KV secrets engine.
+///
+/// The reference is `<path>#<key>`, for example `secret/data/graphgate#token`.
+pub struct VaultSecretResolver {
+    pub addr: String,
+    pub token: String,
+}
+
+impl VaultSecretResolver {
+    /// Creates a resolver from the `VAULT_ADDR` and `VAULT_TOKEN` environment variables.
+    pub fn from_env() -> Option<Self> {
+        Some(Self {
+            addr: std::env::var("VAULT_ADDR").ok()?,
+            token: std::env::var("VAULT_TOKEN").ok()?,
+        })
+    }
+}
+
+impl SecretResolver for VaultSecretResolver {
+    fn resolve<'a>(&'a self, reference: &'a str) -> BoxFuture<'a, Result<String>> {
+        Box::pin(async move {
+            let (path, key) = reference
+                .split_once('#')
+                .with_context(|| format!("Invalid Vault secret reference '{}'.", reference))?;
+            let url = format!("{}/v1/{}", self.addr.trim_end_matches('/'), path);
+            let resp = HTTP_CLIENT
+                .get(&url)
+                .header("x-vault-token", &self.token)
+                .send()
+                .await?
+                .error_for_status()?
+                .json::<Value>()
+                .await?;
+
+            // KV version 2 nests the secret in another `data` object.
+            let data = match &resp["data"]["data"] {
+                Value::Object(_) => &resp["data"]["data"],
+                _ => &resp["data"],
+            };
+            data[key]
+                .as_str()
+                .map(ToString::to_string)
+                .with_context(|| format!("Vault secret '{}' has no key '{}'.", path, key))
+        })
+    }
+}
+
+/// Reads the secret from AWS Secrets Manager.
+///
+/// The reference is the secret id, optionally followed by `#<key>` to read a
+/// key of a JSON secret. The credentials are loaded with
+/// [`AwsCredentials::load`] for each secret, so the temporary credentials of a
+/// web identity are renewed.
+pub struct AwsSecretsManagerResolver {
+    pub region: String,
+}
+
+impl AwsSecretsManagerResolver {
+    /// Creates a resolver from the `AWS_REGION` environment variable.
+    pub fn from_env() -> Option<Self> {
+        Some(Self {
+            region: std::env::var("AWS_REGION").ok()?,
+        })
+    }
+}
+
+impl SecretResolver for AwsSecretsManagerResolver {
+    fn resolve<'a>(&'a self, reference: &'a str) -> BoxFuture<'a, Result<String>> {
+        Box::pin(async move {
+            let (secret_id, key) = match reference.split_once('#') {
+                Some((secret_id, key)) => (secret_id, Some(key)),
+                None => (reference, None),
+            };
+
+            let url = Url::parse(&format!(
+                "https://secretsmanager.{}.amazonaws.com/",
+                self.region
+            ))?;
+            let body = serde_json::to_vec(&serde_json::json!({ "SecretId": secret_id }))?;
+            let mut headers = HeaderMap::new();
+            headers.insert(
+                CONTENT_TYPE,
+                HeaderValue::from_static("application/x-amz-json-1.1"),
+            );
+            headers.insert(
+                HeaderName::from_static("x-amz-target"),
+                HeaderValue::from_static("secretsmanager.GetSecretValue"),
+            );
+            let credentials = AwsCredentials::load()
+                .await
+                .context("Failed to load the AWS credentials.")?;
+            sign_request(
+                &credentials,
+                &self.region,
+                "secretsmanager",
+                "POST",
+                &url,
+                &mut headers,
+                &body,
+                Utc::now(),
+            );
+
+            let resp = HTTP_CLIENT
+                .post(url)
+                .headers(headers)
+                .body(body)
+                .send()
+                .await?
+                .error_for_status()?
+                .json::<Value>()
+                .await?;
+            let secret = resp["SecretString"]
+                .as_str()
+                .with_context(|| format!("AWS secret '{}' is not a string.", secret_id))?;
+
+            match key {
+                Some(key) => serde_json::from_str::<Value>(secret)?[key]
+                    .as_str()
+                    .map(ToString::to_string)
+                    .with_context(|| format!("AWS secret '{}' has no key '{}'.", secret_id, key)),
+                None => Ok(secret.to_string()),
+            }
+        })
+    }
+}
+
+/// Secret resolvers by scheme.
+///
+/// References are written as `${<scheme>:<reference>}` inside a string, for
+/// example `Bearer ${vault:secret/data/graphgate#token}`.
+#[derive(Default)]
+pub struct SecretResolvers {
+    resolvers: HashMap<String, Box<dyn SecretResolver>>,
+}
+
+impl SecretResolvers {
+    /// Creates the resolvers for the `env` and `file` schemes, and for the
+    /// `vault` and `aws-sm` schemes if they are configured by environment variables.
+    pub fn from_env() -> Self {
+        let mut resolvers = Self::default();
+        resolvers.register("env", EnvSecretResolver);
+        resolvers.register("file", FileSecretResolver);
+        if let Some(vault) = VaultSecretResolver::from_env() {
+            resolvers.register("vault", vault);
+        }
+        if let Some(aws) = AwsSecretsManagerResolver::from_env() {
+            resolvers.register("aws-sm", aws);
+        }
+        resolvers
+    }
+
+    pub fn register(&mut self, scheme: impl Into<String>, resolver: impl SecretResolver + 'static) {
+        self.resolvers.insert(scheme.into(), Box::new(resolver));
+    }
+
+    /// Replaces all secret references in the string with their values.
+    pub async fn resolve_str(&self, s: &str) -> Result<String> {
+        self.replace_references(s, false).await
+    }
+
+    /// Replaces all secret references in the string with `<redacted>`, without
+    /// resolving them.
+    pub async fn redact_str(&self, s: &str) -> Result<String> {
+        self.replace_references(s, true).await
+    }
+
+    async fn replace_references(&self, s: &str, redact: bool) -> Result<String> {
+        let mut output = String::new();
+        for segment in parse_references(s)? {
+            match segment {
+                Segment::Literal(literal) => output.push_str(literal),
+                Segment::Reference { .. } if redact => output.push_str("<redacted>"),
+                Segment::Reference { scheme, reference } => {
+                    let resolver = self
+                        .resolvers
+                        .get(scheme)
+                        .with_context(|| format!("Unknown secret scheme '{}'.", scheme))?;
+                    let value = resolver.resolve(reference).await.with_context(|| {
+                        format!("Failed to resolve secret '{}:{}'.", scheme, reference)
+                    })?;
+                    output.push_str(&value);
+                }
+            }
+        }
+        Ok(output)
+    }
+}
+
+/// Returns the secret references of the string, as `(scheme, reference)`.
+pub fn secret_references(s: &str) -> Result<Vec<(&str, &str)>> {
+    Ok(parse_references(s)?
+        .into_iter()
+        .filter_map(|segment| match segment {
+            Segment::Literal(_) => None,
+            Segment::Reference { scheme, reference } => Some((scheme, reference)),
+        })
+        .collect())
+}
+
+#[derive(Debug, Eq, PartialEq)]
+enum Segment<'a> {
+    Literal(&'a str),
+    Reference { scheme: &'a str, reference: &'a str },
+}
+
+/// Splits the string into literals and secret references.
+///
+/// A `${` that isn't followed by a scheme and a colon is a literal, so that
+/// strings such as `${name}` keep their meaning, and `$${` is a literal `${`.
+fn parse_references(s: &str) -> Result<Vec<Segment<'_>>> {
+    let mut segments = Vec::new();
+    let mut rest = s;
+    while let Some(start) = rest.find("${") {
+        if rest[..start].ends_with('$') {
+            segments.push(Segment::Literal(&rest[..start - 1]));
+            segments.push(Segment::Literal("${"));
+            rest = &rest[start + 2..];
+            continue;
+        }
+
+        let after = &rest[start + 2..];
+        let scheme_len = after
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '-')
+            .unwrap_or(after.len());
+        if scheme_len == 0 || !after[scheme_len..].starts_with(':') {
+            segments.push(Segment::Literal(&rest[..start + 2]));
+            rest = after;
+            continue;
+        }
+
+        let scheme = &after[..scheme_len];
+        let after = &after[scheme_len + 1..];
+        let end = after
+            .find('}')
+            .with_context(|| format!("Unterminated secret reference in '{}'.", s))?;
+        segments.push(Segment::Literal(&rest[..start]));
+        segments.push(Segment::Reference {
+            scheme,
+            reference: &after[..end],
+        });
+        rest = &after[end + 1..];
+    }
+    segments.push(Segment::Literal(rest));
+    segments.retain(|segment| segment != &Segment::Literal(""));
+    Ok(segments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct StaticResolver;
+
+    impl SecretResolver for StaticResolver {
+        fn resolve<'a>(&'a self, reference: &'a str) -> BoxFuture<'a, Result<String>> {
+            Box::pin(async move { Ok(format!("<{}>", reference)) })
+        }
+    }
+
+    fn resolvers() -> SecretResolvers {
+        let mut resolvers = SecretResolvers::default();
+        resolvers.register("test", StaticResolver);
+        resolvers.register("aws-sm", StaticResolver);
+        resolvers
+    }
+
+    #[tokio::test]
+    async fn resolve_references() {
+        let resolvers = resolvers();
+        assert_eq!(
+            resolvers
+                .resolve_str("Bearer ${test:token}, ${aws-sm:db#password}")
+                .await
+                .unwrap(),
+            "Bearer <token>, <db#password>"
+        );
+        assert_eq!(resolvers.resolve_str("${test:}").await.unwrap(), "<>");
+    }
+
+    #[tokio::test]
+    async fn redact_references() {
+        let resolvers = resolvers();
+        assert_eq!(
+            resolvers
+                .redact_str("${test:token} ${env:HOME} ${vault:secret#key}")
+                .await
+                .unwrap(),
+            "<redacted> <redacted> <redacted>"
+        );
+        assert_eq!(
+            resolvers
+                .redact_str("${name} $${test:token}")
+                .await
+                .unwrap(),
+            "${name} ${test:token}"
+        );
+    }
+
+    #[tokio::test]
+    async fn invalid_references() {
+        let resolvers = resolvers();
+        let err = resolvers.resolve_str("a ${test:token").await.unwrap_err();
+        assert!(err.to_string().contains("Unterminated"), "{}", err);
+        let err = resolvers.redact_str("${test:token").await.unwrap_err();
+        assert!(err.to_string().contains("Unterminated"), "{}", err);
+        let err = resolvers
+            .resolve_str("${vault:secret#key}")
+            .await
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("Unknown secret scheme 'vault'"),
+            "{}",
+            err
+        );
+    }
+
+    #[tokio::test]
+    async fn literal_references() {
+        let resolvers = resolvers();
+        for s in [
+            "${name}",
+            "${}",
+            "${ test:token }",
+            "cost: $5 {x}",
+            "${",
+            "template `${user.name}`",
+        ] {
+            assert_eq!(resolvers.resolve_str(s).await.unwrap(), s);
+        }
+        assert_eq!(
+            resolvers.resolve_str("$${test:token}").await.unwrap(),
+            "${test:token}"
+        );
+        assert_eq!(
+            resolvers
+                .resolve_str("$${test:token} ${test:token}")
+                .await
+                .unwrap(),
+            "${test:token} <token>"
+        );
+    }
+
+    #[test]
+    fn list_references() {
+        assert_eq!(
+            secret_references("${name} ${env:HOME}:${file:/run/key}").unwrap(),
+            vec![("env", "HOME"), ("file", "/run/key")]
+        );
+    }
+}
@@ -1,12 +1,63 @@
 use std::collections::HashMap;
 use std::ops::{Deref, DerefMut};
+use std::str::FromStr;
+use std::time::Instant;
 
-use futures_util::TryFutureExt;
-use graphgate_planner::{Request, Response};
-use http::HeaderMap;
-use once_cell::sync::Lazy;
+use chrono::{DateTime, Utc};
+use graphgate_planner::{BodySizes, Request, Response, EXTENSION_SERVICE_NAME};
+use http::header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE};
+use http::{HeaderMap, StatusCode};
+use opentelemetry::trace::get_active_span;
+use serde::{Deserialize, Serialize};
+use value::ConstValue;
 
-static HTTP_CLIENT: Lazy<reqwest::Client> = Lazy::new(Default::default);
+use crate::aws_sigv4::AwsSigV4Options;
+use crate::body_encoding::BodyEncoding;
+use crate::constants::KEY_HTTP_VERSION;
+use crate::maintenance_window::{self, MaintenanceWindow};
+use crate::metrics::{Metrics, METRICS};
+use crate::oauth2::OAuth2Options;
+use crate::response_limits::{read_response, ResponseLimits};
+use crate::upstream_pool::InFlightGuard;
+use crate::upstream_tls;
+use crate::websocket::Protocols;
+
+/// HTTP version used for the requests sent to a service.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HttpVersion {
+    /// HTTP/2 if negotiated with ALPN over TLS, otherwise HTTP/1.1.
+    Auto,
+    /// Always HTTP/1.1.
+    Http1,
+    /// HTTP/2 with prior knowledge, also without TLS.
+    Http2,
+}
+
+impl Default for HttpVersion {
+    fn default() -> Self {
+        HttpVersion::Auto
+    }
+}
+
+impl FromStr for HttpVersion {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(HttpVersion::Auto),
+            "http1" => Ok(HttpVersion::Http1),
+            "http2" => Ok(HttpVersion::Http2),
+            _ => anyhow::bail!("Unknown HTTP version '{}'.", s),
+        }
+    }
+}
+
+impl HttpVersion {
+    fn client(&self) -> reqwest::Client {
+        upstream_tls::http_client(*self)
+    }
+}
 
 /// Service routing information.
 #[derive(Clone, Eq, PartialEq, Debug)]
@@ -28,6 +79,43 @@ pub struct ServiceRoute {
     pub introspection_path: Option<String>,
 
     pub websocket_path: Option<String>,
+
+    /// Use the HTTP callback protocol for subscriptions instead of a WebSocket.
+    pub subscription_callback: bool,
+
+    pub http_version: HttpVersion,
+
+    pub response_limits: ResponseLimits,
+
+    /// Encoding of the requests sent to the service, JSON is used again if the
+    /// service answers `415 Unsupported Media Type`.
+    pub encoding: BodyEncoding,
+
+    /// WebSocket subprotocol used for the subscriptions, regardless of the
+    /// negotiation. Both are offered to the service if not set.
+    pub websocket_protocol: Option<Protocols>,
+
+    /// OAuth2 client credentials of the gateway, its token replaces the
+    /// `Authorization` header of the client.
+    pub oauth2: Option<OAuth2Options>,
+
+    /// Signing of the requests with AWS Signature Version 4, it replaces the
+    /// `Authorization` header of the client.
+    pub aws_sigv4: Option<AwsSigV4Options>,
+
+    /// Planned maintenance windows, the queries sent during a window fail
+    /// without reaching the service.
+    pub maintenance_windows: Vec<MaintenanceWindow>,
+}
+
+impl ServiceRoute {
+    /// Returns the active maintenance window and its end.
+    pub(crate) fn maintenance(
+        &self,
+        now: DateTime<Utc>,
+    ) -> Option<(&MaintenanceWindow, DateTime<Utc>)> {
+        maintenance_window::active_window(&self.maintenance_windows, now)
+    }
 }
 
 /// Service routing table
@@ -50,7 +138,45 @@ impl DerefMut for ServiceRouteTable {
     }
 }
 
+/// Differences between two service routing tables.
+#[derive(Default, Debug, Clone, Eq, PartialEq)]
+pub struct RouteTableDiff {
+    /// Services that only exist in the new routing table.
+    pub added: Vec<String>,
+
+    /// Services that only exist in the old routing table.
+    pub removed: Vec<String>,
+
+    /// Services whose route has changed.
+    pub changed: Vec<String>,
+}
+
+impl RouteTableDiff {
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
 impl ServiceRouteTable {
+    /// Compare with a new routing table.
+    pub fn diff(&self, new: &ServiceRouteTable) -> RouteTableDiff {
+        let mut diff = RouteTableDiff::default();
+        for (service, route) in new.iter() {
+            match self.get(service) {
+                Some(old_route) if old_route != route => diff.changed.push(service.clone()),
+                Some(_) => {}
+                None => diff.added.push(service.clone()),
+            }
+        }
+        for service in self.keys() {
+            if !new.contains_key(service) {
+                diff.removed.push(service.clone());
+            }
+        }
+        diff
+    }
+
     /// Call the GraphQL query of the specified service.
     pub async fn query(
         &self,
@@ -59,12 +185,47 @@ impl ServiceRouteTable {
         header_map: Option<&HeaderMap>,
         introspection: Option<bool>,
     ) -> anyhow::Result<Response> {
-        let service = service.as_ref();
+        self.send_request(
+            service.as_ref(),
+            request,
+            header_map,
+            introspection.unwrap_or(false),
+            None,
+        )
+        .await
+    }
+
+    /// Call the introspection query of the specified service, the response is
+    /// read with the specified limits instead of the limits of the route.
+    pub async fn introspect(
+        &self,
+        service: impl AsRef<str>,
+        request: Request,
+        limits: &ResponseLimits,
+    ) -> anyhow::Result<Response> {
+        self.send_request(service.as_ref(), request, None, true, Some(limits))
+            .await
+    }
+
+    async fn send_request(
+        &self,
+        service: &str,
+        request: Request,
+        header_map: Option<&HeaderMap>,
+        introspection: bool,
+        limits: Option<&ResponseLimits>,
+    ) -> anyhow::Result<Response> {
         let route = self.0.get(service).ok_or_else(|| {
             anyhow::anyhow!("Service '{}' is not defined in the routing table.", service)
         })?;
 
-        let introspection = introspection.unwrap_or(false);
+        if !introspection {
+            if let Some((window, end)) = route.maintenance(Utc::now()) {
+                return Ok(maintenance_window::maintenance_response(
+                    service, window, end,
+                ));
+            }
+        }
 
         let scheme = match route.tls {
             true => "https",
@@ -83,13 +244,81 @@ impl ServiceRouteTable {
             }
         };
 
-        let raw_resp = HTTP_CLIENT
-            .post(&url)
-            .headers(header_map.cloned().unwrap_or_default())
-            .json(&request)
-            .send()
-            .and_then(|res| async move { res.error_for_status() })
-            .await?;
+        let access_token = match &route.oauth2 {
+            Some(oauth2) => Some(oauth2.access_token().await?),
+            None => None,
+        };
+
+        let replace_authorization = access_token.is_some() || route.aws_sigv4.is_some();
+
+        let send = |encoding: BodyEncoding| {
+            let body = encoding.encode(&request);
+            let mut builder = route.http_version.client().post(&url);
+            // The headers are appended one by one, `RequestBuilder::headers` takes
+            // the map by value and would need a clone of it for every fetch.
+            for (name, value) in header_map.into_iter().flatten() {
+                if replace_authorization && name == AUTHORIZATION {
+                    continue;
+                }
+                builder = builder.header(name, value);
+            }
+            if let Some(access_token) = &access_token {
+                builder = builder.bearer_auth(access_token);
+            }
+            let url = &url;
+            async move {
+                let body = body?;
+                let request_size = body.len();
+                if let Some(aws_sigv4) = &route.aws_sigv4 {
+                    let signed_headers =
+                        aws_sigv4.sign(url, encoding.content_type(), &body).await?;
+                    for (name, value) in &signed_headers {
+                        builder = builder.header(name, value);
+                    }
+                }
+                Ok::<_, anyhow::Error>((
+                    builder
+                        .header(CONTENT_TYPE, encoding.content_type())
+                        .header(ACCEPT, encoding.accept())
+                        .body(body)
+                        .send()
+                        .await?,
+                    request_size,
+                ))
+            }
+        };
+        let _in_flight = InFlightGuard::new(service);
+        let start_time = Instant::now();
+        let (raw_resp, request_size) = match send(route.encoding).await? {
+            (raw_resp, _)
+                if route.encoding != BodyEncoding::Json
+                    && raw_resp.status() == StatusCode::UNSUPPORTED_MEDIA_TYPE =>
+            {
+                tracing::debug!(
+                    service = service,
+                    encoding = ?route.encoding,
+                    "Service does not accept the body encoding, falling back to JSON."
+                );
+                send(BodyEncoding::Json).await?
+            }
+            sent => sent,
+        };
+        if raw_resp.status() == StatusCode::UNAUTHORIZED {
+            if let Some(oauth2) = &route.oauth2 {
+                oauth2.invalidate().await;
+            }
+        }
+        let raw_resp = raw_resp.error_for_status()?;
+
+        let version = format!("{:?}", raw_resp.version());
+        get_active_span(|span| span.set_attribute(KEY_HTTP_VERSION.string(version.clone())));
+        let labels = Metrics::upstream_labels(service, version);
+        METRICS.upstream_request_histogram.record(
+            &opentelemetry::Context::current(),
+            start_time.elapsed().as_secs_f64(),
+            &labels,
+        );
+        METRICS.upstream_request_counter.add(1, &labels);
 
         let mut headers: HashMap<String, Vec<String>> = HashMap::new();
 
@@ -107,8 +336,19 @@ impl ServiceRouteTable {
             }
         }
 
-        let mut resp = raw_resp.json::<Response>().await?;
+        let limits = limits.unwrap_or(&route.response_limits);
+        let (mut resp, response_size) = read_response(service, raw_resp, limits).await?;
+        for error in resp.errors.iter_mut().filter(|error| error.is_malformed()) {
+            error.extensions.insert(
+                EXTENSION_SERVICE_NAME.to_string(),
+                ConstValue::String(service.to_string()),
+            );
+        }
         resp.headers = Some(headers);
+        resp.body_sizes = Some(BodySizes {
+            request: request_size,
+            response: response_size,
+        });
         Ok(resp)
     }
 }
@@ -0,0 +1,780 @@
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::convert::Infallible;
+
+use graphgate_planner::{Request, ServerError};
+use graphgate_schema::{GatewayField, ShapingDirective};
+use http::HeaderMap;
+use indexmap::IndexMap;
+use parser::types::{Directive, ExecutableDocument, FragmentDefinition, Selection, SelectionSet};
+use parser::Positioned;
+use value::{ConstValue, Name, Variables};
+
+/// Header that asks the gateway to remove the null fields from the response.
+pub const REMOVE_NULLS_HEADER: &str = "x-graphgate-remove-nulls";
+
+/// Request extension that asks the gateway to remove the null fields from the response.
+pub const REMOVE_NULLS_EXTENSION: &str = "removeNulls";
+
+/// Returns `true` if the client asked for null fields to be removed, with
+/// either the request extension or the header.
+pub fn remove_nulls_requested(request: &Request, header_map: &HeaderMap) -> bool {
+    if let Some(ConstValue::Boolean(enabled)) = request.extensions.get(REMOVE_NULLS_EXTENSION) {
+        return *enabled;
+    }
+    header_map
+        .get(REMOVE_NULLS_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.eq_ignore_ascii_case("true") || value == "1")
+        .unwrap_or_default()
+}
+
+/// Removes the object fields whose value is null.
+///
+/// Null items of lists are kept, because removing them would change the
+/// position of the other items. The null fields at the path of an error are
+/// also kept, so that the error still points to the field that failed.
+pub fn remove_nulls(value: &mut ConstValue, errors: &[ServerError]) {
+    let error_paths: Vec<&[ConstValue]> = errors
+        .iter()
+        .map(|error| error.path.as_slice())
+        .filter(|path| !path.is_empty())
+        .collect();
+    remove_nulls_at(value, &mut Vec::new(), &error_paths);
+}
+
+fn remove_nulls_at(
+    value: &mut ConstValue,
+    path: &mut Vec<ConstValue>,
+    error_paths: &[&[ConstValue]],
+) {
+    match value {
+        ConstValue::Object(object) => {
+            object.retain(|key, value| {
+                if !matches!(value, ConstValue::Null) {
+                    return true;
+                }
+                path.push(ConstValue::String(key.to_string()));
+                let keep = error_paths.contains(&path.as_slice());
+                path.pop();
+                keep
+            });
+            for (key, value) in object.iter_mut() {
+                path.push(ConstValue::String(key.to_string()));
+                remove_nulls_at(value, path, error_paths);
+                path.pop();
+            }
+        }
+        ConstValue::List(list) => {
+            for (idx, item) in list.iter_mut().enumerate() {
+                path.push(ConstValue::Number(idx.into()));
+                remove_nulls_at(item, path, error_paths);
+                path.pop();
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Header that asks the gateway to normalize the repeated entities of the response.
+pub const NORMALIZE_HEADER: &str = "x-graphgate-normalize";
+
+/// Request extension that asks the gateway to normalize the repeated entities
+/// of the response.
+pub const NORMALIZE_EXTENSION: &str = "normalize";
+
+/// Response extension containing the normalized entities.
+pub const RECORDS_EXTENSION: &str = "records";
+
+/// Returns `true` if the client asked for the repeated entities to be
+/// normalized, with either the request extension or the header.
+pub fn normalize_requested(request: &Request, header_map: &HeaderMap) -> bool {
+    if let Some(ConstValue::Boolean(enabled)) = request.extensions.get(NORMALIZE_EXTENSION) {
+        return *enabled;
+    }
+    header_map
+        .get(NORMALIZE_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.eq_ignore_ascii_case("true") || value == "1")
+        .unwrap_or_default()
+}
+
+/// Replaces the entities that appear more than once in the data with a
+/// reference such as `{"__ref": "User:1"}`, and returns the entities by
+/// reference, `None` if no entity is repeated.
+///
+/// An entity is an object with the `__typename` and `id` fields. It is only
+/// replaced if all its occurrences are identical, the entities selected with
+/// different fields are kept in place. The entities inside the replaced ones
+/// are replaced too.
+pub(crate) fn normalize(data: &mut ConstValue) -> Option<ConstValue> {
+    fn entity_ref(value: &ConstValue) -> Option<String> {
+        let object = match value {
+            ConstValue::Object(object) => object,
+            _ => return None,
+        };
+        let typename = match object.get("__typename") {
+            Some(ConstValue::String(typename)) => typename,
+            _ => return None,
+        };
+        match object.get("id") {
+            Some(ConstValue::String(id)) => Some(format!("{}:{}", typename, id)),
+            Some(ConstValue::Number(id)) => Some(format!("{}:{}", typename, id)),
+            _ => None,
+        }
+    }
+
+    /// Counts the occurrences of the entities, `None` if they differ.
+    fn count<'a>(
+        value: &'a ConstValue,
+        entities: &mut HashMap<String, Option<(&'a ConstValue, usize)>>,
+    ) {
+        match value {
+            ConstValue::Object(object) => object.values().for_each(|value| count(value, entities)),
+            ConstValue::List(list) => list.iter().for_each(|value| count(value, entities)),
+            _ => return,
+        }
+        if let Some(reference) = entity_ref(value) {
+            match entities.entry(reference).or_insert(Some((value, 0))) {
+                Some((first, count)) if *first == value => *count += 1,
+                entry => *entry = None,
+            }
+        }
+    }
+
+    fn replace(
+        value: &mut ConstValue,
+        repeated: &HashSet<String>,
+        records: &mut IndexMap<Name, ConstValue>,
+    ) {
+        match value {
+            ConstValue::Object(object) => object
+                .values_mut()
+                .for_each(|value| replace(value, repeated, records)),
+            ConstValue::List(list) => list
+                .iter_mut()
+                .for_each(|value| replace(value, repeated, records)),
+            _ => return,
+        }
+        if let Some(reference) = entity_ref(value).filter(|reference| repeated.contains(reference))
+        {
+            let mut object = IndexMap::new();
+            object.insert(Name::new("__ref"), ConstValue::String(reference.clone()));
+            let entity = std::mem::replace(value, ConstValue::Object(object));
+            records.entry(Name::new(reference)).or_insert(entity);
+        }
+    }
+
+    let mut entities = HashMap::new();
+    count(data, &mut entities);
+    let repeated = entities
+        .into_iter()
+        .filter_map(|(reference, entity)| match entity {
+            Some((_, count)) if count > 1 => Some(reference),
+            _ => None,
+        })
+        .collect::<HashSet<_>>();
+    if repeated.is_empty() {
+        return None;
+    }
+
+    let mut records = IndexMap::new();
+    replace(data, &repeated, &mut records);
+    Some(ConstValue::Object(records))
+}
+
+/// Removes the object fields that the executed operation does not select,
+/// such as the extra fields returned by the services.
+///
+/// The type conditions of the fragments are not checked, a field selected in
+/// any fragment is kept.
+pub(crate) fn retain_selected(
+    data: &mut ConstValue,
+    document: &ExecutableDocument,
+    operation_name: Option<&str>,
+) {
+    let operation = document
+        .operations
+        .iter()
+        .find(|(name, _)| operation_name.is_none() || name.map(Name::as_str) == operation_name);
+    if let Some((_, operation)) = operation {
+        retain_selected_rec(
+            data,
+            &[&operation.node.selection_set.node],
+            &document.fragments,
+        );
+    }
+}
+
+fn retain_selected_rec(
+    value: &mut ConstValue,
+    selection_sets: &[&SelectionSet],
+    fragments: &HashMap<Name, Positioned<FragmentDefinition>>,
+) {
+    fn collect<'a>(
+        selection_set: &'a SelectionSet,
+        fragments: &'a HashMap<Name, Positioned<FragmentDefinition>>,
+        selected: &mut HashMap<&'a Name, Vec<&'a SelectionSet>>,
+    ) {
+        for selection in &selection_set.items {
+            match &selection.node {
+                Selection::Field(field) => selected
+                    .entry(&field.node.response_key().node)
+                    .or_default()
+                    .push(&field.node.selection_set.node),
+                Selection::FragmentSpread(fragment_spread) => {
+                    if let Some(fragment) = fragments.get(&fragment_spread.node.fragment_name.node)
+                    {
+                        collect(&fragment.node.selection_set.node, fragments, selected);
+                    }
+                }
+                Selection::InlineFragment(inline_fragment) => collect(
+                    &inline_fragment.node.selection_set.node,
+                    fragments,
+                    selected,
+                ),
+            }
+        }
+    }
+
+    // The values of the leaf fields, such as JSON scalars, are kept as is.
+    if selection_sets
+        .iter()
+        .all(|selection_set| selection_set.items.is_empty())
+    {
+        return;
+    }
+
+    match value {
+        ConstValue::List(list) => list
+            .iter_mut()
+            .for_each(|value| retain_selected_rec(value, selection_sets, fragments)),
+        ConstValue::Object(object) => {
+            let mut selected = HashMap::new();
+            for selection_set in selection_sets {
+                collect(selection_set, fragments, &mut selected);
+            }
+            object.retain(|key, _| selected.contains_key(key));
+            for (key, value) in object.iter_mut() {
+                if let Some(selection_sets) = selected.get(key) {
+                    retain_selected_rec(value, selection_sets, fragments);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Evaluates the directives of the gateway on the list fields of the data,
+/// in the order they are written on each field.
+///
+/// The nested lists are shaped first, so that an item can be sorted by a
+/// field of its own shaped data.
+pub(crate) fn apply_shaping_directives(
+    data: &mut ConstValue,
+    shaping_directives: &[ShapingDirective],
+    document: &ExecutableDocument,
+    operation_name: Option<&str>,
+    variables: &Variables,
+) {
+    if shaping_directives.is_empty() {
+        return;
+    }
+    let operation = document
+        .operations
+        .iter()
+        .find(|(name, _)| operation_name.is_none() || name.map(Name::as_str) == operation_name);
+    if let Some((_, operation)) = operation {
+        let variable_value = |name: Name| {
+            let value = variables.get(&name).cloned().or_else(|| {
+                operation
+                    .node
+                    .variable_definitions
+                    .iter()
+                    .find(|definition| definition.node.name.node == name)
+                    .and_then(|definition| definition.node.default_value.as_ref())
+                    .map(|default_value| default_value.node.clone())
+            });
+            Ok::<_, Infallible>(value.unwrap_or_default())
+        };
+        apply_shaping_directives_rec(
+            data,
+            &[&operation.node.selection_set.node],
+            &document.fragments,
+            shaping_directives,
+            &variable_value,
+        );
+    }
+}
+
+fn apply_shaping_directives_rec(
+    value: &mut ConstValue,
+    selection_sets: &[&SelectionSet],
+    fragments: &HashMap<Name, Positioned<FragmentDefinition>>,
+    shaping_directives: &[ShapingDirective],
+    variable_value: &dyn Fn(Name) -> Result<ConstValue, Infallible>,
+) {
+    type Selected<'a> = HashMap<&'a Name, (&'a [Positioned<Directive>], Vec<&'a SelectionSet>)>;
+
+    fn collect<'a>(
+        selection_set: &'a SelectionSet,
+        fragments: &'a HashMap<Name, Positioned<FragmentDefinition>>,
+        selected: &mut Selected<'a>,
+    ) {
+        for selection in &selection_set.items {
+            match &selection.node {
+                Selection::Field(field) => selected
+                    .entry(&field.node.response_key().node)
+                    .or_insert_with(|| (&field.node.directives, Vec::new()))
+                    .1
+                    .push(&field.node.selection_set.node),
+                Selection::FragmentSpread(fragment_spread) => {
+                    if let Some(fragment) = fragments.get(&fragment_spread.node.fragment_name.node)
+                    {
+                        collect(&fragment.node.selection_set.node, fragments, selected);
+                    }
+                }
+                Selection::InlineFragment(inline_fragment) => collect(
+                    &inline_fragment.node.selection_set.node,
+                    fragments,
+                    selected,
+                ),
+            }
+        }
+    }
+
+    match value {
+        ConstValue::List(list) => list.iter_mut().for_each(|value| {
+            apply_shaping_directives_rec(
+                value,
+                selection_sets,
+                fragments,
+                shaping_directives,
+                variable_value,
+            )
+        }),
+        ConstValue::Object(object) => {
+            let mut selected = HashMap::new();
+            for selection_set in selection_sets {
+                collect(selection_set, fragments, &mut selected);
+            }
+            for (key, value) in object.iter_mut() {
+                let (directives, selection_sets) = match selected.get(key) {
+                    Some(selected) => selected,
+                    None => continue,
+                };
+                apply_shaping_directives_rec(
+                    value,
+                    selection_sets,
+                    fragments,
+                    shaping_directives,
+                    variable_value,
+                );
+                let list = match value {
+                    ConstValue::List(list) => list,
+                    _ => continue,
+                };
+                for directive in directives.iter() {
+                    let shaping_directive =
+                        match ShapingDirective::from_name(directive.node.name.node.as_str()) {
+                            Some(shaping_directive)
+                                if shaping_directives.contains(&shaping_directive) =>
+                            {
+                                shaping_directive
+                            }
+                            _ => continue,
+                        };
+                    let argument = |name: &str| {
+                        directive.node.get_argument(name).and_then(|value| {
+                            value.node.clone().into_const_with(variable_value).ok()
+                        })
+                    };
+                    match shaping_directive {
+                        ShapingDirective::First => {
+                            if let Some(ConstValue::Number(n)) = argument("n") {
+                                if let Some(n) = n.as_u64() {
+                                    list.truncate(n as usize);
+                                }
+                            }
+                        }
+                        ShapingDirective::SortBy => {
+                            if let Some(ConstValue::String(field)) = argument("field") {
+                                let desc = argument("desc") == Some(ConstValue::Boolean(true));
+                                sort_by_field(list, &field, desc);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Sorts the objects of a list by the value of one of their fields, the items
+/// without a scalar value are put last in their original order.
+fn sort_by_field(list: &mut [ConstValue], field: &str, desc: bool) {
+    fn sort_key<'a>(item: &'a ConstValue, field: &str) -> Option<&'a ConstValue> {
+        match item {
+            ConstValue::Object(object) => object.get(field).filter(|value| {
+                !matches!(
+                    value,
+                    ConstValue::Null | ConstValue::List(_) | ConstValue::Object(_)
+                )
+            }),
+            _ => None,
+        }
+    }
+
+    fn compare(a: &ConstValue, b: &ConstValue) -> Ordering {
+        match (a, b) {
+            (ConstValue::Number(a), ConstValue::Number(b)) => a
+                .as_f64()
+                .partial_cmp(&b.as_f64())
+                .unwrap_or(Ordering::Equal),
+            (ConstValue::String(a), ConstValue::String(b)) => a.cmp(b),
+            (ConstValue::Enum(a), ConstValue::Enum(b)) => a.cmp(b),
+            (ConstValue::Boolean(a), ConstValue::Boolean(b)) => a.cmp(b),
+            _ => Ordering::Equal,
+        }
+    }
+
+    list.sort_by(|a, b| match (sort_key(a, field), sort_key(b, field)) {
+        (Some(a), Some(b)) if desc => compare(b, a),
+        (Some(a), Some(b)) => compare(a, b),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    });
+}
+
+/// Prefix of the aliases of the source fields of the gateway fields.
+const GATEWAY_FIELD_PREFIX: &str = "__gateway";
+
+/// Computes the values of the fields resolved by the gateway, and removes
+/// their source fields aliased by the planner.
+pub(crate) fn resolve_gateway_fields(value: &mut ConstValue, gateway_fields: &[GatewayField]) {
+    match value {
+        ConstValue::Object(object) => {
+            let aliases = object
+                .keys()
+                .filter(|key| key.starts_with(GATEWAY_FIELD_PREFIX))
+                .cloned()
+                .collect::<Vec<_>>();
+            let mut sources: IndexMap<(usize, Name), Vec<(usize, ConstValue)>> = IndexMap::new();
+            for alias in aliases {
+                let value = object.shift_remove(&alias).unwrap_or_default();
+                if let Some((id, idx, response_key)) = parse_gateway_alias(&alias) {
+                    sources
+                        .entry((id, Name::new(response_key)))
+                        .or_default()
+                        .push((idx, value));
+                }
+            }
+            for ((id, response_key), mut values) in sources {
+                if let Some(gateway_field) = gateway_fields.get(id) {
+                    values.sort_by_key(|(idx, _)| *idx);
+                    let value = gateway_field
+                        .resolver
+                        .resolve(values.into_iter().map(|(_, value)| value).collect());
+                    object.insert(response_key, value);
+                }
+            }
+            object
+                .values_mut()
+                .for_each(|value| resolve_gateway_fields(value, gateway_fields));
+        }
+        ConstValue::List(list) => list
+            .iter_mut()
+            .for_each(|value| resolve_gateway_fields(value, gateway_fields)),
+        _ => {}
+    }
+}
+
+/// Parses an alias `__gateway{id}_{index}_{response key}`.
+fn parse_gateway_alias(alias: &str) -> Option<(usize, usize, &str)> {
+    let (id, rest) = alias.strip_prefix(GATEWAY_FIELD_PREFIX)?.split_once('_')?;
+    let (idx, response_key) = rest.split_once('_')?;
+    Some((id.parse().ok()?, idx.parse().ok()?, response_key))
+}
+
+/// Applies a selection set to a value, as if the value was returned by a resolver.
+pub(crate) fn project(
+    value: ConstValue,
+    selection_set: &Positioned<SelectionSet>,
+    fragments: &HashMap<Name, Positioned<SelectionSet>>,
+) -> ConstValue {
+    if selection_set.node.items.is_empty() {
+        return value;
+    }
+    match value {
+        ConstValue::List(values) => ConstValue::List(
+            values
+                .into_iter()
+                .map(|value| project(value, selection_set, fragments))
+                .collect(),
+        ),
+        ConstValue::Object(object) => {
+            let mut output = IndexMap::new();
+            project_object(&object, selection_set, fragments, &mut output);
+            ConstValue::Object(output)
+        }
+        value => value,
+    }
+}
+
+fn project_object(
+    object: &IndexMap<Name, ConstValue>,
+    selection_set: &Positioned<SelectionSet>,
+    fragments: &HashMap<Name, Positioned<SelectionSet>>,
+    output: &mut IndexMap<Name, ConstValue>,
+) {
+    for selection in &selection_set.node.items {
+        match &selection.node {
+            Selection::Field(field) => {
+                let value = object
+                    .get(&field.node.name.node)
+                    .cloned()
+                    .unwrap_or_default();
+                output.insert(
+                    field.node.response_key().node.clone(),
+                    project(value, &field.node.selection_set, fragments),
+                );
+            }
+            Selection::FragmentSpread(fragment_spread) => {
+                if let Some(selection_set) = fragments.get(&fragment_spread.node.fragment_name.node)
+                {
+                    project_object(object, selection_set, fragments, output);
+                }
+            }
+            Selection::InlineFragment(inline_fragment) => {
+                project_object(
+                    object,
+                    &inline_fragment.node.selection_set,
+                    fragments,
+                    output,
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retain() {
+        let document = parser::parse_query(
+            r#"
+            query A { me { id name: username ...F ... on User { json } } }
+            fragment F on User { reviews { body } }
+            "#,
+        )
+        .unwrap();
+        let mut data = ConstValue::from_json(serde_json::json!({
+            "me": {
+                "id": 1,
+                "name": "a",
+                "password": "b",
+                "reviews": [{ "body": "c", "internal": true }],
+                "json": { "x": 1 },
+            },
+            "extra": 2,
+        }))
+        .unwrap();
+        retain_selected(&mut data, &document, Some("A"));
+        assert_eq!(
+            data.into_json().unwrap(),
+            serde_json::json!({
+                "me": {
+                    "id": 1,
+                    "name": "a",
+                    "reviews": [{ "body": "c" }],
+                    "json": { "x": 1 },
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn remove_null_fields() {
+        let mut data = ConstValue::from_json(serde_json::json!({
+            "me": {
+                "id": 1,
+                "name": null,
+                "profile": { "bio": null, "avatar": { "url": null } },
+                "reviews": [null, { "body": null, "rating": 5 }, []],
+            },
+            "topProducts": null,
+        }))
+        .unwrap();
+        remove_nulls(&mut data, &[]);
+        assert_eq!(
+            data.into_json().unwrap(),
+            serde_json::json!({
+                "me": {
+                    "id": 1,
+                    "profile": { "avatar": {} },
+                    "reviews": [null, { "rating": 5 }, []],
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn keep_null_errors() {
+        let mut data = ConstValue::from_json(serde_json::json!({
+            "me": {
+                "name": null,
+                "reviews": [{ "body": null }, { "body": null }],
+            },
+            "topProducts": null,
+        }))
+        .unwrap();
+        let errors = vec![
+            ServerError::new("Field failed.").with_path(vec![
+                ConstValue::String("me".to_string()),
+                ConstValue::String("reviews".to_string()),
+                ConstValue::Number(1.into()),
+                ConstValue::String("body".to_string()),
+            ]),
+            ServerError::new("Service failed.")
+                .with_path(vec![ConstValue::String("topProducts".to_string())]),
+            ServerError::new("No path."),
+        ];
+        remove_nulls(&mut data, &errors);
+        assert_eq!(
+            data.into_json().unwrap(),
+            serde_json::json!({
+                "me": { "reviews": [{}, { "body": null }] },
+                "topProducts": null,
+            })
+        );
+    }
+
+    #[test]
+    fn shaping_directives() {
+        let document = parser::parse_query(
+            r#"
+            query($n: Int = 2) {
+                users @sortBy(field: "name", desc: true) @first(n: $n) {
+                    name
+                    posts @sortBy(field: "score") { score }
+                }
+                tags @first(n: 1)
+            }
+            "#,
+        )
+        .unwrap();
+        let mut data = ConstValue::from_json(serde_json::json!({
+            "users": [
+                { "name": "b", "posts": [{ "score": 2 }, { "score": null }, { "score": 1 }] },
+                { "name": null, "posts": [] },
+                { "name": "c", "posts": [] },
+                { "name": "a", "posts": [] },
+            ],
+            "tags": ["x", "y"],
+        }))
+        .unwrap();
+        apply_shaping_directives(
+            &mut data,
+            &[ShapingDirective::SortBy, ShapingDirective::First],
+            &document,
+            None,
+            &Variables::default(),
+        );
+        assert_eq!(
+            data.into_json().unwrap(),
+            serde_json::json!({
+                "users": [
+                    { "name": "c", "posts": [] },
+                    { "name": "b", "posts": [{ "score": 1 }, { "score": 2 }, { "score": null }] },
+                ],
+                "tags": ["x"],
+            })
+        );
+    }
+
+    #[test]
+    fn normalize_entities() {
+        let author = serde_json::json!({ "__typename": "User", "id": "1", "name": "a" });
+        let mut data = ConstValue::from_json(serde_json::json!({
+            "posts": [
+                { "__typename": "Post", "id": 1, "author": author },
+                { "__typename": "Post", "id": 2, "author": author },
+                { "__typename": "Post", "id": 3, "author": { "__typename": "User", "id": "2" } },
+            ],
+            "me": { "__typename": "User", "id": "2", "name": "b" },
+        }))
+        .unwrap();
+        let records = normalize(&mut data).unwrap();
+
+        assert_eq!(
+            data.into_json().unwrap(),
+            serde_json::json!({
+                "posts": [
+                    { "__typename": "Post", "id": 1, "author": { "__ref": "User:1" } },
+                    { "__typename": "Post", "id": 2, "author": { "__ref": "User:1" } },
+                    { "__typename": "Post", "id": 3, "author": { "__typename": "User", "id": "2" } },
+                ],
+                "me": { "__typename": "User", "id": "2", "name": "b" },
+            })
+        );
+        assert_eq!(
+            records.into_json().unwrap(),
+            serde_json::json!({ "User:1": author })
+        );
+
+        let mut data = ConstValue::from_json(serde_json::json!({ "me": author })).unwrap();
+        assert!(normalize(&mut data).is_none());
+    }
+
+    #[test]
+    fn gateway_fields() {
+        let schema = parser::parse_schema(
+            r#"
+            extend type User {
+                login: String @gateway(from: "username")
+                label: String @gateway(concat: ["id", "username"], separator: "-")
+                version: String @gateway(value: "v2")
+            }
+            "#,
+        )
+        .unwrap();
+        let mut composed_schema = graphgate_schema::ComposedSchema::parse(
+            "type Query { users: [User] } type User { id: ID! username: String }",
+        )
+        .unwrap();
+        composed_schema.extend_gateway_fields(schema).unwrap();
+
+        let mut data = ConstValue::from_json(serde_json::json!({
+            "users": [
+                {
+                    "id": "1",
+                    "__gateway0_0_name": "a",
+                    "__gateway1_1_label": "a",
+                    "__gateway1_0_label": "1",
+                    "__gateway2_0_version": "User",
+                },
+                {
+                    "id": "2",
+                    "__gateway0_0_name": null,
+                    "__gateway1_0_label": "2",
+                    "__gateway1_1_label": null,
+                },
+            ],
+        }))
+        .unwrap();
+        resolve_gateway_fields(&mut data, &composed_schema.gateway_fields);
+        assert_eq!(
+            data.into_json().unwrap(),
+            serde_json::json!({
+                "users": [
+                    { "id": "1", "name": "a", "label": "1-a", "version": "v2" },
+                    { "id": "2", "name": null, "label": "2" },
+                ],
+            })
+        );
+    }
+}
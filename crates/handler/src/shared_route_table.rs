@@ -1,28 +1,177 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::net::IpAddr;
+use std::panic::AssertUnwindSafe;
 use std::sync::Arc;
 
 use anyhow::{Context, Error, Result};
-use graphgate_planner::{PlanBuilder, Request, Response, ServerError};
-use graphgate_schema::ComposedSchema;
-use http::{header::HeaderName, HeaderValue};
-use opentelemetry::trace::{TraceContextExt, Tracer};
+use chrono::{DateTime, Utc};
+use futures_util::{FutureExt, StreamExt};
+use graphgate_planner::{
+    operation_signature, ErrorCode, GraphFormat, InlineVariables, PlanBuilder, PlanLimits,
+    PlanNode, Request, Response, RootNode, ScalarValidator, ServerError,
+};
+use graphgate_schema::{ComposedSchema, ShapingDirective};
+use http::header::{HeaderName, CACHE_CONTROL, RETRY_AFTER};
+use http::HeaderValue;
+use opentelemetry::trace::{get_active_span, TraceContextExt, Tracer};
 use opentelemetry::{global, Context as OpenTelemetryContext};
+use parser::types::{ExecutableDocument, OperationType, ServiceDocument};
 use serde::Deserialize;
 use tokio::sync::{mpsc, RwLock};
 use tokio::time::{Duration, Instant};
-use value::ConstValue;
+use value::{ConstValue, Variables};
 use warp::http::{HeaderMap, Response as HttpResponse, StatusCode};
+use warp::hyper::Body;
 
+use crate::audit::{AuditFetcher, AuditOptions, StreamAudit};
+use crate::auth::Claims;
+use crate::callback::CallbackRegistry;
+#[cfg(feature = "chaos")]
+use crate::chaos::{ChaosFetcher, ChaosOptions, ChaosRule};
+use crate::computed_variables::ComputedVariable;
+use crate::constants::KEY_QUERY;
+use crate::differential::{Comparison, DifferentialOptions};
+use crate::error_responses::{self, ErrorResponses, FailureCategory};
+use crate::event_source::EventSources;
 use crate::executor::Executor;
 use crate::fetcher::HttpFetcher;
+use crate::incremental::{self, IncrementalDelivery, IncrementalFormat, Payload};
+use crate::inject::InjectRule;
+use crate::metrics::{self, METRICS};
+use crate::mirror::{MirrorFetcher, MirrorOptions};
+use crate::operation_policy::{self, OperationPolicy};
+use crate::public_schema::{PublicSchema, PublicSchemaOptions};
+use crate::query_plan;
+use crate::record::{RecordOptions, RecordingFetcher};
+use crate::recover::panic_response;
+use crate::response_headers::ResponseHeaderOptions;
+use crate::response_limits::ResponseLimits;
+use crate::response_rules::{self, ResponseRule};
 use crate::service_route::ServiceRouteTable;
+use crate::shaping;
+use crate::slo::{SloFetcher, SloOptions};
+use crate::slow_query_log::SlowQueryLogOptions;
+use crate::sse;
+use crate::switches::{self, DisabledFields, RuntimeSwitches};
+use crate::token_refresh::TokenRefreshOptions;
+use crate::upstream_pool;
+use crate::validation_cache::{ValidationCache, ValidationCacheOptions, ValidationKey};
+use crate::version_check::{self, ComposedVersions, VersionCheckFetcher};
+use crate::websocket::{ActiveGuard, Protocols, WebSocketController, ACTIVE_SUBSCRIPTIONS};
+
+/// Options for fetching the SDL of the services when the schema is updated.
+#[derive(Debug, Clone)]
+pub struct SchemaUpdateOptions {
+    /// Timeout for fetching the SDL of a single service.
+    pub fetch_timeout: Duration,
+
+    /// Maximum size in bytes of the response of a single service to the SDL
+    /// query, the download is aborted as soon as it is exceeded.
+    pub max_sdl_size: usize,
+
+    /// If `true`, the schema is not updated when any service fails,
+    /// otherwise the schema is composed from the remaining services.
+    pub strict: bool,
+
+    /// Response header in which the services report the version of their
+    /// schema, such as a hash of their SDL.
+    ///
+    /// The version returned with the SDL is compared with the version
+    /// returned with every response, to detect the services deployed without
+    /// updating the composed schema.
+    pub version_header: Option<String>,
+
+    /// Interval between the updates of the schema.
+    pub interval: Duration,
+}
+
+impl Default for SchemaUpdateOptions {
+    fn default() -> Self {
+        Self {
+            fetch_timeout: Duration::from_secs(10),
+            max_sdl_size: 4 * 1024 * 1024,
+            strict: true,
+            version_header: None,
+            interval: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Options of a single client request.
+#[derive(Debug, Clone, Default)]
+pub struct QueryOptions {
+    /// Remove the fields whose value is `null` from the response data.
+    pub remove_nulls: bool,
+
+    /// Replace the repeated entities of the response data with references to
+    /// the `records` extension.
+    pub normalize: bool,
+
+    /// Record the requests sent to the services, if recording is configured.
+    pub record: bool,
+
+    /// Fault injection rules from the chaos header, used if allowed by the configuration.
+    #[cfg(feature = "chaos")]
+    pub chaos: Vec<ChaosRule>,
+
+    /// Use the full schema instead of the public schema, if a public schema is configured.
+    pub authenticated: bool,
+
+    /// Address of the client, written to the slow query log.
+    pub client_ip: Option<IpAddr>,
+
+    /// Deliver the deferred fragments and the streamed lists in subsequent
+    /// payloads, if the client accepts incremental responses.
+    pub incremental: Option<IncrementalFormat>,
+
+    /// Stream the responses of the subscriptions with Server-Sent Events, if
+    /// the client accepts `text/event-stream`.
+    pub event_stream: bool,
+
+    /// Return the graph of the query plan in this format, if the query plan
+    /// extension is enabled.
+    pub query_plan: Option<GraphFormat>,
+}
 
 enum Command {
     Change(ServiceRouteTable),
 }
 
+/// Result of the last SDL fetch from a service.
+#[derive(Debug, Clone)]
+pub(crate) struct ServiceHealth {
+    pub(crate) error: Option<String>,
+    pub(crate) checked_at: DateTime<Utc>,
+    pub(crate) sdl: Option<String>,
+}
+
+/// Version of the composed schema, incremented whenever the SDL of any service changes.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SchemaVersion {
+    pub(crate) version: u64,
+    pub(crate) updated_at: Option<DateTime<Utc>>,
+    hash: u64,
+}
+
 struct Inner {
     schema: Option<Arc<ComposedSchema>>,
     route_table: Option<Arc<ServiceRouteTable>>,
+    update_options: SchemaUpdateOptions,
+
+    /// If `true`, the schema is not composed from the SDL of the services.
+    static_schema: bool,
+
+    /// If `true`, the last update of the schema failed.
+    update_failed: bool,
+
+    schema_version: SchemaVersion,
+    health: HashMap<String, ServiceHealth>,
+
+    /// Schema versions of the services, reported with the SDL used in the
+    /// composed schema.
+    service_versions: Arc<HashMap<String, String>>,
 }
 
 #[derive(Clone)]
@@ -30,6 +179,36 @@ pub struct SharedRouteTable {
     inner: Arc<RwLock<Inner>>,
     tx: mpsc::UnboundedSender<Command>,
     receive_headers: Vec<String>,
+    forward_extensions: Vec<String>,
+    public_schema: Option<Arc<PublicSchema>>,
+    response_headers: Arc<ResponseHeaderOptions>,
+    error_responses: Arc<ErrorResponses>,
+    plan_limits: PlanLimits,
+    validation_cache: Option<Arc<ValidationCache>>,
+    strict_planning: bool,
+    strict_responses: bool,
+    query_plan_extension: bool,
+    response_rules: Arc<Vec<ResponseRule>>,
+    gateway_fields: Option<Arc<ServiceDocument>>,
+    shaping_directives: Arc<Vec<ShapingDirective>>,
+    websocket_protocols: Arc<Vec<Protocols>>,
+    scalar_validators: Arc<HashMap<String, ScalarValidator>>,
+    inline_variables: Arc<InlineVariables>,
+    operation_policies: Arc<HashMap<String, OperationPolicy>>,
+    audit: Option<AuditOptions>,
+    token_refresh: Option<Arc<TokenRefreshOptions>>,
+    record: Option<RecordOptions>,
+    slo: Option<Arc<SloOptions>>,
+    mirror: Option<Arc<MirrorOptions>>,
+    differential: Option<Arc<DifferentialOptions>>,
+    slow_query_log: Option<Arc<SlowQueryLogOptions>>,
+    #[cfg(feature = "chaos")]
+    chaos: Option<ChaosOptions>,
+    inject_rules: Arc<std::sync::RwLock<Arc<Vec<InjectRule>>>>,
+    computed_variables: Arc<Vec<ComputedVariable>>,
+    switches: RuntimeSwitches,
+    event_sources: Arc<EventSources>,
+    callbacks: Option<Arc<CallbackRegistry>>,
 }
 
 impl Default for SharedRouteTable {
@@ -39,9 +218,45 @@ impl Default for SharedRouteTable {
             inner: Arc::new(RwLock::new(Inner {
                 schema: None,
                 route_table: None,
+                update_options: Default::default(),
+                static_schema: false,
+                update_failed: false,
+                schema_version: Default::default(),
+                health: Default::default(),
+                service_versions: Default::default(),
             })),
             tx,
             receive_headers: vec![],
+            forward_extensions: vec![],
+            public_schema: None,
+            response_headers: Default::default(),
+            error_responses: Default::default(),
+            plan_limits: Default::default(),
+            validation_cache: None,
+            strict_planning: false,
+            strict_responses: false,
+            query_plan_extension: false,
+            response_rules: Default::default(),
+            gateway_fields: None,
+            shaping_directives: Default::default(),
+            websocket_protocols: Arc::new(Protocols::ALL.to_vec()),
+            scalar_validators: Default::default(),
+            inline_variables: Default::default(),
+            operation_policies: Default::default(),
+            audit: None,
+            token_refresh: None,
+            record: None,
+            slo: None,
+            mirror: None,
+            differential: None,
+            slow_query_log: None,
+            #[cfg(feature = "chaos")]
+            chaos: None,
+            inject_rules: Default::default(),
+            computed_variables: Default::default(),
+            switches: Default::default(),
+            event_sources: Default::default(),
+            callbacks: None,
         };
         tokio::spawn({
             let shared_route_table = shared_route_table.clone();
@@ -52,26 +267,26 @@ impl Default for SharedRouteTable {
 }
 
 impl SharedRouteTable {
+    /// Updates the schema periodically and when the routing table changes.
+    ///
+    /// The timers use the tokio clock, so they follow the virtual time of a
+    /// runtime whose time is paused.
     async fn update_loop(self, mut rx: mpsc::UnboundedReceiver<Command>) {
-        let mut update_interval = tokio::time::interval_at(
-            Instant::now() + Duration::from_secs(3),
-            Duration::from_secs(30),
-        );
+        let mut next_update = Instant::now() + Duration::from_secs(3);
 
         loop {
             tokio::select! {
-                _ = update_interval.tick() => {
-                    if let Err(err) = self.update().await {
-                        tracing::error!(error = %err, "Failed to update schema.");
-                    }
+                _ = tokio::time::sleep_until(next_update) => {
+                    self.update_schema(None).await;
+                    next_update = Instant::now() + self.inner.read().await.update_options.interval;
                 }
                 command = rx.recv() => {
                     if let Some(command) = command {
                         match command {
                             Command::Change(route_table) => {
-                                let mut inner = self.inner.write().await;
-                                inner.route_table = Some(Arc::new(route_table));
-                                inner.schema = None;
+                                if let Some(services) = self.apply_route_table(route_table).await {
+                                    self.update_schema(services.as_deref()).await;
+                                }
                             }
                         }
                     }
@@ -80,7 +295,60 @@ impl SharedRouteTable {
         }
     }
 
-    async fn update(&self) -> Result<()> {
+    /// Replace the routing table, returns `None` if nothing has changed,
+    /// otherwise the services whose SDL must be fetched, all of them if
+    /// `Some(None)`.
+    ///
+    /// The current schema is kept until it is recomposed, and requests that are
+    /// still using the previous routing table are allowed to finish. The state
+    /// of the removed services is dropped, and the services whose route has
+    /// not changed keep theirs.
+    async fn apply_route_table(
+        &self,
+        route_table: ServiceRouteTable,
+    ) -> Option<Option<Vec<String>>> {
+        let mut inner = self.inner.write().await;
+        let diff = inner
+            .route_table
+            .as_ref()
+            .map(|current| current.diff(&route_table));
+        let services = match diff {
+            Some(diff) => {
+                if diff.is_empty() {
+                    return None;
+                }
+                tracing::info!(
+                    added = ?diff.added,
+                    removed = ?diff.removed,
+                    changed = ?diff.changed,
+                    "Route table changed."
+                );
+                for service in &diff.removed {
+                    inner.health.remove(service);
+                    upstream_pool::remove_service(service);
+                }
+                let mut versions = HashMap::clone(&inner.service_versions);
+                versions.retain(|service, _| route_table.contains_key(service));
+                inner.service_versions = Arc::new(versions);
+                Some(diff.added.into_iter().chain(diff.changed).collect())
+            }
+            None => None,
+        };
+        inner.route_table = Some(Arc::new(route_table));
+        Some(services)
+    }
+
+    /// Updates the schema, only the SDL of the specified services is fetched
+    /// if any, the other services keep the SDL of their last fetch.
+    async fn update_schema(&self, services: Option<&[String]>) {
+        let res = self.update(services).await;
+        if let Err(err) = &res {
+            tracing::error!(error = %err, "Failed to update schema.");
+        }
+        self.inner.write().await.update_failed = res.is_err();
+    }
+
+    async fn update(&self, services: Option<&[String]>) -> Result<()> {
         const QUERY_SDL: &str = "{ _service { sdl }}";
 
         #[derive(Deserialize)]
@@ -94,29 +362,188 @@ impl SharedRouteTable {
             sdl: String,
         }
 
-        let route_table = match self.inner.read().await.route_table.clone() {
-            Some(route_table) => route_table,
-            None => return Ok(()),
+        let (route_table, options, previous_health, previous_versions) = {
+            let inner = self.inner.read().await;
+            if inner.static_schema {
+                return Ok(());
+            }
+            match inner.route_table.clone() {
+                Some(route_table) => (
+                    route_table,
+                    inner.update_options.clone(),
+                    inner.health.clone(),
+                    inner.service_versions.clone(),
+                ),
+                None => return Ok(()),
+            }
+        };
+
+        // The services that are not fetched keep the SDL and the health of
+        // their last successful fetch.
+        let reused = match services {
+            Some(services) => route_table
+                .keys()
+                .filter(|service| !services.contains(service))
+                .filter_map(|service| {
+                    let health = previous_health.get(service)?;
+                    health.sdl.as_ref()?;
+                    Some((service.clone(), health.clone()))
+                })
+                .collect::<HashMap<_, _>>(),
+            None => HashMap::new(),
         };
 
-        let resp = futures_util::future::try_join_all(route_table.keys().map(|service| {
+        let results = futures_util::future::join_all(route_table.keys().map(|service| {
             let route_table = route_table.clone();
+            let options = &options;
+            let reused_sdl = reused.get(service).and_then(|health| health.sdl.clone());
+            let previous_versions = &previous_versions;
+            let limits = ResponseLimits {
+                max_size: Some(options.max_sdl_size),
+                ..Default::default()
+            };
             async move {
-                let resp = route_table
-                    .query(service, Request::new(QUERY_SDL), None, Some(true))
-                    .await
-                    .with_context(|| format!("Failed to fetch SDL from '{}'.", service))?;
+                if let Some(sdl) = reused_sdl {
+                    let document = parser::parse_schema(&sdl)
+                        .with_context(|| format!("Invalid SDL from '{}'.", service))?;
+                    return Ok((sdl, document, previous_versions.get(service).cloned()));
+                }
+
+                let resp = tokio::time::timeout(
+                    options.fetch_timeout,
+                    route_table.introspect(service, Request::new(QUERY_SDL), &limits),
+                )
+                .await
+                .map_err(|_| anyhow::anyhow!("Timeout after {:?}.", options.fetch_timeout))
+                .and_then(|res| res)
+                .with_context(|| format!("Failed to fetch SDL from '{}'.", service))?;
+                let version = options
+                    .version_header
+                    .as_deref()
+                    .and_then(|header| version_check::response_version(&resp, header));
                 let resp: ResponseQuery =
                     value::from_value(resp.data).context("Failed to parse response.")?;
-                let document = parser::parse_schema(resp.service.sdl)
+                let document = parser::parse_schema(&resp.service.sdl)
                     .with_context(|| format!("Invalid SDL from '{}'.", service))?;
-                Ok::<_, Error>((service.to_string(), document))
+                Ok::<_, Error>((resp.service.sdl, document, version))
             }
         }))
-        .await?;
+        .await;
 
-        let schema = ComposedSchema::combine(resp)?;
-        self.inner.write().await.schema = Some(Arc::new(schema));
+        let checked_at = Utc::now();
+        let mut health = HashMap::new();
+        let mut sdls = Vec::with_capacity(results.len());
+        let mut documents = Vec::with_capacity(results.len());
+        let mut versions = HashMap::new();
+        let mut error = None;
+        for ((service, route), result) in route_table.iter().zip(results) {
+            // A service that is down during a planned maintenance keeps the
+            // SDL of its last successful fetch, its fields fail with the
+            // maintenance error instead of being removed from the schema.
+            let result = match (result, route.maintenance(checked_at)) {
+                (Err(err), Some(_)) => match self
+                    .inner
+                    .read()
+                    .await
+                    .health
+                    .get(service)
+                    .and_then(|health| health.sdl.clone())
+                {
+                    Some(sdl) => {
+                        tracing::info!(
+                            service = %service,
+                            error = %format!("{:#}", err),
+                            "Service is under planned maintenance, the previous SDL is used."
+                        );
+                        parser::parse_schema(&sdl)
+                            .map(|document| (sdl, document, None))
+                            .map_err(Error::from)
+                    }
+                    None => Err(err),
+                },
+                (result, _) => result,
+            };
+            match result {
+                Ok((sdl, document, version)) => {
+                    health.insert(
+                        service.clone(),
+                        reused
+                            .get(service)
+                            .cloned()
+                            .unwrap_or_else(|| ServiceHealth {
+                                error: None,
+                                checked_at,
+                                sdl: Some(sdl.clone()),
+                            }),
+                    );
+                    sdls.push((service.clone(), sdl));
+                    documents.push((service.clone(), document));
+                    if let Some(version) = version {
+                        versions.insert(service.clone(), version);
+                    }
+                }
+                Err(err) => {
+                    health.insert(
+                        service.clone(),
+                        ServiceHealth {
+                            error: Some(format!("{:#}", err)),
+                            checked_at,
+                            sdl: None,
+                        },
+                    );
+                    if !options.strict {
+                        tracing::warn!(error = ?err, "Service is excluded from the schema.");
+                    } else if error.is_none() {
+                        error = Some(err);
+                    }
+                }
+            }
+        }
+        self.inner.write().await.health = health;
+        if let Some(err) = error {
+            return Err(err);
+        }
+        if documents.is_empty() && !route_table.is_empty() {
+            anyhow::bail!("No services available.");
+        }
+        for (service, event_source) in self.event_sources.iter() {
+            let document = parser::parse_schema(&event_source.sdl)
+                .with_context(|| format!("Invalid SDL of event source '{}'.", service))?;
+            sdls.push((service.clone(), event_source.sdl.clone()));
+            documents.push((service.clone(), document));
+        }
+
+        let mut schema = ComposedSchema::combine(documents)?;
+        self.extend_schema(&mut schema)?;
+        sdls.sort();
+        let mut hasher = DefaultHasher::new();
+        sdls.hash(&mut hasher);
+        let hash = hasher.finish();
+
+        let mut inner = self.inner.write().await;
+        inner.schema = Some(Arc::new(schema));
+        inner.service_versions = Arc::new(versions);
+        if inner.schema_version.updated_at.is_none() || inner.schema_version.hash != hash {
+            if let Some(validation_cache) = &self.validation_cache {
+                validation_cache.clear();
+            }
+            inner.schema_version.version += 1;
+            inner.schema_version.updated_at = Some(checked_at);
+            inner.schema_version.hash = hash;
+        }
+        Ok(())
+    }
+
+    /// Adds the gateway fields and the shaping directives to a composed schema.
+    fn extend_schema(&self, schema: &mut ComposedSchema) -> Result<()> {
+        if let Some(gateway_fields) = &self.gateway_fields {
+            schema
+                .extend_gateway_fields(ServiceDocument::clone(gateway_fields))
+                .context("Invalid gateway fields.")?;
+        }
+        schema
+            .extend_shaping_directives(&self.shaping_directives)
+            .context("Invalid shaping directives.")?;
         Ok(())
     }
 
@@ -124,10 +551,352 @@ impl SharedRouteTable {
         self.tx.send(Command::Change(route_table)).ok();
     }
 
+    pub async fn set_schema_update_options(&self, options: SchemaUpdateOptions) {
+        self.inner.write().await.update_options = options;
+    }
+
+    /// Uses a static schema, such as a supergraph composed ahead of time,
+    /// instead of composing the schema from the SDL of the services.
+    ///
+    /// The routing table can still be changed, which only changes the
+    /// addresses of the services.
+    pub async fn set_static_schema(&self, schema: Arc<ComposedSchema>) {
+        let schema = if self.gateway_fields.is_some() || !self.shaping_directives.is_empty() {
+            let mut extended = ComposedSchema::clone(&schema);
+            match self.extend_schema(&mut extended) {
+                Ok(()) => Arc::new(extended),
+                Err(err) => {
+                    tracing::error!(error = %format!("{:#}", err), "Failed to extend the static schema.");
+                    schema
+                }
+            }
+        } else {
+            schema
+        };
+        let mut inner = self.inner.write().await;
+        inner.schema = Some(schema);
+        inner.static_schema = true;
+        if let Some(validation_cache) = &self.validation_cache {
+            validation_cache.clear();
+        }
+        inner.schema_version.version += 1;
+        inner.schema_version.updated_at = Some(Utc::now());
+    }
+
     pub fn set_receive_headers(&mut self, receive_headers: Vec<String>) {
         self.receive_headers = receive_headers;
     }
 
+    /// Sets the names of the request extensions forwarded to the services,
+    /// such as tracing flags.
+    pub fn set_forward_extensions(&mut self, forward_extensions: Vec<String>) {
+        self.forward_extensions = forward_extensions;
+    }
+
+    /// Sets the public schema, used for the unauthenticated callers instead of
+    /// the full schema.
+    pub fn set_public_schema(&mut self, public_schema: Option<PublicSchemaOptions>) {
+        self.public_schema = public_schema.map(|options| Arc::new(PublicSchema::new(options)));
+    }
+
+    /// Returns `true` if the caller can use the full schema.
+    pub(crate) fn is_authenticated(
+        &self,
+        claims: Option<&Claims>,
+        client_ip: Option<IpAddr>,
+    ) -> bool {
+        self.public_schema.as_ref().map_or(true, |public_schema| {
+            public_schema.options().is_authenticated(claims, client_ip)
+        })
+    }
+
+    /// Returns the schema used to validate, plan and introspect the operations
+    /// of a caller.
+    pub(crate) fn select_schema(
+        &self,
+        schema: Arc<ComposedSchema>,
+        authenticated: bool,
+    ) -> Arc<ComposedSchema> {
+        match &self.public_schema {
+            Some(public_schema) if !authenticated => public_schema.get(&schema),
+            _ => schema,
+        }
+    }
+
+    pub fn set_response_headers(&mut self, response_headers: ResponseHeaderOptions) {
+        self.response_headers = Arc::new(response_headers);
+    }
+
+    /// Sets the HTTP responses replacing the default responses of some
+    /// failures, such as `503 Service Unavailable` until the schema is composed.
+    pub fn set_error_responses(&mut self, error_responses: ErrorResponses) {
+        self.error_responses = Arc::new(error_responses);
+    }
+
+    pub(crate) fn error_responses(&self) -> &ErrorResponses {
+        &self.error_responses
+    }
+
+    pub fn set_plan_limits(&mut self, plan_limits: PlanLimits) {
+        self.plan_limits = plan_limits;
+    }
+
+    pub fn plan_limits(&self) -> &PlanLimits {
+        &self.plan_limits
+    }
+
+    /// Returns an error response if parsing a document took longer than its
+    /// time budget, before the document is validated.
+    pub(crate) fn check_parse_budget(&self, duration: Duration) -> Option<Response> {
+        let budget = self.plan_limits.max_parse_duration?;
+        if duration <= budget {
+            return None;
+        }
+        let response = Response::from_errors(vec![ServerError::budget_exceeded("parse", budget)]);
+        metrics::count_plan_failure(&response);
+        Some(response)
+    }
+
+    /// Caches the queries validated against the current schema, the cache is
+    /// cleared whenever the schema changes.
+    pub fn set_validation_cache(&mut self, validation_cache: Option<ValidationCacheOptions>) {
+        self.validation_cache =
+            validation_cache.map(|options| Arc::new(ValidationCache::new(options)));
+    }
+
+    /// If enabled, the fields that cannot be planned are reported as errors
+    /// instead of being dropped from the query plan.
+    pub fn set_strict_planning(&mut self, strict_planning: bool) {
+        self.strict_planning = strict_planning;
+    }
+
+    pub fn strict_planning(&self) -> bool {
+        self.strict_planning
+    }
+
+    /// If enabled, the fields returned by the services that the operation
+    /// does not select are removed from the responses.
+    pub fn set_strict_responses(&mut self, strict_responses: bool) {
+        self.strict_responses = strict_responses;
+    }
+
+    /// If enabled, the callers using the full schema can ask for the graph of
+    /// the query plan with the `queryPlan` request extension.
+    pub fn set_query_plan_extension(&mut self, query_plan_extension: bool) {
+        self.query_plan_extension = query_plan_extension;
+    }
+
+    /// Sets the rules transforming the data of the responses, such as masking
+    /// or removing fields.
+    pub fn set_response_rules(&mut self, response_rules: Vec<ResponseRule>) {
+        self.response_rules = Arc::new(response_rules);
+    }
+
+    /// Sets the SDL extension of the fields resolved by the gateway, added to
+    /// the schema whenever it is composed.
+    ///
+    /// See [`ComposedSchema::extend_gateway_fields`].
+    pub fn set_gateway_fields(&mut self, gateway_fields: Option<ServiceDocument>) {
+        self.gateway_fields = gateway_fields.map(Arc::new);
+    }
+
+    /// Sets the directives evaluated by the gateway on the merged responses,
+    /// added to the schema whenever it is composed.
+    ///
+    /// See [`ShapingDirective`].
+    pub fn set_shaping_directives(&mut self, shaping_directives: Vec<ShapingDirective>) {
+        self.shaping_directives = Arc::new(shaping_directives);
+    }
+
+    /// Sets the WebSocket subprotocols accepted from the clients, the first
+    /// one is used if a client does not request any.
+    pub fn set_websocket_protocols(&mut self, websocket_protocols: Vec<Protocols>) {
+        self.websocket_protocols = Arc::new(websocket_protocols);
+    }
+
+    pub fn websocket_protocols(&self) -> &[Protocols] {
+        &self.websocket_protocols
+    }
+
+    /// Sets the validators of the values of the custom scalars in the
+    /// variables, keyed by the name of the scalar.
+    pub fn set_scalar_validators(&mut self, scalar_validators: HashMap<String, ScalarValidator>) {
+        self.scalar_validators = Arc::new(scalar_validators);
+    }
+
+    pub fn scalar_validators(&self) -> &Arc<HashMap<String, ScalarValidator>> {
+        &self.scalar_validators
+    }
+
+    /// Sets the services whose queries get the small variables as literals,
+    /// for the services that cache the responses by query text.
+    pub fn set_inline_variables(&mut self, inline_variables: InlineVariables) {
+        self.inline_variables = Arc::new(inline_variables);
+    }
+
+    pub fn inline_variables(&self) -> &Arc<InlineVariables> {
+        &self.inline_variables
+    }
+
+    /// Sets the policies of the operations with a specific name, such as a
+    /// timeout or plan limits replacing the plan limits of the gateway.
+    pub fn set_operation_policies(&mut self, operation_policies: Vec<OperationPolicy>) {
+        self.operation_policies = Arc::new(
+            operation_policies
+                .into_iter()
+                .map(|policy| (policy.name.clone(), policy))
+                .collect(),
+        );
+    }
+
+    pub fn set_audit(&mut self, audit: Option<AuditOptions>) {
+        self.audit = audit;
+    }
+
+    pub fn set_record(&mut self, record: Option<RecordOptions>) {
+        self.record = record;
+    }
+
+    pub fn set_slo(&mut self, slo: Option<SloOptions>) {
+        self.slo = slo.map(Arc::new);
+    }
+
+    pub fn set_mirror(&mut self, mirror: Option<MirrorOptions>) {
+        self.mirror = mirror.map(Arc::new);
+    }
+
+    pub fn set_differential(&mut self, differential: Option<DifferentialOptions>) {
+        self.differential = differential.map(Arc::new);
+    }
+
+    pub fn set_slow_query_log(&mut self, slow_query_log: Option<SlowQueryLogOptions>) {
+        self.slow_query_log = slow_query_log.map(Arc::new);
+    }
+
+    #[cfg(feature = "chaos")]
+    pub fn set_chaos(&mut self, chaos: Option<ChaosOptions>) {
+        self.chaos = chaos;
+    }
+
+    /// Replaces the inject rules, the change is visible to all clones of this route table.
+    pub fn set_inject_rules(&self, inject_rules: Vec<InjectRule>) {
+        *self.inject_rules.write().unwrap() = Arc::new(inject_rules);
+    }
+
+    pub fn inject_rules(&self) -> Arc<Vec<InjectRule>> {
+        self.inject_rules.read().unwrap().clone()
+    }
+
+    /// Sets the variables computed from the client requests, such as the
+    /// locale from the `Accept-Language` header.
+    pub fn set_computed_variables(&mut self, computed_variables: Vec<ComputedVariable>) {
+        self.computed_variables = Arc::new(computed_variables);
+    }
+
+    pub fn computed_variables(&self) -> &[ComputedVariable] {
+        &self.computed_variables
+    }
+
+    pub fn set_switches(&mut self, switches: RuntimeSwitches) {
+        self.switches = switches;
+    }
+
+    pub fn switches(&self) -> &RuntimeSwitches {
+        &self.switches
+    }
+
+    pub fn set_event_sources(&mut self, event_sources: EventSources) {
+        self.event_sources = Arc::new(event_sources);
+    }
+
+    pub fn event_sources(&self) -> &Arc<EventSources> {
+        &self.event_sources
+    }
+
+    pub fn set_callbacks(&mut self, callbacks: Option<Arc<CallbackRegistry>>) {
+        self.callbacks = callbacks;
+    }
+
+    pub fn callbacks(&self) -> Option<&Arc<CallbackRegistry>> {
+        self.callbacks.as_ref()
+    }
+
+    /// Allows the WebSocket clients to refresh some headers with the payload
+    /// of a `ping` message.
+    pub fn set_token_refresh(&mut self, token_refresh: Option<TokenRefreshOptions>) {
+        self.token_refresh = token_refresh.map(Arc::new);
+    }
+
+    pub fn token_refresh(&self) -> Option<&Arc<TokenRefreshOptions>> {
+        self.token_refresh.as_ref()
+    }
+
+    #[cfg(feature = "chaos")]
+    fn chaos_rules(&self, header_rules: Vec<ChaosRule>) -> Vec<ChaosRule> {
+        match &self.chaos {
+            Some(chaos) if chaos.allow_header => header_rules
+                .into_iter()
+                .chain(chaos.rules.clone())
+                .collect(),
+            Some(chaos) => chaos.rules.clone(),
+            None => Vec::new(),
+        }
+    }
+
+    pub(crate) async fn schema_version(&self) -> (SchemaVersion, bool) {
+        let inner = self.inner.read().await;
+        (inner.schema_version.clone(), inner.static_schema)
+    }
+
+    pub(crate) async fn route_table(&self) -> Option<Arc<ServiceRouteTable>> {
+        self.inner.read().await.route_table.clone()
+    }
+
+    pub(crate) async fn health(&self) -> HashMap<String, ServiceHealth> {
+        self.inner.read().await.health.clone()
+    }
+
+    pub(crate) fn receive_headers(&self) -> &[String] {
+        &self.receive_headers
+    }
+
+    pub(crate) fn audit_enabled(&self) -> bool {
+        self.audit.is_some()
+    }
+
+    /// Returns the execution report of an operation whose requests are sent
+    /// over WebSocket, or `None` if auditing is disabled.
+    pub(crate) fn stream_audit(
+        &self,
+        document: &ExecutableDocument,
+        operation_name: Option<&str>,
+    ) -> Option<Arc<StreamAudit>> {
+        let options = self.audit.clone()?;
+        Some(StreamAudit::new(
+            options,
+            operation_name.map(ToString::to_string),
+            operation_signature(document, operation_name),
+        ))
+    }
+
+    pub(crate) fn record_enabled(&self) -> bool {
+        self.record.is_some()
+    }
+
+    pub(crate) fn slo_enabled(&self) -> bool {
+        self.slo.is_some()
+    }
+
+    /// Returns the schema versions of the services in the composed schema, if
+    /// the services report their version.
+    async fn composed_versions(&self) -> Option<ComposedVersions> {
+        let inner = self.inner.read().await;
+        Some(ComposedVersions {
+            header: inner.update_options.version_header.clone()?,
+            versions: inner.service_versions.clone(),
+        })
+    }
+
     pub async fn get(&self) -> Option<(Arc<ComposedSchema>, Arc<ServiceRouteTable>)> {
         let (composed_schema, route_table) = {
             let inner = self.inner.read().await;
@@ -136,9 +905,223 @@ impl SharedRouteTable {
         composed_schema.zip(route_table)
     }
 
-    pub async fn query(&self, request: Request, header_map: HeaderMap) -> HttpResponse<String> {
+    /// Executes a request, a panic while planning or executing it is answered
+    /// with `500 Internal Server Error` instead of dropping the connection.
+    ///
+    /// If the client accepts incremental responses and the operation defers
+    /// some fragments, the payloads are streamed as they are executed.
+    ///
+    /// If the client accepts Server-Sent Events and the operation is a
+    /// subscription, its responses are streamed as they are received.
+    pub async fn query(
+        &self,
+        request: Request,
+        header_map: HeaderMap,
+        injected_variables: Variables,
+        options: QueryOptions,
+    ) -> HttpResponse<Body> {
+        if options.event_stream && self.switches.check_maintenance().is_none() {
+            let parse_started = Instant::now();
+            if let Ok(document) = parser::parse_query(&request.query) {
+                if is_subscription(&document, request.operation.as_deref()) {
+                    return self
+                        .subscribe(
+                            request,
+                            document,
+                            parse_started.elapsed(),
+                            header_map,
+                            injected_variables,
+                            options,
+                        )
+                        .await;
+                }
+            }
+        }
+
+        let format = match options.incremental {
+            Some(format) => format,
+            None => {
+                return self
+                    .execute_catch_unwind(request, header_map, injected_variables, options, None)
+                    .await
+                    .map(Body::from)
+            }
+        };
+
+        // The execution outlives the response once the initial payload is
+        // sent, so it owns a copy of the route table.
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let shared_route_table = self.clone();
+        let mut task = tokio::spawn(opentelemetry::trace::FutureExt::with_context(
+            async move {
+                shared_route_table
+                    .execute_catch_unwind(
+                        request,
+                        header_map,
+                        injected_variables,
+                        options,
+                        Some(tx),
+                    )
+                    .await
+            },
+            OpenTelemetryContext::current(),
+        ));
+        tokio::select! {
+            biased;
+            Some(initial) = rx.recv() => incremental::http_response(format, initial, rx),
+            res = &mut task => match res {
+                Ok(resp) => resp.map(Body::from),
+                Err(err) => HttpResponse::builder()
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .body(Body::from(err.to_string()))
+                    .unwrap(),
+            },
+        }
+    }
+
+    /// Executes a subscription whose responses are sent with Server-Sent
+    /// Events, the subscription ends when the client closes the connection.
+    async fn subscribe(
+        &self,
+        request: Request,
+        document: ExecutableDocument,
+        parse_duration: Duration,
+        header_map: HeaderMap,
+        injected_variables: Variables,
+        options: QueryOptions,
+    ) -> HttpResponse<Body> {
+        if let Some(resp) = self.check_parse_budget(parse_duration).or_else(|| {
+            self.switches
+                .check_operation(&document, request.operation.as_deref())
+        }) {
+            return sse::error_response(resp);
+        }
+
+        let (schema, route_table) = match self.get().await {
+            Some(schema) => schema,
+            None => {
+                return sse::error_response(Response::from_errors(vec![ServerError::new(
+                    "Not ready.",
+                )
+                .with_code(ErrorCode::NotReady)]))
+            }
+        };
+        let schema = self.select_schema(schema, options.authenticated);
+        let controller = WebSocketController::new(
+            route_table,
+            self.event_sources.clone(),
+            self.callbacks.clone(),
+            &header_map,
+            &injected_variables,
+            None,
+        )
+        .with_audit(self.stream_audit(&document, request.operation.as_deref()));
+
+        let plan_limits = self.plan_limits.clone();
+        let strict_planning = self.strict_planning;
+        let scalar_validators = self.scalar_validators.clone();
+        let inline_variables = self.inline_variables.clone();
+        let shared_route_table = self.clone();
+        let Request {
+            operation: operation_name,
+            variables,
+            ..
+        } = request;
+        let QueryOptions {
+            remove_nulls,
+            normalize,
+            authenticated,
+            ..
+        } = options;
+        let stream = async_stream::stream! {
+            let _subscription = ActiveGuard::new(&ACTIVE_SUBSCRIPTIONS);
+            let mut builder = PlanBuilder::new(&schema, document)
+                .variables(variables)
+                .limits(plan_limits)
+                .strict(strict_planning)
+                .scalar_validators(scalar_validators)
+                .inline_variables(inline_variables);
+            if let Some(operation_name) = operation_name.clone() {
+                builder = builder.operation_name(operation_name);
+            }
+            let node = match builder.plan() {
+                Ok(node) => node,
+                Err(resp) => {
+                    metrics::count_plan_failure(&resp);
+                    yield resp;
+                    return;
+                }
+            };
+            if let Some(resp) = shared_route_table.switches.check_plan(&schema, builder.document(), operation_name.as_deref(), &node) {
+                yield resp;
+                return;
+            }
+            let executor = Executor::new(&schema);
+            let mut stream = executor.execute_stream(controller, "1", &node).await;
+            while let Some(mut item) = stream.next().await {
+                shared_route_table.process_response(&mut item, &schema, &builder, operation_name.as_deref(), authenticated, remove_nulls, &DisabledFields::default());
+                if normalize {
+                    if let Some(records) = shaping::normalize(&mut item.data) {
+                        item.extensions.insert(shaping::RECORDS_EXTENSION.to_string(), records);
+                    }
+                }
+                yield item;
+            }
+        };
+        sse::http_response(
+            AssertUnwindSafe(stream)
+                .catch_unwind()
+                .map(|item| item.unwrap_or_else(panic_response)),
+        )
+    }
+
+    async fn execute_catch_unwind(
+        &self,
+        request: Request,
+        header_map: HeaderMap,
+        injected_variables: Variables,
+        options: QueryOptions,
+        incremental: Option<mpsc::UnboundedSender<Payload>>,
+    ) -> HttpResponse<String> {
+        match AssertUnwindSafe(self.execute(
+            request,
+            header_map,
+            injected_variables,
+            options,
+            incremental,
+        ))
+        .catch_unwind()
+        .await
+        {
+            Ok(resp) => resp,
+            Err(panic) => HttpResponse::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(serde_json::to_string(&panic_response(panic)).unwrap())
+                .unwrap(),
+        }
+    }
+
+    async fn execute(
+        &self,
+        request: Request,
+        header_map: HeaderMap,
+        injected_variables: Variables,
+        options: QueryOptions,
+        incremental: Option<mpsc::UnboundedSender<Payload>>,
+    ) -> HttpResponse<String> {
+        if let Some((response, retry_after)) = self.switches.check_maintenance() {
+            let mut builder = HttpResponse::builder().status(StatusCode::SERVICE_UNAVAILABLE);
+            if let Some(retry_after) = retry_after {
+                builder = builder.header(RETRY_AFTER, retry_after);
+            }
+            return builder
+                .body(serde_json::to_string(&response).unwrap())
+                .unwrap();
+        }
+
         let tracer = global::tracer("graphql");
 
+        let parse_started = std::time::Instant::now();
         let document = match tracer.in_span("parse", |_| parser::parse_query(&request.query)) {
             Ok(document) => document,
             Err(err) => {
@@ -148,34 +1131,154 @@ impl SharedRouteTable {
                     .unwrap();
             }
         };
+        if let Some(response) = self.check_parse_budget(parse_started.elapsed()) {
+            return HttpResponse::builder()
+                .status(StatusCode::OK)
+                .body(serde_json::to_string(&response).unwrap())
+                .unwrap();
+        }
+
+        let signature = operation_signature(&document, request.operation.as_deref());
+        if let Some(signature) = &signature {
+            get_active_span(|span| span.set_attribute(KEY_QUERY.string(signature.clone())));
+        }
+
+        if let Some(response) = self
+            .switches
+            .check_operation(&document, request.operation.as_deref())
+        {
+            return HttpResponse::builder()
+                .status(StatusCode::OK)
+                .body(serde_json::to_string(&response).unwrap())
+                .unwrap();
+        }
 
-        let (composed_schema, route_table) = match self.get().await {
+        if let Some(response) = subscription_over_http(
+            &document,
+            request.operation.as_deref(),
+            &self.websocket_protocols,
+        ) {
+            return HttpResponse::builder()
+                .status(StatusCode::OK)
+                .body(serde_json::to_string(&response).unwrap())
+                .unwrap();
+        }
+
+        let is_query = switches::get_operation(&document, request.operation.as_deref())
+            .map_or(false, |operation| operation.node.ty == OperationType::Query);
+        let mirror = self
+            .mirror
+            .as_deref()
+            .filter(|mirror| is_query && mirror.sample());
+        if let Some(mirror) = mirror {
+            mirror.mirror_request(&request, &header_map);
+        }
+        let comparison = self
+            .differential
+            .as_deref()
+            .filter(|differential| is_query && differential.sample())
+            .map(|differential| {
+                (
+                    differential,
+                    Comparison {
+                        request: request.clone(),
+                        header_map: header_map.clone(),
+                        injected_variables: injected_variables.clone(),
+                        signature: signature.clone(),
+                    },
+                )
+            });
+
+        let policy = operation_policy::operation_name(&document, request.operation.as_deref())
+            .and_then(|name| self.operation_policies.get(name));
+
+        let (schema_version, schema) = {
+            let inner = self.inner.read().await;
+            (
+                inner.schema_version.version,
+                inner.schema.clone().zip(inner.route_table.clone()),
+            )
+        };
+        let (composed_schema, route_table) = match schema {
             Some((composed_schema, route_table)) => (composed_schema, route_table),
             _ => {
-                return HttpResponse::builder()
-                    .status(StatusCode::BAD_REQUEST)
-                    .body(
-                        serde_json::to_string(&Response {
-                            data: ConstValue::Null,
-                            errors: vec![ServerError::new("Not ready.")],
-                            extensions: Default::default(),
-                            headers: Default::default(),
-                        })
-                        .unwrap(),
+                let category = if self.inner.read().await.update_failed {
+                    FailureCategory::CompositionFailed
+                } else {
+                    FailureCategory::NotReady
+                };
+                return self.error_responses.response(
+                    category,
+                    StatusCode::BAD_REQUEST,
+                    serde_json::to_string(&Response::from_errors(vec![ServerError::new(
+                        "Not ready.",
                     )
-                    .unwrap();
+                    .with_code(ErrorCode::NotReady)]))
+                    .unwrap(),
+                );
             }
         };
+        let composed_schema = self.select_schema(composed_schema, options.authenticated);
 
-        let mut plan_builder =
-            PlanBuilder::new(&composed_schema, document).variables(request.variables);
-        if let Some(operation) = request.operation {
+        let record = self.record.as_ref().filter(|_| options.record);
+        let recorded_request = record.map(|_| request.clone());
+        let validation_cache_key = self.validation_cache.as_ref().map(|validation_cache| {
+            let key = ValidationKey::new(
+                &document,
+                request.operation.as_deref(),
+                schema_version,
+                options.authenticated,
+            );
+            let validated = validation_cache.contains(&key);
+            (validation_cache, key, validated)
+        });
+        let disabled_fields = self.switches.disabled_fields(
+            &composed_schema,
+            &document,
+            request.operation.as_deref(),
+        );
+        let mut plan_builder = PlanBuilder::new(&composed_schema, document)
+            .variables(request.variables)
+            .limits(
+                policy
+                    .and_then(|policy| policy.plan_limits.clone())
+                    .unwrap_or_else(|| self.plan_limits.clone()),
+            )
+            .strict(self.strict_planning)
+            .scalar_validators(self.scalar_validators.clone())
+            .inline_variables(self.inline_variables.clone())
+            .validated(
+                validation_cache_key
+                    .as_ref()
+                    .map_or(false, |(_, _, validated)| *validated),
+            );
+        if let Some(operation) = request.operation.clone() {
             plan_builder = plan_builder.operation_name(operation);
         }
+        if !disabled_fields.is_empty() {
+            plan_builder = match plan_builder
+                .rewrite(|document| disabled_fields.remove_from(&composed_schema, document))
+            {
+                Ok(plan_builder) => plan_builder,
+                Err(response) => {
+                    metrics::count_plan_failure(&response);
+                    return HttpResponse::builder()
+                        .status(StatusCode::OK)
+                        .body(serde_json::to_string(&response).unwrap())
+                        .unwrap();
+                }
+            };
+        }
 
         let plan = match tracer.in_span("plan", |_| plan_builder.plan()) {
-            Ok(plan) => plan,
+            Ok(plan) => {
+                if let Some((validation_cache, key, false)) = validation_cache_key {
+                    validation_cache.insert(key);
+                }
+                plan
+            }
             Err(response) => {
+                metrics::count_plan_failure(&response);
                 return HttpResponse::builder()
                     .status(StatusCode::OK)
                     .body(serde_json::to_string(&response).unwrap())
@@ -183,15 +1286,225 @@ impl SharedRouteTable {
             }
         };
 
-        let executor = Executor::new(&composed_schema);
-        let resp = opentelemetry::trace::FutureExt::with_context(
-            executor.execute_query(&HttpFetcher::new(&*route_table, &header_map), &plan),
-            OpenTelemetryContext::current_with_span(tracer.span_builder("execute").start(&tracer)),
-        )
-        .await;
+        if let Some(response) = self.switches.check_plan(
+            &composed_schema,
+            plan_builder.document(),
+            request.operation.as_deref(),
+            &plan,
+        ) {
+            return HttpResponse::builder()
+                .status(StatusCode::OK)
+                .body(serde_json::to_string(&response).unwrap())
+                .unwrap();
+        }
+
+        let plan_graph = options
+            .query_plan
+            .filter(|_| self.query_plan_extension && options.authenticated)
+            .map(|format| plan.to_graph(format));
+
+        let executor = Executor::new(&composed_schema).response_headers(&self.response_headers);
+        let forward_extensions = request
+            .extensions
+            .iter()
+            .filter(|(name, _)| self.forward_extensions.contains(name))
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        let fetcher = HttpFetcher::new(
+            &*route_table,
+            &header_map,
+            &injected_variables,
+            &forward_extensions,
+        );
+        let fetcher = MirrorFetcher::new(fetcher, mirror, &header_map);
+        #[cfg(feature = "chaos")]
+        let fetcher = ChaosFetcher::new(fetcher, self.chaos_rules(options.chaos));
+        let fetcher = SloFetcher::new(fetcher, request.operation.as_deref(), self.slo.as_deref());
+        let fetcher = VersionCheckFetcher::new(fetcher, self.composed_versions().await);
+        let fetcher = AuditFetcher::new(
+            RecordingFetcher::new(fetcher, record.is_some()),
+            self.audit.is_some() || self.slow_query_log.is_some(),
+        );
+        let execute_context =
+            OpenTelemetryContext::current_with_span(tracer.span_builder("execute").start(&tracer));
+        let timeout = policy.and_then(|policy| policy.timeout);
+        // The disabled fields are nulled in the complete response, so the
+        // deferred fragments are not delivered on their own.
+        let incremental = incremental.filter(|_| disabled_fields.is_empty());
+        let (mut resp, delivered) = match (&plan, incremental) {
+            (RootNode::Query(PlanNode::Defer(defer)), Some(tx)) => {
+                let mut delivery = IncrementalDelivery::new(defer);
+                let deliver = opentelemetry::trace::FutureExt::with_context(
+                    async {
+                        let mut responses = executor.execute_incremental(&fetcher, &plan);
+                        while let Some(mut resp) = responses.next().await {
+                            self.process_response(
+                                &mut resp,
+                                &composed_schema,
+                                &plan_builder,
+                                request.operation.as_deref(),
+                                options.authenticated,
+                                options.remove_nulls,
+                                &disabled_fields,
+                            );
+                            let mut headers = if delivery.is_initial() {
+                                Some(self.response_header_map(&resp, policy))
+                            } else {
+                                None
+                            };
+                            for mut payload in delivery.payloads(&resp) {
+                                if let Some(headers) = headers.take() {
+                                    payload.headers = headers;
+                                }
+                                let _ = tx.send(payload);
+                            }
+                        }
+                    },
+                    execute_context,
+                );
+                let timed_out = match timeout {
+                    Some(timeout) => tokio::time::timeout(timeout, deliver).await.is_err(),
+                    None => {
+                        deliver.await;
+                        false
+                    }
+                };
+                match timeout.filter(|_| timed_out) {
+                    Some(timeout) if delivery.is_initial() => {
+                        (Response::from_errors(vec![timeout_error(timeout)]), false)
+                    }
+                    Some(timeout) => {
+                        let _ = tx.send(delivery.finish(vec![timeout_error(timeout)]));
+                        (Response::default(), true)
+                    }
+                    None => (Response::default(), true),
+                }
+            }
+            _ => {
+                let execute = opentelemetry::trace::FutureExt::with_context(
+                    executor.execute_query(&fetcher, &plan),
+                    execute_context,
+                );
+                let resp = match timeout {
+                    Some(timeout) => match tokio::time::timeout(timeout, execute).await {
+                        Ok(resp) => resp,
+                        Err(_) => Response::from_errors(vec![timeout_error(timeout)]),
+                    },
+                    None => execute.await,
+                };
+                (resp, false)
+            }
+        };
+        if let Some((differential, comparison)) = comparison.filter(|_| !delivered) {
+            differential.compare(
+                composed_schema.clone(),
+                &route_table,
+                comparison,
+                resp.clone(),
+            );
+        }
+        if let Some((recording, record)) = recorded_request
+            .and_then(|request| fetcher.inner().take_recording(request))
+            .zip(record.cloned())
+        {
+            tokio::spawn(async move {
+                match recording.save(&record).await {
+                    Ok(path) => tracing::info!(path = %path.display(), "Request recorded."),
+                    Err(err) => tracing::error!(error = %err, "Failed to save recording."),
+                }
+            });
+        }
+        self.process_response(
+            &mut resp,
+            &composed_schema,
+            &plan_builder,
+            request.operation.as_deref(),
+            options.authenticated,
+            options.remove_nulls,
+            &disabled_fields,
+        );
+        if let Some(report) = fetcher.into_report(request.operation, signature) {
+            if let Some(slow_query_log) = &self.slow_query_log {
+                slow_query_log.log(&report, plan.stats(), options.client_ip);
+            }
+            if let Some(audit) = &self.audit {
+                report.emit(audit);
+            }
+        }
+        if delivered {
+            // The payloads are already sent, this response is discarded.
+            return HttpResponse::new(String::new());
+        }
+        if options.normalize {
+            if let Some(records) = shaping::normalize(&mut resp.data) {
+                resp.extensions
+                    .insert(shaping::RECORDS_EXTENSION.to_string(), records);
+            }
+        }
+        if let Some(plan_graph) = plan_graph {
+            resp.extensions.insert(
+                query_plan::QUERY_PLAN_EXTENSION.to_string(),
+                ConstValue::String(plan_graph),
+            );
+        }
+        if self
+            .error_responses
+            .contains(FailureCategory::UpstreamUnavailable)
+            && error_responses::is_upstream_unavailable(&resp)
+        {
+            return self.error_responses.response(
+                FailureCategory::UpstreamUnavailable,
+                StatusCode::OK,
+                serde_json::to_string(&resp).unwrap(),
+            );
+        }
 
         let mut builder = HttpResponse::builder().status(StatusCode::OK);
+        if let Some(x) = builder.headers_mut() {
+            x.extend(self.response_header_map(&resp, policy));
+        }
+        builder.body(serde_json::to_string(&resp).unwrap()).unwrap()
+    }
+
+    /// Removes the fields not selected by the operation, applies the shaping
+    /// directives and the response rules, and nulls the disabled fields.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn process_response(
+        &self,
+        resp: &mut Response,
+        composed_schema: &ComposedSchema,
+        plan_builder: &PlanBuilder<'_>,
+        operation: Option<&str>,
+        authenticated: bool,
+        remove_nulls: bool,
+        disabled_fields: &DisabledFields,
+    ) {
+        if self.strict_responses {
+            shaping::retain_selected(&mut resp.data, plan_builder.document(), operation);
+        }
+        shaping::apply_shaping_directives(
+            &mut resp.data,
+            &composed_schema.shaping_directives,
+            plan_builder.document(),
+            operation,
+            plan_builder.variables(),
+        );
+        response_rules::apply_response_rules(
+            &mut resp.data,
+            &self.response_rules,
+            authenticated,
+            plan_builder.document(),
+            operation,
+        );
+        disabled_fields.apply(resp);
+        if remove_nulls {
+            shaping::remove_nulls(&mut resp.data, &resp.errors);
+        }
+    }
 
+    /// Returns the headers of the HTTP response, the received headers of the
+    /// services and the cache policy of the operation.
+    fn response_header_map(&self, resp: &Response, policy: Option<&OperationPolicy>) -> HeaderMap {
         let mut header_map = HeaderMap::new();
 
         match resp.headers.clone() {
@@ -211,11 +1524,65 @@ impl SharedRouteTable {
             _ => {}
         }
 
-        match builder.headers_mut() {
-            Some(x) => x.extend(header_map),
-            None => {}
+        if let Some(cache_control) = policy
+            .and_then(OperationPolicy::cache_control)
+            .filter(|_| resp.errors.is_empty())
+        {
+            header_map.insert(
+                CACHE_CONTROL,
+                HeaderValue::from_str(&cache_control).unwrap(),
+            );
         }
 
-        builder.body(serde_json::to_string(&resp).unwrap()).unwrap()
+        header_map
     }
 }
+
+fn timeout_error(timeout: Duration) -> ServerError {
+    ServerError::new(format!(
+        "Operation timed out after {}ms.",
+        timeout.as_millis()
+    ))
+    .with_code(ErrorCode::Timeout)
+}
+
+/// Returns `true` if the selected operation is a subscription.
+fn is_subscription(document: &ExecutableDocument, operation_name: Option<&str>) -> bool {
+    switches::get_operation(document, operation_name).map_or(false, |operation| {
+        operation.node.ty == OperationType::Subscription
+    })
+}
+
+/// Returns the error response of a subscription sent over HTTP without
+/// accepting `text/event-stream`, advertising the transports of the
+/// subscriptions and the WebSocket protocols served on the same URL.
+///
+/// The `SUBSCRIPTION_REQUIRES_WEBSOCKET` code is kept for the clients that
+/// already match it.
+fn subscription_over_http(
+    document: &ExecutableDocument,
+    operation_name: Option<&str>,
+    protocols: &[Protocols],
+) -> Option<Response> {
+    if !is_subscription(document, operation_name) {
+        return None;
+    }
+
+    METRICS.subscription_over_http_counter.add(1, &[]);
+    let protocols = protocols
+        .iter()
+        .map(|protocol| ConstValue::String(protocol.sec_websocket_protocol().to_string()))
+        .collect();
+    Some(Response::from_errors(vec![ServerError::new(
+        "Subscriptions over HTTP require the 'Accept: text/event-stream' header, or open a WebSocket connection to the same URL.",
+    )
+    .with_code(ErrorCode::SubscriptionRequiresWebSocket)
+    .with_extension(
+        "transports",
+        ConstValue::List(vec![
+            ConstValue::String("websocket".to_string()),
+            ConstValue::String("sse".to_string()),
+        ]),
+    )
+    .with_extension("protocols", ConstValue::List(protocols))]))
+}
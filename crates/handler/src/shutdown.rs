@@ -0,0 +1,93 @@
+use std::sync::atomic::Ordering;
+
+use once_cell::sync::Lazy;
+use tokio::sync::watch;
+use tokio::time::{Duration, Instant};
+
+use crate::websocket::ACTIVE_CONNECTIONS;
+
+/// Interval between the checks of the open WebSocket connections while
+/// draining.
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Maximum time to wait for the WebSocket connections to close once they are
+/// asked to.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// State of the shutdown of the gateway.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) enum ShutdownState {
+    Running,
+
+    /// The gateway no longer accepts connections, the WebSocket connections
+    /// are closed once their subscriptions complete.
+    Draining,
+
+    /// The drain timed out, the WebSocket connections are closed.
+    Closing,
+}
+
+/// The receiver is kept so that the state is updated without any other
+/// receiver.
+static SHUTDOWN: Lazy<(watch::Sender<ShutdownState>, watch::Receiver<ShutdownState>)> =
+    Lazy::new(|| watch::channel(ShutdownState::Running));
+
+/// Returns a receiver of the shutdown state.
+pub(crate) fn subscribe() -> watch::Receiver<ShutdownState> {
+    SHUTDOWN.1.clone()
+}
+
+/// Returns `true` once the shutdown of the gateway started.
+pub fn is_shutting_down() -> bool {
+    *SHUTDOWN.1.borrow() != ShutdownState::Running
+}
+
+/// Starts the shutdown of the gateway, the WebSocket connections without
+/// subscriptions are closed and the others are closed once their
+/// subscriptions complete.
+pub fn begin_shutdown() {
+    if !is_shutting_down() {
+        let _ = SHUTDOWN.0.send(ShutdownState::Draining);
+    }
+}
+
+/// Waits until the shutdown starts.
+pub async fn shutdown_started() {
+    let mut rx = subscribe();
+    while *rx.borrow() == ShutdownState::Running {
+        if rx.changed().await.is_err() {
+            return;
+        }
+    }
+}
+
+async fn wait_connections_closed(deadline: Instant) -> bool {
+    loop {
+        if ACTIVE_CONNECTIONS.load(Ordering::Relaxed) == 0 {
+            return true;
+        }
+        if Instant::now() >= deadline {
+            return false;
+        }
+        tokio::time::sleep(DRAIN_POLL_INTERVAL).await;
+    }
+}
+
+/// Drains the WebSocket connections, waiting for their subscriptions to
+/// complete until the timeout. The remaining connections are then closed,
+/// with the code `1012` (service restart) so that the clients reconnect to
+/// the new process.
+///
+/// Returns the number of connections that were closed before their
+/// subscriptions completed.
+pub async fn drain(timeout: Duration) -> usize {
+    begin_shutdown();
+    if wait_connections_closed(Instant::now() + timeout).await {
+        return 0;
+    }
+
+    let remaining = ACTIVE_CONNECTIONS.load(Ordering::Relaxed);
+    let _ = SHUTDOWN.0.send(ShutdownState::Closing);
+    wait_connections_closed(Instant::now() + CLOSE_TIMEOUT).await;
+    remaining
+}
@@ -0,0 +1,212 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use graphgate_planner::{Request, Response};
+use once_cell::sync::Lazy;
+use opentelemetry::metrics::ObserverResult;
+use opentelemetry::{Key, KeyValue};
+
+use crate::fetcher::Fetcher;
+use crate::metrics::METRICS;
+
+const KEY_SERVICE: Key = Key::from_static_str("service");
+const KEY_OPERATION: Key = Key::from_static_str("operation");
+const KEY_QUANTILE: Key = Key::from_static_str("quantile");
+const KEY_REASON: Key = Key::from_static_str("reason");
+
+/// Latency objective of a service, optionally limited to an operation.
+#[derive(Debug, Clone)]
+pub struct SloObjective {
+    /// Name of the service, all services if not set.
+    pub service: Option<String>,
+
+    /// Name of the client operation, all operations if not set.
+    pub operation: Option<String>,
+
+    /// Requests slower than this violate the objective, it is also the
+    /// satisfied threshold of the apdex score.
+    pub latency: Duration,
+}
+
+/// Options of the service level indicators.
+///
+/// The success ratio, latency quantiles and apdex score of each service and
+/// operation are computed over the most recent `window_size` requests, and
+/// every failed or slow request increments `graphgate.slo_violation_total`.
+#[derive(Debug, Clone)]
+pub struct SloOptions {
+    /// The first matching objective is used.
+    pub objectives: Vec<SloObjective>,
+
+    /// Latency objective of the requests not matching any objective.
+    pub default_latency: Duration,
+
+    pub window_size: usize,
+}
+
+impl Default for SloOptions {
+    fn default() -> Self {
+        Self {
+            objectives: Vec::new(),
+            default_latency: Duration::from_millis(500),
+            window_size: 1000,
+        }
+    }
+}
+
+impl SloOptions {
+    fn latency(&self, service: &str, operation: &str) -> Duration {
+        self.objectives
+            .iter()
+            .find(|objective| {
+                objective
+                    .service
+                    .as_deref()
+                    .map_or(true, |name| name == service)
+                    && objective
+                        .operation
+                        .as_deref()
+                        .map_or(true, |name| name == operation)
+            })
+            .map(|objective| objective.latency)
+            .unwrap_or(self.default_latency)
+    }
+}
+
+struct Sample {
+    duration: f64,
+    success: bool,
+    apdex: f64,
+}
+
+/// Most recent requests of each service and operation.
+static WINDOWS: Lazy<Mutex<HashMap<(String, String), VecDeque<Sample>>>> =
+    Lazy::new(Default::default);
+
+fn record(service: &str, operation: &str, sample: Sample, window_size: usize) {
+    let mut windows = WINDOWS.lock().unwrap();
+    let window = windows
+        .entry((service.to_string(), operation.to_string()))
+        .or_default();
+    if window.len() >= window_size {
+        window.pop_front();
+    }
+    window.push_back(sample);
+}
+
+fn labels(service: &str, operation: &str) -> Vec<KeyValue> {
+    vec![
+        KEY_SERVICE.string(service.to_string()),
+        KEY_OPERATION.string(operation.to_string()),
+    ]
+}
+
+pub(crate) fn observe_success_ratio(result: ObserverResult<f64>) {
+    for ((service, operation), window) in WINDOWS.lock().unwrap().iter() {
+        if window.is_empty() {
+            continue;
+        }
+        let success = window.iter().filter(|sample| sample.success).count();
+        result.observe(
+            success as f64 / window.len() as f64,
+            &labels(service, operation),
+        );
+    }
+}
+
+pub(crate) fn observe_latency(result: ObserverResult<f64>) {
+    for ((service, operation), window) in WINDOWS.lock().unwrap().iter() {
+        if window.is_empty() {
+            continue;
+        }
+        let mut durations = window
+            .iter()
+            .map(|sample| sample.duration)
+            .collect::<Vec<_>>();
+        durations.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        for (name, quantile) in [("0.5", 0.5), ("0.95", 0.95), ("0.99", 0.99)] {
+            let index = ((durations.len() as f64 * quantile).ceil() as usize).max(1) - 1;
+            let mut labels = labels(service, operation);
+            labels.push(KEY_QUANTILE.string(name));
+            result.observe(durations[index], &labels);
+        }
+    }
+}
+
+pub(crate) fn observe_apdex(result: ObserverResult<f64>) {
+    for ((service, operation), window) in WINDOWS.lock().unwrap().iter() {
+        if window.is_empty() {
+            continue;
+        }
+        let score = window.iter().map(|sample| sample.apdex).sum::<f64>();
+        result.observe(score / window.len() as f64, &labels(service, operation));
+    }
+}
+
+/// A fetcher that records the service level indicators of the requests sent
+/// by another fetcher.
+pub struct SloFetcher<'a, F> {
+    inner: F,
+    operation: String,
+    options: Option<&'a SloOptions>,
+}
+
+impl<'a, F: Fetcher> SloFetcher<'a, F> {
+    pub fn new(inner: F, operation: Option<&str>, options: Option<&'a SloOptions>) -> Self {
+        Self {
+            inner,
+            operation: operation.unwrap_or_default().to_string(),
+            options,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<'a, F: Fetcher> Fetcher for SloFetcher<'a, F> {
+    async fn query(&self, service: &str, request: Request) -> Result<Response> {
+        let options = match self.options {
+            Some(options) => options,
+            None => return self.inner.query(service, request).await,
+        };
+
+        let start_time = Instant::now();
+        let res = self.inner.query(service, request).await;
+        let duration = start_time.elapsed();
+
+        let success = matches!(&res, Ok(resp) if resp.errors.is_empty());
+        let latency = options.latency(service, &self.operation);
+        let apdex = if !success || duration > latency * 4 {
+            0.0
+        } else if duration > latency {
+            0.5
+        } else {
+            1.0
+        };
+        record(
+            service,
+            &self.operation,
+            Sample {
+                duration: duration.as_secs_f64(),
+                success,
+                apdex,
+            },
+            options.window_size.max(1),
+        );
+
+        let reason = if !success {
+            Some("error")
+        } else if duration > latency {
+            Some("latency")
+        } else {
+            None
+        };
+        if let Some(reason) = reason {
+            let mut labels = labels(service, &self.operation);
+            labels.push(KEY_REASON.string(reason));
+            METRICS.slo_violation_counter.add(1, &labels);
+        }
+        res
+    }
+}
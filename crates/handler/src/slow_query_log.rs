@@ -0,0 +1,124 @@
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use graphgate_planner::PlanStats;
+use serde::Serialize;
+use tokio::io::AsyncWriteExt;
+
+use crate::audit::{ExecutionReport, FetchEvent};
+
+/// Slow query log options.
+#[derive(Debug, Clone)]
+pub struct SlowQueryLogOptions {
+    /// Minimum duration of the logged operations.
+    pub threshold: Duration,
+
+    /// Fraction of the slow operations that are logged, from `0.0` to `1.0`.
+    pub sample_rate: f64,
+
+    /// If set, the entries are appended to this file as JSON lines instead of
+    /// being emitted on the `graphgate::slow_query` tracing target.
+    pub file: Option<PathBuf>,
+}
+
+impl Default for SlowQueryLogOptions {
+    fn default() -> Self {
+        Self {
+            threshold: Duration::from_secs(1),
+            sample_rate: 1.0,
+            file: None,
+        }
+    }
+}
+
+/// Summary of the query plan of a slow operation.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PlanSummary {
+    fetch_nodes: usize,
+    flatten_rounds: usize,
+    services: Vec<String>,
+}
+
+/// An entry of the slow query log.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SlowQuery<'a> {
+    operation: Option<&'a str>,
+    signature: Option<&'a str>,
+    duration_ms: u128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    client_ip: Option<IpAddr>,
+    plan: PlanSummary,
+    fetches: &'a [FetchEvent],
+}
+
+impl SlowQueryLogOptions {
+    /// Logs the execution if it exceeds the threshold and is sampled.
+    pub(crate) fn log(
+        &self,
+        report: &ExecutionReport,
+        stats: PlanStats<'_>,
+        client_ip: Option<IpAddr>,
+    ) {
+        let duration = (report.end_time - report.start_time)
+            .to_std()
+            .unwrap_or_default();
+        if duration < self.threshold || fastrand::f64() >= self.sample_rate {
+            return;
+        }
+
+        let mut services = stats
+            .services
+            .iter()
+            .map(|service| service.to_string())
+            .collect::<Vec<_>>();
+        services.sort();
+        let entry = SlowQuery {
+            operation: report.operation.as_deref(),
+            signature: report.signature.as_deref(),
+            duration_ms: duration.as_millis(),
+            client_ip,
+            plan: PlanSummary {
+                fetch_nodes: stats.fetch_nodes,
+                flatten_rounds: stats.flatten_rounds,
+                services,
+            },
+            fetches: &report.fetches,
+        };
+        let entry = match serde_json::to_string(&entry) {
+            Ok(entry) => entry,
+            Err(err) => {
+                tracing::error!(error = %err, "Failed to serialize slow query.");
+                return;
+            }
+        };
+
+        match self.file.clone() {
+            Some(path) => {
+                tokio::spawn(async move {
+                    let res = async {
+                        let mut file = tokio::fs::OpenOptions::new()
+                            .create(true)
+                            .append(true)
+                            .open(&path)
+                            .await?;
+                        file.write_all(format!("{}\n", entry).as_bytes()).await
+                    }
+                    .await;
+                    if let Err(err) = res {
+                        tracing::error!(
+                            path = %path.display(),
+                            error = %err,
+                            "Failed to write slow query log."
+                        );
+                    }
+                });
+            }
+            None => {
+                tracing::warn!(target: "graphgate::slow_query", entry = %entry, "Slow query.")
+            }
+        }
+    }
+}
@@ -0,0 +1,97 @@
+use std::convert::Infallible;
+
+use futures_util::stream::{Stream, StreamExt};
+use graphgate_planner::Response;
+use http::header::{ACCEPT, CACHE_CONTROL, CONTENT_TYPE};
+use http::HeaderMap;
+use tokio::time::{Duration, Instant};
+use warp::http::{Response as HttpResponse, StatusCode};
+use warp::hyper::Body;
+
+use crate::shutdown;
+
+const EVENT_STREAM: &str = "text/event-stream";
+
+/// Interval of the comments sent to keep the connection open through the
+/// proxies while no event is sent.
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(12);
+
+/// Returns `true` if the client accepts Server-Sent Events.
+pub(crate) fn accepts_event_stream(header_map: &HeaderMap) -> bool {
+    header_map
+        .get_all(ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .any(|value| value.contains(EVENT_STREAM))
+}
+
+fn event(name: &str, data: &str) -> String {
+    let mut event = format!("event: {}\n", name);
+    if data.is_empty() {
+        event.push_str("data:\n");
+    }
+    for line in data.lines() {
+        event.push_str("data: ");
+        event.push_str(line);
+        event.push('\n');
+    }
+    event.push('\n');
+    event
+}
+
+/// Returns the HTTP response streaming the responses of a subscription, as
+/// in the distinct connections mode of GraphQL over Server-Sent Events.
+///
+/// Each response is a `next` event, followed by a `complete` event once the
+/// subscription ends.
+pub(crate) fn http_response(
+    stream: impl Stream<Item = Response> + Send + 'static,
+) -> HttpResponse<Body> {
+    let events = async_stream::stream! {
+        let mut stream = Box::pin(stream);
+        let mut keep_alive =
+            tokio::time::interval_at(Instant::now() + KEEP_ALIVE_INTERVAL, KEEP_ALIVE_INTERVAL);
+        let mut shutdown = Box::pin(shutdown::shutdown_started());
+        loop {
+            let event = tokio::select! {
+                resp = stream.next() => match resp {
+                    Some(resp) => event("next", &serde_json::to_string(&resp).unwrap()),
+                    None => break,
+                },
+                _ = keep_alive.tick() => ":\n\n".to_string(),
+                // The stream is closed without completing the subscription,
+                // so that the client subscribes again to another process.
+                _ = &mut shutdown => return,
+            };
+            yield Ok::<_, Infallible>(event);
+        }
+        yield Ok(event("complete", ""));
+    };
+
+    HttpResponse::builder()
+        .status(StatusCode::OK)
+        .header(CONTENT_TYPE, EVENT_STREAM)
+        .header(CACHE_CONTROL, "no-cache")
+        .body(Body::wrap_stream(events))
+        .unwrap()
+}
+
+/// Returns the HTTP response of a subscription that failed before it started.
+pub(crate) fn error_response(resp: Response) -> HttpResponse<Body> {
+    http_response(futures_util::stream::once(async move { resp }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_event() {
+        assert_eq!(
+            event("next", r#"{"data":{"a":1}}"#),
+            "event: next\ndata: {\"data\":{\"a\":1}}\n\n"
+        );
+        assert_eq!(event("complete", ""), "event: complete\ndata:\n\n");
+        assert_eq!(event("next", "a\nb"), "event: next\ndata: a\ndata: b\n\n");
+    }
+}
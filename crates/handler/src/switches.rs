@@ -0,0 +1,730 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+
+use graphgate_planner::{ErrorCode, Response, RootNode, ServerError};
+use graphgate_schema::{ComposedSchema, MetaField, MetaType};
+use parser::types::{
+    DocumentOperations, ExecutableDocument, OperationDefinition, OperationType, Selection,
+    SelectionSet,
+};
+use parser::{Pos, Positioned};
+use serde::{Deserialize, Serialize};
+use value::{ConstValue, Name};
+
+const DEFAULT_READ_ONLY_MESSAGE: &str = "The gateway is in read-only mode.";
+const DEFAULT_MAINTENANCE_MESSAGE: &str = "The gateway is under maintenance.";
+
+/// Read-only mode state.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ReadOnlyMode {
+    pub enabled: bool,
+    #[serde(default)]
+    pub message: Option<String>,
+}
+
+/// Maintenance mode state.
+///
+/// When enabled, all operations are answered with a static error.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MaintenanceMode {
+    pub enabled: bool,
+
+    #[serde(default)]
+    pub message: Option<String>,
+
+    /// Extensions of the returned error.
+    #[serde(default)]
+    pub extensions: HashMap<String, ConstValue>,
+
+    /// Value of the `Retry-After` header in seconds.
+    #[serde(default)]
+    pub retry_after: Option<u64>,
+}
+
+/// Schema coordinates and services that are disabled.
+///
+/// The disabled fields of the queries and mutations, and the fields resolved
+/// by a disabled service, are answered with `null` and an error while the
+/// rest of the operation is executed. The subscriptions selecting them, and
+/// the operations still needing a disabled service, such as for the fields
+/// required by another field, are rejected without sending any request to
+/// the services.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DisabledTargets {
+    /// Schema coordinates such as `Query.topProducts`.
+    #[serde(default)]
+    pub fields: BTreeSet<String>,
+
+    /// Service names.
+    #[serde(default)]
+    pub services: BTreeSet<String>,
+}
+
+#[derive(Default)]
+struct State {
+    read_only: ReadOnlyMode,
+    disabled: DisabledTargets,
+    maintenance: MaintenanceMode,
+}
+
+/// Switches that can be changed at runtime, shared by all route tables.
+#[derive(Clone, Default)]
+pub struct RuntimeSwitches(Arc<RwLock<State>>);
+
+impl RuntimeSwitches {
+    pub fn read_only(&self) -> ReadOnlyMode {
+        self.0.read().unwrap().read_only.clone()
+    }
+
+    pub fn set_read_only(&self, read_only: ReadOnlyMode) {
+        self.0.write().unwrap().read_only = read_only;
+    }
+
+    pub fn disabled(&self) -> DisabledTargets {
+        self.0.read().unwrap().disabled.clone()
+    }
+
+    pub fn set_disabled(&self, disabled: DisabledTargets) {
+        self.0.write().unwrap().disabled = disabled;
+    }
+
+    pub fn maintenance(&self) -> MaintenanceMode {
+        self.0.read().unwrap().maintenance.clone()
+    }
+
+    pub fn set_maintenance(&self, maintenance: MaintenanceMode) {
+        self.0.write().unwrap().maintenance = maintenance;
+    }
+
+    /// Returns the static error response and the `Retry-After` seconds if the
+    /// maintenance mode is enabled.
+    pub(crate) fn check_maintenance(&self) -> Option<(Response, Option<u64>)> {
+        let state = self.0.read().unwrap();
+        let maintenance = &state.maintenance;
+        if !maintenance.enabled {
+            return None;
+        }
+
+        let mut error = ServerError::new(
+            maintenance
+                .message
+                .as_deref()
+                .unwrap_or(DEFAULT_MAINTENANCE_MESSAGE),
+        );
+        error.extensions = maintenance.extensions.clone();
+        if error.code().is_none() {
+            error = error.with_code(ErrorCode::Maintenance);
+        }
+        let response = Response::from_errors(vec![error]);
+        Some((response, maintenance.retry_after))
+    }
+
+    /// Returns an error response if the operation is rejected by the switches.
+    pub(crate) fn check_operation(
+        &self,
+        document: &ExecutableDocument,
+        operation_name: Option<&str>,
+    ) -> Option<Response> {
+        let state = self.0.read().unwrap();
+        let operation_type =
+            get_operation(document, operation_name).map(|operation| operation.node.ty);
+        if state.read_only.enabled && operation_type == Some(OperationType::Mutation) {
+            let message = state
+                .read_only
+                .message
+                .as_deref()
+                .unwrap_or(DEFAULT_READ_ONLY_MESSAGE);
+            return Some(error_response(message, ErrorCode::ReadOnly));
+        }
+        None
+    }
+
+    /// Returns the disabled fields selected by an operation, which must be
+    /// removed from its document before it is planned.
+    pub(crate) fn disabled_fields(
+        &self,
+        schema: &ComposedSchema,
+        document: &ExecutableDocument,
+        operation_name: Option<&str>,
+    ) -> DisabledFields {
+        let state = self.0.read().unwrap();
+        let disabled = &state.disabled;
+        if disabled.fields.is_empty() && disabled.services.is_empty() {
+            return DisabledFields::default();
+        }
+        let operation = match get_operation(document, operation_name) {
+            Some(operation) => operation,
+            None => return DisabledFields::default(),
+        };
+        let root_type = match operation.node.ty {
+            OperationType::Query => Some(schema.query_type()),
+            OperationType::Mutation => schema.mutation_type(),
+            OperationType::Subscription => None,
+        }
+        .and_then(|name| schema.get_type_by_name(name));
+        let root_type = match root_type {
+            Some(root_type) => root_type,
+            None => return DisabledFields::default(),
+        };
+
+        let mut collector = DisabledFieldCollector {
+            schema,
+            document,
+            disabled,
+            fields: DisabledFields {
+                root_type: root_type.name.to_string(),
+                ..Default::default()
+            },
+            fragments: Vec::new(),
+        };
+        collector.collect(root_type, &operation.node.selection_set, &mut Vec::new());
+        collector.fields
+    }
+
+    /// Returns an error response if the plan selects a disabled field or
+    /// needs a disabled service.
+    pub(crate) fn check_plan(
+        &self,
+        schema: &ComposedSchema,
+        document: &ExecutableDocument,
+        operation_name: Option<&str>,
+        plan: &RootNode<'_>,
+    ) -> Option<Response> {
+        let state = self.0.read().unwrap();
+        let disabled = &state.disabled;
+
+        if !disabled.services.is_empty() {
+            let stats = plan.stats();
+            if let Some(service) = disabled
+                .services
+                .iter()
+                .find(|service| stats.services.contains(service.as_str()))
+            {
+                return Some(error_response(
+                    &format!("Service '{}' is disabled.", service),
+                    ErrorCode::Disabled,
+                ));
+            }
+        }
+
+        if !disabled.fields.is_empty() {
+            let operation = get_operation(document, operation_name)?;
+            let root_type = match operation.node.ty {
+                OperationType::Query => Some(schema.query_type()),
+                OperationType::Mutation => schema.mutation_type(),
+                OperationType::Subscription => schema.subscription_type(),
+            }
+            .and_then(|name| schema.get_type_by_name(name))?;
+            let mut walker = FieldWalker {
+                schema,
+                document,
+                disabled_fields: &disabled.fields,
+                visited_fragments: HashSet::new(),
+            };
+            if let Some(coordinate) =
+                walker.find_disabled_field(root_type, &operation.node.selection_set)
+            {
+                return Some(error_response(
+                    &format!("Field '{}' is disabled.", coordinate),
+                    ErrorCode::Disabled,
+                ));
+            }
+        }
+
+        None
+    }
+}
+
+struct FieldWalker<'a> {
+    schema: &'a ComposedSchema,
+    document: &'a ExecutableDocument,
+    disabled_fields: &'a BTreeSet<String>,
+    visited_fragments: HashSet<&'a str>,
+}
+
+impl<'a> FieldWalker<'a> {
+    fn find_disabled_field(
+        &mut self,
+        parent_type: &'a MetaType,
+        selection_set: &'a Positioned<SelectionSet>,
+    ) -> Option<String> {
+        for selection in &selection_set.node.items {
+            let found = match &selection.node {
+                Selection::Field(field) => {
+                    let meta_field = match parent_type.field_by_name(&field.node.name.node) {
+                        Some(meta_field) => meta_field,
+                        None => continue,
+                    };
+                    let coordinate = format!("{}.{}", parent_type.name, meta_field.name);
+                    if self.disabled_fields.contains(&coordinate) {
+                        return Some(coordinate);
+                    }
+                    match self.schema.concrete_type_by_name(&meta_field.ty) {
+                        Some(field_type) => {
+                            self.find_disabled_field(field_type, &field.node.selection_set)
+                        }
+                        None => None,
+                    }
+                }
+                Selection::FragmentSpread(fragment_spread) => {
+                    let name = fragment_spread.node.fragment_name.node.as_str();
+                    if !self.visited_fragments.insert(name) {
+                        continue;
+                    }
+                    let fragment = match self.document.fragments.get(name) {
+                        Some(fragment) => fragment,
+                        None => continue,
+                    };
+                    match self
+                        .schema
+                        .get_type_by_name(fragment.node.type_condition.node.on.node.as_str())
+                    {
+                        Some(ty) => self.find_disabled_field(ty, &fragment.node.selection_set),
+                        None => None,
+                    }
+                }
+                Selection::InlineFragment(inline_fragment) => {
+                    let ty = match &inline_fragment.node.type_condition {
+                        Some(type_condition) => {
+                            match self
+                                .schema
+                                .get_type_by_name(type_condition.node.on.node.as_str())
+                            {
+                                Some(ty) => ty,
+                                None => continue,
+                            }
+                        }
+                        None => parent_type,
+                    };
+                    self.find_disabled_field(ty, &inline_fragment.node.selection_set)
+                }
+            };
+            if found.is_some() {
+                return found;
+            }
+        }
+        None
+    }
+}
+
+/// A disabled field selected by an operation.
+struct DisabledField {
+    /// Response keys of the field and of its parents.
+    path: Vec<String>,
+
+    /// Types of the objects that have the field.
+    possible_types: Vec<String>,
+
+    message: String,
+    pos: Pos,
+}
+
+/// The disabled fields selected by an operation.
+///
+/// The root fields are removed from the document, the other fields are
+/// replaced with `__typename` under the same response key, so that the
+/// objects having the field can be told apart once the operation is executed,
+/// even in the lists of abstract types.
+#[derive(Default)]
+pub(crate) struct DisabledFields {
+    root_type: String,
+    fields: Vec<DisabledField>,
+
+    /// Parent types and names of the fields to remove.
+    targets: HashSet<(String, String)>,
+}
+
+impl DisabledFields {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    /// Removes the disabled fields from the operations and the fragments of
+    /// a document.
+    pub(crate) fn remove_from(&self, schema: &ComposedSchema, document: &mut ExecutableDocument) {
+        let operations: Vec<&mut Positioned<OperationDefinition>> = match &mut document.operations {
+            DocumentOperations::Single(operation) => vec![operation],
+            DocumentOperations::Multiple(operations) => operations.values_mut().collect(),
+        };
+        if let Some(root_type) = schema.get_type_by_name(&self.root_type) {
+            for operation in operations {
+                self.remove_fields(schema, root_type, &mut operation.node.selection_set.node);
+            }
+        }
+        for fragment in document.fragments.values_mut() {
+            if let Some(ty) = schema.get_type_by_name(&fragment.node.type_condition.node.on.node) {
+                self.remove_fields(schema, ty, &mut fragment.node.selection_set.node);
+            }
+        }
+    }
+
+    fn remove_fields(
+        &self,
+        schema: &ComposedSchema,
+        parent_type: &MetaType,
+        selection_set: &mut SelectionSet,
+    ) {
+        let is_root = parent_type.name.as_str() == self.root_type;
+        let items = std::mem::take(&mut selection_set.items);
+        selection_set.items = items
+            .into_iter()
+            .filter_map(|mut selection| {
+                self.remove_field(schema, parent_type, is_root, &mut selection.node)
+                    .then(|| selection)
+            })
+            .collect();
+    }
+
+    /// Returns `false` if the selection must be removed.
+    fn remove_field(
+        &self,
+        schema: &ComposedSchema,
+        parent_type: &MetaType,
+        is_root: bool,
+        selection: &mut Selection,
+    ) -> bool {
+        match selection {
+            Selection::Field(field) => {
+                let target = (
+                    parent_type.name.to_string(),
+                    field.node.name.node.to_string(),
+                );
+                if self.targets.contains(&target) {
+                    if is_root {
+                        return false;
+                    }
+                    let field = &mut field.node;
+                    let response_key = field.alias.take().unwrap_or_else(|| field.name.clone());
+                    field.alias = Some(response_key);
+                    field.name.node = Name::new("__typename");
+                    field.arguments.clear();
+                    field.selection_set.node.items.clear();
+                } else if let Some(field_type) = parent_type
+                    .field_by_name(&field.node.name.node)
+                    .and_then(|meta_field| schema.concrete_type_by_name(&meta_field.ty))
+                {
+                    self.remove_fields(schema, field_type, &mut field.node.selection_set.node);
+                }
+                true
+            }
+            Selection::FragmentSpread(_) => true,
+            Selection::InlineFragment(inline_fragment) => {
+                let ty = match &inline_fragment.node.type_condition {
+                    Some(type_condition) => {
+                        schema.get_type_by_name(type_condition.node.on.node.as_str())
+                    }
+                    None => Some(parent_type),
+                };
+                if let Some(ty) = ty {
+                    self.remove_fields(schema, ty, &mut inline_fragment.node.selection_set.node);
+                }
+                true
+            }
+        }
+    }
+
+    /// Sets the disabled fields of a response to `null`, with an error at
+    /// each of their paths.
+    pub(crate) fn apply(&self, resp: &mut Response) {
+        for field in &self.fields {
+            null_field(
+                &mut resp.data,
+                &field.path,
+                &mut Vec::new(),
+                field,
+                &mut resp.errors,
+            );
+        }
+    }
+}
+
+fn null_field(
+    value: &mut ConstValue,
+    keys: &[String],
+    path: &mut Vec<ConstValue>,
+    field: &DisabledField,
+    errors: &mut Vec<ServerError>,
+) {
+    let (key, rest) = match keys.split_first() {
+        Some(res) => res,
+        None => return,
+    };
+    match value {
+        ConstValue::List(items) => {
+            for (idx, item) in items.iter_mut().enumerate() {
+                path.push(ConstValue::Number(idx.into()));
+                null_field(item, keys, path, field, errors);
+                path.pop();
+            }
+        }
+        ConstValue::Object(object) if rest.is_empty() => {
+            // The root fields are removed, the other fields are fetched as
+            // `__typename` by the objects that have them.
+            let disabled = match object.get(key.as_str()) {
+                None => field.possible_types.is_empty(),
+                Some(ConstValue::String(typename)) => field.possible_types.contains(typename),
+                Some(_) => false,
+            };
+            if disabled {
+                object.insert(Name::new(key), ConstValue::Null);
+                let mut path = path.clone();
+                path.push(ConstValue::String(key.clone()));
+                errors.push(
+                    ServerError::new(&field.message)
+                        .with_code(ErrorCode::Disabled)
+                        .with_path(path)
+                        .with_locations(vec![field.pos]),
+                );
+            }
+        }
+        ConstValue::Object(object) => {
+            if let Some(value) = object.get_mut(key.as_str()) {
+                path.push(ConstValue::String(key.clone()));
+                null_field(value, rest, path, field, errors);
+                path.pop();
+            }
+        }
+        _ => {}
+    }
+}
+
+struct DisabledFieldCollector<'a> {
+    schema: &'a ComposedSchema,
+    document: &'a ExecutableDocument,
+    disabled: &'a DisabledTargets,
+    fields: DisabledFields,
+
+    /// Fragments being collected, to stop at the cycles.
+    fragments: Vec<&'a str>,
+}
+
+impl<'a> DisabledFieldCollector<'a> {
+    /// Returns the error message if a field is disabled, or resolved by a
+    /// disabled service. The key fields are also resolved by the services
+    /// referencing the entity, so they are only disabled by their coordinate.
+    fn disabled_message(&self, parent_type: &MetaType, meta_field: &MetaField) -> Option<String> {
+        let coordinate = format!("{}.{}", parent_type.name, meta_field.name);
+        if self.disabled.fields.contains(&coordinate) {
+            return Some(format!("Field '{}' is disabled.", coordinate));
+        }
+        let is_key = parent_type
+            .keys
+            .values()
+            .flatten()
+            .any(|keys| keys.contains_key(&meta_field.name));
+        let service = meta_field
+            .service
+            .as_deref()
+            .or_else(|| parent_type.owner.as_deref().filter(|_| !is_key))?;
+        if self.disabled.services.contains(service) {
+            return Some(format!("Service '{}' is disabled.", service));
+        }
+        None
+    }
+
+    fn collect(
+        &mut self,
+        parent_type: &'a MetaType,
+        selection_set: &'a Positioned<SelectionSet>,
+        path: &mut Vec<String>,
+    ) {
+        for selection in &selection_set.node.items {
+            match &selection.node {
+                Selection::Field(field) => {
+                    let meta_field = match parent_type.field_by_name(&field.node.name.node) {
+                        Some(meta_field) => meta_field,
+                        None => continue,
+                    };
+                    let response_key = field.node.response_key().node.to_string();
+                    if let Some(message) = self.disabled_message(parent_type, meta_field) {
+                        let mut path = path.clone();
+                        path.push(response_key);
+                        let possible_types = if parent_type.name.as_str() == self.fields.root_type {
+                            Vec::new()
+                        } else if parent_type.is_abstract() {
+                            parent_type
+                                .possible_types
+                                .iter()
+                                .map(ToString::to_string)
+                                .collect()
+                        } else {
+                            vec![parent_type.name.to_string()]
+                        };
+                        self.fields.fields.push(DisabledField {
+                            path,
+                            possible_types,
+                            message,
+                            pos: field.pos,
+                        });
+                        self.fields
+                            .targets
+                            .insert((parent_type.name.to_string(), meta_field.name.to_string()));
+                        continue;
+                    }
+                    if let Some(field_type) = self.schema.concrete_type_by_name(&meta_field.ty) {
+                        path.push(response_key);
+                        self.collect(field_type, &field.node.selection_set, path);
+                        path.pop();
+                    }
+                }
+                Selection::FragmentSpread(fragment_spread) => {
+                    let name = fragment_spread.node.fragment_name.node.as_str();
+                    if self.fragments.contains(&name) {
+                        continue;
+                    }
+                    let fragment = match self.document.fragments.get(name) {
+                        Some(fragment) => fragment,
+                        None => continue,
+                    };
+                    if let Some(ty) = self
+                        .schema
+                        .get_type_by_name(fragment.node.type_condition.node.on.node.as_str())
+                    {
+                        self.fragments.push(name);
+                        self.collect(ty, &fragment.node.selection_set, path);
+                        self.fragments.pop();
+                    }
+                }
+                Selection::InlineFragment(inline_fragment) => {
+                    let ty = match &inline_fragment.node.type_condition {
+                        Some(type_condition) => self
+                            .schema
+                            .get_type_by_name(type_condition.node.on.node.as_str()),
+                        None => Some(parent_type),
+                    };
+                    if let Some(ty) = ty {
+                        self.collect(ty, &inline_fragment.node.selection_set, path);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn error_response(message: &str, code: ErrorCode) -> Response {
+    Response::from_errors(vec![ServerError::new(message).with_code(code)])
+}
+
+pub(crate) fn get_operation<'a>(
+    document: &'a ExecutableDocument,
+    operation_name: Option<&str>,
+) -> Option<&'a Positioned<OperationDefinition>> {
+    match (&document.operations, operation_name) {
+        (DocumentOperations::Single(operation), _) => Some(operation),
+        (DocumentOperations::Multiple(operations), Some(operation_name)) => {
+            operations.get(operation_name)
+        }
+        (DocumentOperations::Multiple(operations), None) if operations.len() == 1 => {
+            operations.values().next()
+        }
+        (DocumentOperations::Multiple(_), None) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use graphgate_planner::PlanBuilder;
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn null_disabled_fields() {
+        let schema = ComposedSchema::parse(
+            r#"
+            type Query {
+                me: User @resolve(service: "accounts")
+                topProducts: [Product!]! @resolve(service: "products")
+            }
+
+            type User @owner(service: "accounts") @key(fields: "id" service: "accounts") @key(fields: "id" service: "reviews") {
+                id: ID!
+                username: String!
+                reviews: [Review!]! @resolve(service: "reviews")
+            }
+
+            type Review @owner(service: "reviews") @key(fields: "id" service: "reviews") {
+                id: ID!
+                body: String!
+            }
+
+            type Product @owner(service: "products") @key(fields: "upc" service: "products") {
+                upc: String!
+                name: String!
+            }
+            "#,
+        )
+        .unwrap();
+        let document = parser::parse_query(
+            r#"{
+                me { id name: username reviews { body } }
+                topProducts { upc name }
+            }"#,
+        )
+        .unwrap();
+        let switches = RuntimeSwitches::default();
+        switches.set_disabled(DisabledTargets {
+            fields: vec!["User.username".to_string()].into_iter().collect(),
+            services: vec!["products".to_string()].into_iter().collect(),
+        });
+
+        let disabled_fields = switches.disabled_fields(&schema, &document, None);
+        let builder = PlanBuilder::new(&schema, document)
+            .rewrite(|document| disabled_fields.remove_from(&schema, document))
+            .unwrap();
+        let operation = get_operation(builder.document(), None).unwrap();
+        let root_fields: Vec<_> = operation
+            .node
+            .selection_set
+            .node
+            .items
+            .iter()
+            .filter_map(|selection| match &selection.node {
+                Selection::Field(field) => Some(field.node.response_key().node.to_string()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(root_fields, vec!["me".to_string()]);
+        assert!(builder.plan().is_ok());
+        assert!(switches
+            .check_plan(&schema, builder.document(), None, &builder.plan().unwrap())
+            .is_none());
+
+        let mut resp = Response {
+            data: ConstValue::from_json(json!({
+                "me": { "id": "1", "name": "User", "reviews": [{ "body": "Good" }] },
+            }))
+            .unwrap(),
+            ..Default::default()
+        };
+        disabled_fields.apply(&mut resp);
+        assert_eq!(
+            resp.data.into_json().unwrap(),
+            json!({
+                "me": { "id": "1", "name": null, "reviews": [{ "body": "Good" }] },
+                "topProducts": null,
+            })
+        );
+        let errors: Vec<_> = resp
+            .errors
+            .iter()
+            .map(|error| (error.message.as_str(), error.path.clone()))
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                (
+                    "Field 'User.username' is disabled.",
+                    vec![
+                        ConstValue::String("me".to_string()),
+                        ConstValue::String("name".to_string())
+                    ],
+                ),
+                (
+                    "Service 'products' is disabled.",
+                    vec![ConstValue::String("topProducts".to_string())],
+                ),
+            ]
+        );
+    }
+}
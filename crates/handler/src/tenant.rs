@@ -0,0 +1,45 @@
+use std::collections::HashMap;
+
+use http::HeaderMap;
+
+use crate::SharedRouteTable;
+
+/// Route tables of multiple tenants, selected by a request header.
+#[derive(Clone)]
+pub struct TenantRouteTables {
+    header: String,
+    route_tables: HashMap<String, SharedRouteTable>,
+}
+
+impl TenantRouteTables {
+    pub fn new(header: impl Into<String>) -> Self {
+        Self {
+            header: header.into(),
+            route_tables: Default::default(),
+        }
+    }
+
+    /// Name of the header that specifies the tenant.
+    #[inline]
+    pub fn header(&self) -> &str {
+        &self.header
+    }
+
+    pub fn insert(&mut self, tenant: impl Into<String>, route_table: SharedRouteTable) {
+        self.route_tables.insert(tenant.into(), route_table);
+    }
+
+    /// Returns the tenant and the route table selected by the request headers.
+    pub fn select(&self, header_map: &HeaderMap) -> Option<(&str, &SharedRouteTable)> {
+        let tenant = header_map.get(&self.header)?.to_str().ok()?;
+        self.route_tables
+            .get_key_value(tenant)
+            .map(|(tenant, route_table)| (tenant.as_str(), route_table))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &SharedRouteTable)> {
+        self.route_tables
+            .iter()
+            .map(|(tenant, route_table)| (tenant.as_str(), route_table))
+    }
+}
@@ -0,0 +1,279 @@
+//! Utilities for testing the gateway.
+//!
+//! The [`MockFetcher`] replaces the services with scripted replies, so that
+//! the merging of the responses can be tested with [`execute`] and
+//! [`assert_data`].
+//!
+//! The timers of the gateway, such as the schema update interval, the connect
+//! timeout of the upstream websockets and the heartbeats of the subscription
+//! callbacks, use the tokio clock. In a runtime whose time is paused, such as
+//! one started by `#[tokio::test(start_paused = true)]`, they only fire when
+//! the time is advanced, so the tests neither wait nor depend on the load of
+//! the machine.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::Result;
+use graphgate_planner::{PlanBuilder, Request, Response, ServerError};
+use graphgate_schema::ComposedSchema;
+use indexmap::IndexMap;
+use parser::types::{FragmentDefinition, Selection, SelectionSet};
+use parser::Positioned;
+pub use tokio::time::{pause, resume, Instant};
+use value::{ConstValue, Name, Variables};
+
+use crate::executor::Executor;
+use crate::fetcher::Fetcher;
+
+/// Number of times the current task yields after advancing the time, enough
+/// for the spawned tasks woken by the timers to run.
+const SETTLE_YIELDS: usize = 16;
+
+/// Advances the virtual time, then lets the tasks woken by the timers run
+/// until they are waiting again.
+///
+/// The time must be paused.
+pub async fn advance(duration: Duration) {
+    tokio::time::advance(duration).await;
+    settle().await;
+}
+
+/// Lets the spawned tasks that are ready run before returning.
+pub async fn settle() {
+    for _ in 0..SETTLE_YIELDS {
+        tokio::task::yield_now().await;
+    }
+}
+
+/// A reply of the [`MockFetcher`] to a request sent to a service.
+#[derive(Debug, Clone)]
+pub enum MockReply {
+    /// The data of the service, such as `{"me": {"id": "1", "name": "a"}}`.
+    ///
+    /// The data is projected on the query of the request, so it is keyed by
+    /// field names and the aliases of the gateway, such as the key fields of
+    /// the entities, are added like a real service would. The entities are in
+    /// the `_entities` field, in the order of the representations.
+    Data(serde_json::Value),
+
+    /// The response of the service, returned as is.
+    Response(Response),
+
+    /// A request that fails, such as a refused connection.
+    Error(String),
+}
+
+struct Script {
+    delay: Duration,
+    reply: MockReply,
+}
+
+/// A fetcher replying to the requests with scripted replies, in order for
+/// each service, to test the execution of the plans without services.
+///
+/// A request to a service without remaining replies fails.
+#[derive(Default)]
+pub struct MockFetcher {
+    scripts: Mutex<HashMap<String, VecDeque<Script>>>,
+    requests: Mutex<Vec<(String, Request)>>,
+}
+
+impl MockFetcher {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Queues the data of the next request to a service.
+    pub fn data(self, service: impl Into<String>, data: serde_json::Value) -> Self {
+        self.reply(service, MockReply::Data(data))
+    }
+
+    /// Queues a failure of the next request to a service.
+    pub fn error(self, service: impl Into<String>, message: impl Into<String>) -> Self {
+        self.reply(service, MockReply::Error(message.into()))
+    }
+
+    /// Queues the reply of the next request to a service.
+    pub fn reply(self, service: impl Into<String>, reply: MockReply) -> Self {
+        self.reply_after(service, Duration::ZERO, reply)
+    }
+
+    /// Queues the reply of the next request to a service, returned after a
+    /// delay.
+    pub fn reply_after(
+        self,
+        service: impl Into<String>,
+        delay: Duration,
+        reply: MockReply,
+    ) -> Self {
+        self.scripts
+            .lock()
+            .unwrap()
+            .entry(service.into())
+            .or_default()
+            .push_back(Script { delay, reply });
+        self
+    }
+
+    /// Returns the requests sent to the services, in order.
+    pub fn requests(&self) -> Vec<(String, Request)> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+#[async_trait::async_trait]
+impl Fetcher for MockFetcher {
+    async fn query(&self, service: &str, request: Request) -> Result<Response> {
+        self.requests
+            .lock()
+            .unwrap()
+            .push((service.to_string(), request.clone()));
+        let script = self
+            .scripts
+            .lock()
+            .unwrap()
+            .get_mut(service)
+            .and_then(VecDeque::pop_front)
+            .ok_or_else(|| anyhow::anyhow!("No reply scripted for service '{}'.", service))?;
+        if script.delay > Duration::ZERO {
+            tokio::time::sleep(script.delay).await;
+        }
+
+        match script.reply {
+            MockReply::Data(data) => {
+                let document = parser::parse_query(&request.query)?;
+                let selection_set = match document.operations.iter().next() {
+                    Some((_, operation)) => &operation.node.selection_set.node,
+                    None => anyhow::bail!("Request without operation."),
+                };
+                let data = ConstValue::from_json(data)?;
+                Ok(Response {
+                    data: project(&data, selection_set, &document.fragments),
+                    ..Default::default()
+                })
+            }
+            MockReply::Response(response) => Ok(response),
+            MockReply::Error(message) => Err(anyhow::anyhow!(message)),
+        }
+    }
+}
+
+/// Returns the fields of the scripted data selected by a query, keyed by
+/// their response keys.
+fn project(
+    data: &ConstValue,
+    selection_set: &SelectionSet,
+    fragments: &HashMap<Name, Positioned<FragmentDefinition>>,
+) -> ConstValue {
+    fn project_object(
+        object: &IndexMap<Name, ConstValue>,
+        selection_set: &SelectionSet,
+        fragments: &HashMap<Name, Positioned<FragmentDefinition>>,
+        result: &mut IndexMap<Name, ConstValue>,
+    ) {
+        let matches_type = |type_name: &Name| match object.get("__typename") {
+            Some(ConstValue::String(typename)) => typename == type_name.as_str(),
+            _ => true,
+        };
+
+        for selection in &selection_set.items {
+            match &selection.node {
+                Selection::Field(field) => {
+                    let field = &field.node;
+                    let value = match object.get(&field.name.node) {
+                        Some(value) if field.selection_set.node.items.is_empty() => value.clone(),
+                        Some(value) => project(value, &field.selection_set.node, fragments),
+                        None => ConstValue::Null,
+                    };
+                    result.insert(field.response_key().node.clone(), value);
+                }
+                Selection::FragmentSpread(fragment_spread) => {
+                    if let Some(fragment) = fragments.get(&fragment_spread.node.fragment_name.node)
+                    {
+                        if matches_type(&fragment.node.type_condition.node.on.node) {
+                            project_object(
+                                object,
+                                &fragment.node.selection_set.node,
+                                fragments,
+                                result,
+                            );
+                        }
+                    }
+                }
+                Selection::InlineFragment(inline_fragment) => {
+                    let inline_fragment = &inline_fragment.node;
+                    if inline_fragment
+                        .type_condition
+                        .as_ref()
+                        .map_or(true, |type_condition| {
+                            matches_type(&type_condition.node.on.node)
+                        })
+                    {
+                        project_object(
+                            object,
+                            &inline_fragment.selection_set.node,
+                            fragments,
+                            result,
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    match data {
+        ConstValue::List(list) => ConstValue::List(
+            list.iter()
+                .map(|value| project(value, selection_set, fragments))
+                .collect(),
+        ),
+        ConstValue::Object(object) => {
+            let mut result = IndexMap::new();
+            project_object(object, selection_set, fragments, &mut result);
+            ConstValue::Object(result)
+        }
+        _ => ConstValue::Null,
+    }
+}
+
+/// Plans and executes a query against a schema, with the services replaced
+/// by a fetcher.
+pub async fn execute(
+    schema: &ComposedSchema,
+    fetcher: &MockFetcher,
+    query: &str,
+    variables: serde_json::Value,
+) -> Response {
+    let document = match parser::parse_query(query) {
+        Ok(document) => document,
+        Err(err) => return Response::from_errors(vec![ServerError::new(err.to_string())]),
+    };
+    let builder = PlanBuilder::new(schema, document).variables(Variables::from_json(variables));
+    match builder.plan() {
+        Ok(plan) => Executor::new(schema).execute_query(fetcher, &plan).await,
+        Err(response) => response,
+    }
+}
+
+/// Asserts that a response has the expected data and no errors.
+pub fn assert_data(response: &Response, expected: serde_json::Value) {
+    let messages = error_messages(response);
+    assert!(messages.is_empty(), "unexpected errors: {:?}", messages);
+    assert_eq!(response.data.clone().into_json().unwrap(), expected);
+}
+
+/// Asserts that a response has the expected data and error messages.
+pub fn assert_errors(response: &Response, expected: serde_json::Value, messages: &[&str]) {
+    assert_eq!(response.data.clone().into_json().unwrap(), expected);
+    assert_eq!(error_messages(response), messages);
+}
+
+fn error_messages(response: &Response) -> Vec<&str> {
+    response
+        .errors
+        .iter()
+        .map(|error| error.message.as_str())
+        .collect()
+}
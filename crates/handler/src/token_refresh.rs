@@ -0,0 +1,104 @@
+use std::str::FromStr;
+use std::time::Duration;
+
+use http::header::HeaderName;
+use http::{HeaderMap, HeaderValue};
+use once_cell::sync::Lazy;
+
+static HTTP_CLIENT: Lazy<reqwest::Client> = Lazy::new(Default::default);
+
+/// Refreshing of the headers of a WebSocket connection, with the payload of a
+/// `ping` message such as `{"type":"ping","payload":{"Authorization":"Bearer ..."}}`.
+///
+/// The new headers are used when the connections to the services are opened
+/// again, the subscriptions in progress are not affected.
+#[derive(Debug, Clone, Default)]
+pub struct TokenRefreshOptions {
+    /// Headers that can be refreshed, the other fields of the payload are ignored.
+    pub headers: Vec<String>,
+
+    /// If set, the refreshed headers are posted to this URL and the connection
+    /// is closed unless it answers with a success status.
+    pub revalidate_url: Option<String>,
+}
+
+impl TokenRefreshOptions {
+    /// Returns the headers to refresh from the payload of a `ping` message,
+    /// `None` if the payload has none of them.
+    pub(crate) fn headers_from_payload(&self, payload: &serde_json::Value) -> Option<HeaderMap> {
+        let mut header_map = HeaderMap::new();
+        for (name, value) in payload.as_object()? {
+            if !self
+                .headers
+                .iter()
+                .any(|header| header.eq_ignore_ascii_case(name))
+            {
+                continue;
+            }
+            let value = match value.as_str().map(HeaderValue::from_str) {
+                Some(Ok(value)) => value,
+                _ => continue,
+            };
+            if let Ok(name) = HeaderName::from_str(name) {
+                header_map.insert(name, value);
+            }
+        }
+        Some(header_map).filter(|header_map| !header_map.is_empty())
+    }
+
+    /// Returns `true` if the refreshed headers are accepted by the revalidation
+    /// URL, or if it is not set.
+    pub(crate) async fn revalidate(&self, header_map: &HeaderMap) -> bool {
+        let revalidate_url = match &self.revalidate_url {
+            Some(revalidate_url) => revalidate_url,
+            None => return true,
+        };
+        let res = HTTP_CLIENT
+            .post(revalidate_url)
+            .headers(header_map.clone())
+            .timeout(Duration::from_secs(5))
+            .send()
+            .await
+            .and_then(|res| res.error_for_status());
+        match res {
+            Ok(_) => true,
+            Err(err) => {
+                tracing::warn!(
+                    url = %revalidate_url,
+                    error = %err,
+                    "Refreshed websocket headers rejected."
+                );
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn headers_from_payload() {
+        let options = TokenRefreshOptions {
+            headers: vec!["Authorization".to_string()],
+            revalidate_url: None,
+        };
+
+        let header_map = options
+            .headers_from_payload(&serde_json::json!({
+                "authorization": "Bearer new",
+                "x-tenant": "other",
+            }))
+            .unwrap();
+        assert_eq!(header_map.len(), 1);
+        assert_eq!(header_map["authorization"], "Bearer new");
+
+        assert!(options
+            .headers_from_payload(&serde_json::json!({ "x-tenant": "other" }))
+            .is_none());
+        assert!(options
+            .headers_from_payload(&serde_json::json!("Bearer new"))
+            .is_none());
+    }
+}
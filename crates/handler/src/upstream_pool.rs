@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::Result;
+use once_cell::sync::Lazy;
+use opentelemetry::metrics::ObserverResult;
+
+use crate::metrics::Metrics;
+use crate::upstream_tls;
+
+/// Requests in flight to each service.
+static IN_FLIGHT: Lazy<Mutex<HashMap<String, InFlight>>> = Lazy::new(Default::default);
+
+#[derive(Default)]
+struct InFlight {
+    count: u64,
+    /// The service was removed from the routing table, the entry is removed
+    /// when the last request completes.
+    removed: bool,
+}
+
+/// Connection pool of the HTTP clients used for the services.
+///
+/// The clients are shared by all the requests, and keep a pool of
+/// connections for each address, so every service has its own pool.
+#[derive(Debug, Clone)]
+pub struct UpstreamPoolOptions {
+    /// Maximum number of idle connections kept for each address.
+    pub max_idle_per_host: usize,
+
+    /// Idle connections are closed after this duration, never if `None`.
+    pub idle_timeout: Option<Duration>,
+
+    /// Interval of the TCP keep-alive probes, disabled if `None`.
+    pub tcp_keepalive: Option<Duration>,
+
+    /// Interval of the HTTP/2 pings keeping the connections alive, disabled
+    /// if `None`.
+    pub http2_keep_alive_interval: Option<Duration>,
+}
+
+impl Default for UpstreamPoolOptions {
+    fn default() -> Self {
+        Self {
+            max_idle_per_host: usize::MAX,
+            idle_timeout: Some(Duration::from_secs(90)),
+            tcp_keepalive: None,
+            http2_keep_alive_interval: None,
+        }
+    }
+}
+
+impl UpstreamPoolOptions {
+    pub(crate) fn configure(&self, mut builder: reqwest::ClientBuilder) -> reqwest::ClientBuilder {
+        builder = builder
+            .pool_max_idle_per_host(self.max_idle_per_host)
+            .pool_idle_timeout(self.idle_timeout)
+            .tcp_keepalive(self.tcp_keepalive);
+        if let Some(interval) = self.http2_keep_alive_interval {
+            builder = builder
+                .http2_keep_alive_interval(interval)
+                .http2_keep_alive_while_idle(true);
+        }
+        builder
+    }
+
+    /// Replaces the HTTP clients used for the services, the requests in
+    /// flight keep using the previous clients.
+    pub fn apply(&self) -> Result<()> {
+        upstream_tls::set_pool(self.clone())
+    }
+}
+
+/// Counts a request in flight to a service until dropped.
+pub(crate) struct InFlightGuard(String);
+
+impl InFlightGuard {
+    pub(crate) fn new(service: &str) -> Self {
+        let mut in_flight = IN_FLIGHT.lock().unwrap();
+        let entry = in_flight.entry(service.to_string()).or_default();
+        entry.count += 1;
+        // A request to the service means that it is routed again.
+        entry.removed = false;
+        Self(service.to_string())
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        let mut in_flight = IN_FLIGHT.lock().unwrap();
+        if let Some(entry) = in_flight.get_mut(&self.0) {
+            entry.count = entry.count.saturating_sub(1);
+            if entry.count == 0 && entry.removed {
+                in_flight.remove(&self.0);
+            }
+        }
+    }
+}
+
+/// Stops reporting a service removed from the routing table, once the
+/// requests in flight to it complete.
+pub(crate) fn remove_service(service: &str) {
+    let mut in_flight = IN_FLIGHT.lock().unwrap();
+    if let Some(entry) = in_flight.get_mut(service) {
+        if entry.count == 0 {
+            in_flight.remove(service);
+        } else {
+            entry.removed = true;
+        }
+    }
+}
+
+pub(crate) fn observe_in_flight(result: ObserverResult<u64>) {
+    for (service, entry) in IN_FLIGHT.lock().unwrap().iter() {
+        result.observe(entry.count, &Metrics::service_labels(service));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn count(service: &str) -> Option<u64> {
+        IN_FLIGHT
+            .lock()
+            .unwrap()
+            .get(service)
+            .map(|entry| entry.count)
+    }
+
+    #[test]
+    fn in_flight() {
+        let first = InFlightGuard::new("pool-test");
+        let second = InFlightGuard::new("pool-test");
+        assert_eq!(count("pool-test"), Some(2));
+        drop(first);
+        assert_eq!(count("pool-test"), Some(1));
+        drop(second);
+        assert_eq!(count("pool-test"), Some(0));
+        remove_service("pool-test");
+        assert_eq!(count("pool-test"), None);
+    }
+
+    #[test]
+    fn remove_in_flight() {
+        // The entry is removed when the last request completes.
+        let guard = InFlightGuard::new("pool-removed");
+        remove_service("pool-removed");
+        assert_eq!(count("pool-removed"), Some(1));
+        drop(guard);
+        assert_eq!(count("pool-removed"), None);
+
+        // A request after the removal means that the service is routed again.
+        let first = InFlightGuard::new("pool-readded");
+        remove_service("pool-readded");
+        let second = InFlightGuard::new("pool-readded");
+        drop(first);
+        drop(second);
+        assert_eq!(count("pool-readded"), Some(0));
+    }
+}
@@ -0,0 +1,146 @@
+use std::sync::Mutex;
+use std::time::Duration;
+
+use graphgate_planner::normalized_document;
+use indexmap::IndexMap;
+use parser::types::ExecutableDocument;
+use tokio::time::Instant;
+
+#[derive(Debug, Clone)]
+pub struct ValidationCacheOptions {
+    /// Maximum number of queries in the cache, the least recently used are
+    /// evicted first.
+    pub size: usize,
+
+    /// Duration after which a query is validated again.
+    pub ttl: Duration,
+}
+
+impl Default for ValidationCacheOptions {
+    fn default() -> Self {
+        Self {
+            size: 1000,
+            ttl: Duration::from_secs(3600),
+        }
+    }
+}
+
+/// Key of a query in the validation cache.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct ValidationKey {
+    document: String,
+    operation_name: Option<String>,
+    schema_version: u64,
+    authenticated: bool,
+}
+
+impl ValidationKey {
+    /// The document is printed from its parsed form, so that the queries
+    /// differing only by their whitespace or their comments share the key.
+    pub(crate) fn new(
+        document: &ExecutableDocument,
+        operation_name: Option<&str>,
+        schema_version: u64,
+        authenticated: bool,
+    ) -> Self {
+        Self {
+            document: normalized_document(document),
+            operation_name: operation_name.map(ToString::to_string),
+            schema_version,
+            authenticated,
+        }
+    }
+}
+
+/// Cache of the queries already validated against the current schema, so
+/// that the repeated queries skip the validation rules that only depend on
+/// the document.
+///
+/// Only the outcome of the validation is cached: the plans borrow the request
+/// and depend on its variables, so they are still built for each request.
+pub(crate) struct ValidationCache {
+    options: ValidationCacheOptions,
+    entries: Mutex<IndexMap<ValidationKey, Instant>>,
+}
+
+impl ValidationCache {
+    pub(crate) fn new(options: ValidationCacheOptions) -> Self {
+        Self {
+            options,
+            entries: Default::default(),
+        }
+    }
+
+    /// Returns `true` if the query was validated less than the TTL ago, and
+    /// marks it as the most recently used.
+    pub(crate) fn contains(&self, key: &ValidationKey) -> bool {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.shift_remove_entry(key) {
+            Some((key, inserted_at)) if inserted_at.elapsed() < self.options.ttl => {
+                entries.insert(key, inserted_at);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub(crate) fn insert(&self, key: ValidationKey) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.shift_remove(&key);
+        entries.insert(key, Instant::now());
+        while entries.len() > self.options.size {
+            entries.shift_remove_index(0);
+        }
+    }
+
+    /// Removes all the queries, such as when the schema changes.
+    pub(crate) fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(query: &str, schema_version: u64) -> ValidationKey {
+        let document = parser::parse_query(query).unwrap();
+        ValidationKey::new(&document, None, schema_version, false)
+    }
+
+    #[test]
+    fn normalize() {
+        assert_eq!(key("{ me { id } }", 1), key("{\n  me {\n    id\n  }\n}", 1));
+        assert_eq!(
+            key("{ user(id: \"a  b\") { name } }", 1),
+            key("# comment\n{ user(id:\"a  b\") {\tname } }", 1)
+        );
+        assert_ne!(key("{ me { id } }", 1), key("{ me { id } }", 2));
+        assert_ne!(
+            key("{ user(id: \"a\") { name } }", 1),
+            key("{ user(id: \"b\") { name } }", 1)
+        );
+        assert_ne!(key("{ me { id } }", 1), key("{ me { a: id } }", 1));
+        assert_ne!(key("{ a # evil\n }", 1), key("{ a #\n evil }", 1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn eviction() {
+        let cache = ValidationCache::new(ValidationCacheOptions {
+            size: 2,
+            ttl: Duration::from_secs(60),
+        });
+        let (a, b, c) = (key("{ a }", 1), key("{ b }", 1), key("{ c }", 1));
+        cache.insert(a.clone());
+        cache.insert(b.clone());
+        assert!(cache.contains(&a));
+        cache.insert(c.clone());
+        assert!(cache.contains(&a));
+        assert!(!cache.contains(&b));
+        assert!(cache.contains(&c));
+
+        tokio::time::advance(Duration::from_secs(61)).await;
+        assert!(!cache.contains(&a));
+        assert!(!cache.contains(&c));
+    }
+}
@@ -0,0 +1,85 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use graphgate_planner::{Request, Response};
+use once_cell::sync::Lazy;
+use opentelemetry::trace::get_active_span;
+
+use crate::constants::KEY_SCHEMA_VERSION;
+use crate::fetcher::Fetcher;
+use crate::metrics::{Metrics, METRICS};
+
+/// The mismatches that have already been logged, as `(service, composed, served)`.
+static REPORTED: Lazy<Mutex<HashSet<(String, String, String)>>> = Lazy::new(Default::default);
+
+/// Returns the schema version in the headers of a service response.
+pub(crate) fn response_version(response: &Response, header: &str) -> Option<String> {
+    response
+        .headers
+        .as_ref()?
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(header))
+        .and_then(|(_, values)| values.first().cloned())
+}
+
+/// Schema versions of the services, as reported with the SDL used in the
+/// current composition.
+#[derive(Debug, Clone)]
+pub(crate) struct ComposedVersions {
+    pub(crate) header: String,
+    pub(crate) versions: Arc<HashMap<String, String>>,
+}
+
+/// A fetcher that records the schema version that served each request, and
+/// reports the services whose version differs from the composed one.
+pub(crate) struct VersionCheckFetcher<F> {
+    inner: F,
+    composed: Option<ComposedVersions>,
+}
+
+impl<F: Fetcher> VersionCheckFetcher<F> {
+    pub(crate) fn new(inner: F, composed: Option<ComposedVersions>) -> Self {
+        Self { inner, composed }
+    }
+}
+
+#[async_trait::async_trait]
+impl<F: Fetcher> Fetcher for VersionCheckFetcher<F> {
+    async fn query(&self, service: &str, request: Request) -> Result<Response> {
+        let resp = self.inner.query(service, request).await?;
+        let composed = match &self.composed {
+            Some(composed) => composed,
+            None => return Ok(resp),
+        };
+        let served = match response_version(&resp, &composed.header) {
+            Some(served) => served,
+            None => return Ok(resp),
+        };
+
+        get_active_span(|span| span.set_attribute(KEY_SCHEMA_VERSION.string(served.clone())));
+        if let Some(expected) = composed
+            .versions
+            .get(service)
+            .filter(|expected| **expected != served)
+        {
+            METRICS
+                .schema_version_mismatch_counter
+                .add(1, &Metrics::service_labels(service));
+            let first = REPORTED.lock().unwrap().insert((
+                service.to_string(),
+                expected.clone(),
+                served.clone(),
+            ));
+            if first {
+                tracing::warn!(
+                    service = service,
+                    composed = %expected,
+                    served = %served,
+                    "Service serves a different schema version than the composed schema."
+                );
+            }
+        }
+        Ok(resp)
+    }
+}
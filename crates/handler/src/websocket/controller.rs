@@ -3,20 +3,26 @@ use std::str::FromStr;
 use std::sync::Arc;
 
 use anyhow::Result;
+use chrono::Utc;
 use futures_util::stream::{SplitSink, SplitStream};
 use futures_util::{SinkExt, StreamExt};
 use graphgate_planner::{Request, Response};
 use http::{HeaderMap, Request as HttpRequest};
 use tokio::net::TcpStream;
 use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
 use tokio::time::Duration;
 use tokio_tungstenite::tungstenite::protocol::CloseFrame;
 use tokio_tungstenite::tungstenite::{Message, Result as WsResult};
 use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+use value::Variables;
 
 use super::grouped_stream::{GroupedStream, StreamEvent};
 use super::protocol::{ClientMessage, Protocols, ServerMessage};
-use crate::ServiceRouteTable;
+use crate::audit::{FetchEvent, StreamAudit};
+use crate::callback::CallbackRegistry;
+use crate::event_source::{EventSource, EventSources};
+use crate::{upstream_tls, ServiceRouteTable};
 
 const CONNECT_TIMEOUT_SECONDS: u64 = 5;
 
@@ -27,6 +33,7 @@ struct SubscribeCommand {
     payload: Request,
     tx: mpsc::UnboundedSender<Response>,
     reply: oneshot::Sender<Result<()>>,
+    audit: Option<Arc<StreamAudit>>,
 }
 
 struct StopCommand {
@@ -36,23 +43,33 @@ struct StopCommand {
 enum Command {
     Subscribe(SubscribeCommand),
     Stop(StopCommand),
+    UpdateHeaders(HeaderMap),
 }
 
 #[derive(Clone)]
 pub struct WebSocketController {
     tx_command: mpsc::UnboundedSender<Command>,
+    audit: Option<Arc<StreamAudit>>,
 }
 
 impl WebSocketController {
     pub fn new(
         route_table: Arc<ServiceRouteTable>,
+        event_sources: Arc<EventSources>,
+        callbacks: Option<Arc<CallbackRegistry>>,
         header_map: &HeaderMap,
+        variables: &Variables,
         init_payload: Option<serde_json::Value>,
     ) -> Self {
         let (tx_command, rx_command) = mpsc::unbounded_channel();
         let ctx = WebSocketContext {
             route_table,
+            event_sources,
+            event_tasks: Default::default(),
+            callbacks,
+            callback_ids: Default::default(),
             header_map: header_map.clone(),
+            variables: variables.clone(),
             init_payload,
             upstream: GroupedStream::default(),
             upstream_info: Default::default(),
@@ -61,7 +78,19 @@ impl WebSocketController {
         };
 
         tokio::spawn(ctx.main());
-        Self { tx_command }
+        Self {
+            tx_command,
+            audit: None,
+        }
+    }
+
+    /// Returns a controller sharing the same connections, whose upstream
+    /// subscriptions are added to the execution report of an operation.
+    pub(crate) fn with_audit(&self, audit: Option<Arc<StreamAudit>>) -> Self {
+        Self {
+            tx_command: self.tx_command.clone(),
+            audit,
+        }
     }
 
     pub async fn subscribe(
@@ -80,6 +109,7 @@ impl WebSocketController {
                 payload: request,
                 tx,
                 reply: tx_reply,
+                audit: self.audit.clone(),
             }))
             .is_err()
         {
@@ -95,6 +125,14 @@ impl WebSocketController {
             .send(Command::Stop(StopCommand { id: id.into() }))
             .ok();
     }
+
+    /// Replaces the headers sent when connecting to the services, the
+    /// connections already open are kept.
+    pub fn update_headers(&self, header_map: HeaderMap) {
+        self.tx_command
+            .send(Command::UpdateHeaders(header_map))
+            .ok();
+    }
 }
 
 struct UpstreamInfo {
@@ -106,11 +144,20 @@ struct UpstreamInfo {
 struct SubscribeInfo {
     services: HashSet<String>,
     tx: mpsc::UnboundedSender<Response>,
+
+    /// Upstream subscriptions by service, reported when they complete.
+    fetches: HashMap<String, FetchEvent>,
+    audit: Option<Arc<StreamAudit>>,
 }
 
 struct WebSocketContext {
     route_table: Arc<ServiceRouteTable>,
+    event_sources: Arc<EventSources>,
+    event_tasks: HashMap<String, Vec<JoinHandle<()>>>,
+    callbacks: Option<Arc<CallbackRegistry>>,
+    callback_ids: HashMap<String, Vec<String>>,
     header_map: HeaderMap,
+    variables: Variables,
     init_payload: Option<serde_json::Value>,
     upstream: GroupedStream<String, SplitStream<WebSocketStream<MaybeTlsStream<TcpStream>>>>,
     upstream_info: HashMap<String, UpstreamInfo>,
@@ -124,22 +171,29 @@ impl WebSocketContext {
             tokio::select! {
                 command = self.rx_command.recv() => match command {
                     Some(command) => self.handle_command(command).await,
-                    None => return,
+                    None => break,
                 },
                 event = self.upstream.next() => match event {
                     Some(event) => if !self.handle_event(event).await {
-                        return;
+                        break;
                     },
-                    None => return,
+                    None => break,
                 }
             }
         }
+
+        // Reports the subscriptions still open when the connection ends.
+        let ids: Vec<_> = self.subscribes.keys().cloned().collect();
+        for id in ids {
+            self.finish_subscribe(&id);
+        }
     }
 
     async fn handle_command(&mut self, command: Command) {
         match command {
             Command::Subscribe(command) => self.handle_command_subscribe(command).await,
             Command::Stop(command) => self.handle_command_stop(command).await,
+            Command::UpdateHeaders(header_map) => self.header_map = header_map,
         }
     }
 
@@ -147,7 +201,6 @@ impl WebSocketContext {
         &mut self,
         service: &str,
     ) -> Result<(WebSocketStream<MaybeTlsStream<TcpStream>>, Protocols)> {
-        const PROTOCOLS: &str = "graphql-ws, graphql-transport-ws";
         let route = self.route_table.get(service).ok_or_else(|| {
             anyhow::anyhow!("Service '{}' is not defined in the routing table.", service)
         })?;
@@ -161,21 +214,45 @@ impl WebSocketContext {
             None => format!("{}://{}", scheme, route.addr),
         };
 
+        let protocols = match route.websocket_protocol {
+            Some(protocol) => protocol.sec_websocket_protocol().to_string(),
+            None => Protocols::ALL
+                .iter()
+                .map(Protocols::sec_websocket_protocol)
+                .collect::<Vec<_>>()
+                .join(", "),
+        };
+
         tracing::debug!(url = %url, service = service, "Connect to upstream websocket");
         let mut http_request = HttpRequest::builder()
             .uri(&url)
-            .header("Sec-WebSocket-Protocol", PROTOCOLS)
+            .header("Sec-WebSocket-Protocol", protocols)
             .body(())
             .unwrap();
         http_request.headers_mut().extend(self.header_map.clone());
-        let (mut stream, http_response) = tokio_tungstenite::connect_async(http_request).await?;
-        let protocol = http_response
+        if let Some(oauth2) = &route.oauth2 {
+            oauth2.authorize(http_request.headers_mut()).await?;
+        }
+        let (mut stream, http_response) = tokio_tungstenite::connect_async_tls_with_config(
+            http_request,
+            None,
+            upstream_tls::websocket_connector(),
+        )
+        .await?;
+        let protocol = match http_response
             .headers()
             .get("Sec-WebSocket-Protocol")
             .and_then(|value| value.to_str().ok())
-            .map(|value| Protocols::from_str(value).ok())
-            .flatten()
-            .ok_or_else(|| anyhow::anyhow!("Unknown protocol: {}", url))?;
+        {
+            Some(value) => Protocols::from_str(value).ok().filter(|protocol| {
+                route
+                    .websocket_protocol
+                    .map_or(true, |forced| forced == *protocol)
+            }),
+            // Services that do not answer the header speak the forced protocol.
+            None => route.websocket_protocol,
+        }
+        .ok_or_else(|| anyhow::anyhow!("Unknown protocol: {}", url))?;
 
         stream
             .send(Message::Text(
@@ -216,7 +293,26 @@ impl WebSocketContext {
         Ok((stream, protocol))
     }
 
-    async fn handle_command_subscribe(&mut self, command: SubscribeCommand) {
+    async fn handle_command_subscribe(&mut self, mut command: SubscribeCommand) {
+        if !self.variables.is_empty() {
+            command.payload = command.payload.extend_variables(self.variables.clone());
+        }
+
+        if let Some(event_source) = self.event_sources.get(&command.service).cloned() {
+            self.handle_command_subscribe_event_source(command, &event_source)
+                .await;
+            return;
+        }
+
+        if self
+            .route_table
+            .get(&command.service)
+            .map_or(false, |route| route.subscription_callback)
+        {
+            self.handle_command_subscribe_callback(command).await;
+            return;
+        }
+
         if !self.upstream.contains_key(&command.service) {
             let (stream, protocol) = match self.ensure_upstream(&command.service).await {
                 Ok(stream) => stream,
@@ -240,40 +336,155 @@ impl WebSocketContext {
         if let Some(info) = self.upstream_info.get_mut(&command.service) {
             info.subscribe_count += 1;
 
-            match self.subscribes.get_mut(&command.id) {
-                Some(subscribe_info) => {
-                    assert!(!subscribe_info.services.contains(&command.service));
-                    subscribe_info.services.insert(command.service.clone());
-                }
-                None => {
-                    self.subscribes.insert(
-                        command.id.clone(),
-                        SubscribeInfo {
-                            services: std::iter::once(command.service.clone()).collect(),
-                            tx: command.tx,
-                        },
-                    );
+            let tx = command.tx;
+            let subscribe_info = self
+                .subscribes
+                .entry(command.id.clone())
+                .or_insert_with(|| SubscribeInfo {
+                    services: HashSet::new(),
+                    tx,
+                    fetches: HashMap::new(),
+                    audit: None,
+                });
+            assert!(!subscribe_info.services.contains(&command.service));
+            subscribe_info.services.insert(command.service.clone());
+
+            let message = serde_json::to_string(
+                &info
+                    .protocol
+                    .subscribe_message(&command.id, command.payload),
+            )
+            .unwrap();
+            if let Some(audit) = command.audit {
+                let now = Utc::now();
+                subscribe_info.fetches.insert(
+                    command.service.clone(),
+                    FetchEvent {
+                        service: command.service.clone(),
+                        start_time: now,
+                        end_time: now,
+                        request_bytes: message.len(),
+                        response_bytes: 0,
+                        errors: Vec::new(),
+                    },
+                );
+                subscribe_info.audit = Some(audit);
+            }
+            info.sink.send(Message::text(message)).await.ok();
+
+            command.reply.send(Ok(())).ok();
+        }
+    }
+
+    async fn handle_command_subscribe_event_source(
+        &mut self,
+        command: SubscribeCommand,
+        event_source: &EventSource,
+    ) {
+        let mut stream = match event_source.subscribe(&command.payload).await {
+            Ok(stream) => stream,
+            Err(err) => {
+                command.reply.send(Err(err)).ok();
+                return;
+            }
+        };
+
+        let tx = self.add_subscribe(&command.id, &command.service, command.tx);
+        let task = tokio::spawn(async move {
+            while let Some(response) = stream.next().await {
+                if tx.send(response).is_err() {
+                    break;
                 }
             }
+        });
+        self.event_tasks.entry(command.id).or_default().push(task);
+        command.reply.send(Ok(())).ok();
+    }
 
-            info.sink
-                .send(Message::text(
-                    serde_json::to_string(
-                        &info
-                            .protocol
-                            .subscribe_message(&command.id, command.payload),
-                    )
-                    .unwrap(),
-                ))
-                .await
-                .ok();
+    async fn handle_command_subscribe_callback(&mut self, command: SubscribeCommand) {
+        let registry = match self.callbacks.clone() {
+            Some(registry) => registry,
+            None => {
+                command
+                    .reply
+                    .send(Err(anyhow::anyhow!(
+                        "Subscription callbacks are not enabled for service '{}'.",
+                        command.service
+                    )))
+                    .ok();
+                return;
+            }
+        };
 
-            command.reply.send(Ok(())).ok();
+        let tx = self.add_subscribe(&command.id, &command.service, command.tx);
+        let (callback_id, extension) = registry.register(tx);
+        let mut payload = command.payload;
+        payload
+            .extensions
+            .insert("subscription".to_string(), extension);
+        self.callback_ids
+            .entry(command.id.clone())
+            .or_default()
+            .push(callback_id);
+
+        let res = self
+            .route_table
+            .query(&command.service, payload, Some(&self.header_map), None)
+            .await
+            .and_then(|resp| match resp.errors.into_iter().next() {
+                Some(err) => Err(anyhow::anyhow!(err.message)),
+                None => Ok(()),
+            });
+        if res.is_err() {
+            self.finish_subscribe(&command.id);
+        }
+        command.reply.send(res).ok();
+    }
+
+    fn add_subscribe(
+        &mut self,
+        id: &str,
+        service: &str,
+        tx: mpsc::UnboundedSender<Response>,
+    ) -> mpsc::UnboundedSender<Response> {
+        match self.subscribes.get_mut(id) {
+            Some(subscribe_info) => {
+                subscribe_info.services.insert(service.to_string());
+                subscribe_info.tx.clone()
+            }
+            None => {
+                self.subscribes.insert(
+                    id.to_string(),
+                    SubscribeInfo {
+                        services: std::iter::once(service.to_string()).collect(),
+                        tx: tx.clone(),
+                        fetches: HashMap::new(),
+                        audit: None,
+                    },
+                );
+                tx
+            }
         }
     }
 
     fn finish_subscribe(&mut self, id: &str) {
+        if let Some(registry) = &self.callbacks {
+            for callback_id in self.callback_ids.remove(id).unwrap_or_default() {
+                registry.unregister(&callback_id);
+            }
+        }
+        for task in self.event_tasks.remove(id).unwrap_or_default() {
+            task.abort();
+        }
+
         if let Some(subscribe_info) = self.subscribes.remove(id) {
+            if let Some(audit) = &subscribe_info.audit {
+                let now = Utc::now();
+                for (_, mut fetch) in subscribe_info.fetches {
+                    fetch.end_time = now;
+                    audit.push(fetch);
+                }
+            }
             for service in subscribe_info.services {
                 if let Some(upstream_info) = self.upstream_info.get_mut(&service) {
                     upstream_info.subscribe_count -= 1;
@@ -293,7 +504,7 @@ impl WebSocketContext {
 
     async fn handle_event(&mut self, event: StreamEvent<String, WsResult<Message>>) -> bool {
         match event {
-            StreamEvent::Data(_, Ok(Message::Text(text))) => {
+            StreamEvent::Data(service, Ok(Message::Text(text))) => {
                 let message = match serde_json::from_str::<ServerMessage>(&text) {
                     Ok(message) => message,
                     Err(_) => return false,
@@ -301,6 +512,12 @@ impl WebSocketContext {
                 match message {
                     ServerMessage::Data { id, payload } | ServerMessage::Next { id, payload } => {
                         if let Some(info) = self.subscribes.get_mut(id) {
+                            if let Some(fetch) = info.fetches.get_mut(&service) {
+                                fetch.response_bytes += text.len();
+                                fetch
+                                    .errors
+                                    .extend(payload.errors.iter().map(|err| err.message.clone()));
+                            }
                             if info.tx.send(payload).is_err() {
                                 self.finish_subscribe(id);
                             }
@@ -38,6 +38,11 @@ impl<K: Eq + Hash + Clone, S> GroupedStream<K, S> {
         self.streams.remove(key);
     }
 
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.streams.is_empty()
+    }
+
     #[inline]
     pub fn contains_key<Q: ?Sized>(&self, key: &Q) -> bool
     where
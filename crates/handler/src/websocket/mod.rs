@@ -3,6 +3,31 @@ mod grouped_stream;
 mod protocol;
 mod server;
 
+use std::sync::atomic::{AtomicUsize, Ordering};
+
 pub use controller::WebSocketController;
 pub use protocol::Protocols;
 pub use server::server;
+
+/// Number of open WebSocket connections.
+pub(crate) static ACTIVE_CONNECTIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// Number of running subscriptions of all WebSocket connections and
+/// Server-Sent Events streams.
+pub(crate) static ACTIVE_SUBSCRIPTIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// Increments a counter until dropped.
+pub(crate) struct ActiveGuard(&'static AtomicUsize);
+
+impl ActiveGuard {
+    pub(crate) fn new(counter: &'static AtomicUsize) -> Self {
+        counter.fetch_add(1, Ordering::Relaxed);
+        Self(counter)
+    }
+}
+
+impl Drop for ActiveGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
@@ -1,16 +1,22 @@
+use std::str::FromStr;
+
 use anyhow::Error;
 use graphgate_planner::{Request, Response};
 use serde::{Deserialize, Serialize};
 
-#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+/// WebSocket subprotocol, named by its `Sec-WebSocket-Protocol` value in the
+/// configuration.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
 pub enum Protocols {
     /// [subscriptions-transport-ws protocol](https://github.com/apollographql/subscriptions-transport-ws/blob/master/PROTOCOL.md).
+    #[serde(rename = "graphql-ws")]
     SubscriptionsTransportWS,
     /// [graphql-ws protocol](https://github.com/enisdenjo/graphql-ws/blob/master/PROTOCOL.md).
+    #[serde(rename = "graphql-transport-ws")]
     GraphQLWS,
 }
 
-impl std::str::FromStr for Protocols {
+impl FromStr for Protocols {
     type Err = Error;
 
     fn from_str(protocol: &str) -> Result<Self, Self::Err> {
@@ -25,6 +31,24 @@ impl std::str::FromStr for Protocols {
 }
 
 impl Protocols {
+    /// All the supported protocols, the default one first.
+    pub const ALL: [Protocols; 2] = [Protocols::SubscriptionsTransportWS, Protocols::GraphQLWS];
+
+    /// Returns the first protocol requested in a `Sec-WebSocket-Protocol`
+    /// header that is enabled, or the first enabled protocol if the header is
+    /// missing.
+    ///
+    /// Returns `None` if none of the requested protocols is enabled.
+    pub fn negotiate(requested: Option<&str>, enabled: &[Protocols]) -> Option<Protocols> {
+        match requested {
+            Some(requested) => requested
+                .split(',')
+                .filter_map(|protocol| Protocols::from_str(protocol.trim()).ok())
+                .find(|protocol| enabled.contains(protocol)),
+            None => enabled.first().copied(),
+        }
+    }
+
     pub fn sec_websocket_protocol(&self) -> &str {
         match self {
             Protocols::SubscriptionsTransportWS => "graphql-ws",
@@ -65,6 +89,8 @@ pub enum ClientMessage<'a> {
     Stop { id: &'a str },
     Complete { id: &'a str },
     ConnectionTerminate,
+    Ping { payload: Option<serde_json::Value> },
+    Pong { payload: Option<serde_json::Value> },
 }
 
 #[derive(Deserialize, Serialize)]
@@ -81,4 +107,5 @@ pub enum ServerMessage<'a> {
     Data { id: &'a str, payload: Response },
     Next { id: &'a str, payload: Response },
     Complete { id: &'a str },
+    Pong { payload: Option<serde_json::Value> },
 }
@@ -1,11 +1,13 @@
+use std::panic::AssertUnwindSafe;
 use std::sync::Arc;
+use std::time::Instant;
 
 use futures_util::sink::Sink;
 use futures_util::stream::Stream;
 use futures_util::{SinkExt, StreamExt};
-use graphgate_planner::{PlanBuilder, Response, ServerError};
+use graphgate_planner::{ErrorCode, PlanBuilder, Response, ServerError};
 use graphgate_schema::ComposedSchema;
-use value::ConstValue;
+use value::Variables;
 use warp::http::HeaderMap;
 use warp::ws::Message;
 use warp::Error;
@@ -13,23 +15,51 @@ use warp::Error;
 use super::controller::WebSocketController;
 use super::grouped_stream::{GroupedStream, StreamEvent};
 use super::protocol::{ClientMessage, ConnectionError, Protocols, ServerMessage};
+use super::{ActiveGuard, ACTIVE_CONNECTIONS, ACTIVE_SUBSCRIPTIONS};
+use crate::computed_variables::ComputedVariables;
 use crate::executor::Executor;
-use crate::ServiceRouteTable;
+use crate::metrics;
+use crate::recover::panic_response;
+use crate::shaping;
+use crate::shutdown::{self, ShutdownState};
+use crate::switches::DisabledFields;
+use crate::{ServiceRouteTable, SharedRouteTable};
 
+#[allow(clippy::too_many_arguments)]
 pub async fn server(
     schema: Arc<ComposedSchema>,
     route_table: Arc<ServiceRouteTable>,
     stream: impl Stream<Item = Result<Message, Error>> + Sink<Message>,
     protocol: Protocols,
     header_map: HeaderMap,
+    injected_variables: Variables,
+    computed_variables: ComputedVariables,
+    authenticated: bool,
+    shared_route_table: SharedRouteTable,
 ) {
+    let _connection = ActiveGuard::new(&ACTIVE_CONNECTIONS);
     let (mut sink, mut stream) = stream.split();
     let mut streams = GroupedStream::default();
     let mut controller = None;
-    let header_map = Arc::new(header_map);
+    let mut header_map = Arc::new(header_map);
+    let mut shutdown = shutdown::subscribe();
 
     loop {
+        // While the gateway shuts down, the connection is closed once its
+        // subscriptions complete, so that the client reconnects to the new
+        // process.
+        let state = *shutdown.borrow();
+        if state == ShutdownState::Closing
+            || (state == ShutdownState::Draining && streams.is_empty())
+        {
+            sink.send(Message::close_with(1012u16, "Service restart."))
+                .await
+                .ok();
+            return;
+        }
+
         tokio::select! {
+            Ok(()) = shutdown.changed() => {}
             message = stream.next() => match message {
                 Some(Ok(message)) if message.is_text() => {
                     let text = message.into_bytes();
@@ -40,7 +70,7 @@ pub async fn server(
 
                     match client_msg {
                         ClientMessage::ConnectionInit { payload } if controller.is_none() => {
-                            controller = Some(WebSocketController::new(route_table.clone(), &header_map, payload));
+                            controller = Some(WebSocketController::new(route_table.clone(), shared_route_table.event_sources().clone(), shared_route_table.callbacks().cloned(), &header_map, &injected_variables, payload));
                             sink.send(Message::text(serde_json::to_string(&ServerMessage::ConnectionAck).unwrap())).await.ok();
                         }
                         ClientMessage::ConnectionInit { .. } => {
@@ -61,17 +91,23 @@ pub async fn server(
                                 }
                             }
                         }
-                        ClientMessage::Start { id, payload } | ClientMessage::Subscribe { id, payload } => {
-                            let controller = controller.get_or_insert_with(|| WebSocketController::new(route_table.clone(), &header_map, None)).clone();
+                        ClientMessage::Start { id, mut payload } | ClientMessage::Subscribe { id, mut payload } => {
+                            computed_variables.apply(&mut payload.variables);
+                            if let Some((resp, _)) = shared_route_table.switches().check_maintenance() {
+                                let data = ServerMessage::Data { id, payload: resp };
+                                sink.send(Message::text(serde_json::to_string(&data).unwrap())).await.ok();
+
+                                let complete = ServerMessage::Complete { id };
+                                sink.send(Message::text(serde_json::to_string(&complete).unwrap())).await.ok();
+                                continue;
+                            }
+
+                            let controller = controller.get_or_insert_with(|| WebSocketController::new(route_table.clone(), shared_route_table.event_sources().clone(), shared_route_table.callbacks().cloned(), &header_map, &injected_variables, None)).clone();
+                            let parse_started = Instant::now();
                             let document = match parser::parse_query(&payload.query) {
                                 Ok(document) => document,
                                 Err(err) => {
-                                    let resp = Response {
-                                        data: ConstValue::Null,
-                                        errors: vec![ServerError::new(err.to_string())],
-                                        extensions: Default::default(),
-                                        headers: Default::default()
-                                    };
+                                    let resp = Response::from_errors(vec![ServerError::new(err.to_string()).with_code(ErrorCode::ParseFailed)]);
                                     let data = ServerMessage::Data { id, payload: resp };
                                     sink.send(Message::text(serde_json::to_string(&data).unwrap())).await.ok();
 
@@ -81,30 +117,102 @@ pub async fn server(
                                 }
                             };
 
+                            if let Some(resp) = shared_route_table.check_parse_budget(parse_started.elapsed()).or_else(|| shared_route_table.switches().check_operation(&document, payload.operation.as_deref())) {
+                                let data = ServerMessage::Data { id, payload: resp };
+                                sink.send(Message::text(serde_json::to_string(&data).unwrap())).await.ok();
+
+                                let complete = ServerMessage::Complete { id };
+                                sink.send(Message::text(serde_json::to_string(&complete).unwrap())).await.ok();
+                                continue;
+                            }
+                            let controller = controller.with_audit(shared_route_table.stream_audit(&document, payload.operation.as_deref()));
+
                             let id = Arc::new(id.to_string());
                             let schema = schema.clone();
+                            let plan_limits = shared_route_table.plan_limits().clone();
+                            let strict_planning = shared_route_table.strict_planning();
+                            let scalar_validators = shared_route_table.scalar_validators().clone();
+                            let inline_variables = shared_route_table.inline_variables().clone();
+                            let switches = shared_route_table.switches().clone();
+                            let operation_name = payload.operation.clone();
+                            let remove_nulls = shaping::remove_nulls_requested(&payload, &header_map);
+                            let normalize = shaping::normalize_requested(&payload, &header_map);
+                            let shared_route_table = shared_route_table.clone();
                             let stream = {
                                 let id = id.clone();
                                 async_stream::stream! {
-                                    let builder = PlanBuilder::new(&schema, document).variables(payload.variables);
+                                    let _subscription = ActiveGuard::new(&ACTIVE_SUBSCRIPTIONS);
+                                    let mut builder = PlanBuilder::new(&schema, document).variables(payload.variables).limits(plan_limits).strict(strict_planning).scalar_validators(scalar_validators).inline_variables(inline_variables);
+                                    if let Some(operation_name) = operation_name.clone() {
+                                        builder = builder.operation_name(operation_name);
+                                    }
                                     let node = match builder.plan() {
                                         Ok(node) => node,
                                         Err(resp) => {
+                                            metrics::count_plan_failure(&resp);
                                             yield resp;
                                             return;
                                         }
                                     };
+                                    if let Some(resp) = switches.check_plan(&schema, builder.document(), operation_name.as_deref(), &node) {
+                                        yield resp;
+                                        return;
+                                    }
                                     let executor = Executor::new(&schema);
                                     let mut stream = executor.execute_stream(controller.clone(), &id, &node).await;
-                                    while let Some(item) = stream.next().await {
+                                    while let Some(mut item) = stream.next().await {
+                                        shared_route_table.process_response(&mut item, &schema, &builder, operation_name.as_deref(), authenticated, remove_nulls, &DisabledFields::default());
+                                        if normalize {
+                                            if let Some(records) = shaping::normalize(&mut item.data) {
+                                                item.extensions.insert(shaping::RECORDS_EXTENSION.to_string(), records);
+                                            }
+                                        }
                                         yield item;
                                     }
                                 }
                             };
+                            let stream = AssertUnwindSafe(stream)
+                                .catch_unwind()
+                                .map(|item| item.unwrap_or_else(panic_response));
                             streams.insert(id, Box::pin(stream));
                         }
+                        ClientMessage::Ping { payload } => {
+                            let refresh = shared_route_table.token_refresh().and_then(|token_refresh| {
+                                Some((token_refresh, token_refresh.headers_from_payload(payload.as_ref()?)?))
+                            });
+                            if let Some((token_refresh, refreshed_headers)) = refresh {
+                                let mut refreshed = (*header_map).clone();
+                                for (name, value) in &refreshed_headers {
+                                    refreshed.insert(name.clone(), value.clone());
+                                }
+                                if !token_refresh.revalidate(&refreshed).await {
+                                    match protocol {
+                                        Protocols::SubscriptionsTransportWS => {
+                                            let err_msg = Message::text(
+                                                serde_json::to_string(&ServerMessage::ConnectionError {
+                                                    payload: ConnectionError {
+                                                        message: "Forbidden.",
+                                                    },
+                                                }).unwrap());
+                                            sink.send(err_msg).await.ok();
+                                        }
+                                        Protocols::GraphQLWS => {
+                                            sink.send(Message::close_with(4403u16, "Forbidden.")).await.ok();
+                                        }
+                                    }
+                                    return;
+                                }
+                                if let Some(controller) = &controller {
+                                    controller.update_headers(refreshed.clone());
+                                }
+                                header_map = Arc::new(refreshed);
+                            }
+                            if protocol == Protocols::GraphQLWS {
+                                sink.send(Message::text(serde_json::to_string(&ServerMessage::Pong { payload: None }).unwrap())).await.ok();
+                            }
+                        }
                         ClientMessage::Stop { id } => {
-                            let controller = controller.get_or_insert_with(|| WebSocketController::new(route_table.clone(), &header_map, None)).clone();
+                            let controller = controller.get_or_insert_with(|| WebSocketController::new(route_table.clone(), shared_route_table.event_sources().clone(), shared_route_table.callbacks().cloned(), &header_map, &injected_variables, None)).clone();
                             controller.stop(id).await;
                         }
                         _ => {}
@@ -1,33 +1,155 @@
 #![allow(clippy::too_many_arguments)]
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use graphgate_schema::{ComposedSchema, KeyFields, MetaField, MetaType, TypeKind, ValueExt};
+use graphgate_schema::{
+    ComposedSchema, GatewayField, KeyFields, MetaField, MetaType, TypeKind, ValueExt,
+};
+use graphgate_validation::{ScalarValidator, ValidationLimits};
 use indexmap::IndexMap;
 use parser::types::{
-    BaseType, DocumentOperations, ExecutableDocument, Field, FragmentDefinition,
+    BaseType, Directive, DocumentOperations, ExecutableDocument, Field, FragmentDefinition,
     OperationDefinition, OperationType, Selection, SelectionSet, Type, VariableDefinition,
 };
 use parser::Positioned;
 use value::{ConstValue, Name, Value, Variables};
 
 use crate::plan::{
-    FetchNode, FlattenNode, IntrospectionDirective, IntrospectionField, IntrospectionNode,
-    IntrospectionSelectionSet, ParallelNode, PathSegment, PlanNode, ResponsePath, SequenceNode,
+    DeferNode, DeferredNode, FetchNode, FlattenNode, IntrospectionDirective, IntrospectionField,
+    IntrospectionNode, IntrospectionSelectionSet, ParallelNode, PathSegment, PlanNode,
+    ResponsePath, SequenceNode, StreamNode,
 };
 use crate::types::{
-    FetchEntity, FetchEntityGroup, FetchEntityKey, FetchQuery, FieldRef, MutationRootGroup,
-    QueryRootGroup, RequiredRef, RootGroup, SelectionRef, SelectionRefSet, VariableDefinitionsRef,
-    VariablesRef,
+    is_forwarded, FetchEntity, FetchEntityGroup, FetchEntityKey, FetchQuery, FieldRef,
+    GatewayFieldRef, MutationRootGroup, QueryRootGroup, RequiredRef, RootGroup, SelectionRef,
+    SelectionRefSet, VariableDefinitionsRef, VariablesRef,
 };
-use crate::{Response, RootNode, ServerError, SubscribeNode};
+use crate::{ErrorCode, Response, RootNode, ServerError, SubscribeNode};
 
 #[derive(Debug)]
 struct Context<'a> {
     schema: &'a ComposedSchema,
     fragments: &'a HashMap<Name, Positioned<FragmentDefinition>>,
     variables: &'a Variables,
-    key_id: usize,
+    variable_definitions: &'a [Positioned<VariableDefinition>],
+    key_prefixes: HashSet<usize>,
+    operation_name: Option<&'a str>,
+    fetch_ids: HashMap<&'a str, usize>,
+    errors: Vec<ServerError>,
+    strict: bool,
+    inline_variables: &'a InlineVariables,
+
+    /// Start and time budget of the planning.
+    budget: Option<(Instant, Duration)>,
+    budget_exceeded: bool,
+
+    /// `true` if the `@defer` and `@stream` directives are planned, only for
+    /// the queries.
+    incremental: bool,
+    deferred: Vec<DeferredFragment<'a>>,
+    streams: Vec<StreamNode<'a>>,
+}
+
+/// A fragment marked with `@defer`, planned once the primary plan is built.
+#[derive(Debug)]
+struct DeferredFragment<'a> {
+    label: Option<String>,
+    path: ResponsePath<'a>,
+    parent_type: &'a MetaType,
+    selection_set: &'a SelectionSet,
+
+    /// The service and key prefix of the entities the fragment is fetched
+    /// for, `None` for a fragment of the root type.
+    entity: Option<(&'a str, usize)>,
+}
+
+/// Limits of the size of a query plan.
+///
+/// Operations whose plan exceeds any of the limits are rejected.
+#[derive(Debug, Default, Clone)]
+pub struct PlanLimits {
+    /// Maximum number of requests sent to the services.
+    pub max_fetch_nodes: Option<usize>,
+
+    /// Maximum number of sequential entity fetch rounds.
+    pub max_flatten_rounds: Option<usize>,
+
+    /// Maximum number of services touched by the plan.
+    pub max_services: Option<usize>,
+
+    /// Limits of the aliases and repeated selections, checked during validation.
+    pub validation: ValidationLimits,
+
+    /// Maximum time spent parsing the document. The parser cannot be
+    /// interrupted, so it is checked by the caller before the validation.
+    pub max_parse_duration: Option<Duration>,
+
+    /// Maximum time spent planning the operation, the planning stops as soon
+    /// as it is exceeded.
+    pub max_planning_duration: Option<Duration>,
+}
+
+impl PlanLimits {
+    fn check(&self, node: &RootNode<'_>) -> Result<(), Response> {
+        let stats = node.stats();
+        let checks = [
+            ("fetch nodes", stats.fetch_nodes, self.max_fetch_nodes),
+            (
+                "flatten rounds",
+                stats.flatten_rounds,
+                self.max_flatten_rounds,
+            ),
+            ("services", stats.services.len(), self.max_services),
+        ];
+        let errors = checks
+            .iter()
+            .filter_map(|(name, value, limit)| match limit {
+                Some(limit) if value > limit => Some(
+                    ServerError::new(format!(
+                        "Query plan is too large, the number of {} is {}, but the limit is {}.",
+                        name, value, limit
+                    ))
+                    .with_code(ErrorCode::PlanLimitExceeded),
+                ),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        if !errors.is_empty() {
+            return Err(Response {
+                data: ConstValue::Null,
+                errors,
+                extensions: Default::default(),
+                headers: Default::default(),
+                body_sizes: None,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Inlining of the small variables into the queries sent to some services,
+/// for the services that cache the responses by query text.
+///
+/// Only the booleans, numbers, enums and strings are inlined, the lists,
+/// objects and uploads are always sent as variables.
+#[derive(Debug, Clone)]
+pub struct InlineVariables {
+    /// Services whose queries get the inlined variables.
+    pub services: HashSet<String>,
+
+    /// Maximum length of an inlined value, written as a GraphQL literal.
+    pub max_size: usize,
+}
+
+impl Default for InlineVariables {
+    fn default() -> Self {
+        Self {
+            services: Default::default(),
+            max_size: 64,
+        }
+    }
 }
 
 /// Query plan generator
@@ -36,6 +158,11 @@ pub struct PlanBuilder<'a> {
     document: ExecutableDocument,
     operation_name: Option<String>,
     variables: Variables,
+    limits: PlanLimits,
+    strict: bool,
+    scalar_validators: Arc<HashMap<String, ScalarValidator>>,
+    inline_variables: Arc<InlineVariables>,
+    validated: bool,
 }
 
 impl<'a> PlanBuilder<'a> {
@@ -45,6 +172,11 @@ impl<'a> PlanBuilder<'a> {
             document,
             operation_name: None,
             variables: Default::default(),
+            limits: Default::default(),
+            strict: false,
+            scalar_validators: Default::default(),
+            inline_variables: Default::default(),
+            validated: false,
         }
     }
 
@@ -53,27 +185,126 @@ impl<'a> PlanBuilder<'a> {
         self
     }
 
-    pub fn variables(self, variables: Variables) -> Self {
+    /// Sets the variables of the request.
+    ///
+    /// The variables not defined by any operation of the document are dropped
+    /// right away, so unused large values such as uploads are not kept until
+    /// the end of the execution.
+    pub fn variables(self, mut variables: Variables) -> Self {
+        let defined = self
+            .document
+            .operations
+            .iter()
+            .flat_map(|(_, operation)| &operation.node.variable_definitions)
+            .map(|variable| variable.node.name.node.as_str())
+            .collect::<HashSet<_>>();
+        variables.retain(|name, _| defined.contains(name.as_str()));
         Self { variables, ..self }
     }
 
+    pub fn limits(self, limits: PlanLimits) -> Self {
+        Self { limits, ..self }
+    }
+
+    /// In strict mode, the fields that cannot be planned, such as the fields
+    /// missing from the schema or without a service, are reported as errors
+    /// instead of being dropped from the plan.
+    pub fn strict(self, strict: bool) -> Self {
+        Self { strict, ..self }
+    }
+
+    /// Sets the validators of the custom scalars, the variables whose values
+    /// are rejected by a validator fail the validation.
+    pub fn scalar_validators(
+        self,
+        scalar_validators: Arc<HashMap<String, ScalarValidator>>,
+    ) -> Self {
+        Self {
+            scalar_validators,
+            ..self
+        }
+    }
+
+    /// Sets the services whose queries get the small variables as literals.
+    pub fn inline_variables(self, inline_variables: Arc<InlineVariables>) -> Self {
+        Self {
+            inline_variables,
+            ..self
+        }
+    }
+
+    /// Marks the document as already validated against the schema, such as
+    /// by a previous request with the same query, so that only the rules
+    /// depending on the variables are checked.
+    pub fn validated(self, validated: bool) -> Self {
+        Self { validated, ..self }
+    }
+
+    /// Validates the document, then rewrites it, such as to remove the fields
+    /// that must not be fetched. The rewritten document is planned without
+    /// checking again the rules that only depend on the document.
+    pub fn rewrite(
+        mut self,
+        rewrite: impl FnOnce(&mut ExecutableDocument),
+    ) -> Result<Self, Response> {
+        self.check_rules()?;
+        rewrite(&mut self.document);
+        Ok(Self {
+            validated: true,
+            ..self
+        })
+    }
+
+    pub fn document(&self) -> &ExecutableDocument {
+        &self.document
+    }
+
+    pub fn variables(&self) -> &Variables {
+        &self.variables
+    }
+
     fn check_rules(&self) -> Result<(), Response> {
-        let rule_errors =
-            graphgate_validation::check_rules(self.schema, &self.document, &self.variables);
+        let started = Instant::now();
+        let rule_errors = if self.validated {
+            graphgate_validation::check_variable_rules(
+                self.schema,
+                &self.document,
+                &self.variables,
+                &self.scalar_validators,
+            )
+        } else {
+            graphgate_validation::check_rules(
+                self.schema,
+                &self.document,
+                &self.variables,
+                &self.limits.validation,
+                &self.scalar_validators,
+            )
+        };
+        // The validation stops early once its budget is exceeded, the errors
+        // found so far are incomplete.
+        if let Some(budget) = self.limits.validation.max_duration {
+            if started.elapsed() > budget {
+                return Err(Response::from_errors(vec![ServerError::budget_exceeded(
+                    "validation",
+                    budget,
+                )]));
+            }
+        }
         if !rule_errors.is_empty() {
             return Err(Response {
                 data: ConstValue::Null,
                 errors: rule_errors
                     .into_iter()
-                    .map(|err| ServerError {
-                        message: err.message,
-                        path: Default::default(),
-                        locations: err.locations,
-                        extensions: Default::default(),
+                    .map(|err| {
+                        ServerError::new(err.message)
+                            .with_locations(err.locations)
+                            .with_code(ErrorCode::ValidationFailed)
                     })
                     .collect(),
                 extensions: Default::default(),
                 headers: Default::default(),
+                body_sizes: None,
             });
         }
         Ok(())
@@ -81,61 +312,128 @@ impl<'a> PlanBuilder<'a> {
 
     fn create_context(&self) -> Context<'_> {
         let fragments = &self.document.fragments;
+        let operation_name =
+            self.operation_name
+                .as_deref()
+                .or_else(|| match &self.document.operations {
+                    DocumentOperations::Multiple(operations) if operations.len() == 1 => {
+                        operations.keys().next().map(Name::as_str)
+                    }
+                    _ => None,
+                });
         Context {
             schema: self.schema,
             fragments,
             variables: &self.variables,
-            key_id: 1,
+            variable_definitions: &[],
+            key_prefixes: Default::default(),
+            operation_name,
+            fetch_ids: Default::default(),
+            errors: Vec::new(),
+            strict: self.strict,
+            inline_variables: &self.inline_variables,
+            budget: self
+                .limits
+                .max_planning_duration
+                .map(|budget| (Instant::now(), budget)),
+            budget_exceeded: false,
+            incremental: false,
+            deferred: Vec::new(),
+            streams: Vec::new(),
         }
     }
 
     pub fn plan(&self) -> Result<RootNode, Response> {
         self.check_rules()?;
+        let node = self.build_plan()?;
+        self.limits.check(&node)?;
+        Ok(node)
+    }
 
+    fn build_plan(&self) -> Result<RootNode, Response> {
         let mut ctx = self.create_context();
-        let operation_definition = get_operation(&self.document, self.operation_name.as_deref());
+        let operation_definition = get_operation(&self.document, self.operation_name.as_deref())?;
+        ctx.variable_definitions = &operation_definition.node.variable_definitions;
+        ctx.incremental = operation_definition.node.ty == OperationType::Query;
 
         let root_type = match operation_definition.node.ty {
-            OperationType::Query => ctx.schema.query_type(),
-            OperationType::Mutation => ctx
-                .schema
-                .mutation_type()
-                .expect("The query validator should find this error."),
-            OperationType::Subscription => ctx
-                .schema
-                .subscription_type()
-                .expect("The query validator should find this error."),
+            OperationType::Query => Some(ctx.schema.query_type()),
+            OperationType::Mutation => ctx.schema.mutation_type(),
+            OperationType::Subscription => ctx.schema.subscription_type(),
+        }
+        .and_then(|root_type| ctx.schema.get_type_by_name(root_type));
+        let root_type = match root_type {
+            Some(root_type) => root_type,
+            None => {
+                return Err(Response::from_errors(vec![ServerError::new(format!(
+                    "Schema is not configured for {}s.",
+                    operation_definition.node.ty
+                ))
+                .with_code(ErrorCode::ValidationFailed)]))
+            }
         };
-
-        if let Some(root_type) = ctx.schema.types.get(root_type) {
-            match operation_definition.node.ty {
-                OperationType::Query => Ok(RootNode::Query(ctx.build_root_selection_set(
+        let node = match operation_definition.node.ty {
+            OperationType::Query => {
+                let primary = ctx.build_root_selection_set(
                     QueryRootGroup::default(),
                     operation_definition.node.ty,
                     &operation_definition.node.variable_definitions,
                     root_type,
                     &operation_definition.node.selection_set.node,
-                ))),
-                OperationType::Mutation => Ok(RootNode::Query(ctx.build_root_selection_set(
-                    MutationRootGroup::default(),
-                    operation_definition.node.ty,
-                    &operation_definition.node.variable_definitions,
-                    root_type,
-                    &operation_definition.node.selection_set.node,
-                ))),
-                OperationType::Subscription => Ok(RootNode::Subscribe(ctx.build_subscribe(
-                    &operation_definition.node.variable_definitions,
-                    root_type,
-                    &operation_definition.node.selection_set.node,
-                ))),
+                );
+                RootNode::Query(
+                    ctx.build_defer(primary, &operation_definition.node.variable_definitions),
+                )
             }
-        } else {
-            unreachable!("The query validator should find this error.")
+            OperationType::Mutation => RootNode::Query(ctx.build_root_selection_set(
+                MutationRootGroup::default(),
+                operation_definition.node.ty,
+                &operation_definition.node.variable_definitions,
+                root_type,
+                &operation_definition.node.selection_set.node,
+            )),
+            OperationType::Subscription => RootNode::Subscribe(ctx.build_subscribe(
+                &operation_definition.node.variable_definitions,
+                root_type,
+                &operation_definition.node.selection_set.node,
+            )),
+        };
+
+        if let Some((_, budget)) = ctx.budget.filter(|_| ctx.budget_exceeded) {
+            return Err(Response::from_errors(vec![ServerError::budget_exceeded(
+                "planning", budget,
+            )]));
         }
+        if !ctx.errors.is_empty() {
+            return Err(Response::from_errors(ctx.errors));
+        }
+        Ok(node)
     }
 }
 
 impl<'a> Context<'a> {
+    /// Returns `true` if the planning exceeded its time budget, the remaining
+    /// fields are then not planned.
+    fn check_budget(&mut self) -> bool {
+        if !self.budget_exceeded {
+            self.budget_exceeded = matches!(
+                self.budget,
+                Some((started, budget)) if started.elapsed() > budget
+            );
+        }
+        self.budget_exceeded
+    }
+
+    /// Returns the maximum length of the variables inlined into the queries
+    /// sent to a service, `None` if they are not inlined.
+    fn inline_max_size(&self, service: &str) -> Option<usize> {
+        if self.inline_variables.services.contains(service) {
+            Some(self.inline_variables.max_size)
+        } else {
+            None
+        }
+    }
+
     fn build_root_selection_set(
         &mut self,
         mut root_group: impl RootGroup<'a>,
@@ -158,7 +456,15 @@ impl<'a> Context<'a> {
                         let field_name = field.node.name.node.as_str();
                         let field_definition = match parent_type.fields.get(field_name) {
                             Some(field_definition) => field_definition,
-                            None => continue,
+                            None => {
+                                ctx.drop_field(
+                                    &ResponsePath::default(),
+                                    parent_type,
+                                    &field.node,
+                                    "it is not defined in the schema",
+                                );
+                                continue;
+                            }
                         };
                         if is_introspection_field(field_name) {
                             ctx.build_introspection_field(inspection_selection_set, &field.node);
@@ -176,6 +482,13 @@ impl<'a> Context<'a> {
                                 parent_type,
                                 &field.node,
                             );
+                        } else {
+                            ctx.drop_field(
+                                &ResponsePath::default(),
+                                parent_type,
+                                &field.node,
+                                "no service resolves it",
+                            );
                         }
                     }
                     Selection::FragmentSpread(fragment_spread) => {
@@ -183,23 +496,25 @@ impl<'a> Context<'a> {
                             .fragments
                             .get(fragment_spread.node.fragment_name.node.as_str())
                         {
-                            build_root_selection_set_rec(
+                            build_root_fragment(
                                 ctx,
                                 root_group,
                                 fetch_entity_group,
                                 inspection_selection_set,
                                 parent_type,
+                                &fragment_spread.node.directives,
                                 &fragment.node.selection_set.node,
                             );
                         }
                     }
                     Selection::InlineFragment(inline_fragment) => {
-                        build_root_selection_set_rec(
+                        build_root_fragment(
                             ctx,
                             root_group,
                             fetch_entity_group,
                             inspection_selection_set,
                             parent_type,
+                            &inline_fragment.node.directives,
                             &inline_fragment.node.selection_set.node,
                         );
                     }
@@ -207,6 +522,61 @@ impl<'a> Context<'a> {
             }
         }
 
+        // The selections of a fragment with directives are wrapped in an inline
+        // fragment of each service, so that the directives are forwarded.
+        fn build_root_fragment<'a>(
+            ctx: &mut Context<'a>,
+            root_group: &mut impl RootGroup<'a>,
+            fetch_entity_group: &mut FetchEntityGroup<'a>,
+            inspection_selection_set: &mut IntrospectionSelectionSet,
+            parent_type: &'a MetaType,
+            directives: &'a [Positioned<Directive>],
+            selection_set: &'a SelectionSet,
+        ) {
+            if let Some(label) = ctx.defer_label(directives) {
+                if !ctx.is_skipped(directives) {
+                    ctx.deferred.push(DeferredFragment {
+                        label,
+                        path: ResponsePath::default(),
+                        parent_type,
+                        selection_set,
+                        entity: None,
+                    });
+                }
+                return;
+            }
+            if directives.is_empty() {
+                build_root_selection_set_rec(
+                    ctx,
+                    root_group,
+                    fetch_entity_group,
+                    inspection_selection_set,
+                    parent_type,
+                    selection_set,
+                );
+                return;
+            }
+
+            let mut fragment_group = QueryRootGroup::default();
+            build_root_selection_set_rec(
+                ctx,
+                &mut fragment_group,
+                fetch_entity_group,
+                inspection_selection_set,
+                parent_type,
+                selection_set,
+            );
+            for (service, selection_set) in fragment_group.into_selection_set() {
+                root_group
+                    .selection_set_mut(service)
+                    .push(SelectionRef::InlineFragment {
+                        type_condition: None,
+                        directives,
+                        selection_set,
+                    });
+            }
+        }
+
         let mut fetch_entity_group = FetchEntityGroup::default();
         let mut inspection_selection_set = IntrospectionSelectionSet::default();
         build_root_selection_set_rec(
@@ -228,15 +598,21 @@ impl<'a> Context<'a> {
         let fetch_node = {
             let mut nodes = Vec::new();
             for (service, selection_set) in root_group.into_selection_set() {
-                let (variables, variable_definitions) =
-                    referenced_variables(&selection_set, self.variables, variable_definitions);
+                let (variables, variable_definitions, inlined_variables) = referenced_variables(
+                    &selection_set,
+                    self.variables,
+                    variable_definitions,
+                    self.inline_max_size(service),
+                );
                 nodes.push(PlanNode::Fetch(FetchNode {
                     service,
                     variables,
                     query: FetchQuery {
                         entity_type: None,
+                        operation_name: self.fetch_operation_name(service),
                         operation_type,
                         variable_definitions,
+                        inlined_variables,
                         selection_set,
                     },
                 }));
@@ -249,6 +625,19 @@ impl<'a> Context<'a> {
         };
         nodes.push(fetch_node);
 
+        nodes.extend(self.build_entity_fetches(fetch_entity_group, variable_definitions));
+
+        PlanNode::Sequence(SequenceNode { nodes }).flatten()
+    }
+
+    /// Builds the entity fetches of the fields resolved by other services, one
+    /// parallel node for each round.
+    fn build_entity_fetches(
+        &mut self,
+        mut fetch_entity_group: FetchEntityGroup<'a>,
+        variable_definitions: &'a [Positioned<VariableDefinition>],
+    ) -> Vec<PlanNode<'a>> {
+        let mut nodes = Vec::new();
         while !fetch_entity_group.is_empty() {
             let mut flatten_nodes = Vec::new();
             let mut next_group = FetchEntityGroup::new();
@@ -277,8 +666,12 @@ impl<'a> Context<'a> {
                     );
                 }
 
-                let (variables, variable_definitions) =
-                    referenced_variables(&selection_ref_set, self.variables, variable_definitions);
+                let (variables, variable_definitions, inlined_variables) = referenced_variables(
+                    &selection_ref_set,
+                    self.variables,
+                    variable_definitions,
+                    self.inline_max_size(service),
+                );
                 flatten_nodes.push(PlanNode::Flatten(FlattenNode {
                     path,
                     prefix,
@@ -286,8 +679,10 @@ impl<'a> Context<'a> {
                     variables,
                     query: FetchQuery {
                         entity_type: Some(parent_type.name.as_str()),
-                        operation_type: OperationType::Subscription,
+                        operation_name: self.fetch_operation_name(service),
+                        operation_type: OperationType::Query,
                         variable_definitions,
+                        inlined_variables,
                         selection_set: selection_ref_set,
                     },
                 }));
@@ -301,8 +696,85 @@ impl<'a> Context<'a> {
             );
             fetch_entity_group = next_group;
         }
+        nodes
+    }
 
-        PlanNode::Sequence(SequenceNode { nodes }).flatten()
+    /// Plans the fragments marked with `@defer`, the primary node is returned
+    /// as is if the query defers and streams nothing.
+    ///
+    /// The fragments deferred inside a deferred fragment follow it, the
+    /// deferred nodes are executed in order.
+    fn build_defer(
+        &mut self,
+        primary: PlanNode<'a>,
+        variable_definitions: &'a [Positioned<VariableDefinition>],
+    ) -> PlanNode<'a> {
+        let mut deferred = Vec::new();
+        while !self.deferred.is_empty() {
+            for fragment in std::mem::take(&mut self.deferred) {
+                let node = match fragment.entity {
+                    None => self.build_root_selection_set(
+                        QueryRootGroup::default(),
+                        OperationType::Query,
+                        variable_definitions,
+                        fragment.parent_type,
+                        fragment.selection_set,
+                    ),
+                    Some((service, prefix)) => {
+                        let mut path = fragment.path.clone();
+                        let mut selection_ref_set = SelectionRefSet::default();
+                        let mut fetch_entity_group = FetchEntityGroup::new();
+                        self.build_selection_set(
+                            &mut path,
+                            &mut selection_ref_set,
+                            &mut fetch_entity_group,
+                            service,
+                            fragment.parent_type,
+                            fragment.selection_set,
+                        );
+                        let (variables, fetch_variable_definitions, inlined_variables) =
+                            referenced_variables(
+                                &selection_ref_set,
+                                self.variables,
+                                variable_definitions,
+                                self.inline_max_size(service),
+                            );
+                        let mut nodes = vec![PlanNode::Flatten(FlattenNode {
+                            path,
+                            prefix,
+                            service,
+                            variables,
+                            query: FetchQuery {
+                                entity_type: Some(fragment.parent_type.name.as_str()),
+                                operation_name: self.fetch_operation_name(service),
+                                operation_type: OperationType::Query,
+                                variable_definitions: fetch_variable_definitions,
+                                inlined_variables,
+                                selection_set: selection_ref_set,
+                            },
+                        })];
+                        nodes.extend(
+                            self.build_entity_fetches(fetch_entity_group, variable_definitions),
+                        );
+                        PlanNode::Sequence(SequenceNode { nodes }).flatten()
+                    }
+                };
+                deferred.push(DeferredNode {
+                    label: fragment.label,
+                    path: fragment.path,
+                    node,
+                });
+            }
+        }
+
+        if deferred.is_empty() && self.streams.is_empty() {
+            return primary;
+        }
+        PlanNode::Defer(DeferNode {
+            primary: Box::new(primary),
+            deferred,
+            streams: std::mem::take(&mut self.streams),
+        })
     }
 
     fn build_subscribe(
@@ -311,44 +783,155 @@ impl<'a> Context<'a> {
         parent_type: &'a MetaType,
         selection_set: &'a SelectionSet,
     ) -> SubscribeNode<'a> {
-        let mut root_group = QueryRootGroup::default();
-        let mut fetch_entity_group = FetchEntityGroup::default();
-
-        for selection in &selection_set.items {
-            if let Selection::Field(field) = &selection.node {
-                let field_name = field.node.name.node.as_str();
-                let field_definition = match parent_type.fields.get(field_name) {
-                    Some(field_definition) => field_definition,
-                    None => continue,
-                };
+        fn build_subscribe_selection_set<'a>(
+            ctx: &mut Context<'a>,
+            root_group: &mut QueryRootGroup<'a>,
+            fetch_entity_group: &mut FetchEntityGroup<'a>,
+            parent_type: &'a MetaType,
+            selection_set: &'a SelectionSet,
+        ) {
+            for selection in &selection_set.items {
+                match &selection.node {
+                    Selection::Field(field) => {
+                        let field_name = field.node.name.node.as_str();
+                        if is_introspection_field(field_name) {
+                            ctx.drop_field(
+                                &ResponsePath::default(),
+                                parent_type,
+                                &field.node,
+                                "introspection fields cannot be subscribed",
+                            );
+                            continue;
+                        }
+                        let field_definition = match parent_type.fields.get(field_name) {
+                            Some(field_definition) => field_definition,
+                            None => {
+                                ctx.drop_field(
+                                    &ResponsePath::default(),
+                                    parent_type,
+                                    &field.node,
+                                    "it is not defined in the schema",
+                                );
+                                continue;
+                            }
+                        };
 
-                if let Some(service) = &field_definition.service {
-                    let selection_ref_set = root_group.selection_set_mut(service);
-                    let mut path = ResponsePath::default();
-                    self.build_field(
-                        &mut path,
-                        selection_ref_set,
-                        &mut fetch_entity_group,
-                        service,
-                        parent_type,
-                        &field.node,
-                    );
+                        if let Some(service) = &field_definition.service {
+                            let selection_ref_set = root_group.selection_set_mut(service);
+                            let mut path = ResponsePath::default();
+                            ctx.build_field(
+                                &mut path,
+                                selection_ref_set,
+                                fetch_entity_group,
+                                service,
+                                parent_type,
+                                &field.node,
+                            );
+                        } else {
+                            ctx.drop_field(
+                                &ResponsePath::default(),
+                                parent_type,
+                                &field.node,
+                                "no service resolves it",
+                            );
+                        }
+                    }
+                    Selection::FragmentSpread(fragment_spread) => {
+                        if let Some(fragment) = ctx
+                            .fragments
+                            .get(fragment_spread.node.fragment_name.node.as_str())
+                        {
+                            build_subscribe_fragment(
+                                ctx,
+                                root_group,
+                                fetch_entity_group,
+                                parent_type,
+                                &fragment_spread.node.directives,
+                                &fragment.node.selection_set.node,
+                            );
+                        }
+                    }
+                    Selection::InlineFragment(inline_fragment) => {
+                        build_subscribe_fragment(
+                            ctx,
+                            root_group,
+                            fetch_entity_group,
+                            parent_type,
+                            &inline_fragment.node.directives,
+                            &inline_fragment.node.selection_set.node,
+                        );
+                    }
                 }
             }
         }
 
+        // Same as the fragments of the root selection set of a query.
+        fn build_subscribe_fragment<'a>(
+            ctx: &mut Context<'a>,
+            root_group: &mut QueryRootGroup<'a>,
+            fetch_entity_group: &mut FetchEntityGroup<'a>,
+            parent_type: &'a MetaType,
+            directives: &'a [Positioned<Directive>],
+            selection_set: &'a SelectionSet,
+        ) {
+            if directives.is_empty() {
+                build_subscribe_selection_set(
+                    ctx,
+                    root_group,
+                    fetch_entity_group,
+                    parent_type,
+                    selection_set,
+                );
+                return;
+            }
+
+            let mut fragment_group = QueryRootGroup::default();
+            build_subscribe_selection_set(
+                ctx,
+                &mut fragment_group,
+                fetch_entity_group,
+                parent_type,
+                selection_set,
+            );
+            for (service, selection_set) in fragment_group.into_selection_set() {
+                root_group
+                    .selection_set_mut(service)
+                    .push(SelectionRef::InlineFragment {
+                        type_condition: None,
+                        directives,
+                        selection_set,
+                    });
+            }
+        }
+
+        let mut root_group = QueryRootGroup::default();
+        let mut fetch_entity_group = FetchEntityGroup::default();
+        build_subscribe_selection_set(
+            self,
+            &mut root_group,
+            &mut fetch_entity_group,
+            parent_type,
+            selection_set,
+        );
+
         let fetch_nodes = {
             let mut nodes = Vec::new();
             for (service, selection_ref_set) in root_group.into_selection_set() {
-                let (variables, variable_definitions) =
-                    referenced_variables(&selection_ref_set, self.variables, variable_definitions);
+                let (variables, variable_definitions, inlined_variables) = referenced_variables(
+                    &selection_ref_set,
+                    self.variables,
+                    variable_definitions,
+                    self.inline_max_size(service),
+                );
                 nodes.push(FetchNode {
                     service,
                     variables,
                     query: FetchQuery {
                         entity_type: None,
+                        operation_name: self.fetch_operation_name(service),
                         operation_type: OperationType::Subscription,
                         variable_definitions,
+                        inlined_variables,
                         selection_set: selection_ref_set,
                     },
                 });
@@ -356,59 +939,7 @@ impl<'a> Context<'a> {
             nodes
         };
 
-        let mut query_nodes = Vec::new();
-        while !fetch_entity_group.is_empty() {
-            let mut flatten_nodes = Vec::new();
-            let mut next_group = FetchEntityGroup::new();
-
-            for (
-                FetchEntityKey {
-                    service, mut path, ..
-                },
-                FetchEntity {
-                    parent_type,
-                    prefix,
-                    fields,
-                },
-            ) in fetch_entity_group
-            {
-                let mut selection_ref_set = SelectionRefSet::default();
-
-                for field in fields {
-                    self.build_field(
-                        &mut path,
-                        &mut selection_ref_set,
-                        &mut next_group,
-                        service,
-                        parent_type,
-                        field,
-                    );
-                }
-
-                let (variables, variable_definitions) =
-                    referenced_variables(&selection_ref_set, self.variables, variable_definitions);
-                flatten_nodes.push(PlanNode::Flatten(FlattenNode {
-                    path,
-                    prefix,
-                    service,
-                    variables,
-                    query: FetchQuery {
-                        entity_type: Some(parent_type.name.as_str()),
-                        operation_type: OperationType::Query,
-                        variable_definitions,
-                        selection_set: selection_ref_set,
-                    },
-                }));
-            }
-
-            query_nodes.push(
-                PlanNode::Parallel(ParallelNode {
-                    nodes: flatten_nodes,
-                })
-                .flatten(),
-            );
-            fetch_entity_group = next_group;
-        }
+        let query_nodes = self.build_entity_fetches(fetch_entity_group, variable_definitions);
 
         SubscribeNode {
             subscribe_nodes: fetch_nodes,
@@ -436,6 +967,9 @@ impl<'a> Context<'a> {
                         ctx.build_introspection_field(introspection_selection_set, &field.node);
                     }
                     Selection::FragmentSpread(fragment_spread) => {
+                        if ctx.is_skipped(&fragment_spread.node.directives) {
+                            continue;
+                        }
                         if let Some(fragment) = ctx
                             .fragments
                             .get(fragment_spread.node.fragment_name.node.as_str())
@@ -448,6 +982,9 @@ impl<'a> Context<'a> {
                         }
                     }
                     Selection::InlineFragment(inline_fragment) => {
+                        if ctx.is_skipped(&inline_fragment.node.directives) {
+                            continue;
+                        }
                         build_selection_set(
                             ctx,
                             introspection_selection_set,
@@ -471,11 +1008,9 @@ impl<'a> Context<'a> {
                             .node
                             .clone()
                             .into_const_with(|name| {
-                                Ok::<_, std::convert::Infallible>(
-                                    ctx.variables.get(&name).unwrap().clone(),
-                                )
+                                Ok::<_, std::convert::Infallible>(ctx.variable_value(&name))
                             })
-                            .unwrap(),
+                            .unwrap_or_else(|err| match err {}),
                     )
                 })
                 .collect()
@@ -508,45 +1043,87 @@ impl<'a> Context<'a> {
         parent_type: &'a MetaType,
         field: &'a Field,
     ) {
+        if self.check_budget() {
+            return;
+        }
         let field_name = field.name.node.as_str();
 
         if field_name == "__typename" {
-            selection_ref_set
-                .0
-                .push(SelectionRef::IntrospectionTypename);
+            selection_ref_set.push(SelectionRef::IntrospectionTypename(
+                field.alias.as_ref().map(|alias| alias.node.as_str()),
+            ));
             return;
         }
 
         let field_definition = match parent_type.fields.get(field_name) {
             Some(field_definition) => field_definition,
-            None => return,
+            None => {
+                self.drop_field(path, parent_type, field, "it is not defined in the schema");
+                return;
+            }
         };
         let field_type = match self.schema.get_type(&field_definition.ty) {
             Some(field_type) => field_type,
-            None => return,
+            None => {
+                self.drop_field(
+                    path,
+                    parent_type,
+                    field,
+                    "its type is not defined in the schema",
+                );
+                return;
+            }
         };
 
-        let service = match field_definition
-            .service
-            .as_deref()
-            .or_else(|| parent_type.owner.as_deref())
-        {
-            Some(service) => service,
-            None => current_service,
+        let gateway_field = self.schema.gateway_field(&parent_type.name, field_name);
+        let service = match gateway_field {
+            Some((_, gateway_field)) => {
+                match gateway_field_service(parent_type, gateway_field, current_service) {
+                    Some(service) => service,
+                    None => {
+                        self.drop_field(
+                            path,
+                            parent_type,
+                            field,
+                            "its source fields are resolved by different services",
+                        );
+                        return;
+                    }
+                }
+            }
+            None => match field_definition
+                .service
+                .as_deref()
+                .or_else(|| parent_type.owner.as_deref())
+            {
+                Some(service) => service,
+                None => current_service,
+            },
         };
 
         if service != current_service {
-            let mut keys = parent_type.keys.get(service).and_then(|x| x.get(0));
-            if keys.is_none() {
+            let mut service_keys = parent_type.keys.get(service).filter(|x| !x.is_empty());
+            if service_keys.is_none() {
                 if let Some(owner) = &parent_type.owner {
-                    keys = parent_type.keys.get(owner).and_then(|x| x.get(0));
+                    service_keys = parent_type.keys.get(owner);
                 }
             }
-            let keys = match keys {
+            let keys = match service_keys.and_then(|x| x.get(0)) {
                 Some(keys) => keys,
-                None => return,
+                None => {
+                    self.unreachable_field(path, parent_type, field, service, current_service);
+                    return;
+                }
             };
-            if !self.field_in_keys(field, keys) {
+
+            // The field can be resolved by the current service if it is a part of any key of
+            // the target service or the current service.
+            let in_keys = service_keys
+                .into_iter()
+                .chain(parent_type.keys.get(current_service))
+                .flatten()
+                .any(|keys| self.field_in_keys(field, keys));
+            if !in_keys {
                 self.add_fetch_entity(
                     path,
                     selection_ref_set,
@@ -561,11 +1138,35 @@ impl<'a> Context<'a> {
             }
         }
 
+        if let Some((id, gateway_field)) = gateway_field {
+            selection_ref_set.push(SelectionRef::GatewayFieldRef(GatewayFieldRef {
+                id,
+                field,
+                sources: gateway_field.resolver.sources(),
+            }));
+            return;
+        }
+
         path.push(PathSegment {
             name: field.response_key().node.as_str(),
-            is_list: is_list(&field_definition.ty),
+            list_depth: list_depth(&field_definition.ty),
             possible_type: None,
         });
+        if is_list(&field_definition.ty) {
+            if let Some(directive) = self.incremental_directive(&field.directives, "stream") {
+                self.streams.push(StreamNode {
+                    label: match self.directive_argument(directive, "label") {
+                        ConstValue::String(label) => Some(label),
+                        _ => None,
+                    },
+                    path: path.clone(),
+                    initial_count: match self.directive_argument(directive, "initialCount") {
+                        ConstValue::Number(count) => count.as_u64().unwrap_or_default() as usize,
+                        _ => 0,
+                    },
+                });
+            }
+        }
         let mut sub_selection_set = SelectionRefSet::default();
 
         if matches!(field_type.kind, TypeKind::Interface | TypeKind::Union) {
@@ -588,13 +1189,195 @@ impl<'a> Context<'a> {
             );
         }
 
-        selection_ref_set.0.push(SelectionRef::FieldRef(FieldRef {
+        selection_ref_set.push(SelectionRef::FieldRef(FieldRef {
             field,
+            directives: field
+                .directives
+                .iter()
+                .filter(|directive| {
+                    !self
+                        .schema
+                        .is_shaping_directive(directive.node.name.node.as_str())
+                })
+                .collect(),
             selection_set: sub_selection_set,
         }));
         path.pop();
     }
 
+    /// Returns the value of a variable, the default value of its definition if
+    /// it is not provided, or null.
+    fn variable_value(&self, name: &Name) -> ConstValue {
+        if let Some(value) = self.variables.get(name) {
+            return value.clone();
+        }
+        self.variable_definitions
+            .iter()
+            .find(|variable_definition| variable_definition.node.name.node == *name)
+            .and_then(|variable_definition| variable_definition.node.default_value.as_ref())
+            .map(|default_value| default_value.node.clone())
+            .unwrap_or_default()
+    }
+
+    /// Returns `true` if the `@skip` or `@include` directives exclude a
+    /// selection.
+    fn is_skipped(&self, directives: &[Positioned<Directive>]) -> bool {
+        directives.iter().any(|directive| {
+            let condition = match directive.node.get_argument("if").map(|value| &value.node) {
+                Some(Value::Boolean(condition)) => *condition,
+                Some(Value::Variable(name)) => {
+                    self.variable_value(name) == ConstValue::Boolean(true)
+                }
+                _ => false,
+            };
+            match directive.node.name.node.as_str() {
+                "skip" => condition,
+                "include" => !condition,
+                _ => false,
+            }
+        })
+    }
+
+    /// Returns the value of an argument of a directive, with the variables
+    /// replaced by their values.
+    fn directive_argument(&self, directive: &Directive, name: &str) -> ConstValue {
+        directive
+            .get_argument(name)
+            .map(|value| {
+                value
+                    .node
+                    .clone()
+                    .into_const_with(|name| {
+                        Ok::<_, std::convert::Infallible>(self.variable_value(&name))
+                    })
+                    .unwrap_or_else(|err| match err {})
+            })
+            .unwrap_or_default()
+    }
+
+    /// Returns the `@defer` or `@stream` directive of a selection if it is
+    /// enabled, in a query.
+    fn incremental_directive<'d>(
+        &self,
+        directives: &'d [Positioned<Directive>],
+        name: &str,
+    ) -> Option<&'d Directive> {
+        if !self.incremental {
+            return None;
+        }
+        directives
+            .iter()
+            .map(|directive| &directive.node)
+            .find(|directive| directive.name.node.as_str() == name)
+            .filter(|directive| {
+                self.directive_argument(directive, "if") != ConstValue::Boolean(false)
+            })
+    }
+
+    /// Returns the label of the `@defer` directive of a fragment, `Some(None)`
+    /// if the fragment is deferred without a label.
+    fn defer_label(&self, directives: &[Positioned<Directive>]) -> Option<Option<String>> {
+        self.incremental_directive(directives, "defer")
+            .map(
+                |directive| match self.directive_argument(directive, "label") {
+                    ConstValue::String(label) => Some(label),
+                    _ => None,
+                },
+            )
+    }
+
+    /// Records the error of a field that is dropped from the plan, in strict mode.
+    fn drop_field(
+        &mut self,
+        path: &ResponsePath<'a>,
+        parent_type: &MetaType,
+        field: &Field,
+        reason: &str,
+    ) {
+        if !self.strict {
+            return;
+        }
+        self.errors.push(
+            ServerError::new(format!(
+                "Field \"{}.{}\" cannot be planned: {}.",
+                parent_type.name, field.name.node, reason
+            ))
+            .with_code(ErrorCode::PlanFailed)
+            .with_path(error_path(path, field))
+            .with_locations(vec![field.name.pos]),
+        );
+    }
+
+    /// Records the error of a field that cannot be fetched from the service
+    /// resolving its parent, because the type has no key for its service.
+    fn unreachable_field(
+        &mut self,
+        path: &ResponsePath<'a>,
+        parent_type: &MetaType,
+        field: &Field,
+        service: &str,
+        current_service: &str,
+    ) {
+        let mut services = parent_type
+            .keys
+            .iter()
+            .filter(|(_, keys)| !keys.is_empty())
+            .map(|(service, _)| format!("\"{}\"", service))
+            .collect::<Vec<_>>();
+        services.sort();
+        let defined = if services.is_empty() {
+            "no keys are defined".to_string()
+        } else {
+            format!("keys are only defined for {}", services.join(", "))
+        };
+
+        self.errors.push(
+            ServerError::new(format!(
+                "Field \"{}.{}\" of service \"{}\" cannot be fetched from service \"{}\": type \"{}\" has no key for service \"{}\" or its owner, {}.",
+                parent_type.name,
+                field.name.node,
+                service,
+                current_service,
+                parent_type.name,
+                service,
+                defined,
+            ))
+            .with_code(ErrorCode::PlanFailed)
+            .with_path(error_path(path, field))
+            .with_locations(vec![field.name.pos]),
+        );
+    }
+
+    /// Records the error of a field whose `@requires` fields reference
+    /// variables that the operation does not define, they would be undefined
+    /// in the query sent to the service resolving the keys.
+    fn check_requires_variables(
+        &mut self,
+        path: &ResponsePath<'a>,
+        parent_type: &MetaType,
+        field: &Field,
+        requires: &KeyFields,
+    ) {
+        for name in requires.variables() {
+            let defined = self
+                .variable_definitions
+                .iter()
+                .any(|definition| definition.node.name.node == *name);
+            if defined {
+                continue;
+            }
+            self.errors.push(
+                ServerError::new(format!(
+                    "Field \"{}.{}\" requires variable \"${}\", which is not defined by the operation.",
+                    parent_type.name, field.name.node, name
+                ))
+                .with_code(ErrorCode::PlanFailed)
+                .with_path(error_path(path, field))
+                .with_locations(vec![field.name.pos]),
+            );
+        }
+    }
+
     fn add_fetch_entity(
         &mut self,
         path: &mut ResponsePath<'a>,
@@ -606,6 +1389,9 @@ impl<'a> Context<'a> {
         service: &'a str,
         keys: &'a KeyFields,
     ) {
+        if let Some(requires) = &meta_field.requires {
+            self.check_requires_variables(path, parent_type, field, requires);
+        }
         let fetch_entity_key = FetchEntityKey {
             service,
             path: path.clone(),
@@ -615,16 +1401,23 @@ impl<'a> Context<'a> {
         match fetch_entity_group.get_mut(&fetch_entity_key) {
             Some(fetch_entity) => {
                 fetch_entity.fields.push(field);
-            }
-            None => {
-                let prefix = self.take_key_prefix();
-                selection_ref_set
-                    .0
-                    .push(SelectionRef::RequiredRef(RequiredRef {
-                        prefix,
+                if let Some(requires) = &meta_field.requires {
+                    // The keys may have been selected in another fragment of
+                    // the same object, they are merged with that selection.
+                    selection_ref_set.push(SelectionRef::RequiredRef(RequiredRef {
+                        prefix: fetch_entity.prefix,
                         fields: keys,
-                        requires: meta_field.requires.as_ref(),
+                        requires: vec![requires],
                     }));
+                }
+            }
+            None => {
+                let prefix = self.take_key_prefix(&fetch_entity_key);
+                selection_ref_set.push(SelectionRef::RequiredRef(RequiredRef {
+                    prefix,
+                    fields: keys,
+                    requires: meta_field.requires.iter().collect(),
+                }));
                 fetch_entity_group.insert(
                     fetch_entity_key,
                     FetchEntity {
@@ -663,23 +1456,25 @@ impl<'a> Context<'a> {
                         .fragments
                         .get(fragment_spread.node.fragment_name.node.as_str())
                     {
-                        self.build_selection_set(
+                        self.build_fragment(
                             path,
                             selection_ref_set,
                             fetch_entity_group,
                             current_service,
                             parent_type,
+                            &fragment_spread.node.directives,
                             &fragment.node.selection_set.node,
                         );
                     }
                 }
                 Selection::InlineFragment(inline_fragment) => {
-                    self.build_selection_set(
+                    self.build_fragment(
                         path,
                         selection_ref_set,
                         fetch_entity_group,
                         current_service,
                         parent_type,
+                        &inline_fragment.node.directives,
                         &inline_fragment.node.selection_set.node,
                     );
                 }
@@ -687,6 +1482,80 @@ impl<'a> Context<'a> {
         }
     }
 
+    /// Builds the selections of a fragment, wrapping them in an inline fragment
+    /// if the fragment has directives such as `@include`.
+    fn build_fragment(
+        &mut self,
+        path: &mut ResponsePath<'a>,
+        selection_ref_set: &mut SelectionRefSet<'a>,
+        fetch_entity_group: &mut FetchEntityGroup<'a>,
+        current_service: &'a str,
+        parent_type: &'a MetaType,
+        directives: &'a [Positioned<Directive>],
+        selection_set: &'a SelectionSet,
+    ) {
+        // A fragment is only deferred on the entities of the current service,
+        // the others are fetched with the primary plan.
+        if let Some(label) = self.defer_label(directives) {
+            if self.is_skipped(directives) {
+                return;
+            }
+            if let Some(keys) = parent_type
+                .keys
+                .get(current_service)
+                .and_then(|keys| keys.get(0))
+            {
+                let prefix = self.take_key_prefix(&FetchEntityKey {
+                    service: current_service,
+                    path: path.clone(),
+                    ty: parent_type.name.as_str(),
+                });
+                selection_ref_set.push(SelectionRef::RequiredRef(RequiredRef {
+                    prefix,
+                    fields: keys,
+                    requires: Vec::new(),
+                }));
+                self.deferred.push(DeferredFragment {
+                    label,
+                    path: path.clone(),
+                    parent_type,
+                    selection_set,
+                    entity: Some((current_service, prefix)),
+                });
+                return;
+            }
+        }
+
+        if directives.is_empty() {
+            self.build_selection_set(
+                path,
+                selection_ref_set,
+                fetch_entity_group,
+                current_service,
+                parent_type,
+                selection_set,
+            );
+            return;
+        }
+
+        let mut sub_selection_ref_set = SelectionRefSet::default();
+        self.build_selection_set(
+            path,
+            &mut sub_selection_ref_set,
+            fetch_entity_group,
+            current_service,
+            parent_type,
+            selection_set,
+        );
+        if !sub_selection_ref_set.0.is_empty() {
+            selection_ref_set.push(SelectionRef::InlineFragment {
+                type_condition: None,
+                directives,
+                selection_set: sub_selection_ref_set,
+            });
+        }
+    }
+
     fn build_abstract_selection_set(
         &mut self,
         path: &mut ResponsePath<'a>,
@@ -736,8 +1605,7 @@ impl<'a> Context<'a> {
                             } else {
                                 let field_type = match ctx
                                     .schema
-                                    .types
-                                    .get(&fragment.node.type_condition.node.on.node)
+                                    .get_type_by_name(&fragment.node.type_condition.node.on.node)
                                 {
                                     Some(field_type) => field_type,
                                     None => return,
@@ -798,7 +1666,7 @@ impl<'a> Context<'a> {
 
         let mut selection_ref_set_group = IndexMap::new();
         for possible_type in &parent_type.possible_types {
-            if let Some(ty) = self.schema.types.get(possible_type) {
+            if let Some(ty) = self.schema.get_type_by_name(possible_type) {
                 path.last_mut().unwrap().possible_type = Some(ty.name.as_str());
                 build_fields(
                     self,
@@ -817,17 +1685,40 @@ impl<'a> Context<'a> {
             .into_iter()
             .filter(|(_, selection_ref_set)| !selection_ref_set.0.is_empty())
         {
-            selection_ref_set.0.push(SelectionRef::InlineFragment {
+            selection_ref_set.push(SelectionRef::InlineFragment {
                 type_condition: Some(ty),
+                directives: &[],
                 selection_set: sub_selection_ref_set,
             });
         }
     }
 
-    fn take_key_prefix(&mut self) -> usize {
-        let id = self.key_id;
-        self.key_id += 1;
-        id
+    /// Returns the name of the next query sent to the service, such as
+    /// `MyQuery__accounts__0`, if the client operation is named.
+    fn fetch_operation_name(&mut self, service: &'a str) -> Option<String> {
+        let operation_name = self.operation_name?;
+        let id = self.fetch_ids.entry(service).or_default();
+        let name = format!(
+            "{}__{}__{}",
+            operation_name,
+            service.replace(|c: char| !c.is_ascii_alphanumeric() && c != '_', "_"),
+            id
+        );
+        *id += 1;
+        Some(name)
+    }
+
+    /// Returns the prefix of the key aliases of an entity fetch.
+    ///
+    /// The prefix is derived from the service, path and type of the fetch, so
+    /// that equivalent queries produce the same plans regardless of the order of
+    /// their selections. A colliding prefix is incremented until it is unused.
+    fn take_key_prefix(&mut self, key: &FetchEntityKey<'a>) -> usize {
+        let mut prefix = key_prefix(key);
+        while !self.key_prefixes.insert(prefix) {
+            prefix = prefix.wrapping_add(1);
+        }
+        prefix
     }
 
     fn field_in_keys(&self, field: &Field, keys: &KeyFields) -> bool {
@@ -878,20 +1769,50 @@ impl<'a> Context<'a> {
     }
 }
 
+/// 32-bit FNV-1a hash of the service, path and type of an entity fetch, which
+/// is stable across processes and releases.
+fn key_prefix(key: &FetchEntityKey<'_>) -> usize {
+    format!("{}:{}:{}", key.service, key.path, key.ty)
+        .bytes()
+        .fold(0x811c_9dc5_u32, |hash, b| {
+            (hash ^ b as u32).wrapping_mul(0x0100_0193)
+        }) as usize
+}
+
+/// Returns the path of a field in the response, without the list indices.
+fn error_path(path: &ResponsePath<'_>, field: &Field) -> Vec<ConstValue> {
+    path.iter()
+        .map(|segment| ConstValue::String(segment.name.to_string()))
+        .chain(std::iter::once(ConstValue::String(
+            field.response_key().node.to_string(),
+        )))
+        .collect()
+}
+
 #[inline]
 fn is_list(ty: &Type) -> bool {
     matches!(ty.base, BaseType::List(_))
 }
 
+fn list_depth(ty: &Type) -> usize {
+    match &ty.base {
+        BaseType::List(ty) => list_depth(ty) + 1,
+        BaseType::Named(_) => 0,
+    }
+}
+
+/// Selects the operation to execute, the operation name is required if the
+/// document contains several operations.
 fn get_operation<'a>(
     document: &'a ExecutableDocument,
     operation_name: Option<&str>,
-) -> &'a Positioned<OperationDefinition> {
+) -> Result<&'a Positioned<OperationDefinition>, Response> {
     let operation = if let Some(operation_name) = operation_name {
         match &document.operations {
             DocumentOperations::Single(_) => None,
             DocumentOperations::Multiple(operations) => operations.get(operation_name),
         }
+        .ok_or_else(|| format!(r#"Unknown operation named "{}"."#, operation_name))
     } else {
         match &document.operations {
             DocumentOperations::Single(operation) => Some(operation),
@@ -900,80 +1821,110 @@ fn get_operation<'a>(
             }
             DocumentOperations::Multiple(_) => None,
         }
+        .ok_or_else(|| "Operation name required in request.".to_string())
     };
-    operation.expect("The query validator should find this error.")
+    operation.map_err(|message| {
+        Response::from_errors(vec![
+            ServerError::new(message).with_code(ErrorCode::ValidationFailed)
+        ])
+    })
 }
 
+/// Returns the variables referenced by a selection set and their definitions,
+/// and the variables inlined as literals if `inline_max_size` is set.
 fn referenced_variables<'a>(
     selection_set: &SelectionRefSet<'a>,
     variables: &'a Variables,
     variable_definitions: &'a [Positioned<VariableDefinition>],
-) -> (VariablesRef<'a>, VariableDefinitionsRef<'a>) {
+    inline_max_size: Option<usize>,
+) -> (
+    VariablesRef<'a>,
+    VariableDefinitionsRef<'a>,
+    VariablesRef<'a>,
+) {
+    fn collect_variables<'a>(value: &'a Value, names: &mut Vec<&'a Name>) {
+        match value {
+            Value::Variable(name) => names.push(name),
+            Value::List(values) => {
+                for value in values {
+                    collect_variables(value, names);
+                }
+            }
+            Value::Object(object) => {
+                for value in object.values() {
+                    collect_variables(value, names);
+                }
+            }
+            _ => {}
+        }
+    }
+
     fn referenced_variables_rec<'a>(
         selection_set: &SelectionRefSet<'a>,
-        variables: &'a Variables,
-        variable_definitions: &'a [Positioned<VariableDefinition>],
-        variables_ref: &mut VariablesRef<'a>,
-        variables_definition_ref: &mut IndexMap<&'a str, &'a VariableDefinition>,
+        names: &mut Vec<&'a Name>,
+        key_names: &mut Vec<&'a Name>,
     ) {
         for selection in &selection_set.0 {
             match selection {
                 SelectionRef::FieldRef(field) => {
                     for (_, value) in &field.field.arguments {
-                        for name in value.node.referenced_variables() {
-                            if let Some((value, definition)) = variables.get(name).zip(
-                                variable_definitions
-                                    .iter()
-                                    .find(|d| d.node.name.node.as_str() == name),
-                            ) {
-                                variables_ref.variables.insert(name, value);
-                                variables_definition_ref.insert(name, &definition.node);
-                            } else {
-                                let definition = variable_definitions
-                                    .iter()
-                                    .find(|d| d.node.name.node.as_str() == name)
-                                    .unwrap();
-                                variables_definition_ref.insert(name, &definition.node);
-                            }
+                        collect_variables(&value.node, names);
+                    }
+                    for directive in field.directives.iter().filter(|d| is_forwarded(d)) {
+                        for (_, value) in &directive.node.arguments {
+                            collect_variables(&value.node, names);
                         }
                     }
-
-                    for dir in &field.field.directives {
-                        for (_, value) in &dir.node.arguments {
-                            for name in value.node.referenced_variables() {
-                                if let Some((value, definition)) = variables.get(name).zip(
-                                    variable_definitions
-                                        .iter()
-                                        .find(|d| d.node.name.node.as_str() == name),
-                                ) {
-                                    variables_ref.variables.insert(name, value);
-                                    variables_definition_ref.insert(name, &definition.node);
-                                } else {
-                                    let definition = variable_definitions
-                                        .iter()
-                                        .find(|d| d.node.name.node.as_str() == name)
-                                        .unwrap();
-                                    variables_definition_ref.insert(name, &definition.node);
-                                }
-                            }
+                    referenced_variables_rec(&field.selection_set, names, key_names);
+                }
+                SelectionRef::InlineFragment {
+                    directives,
+                    selection_set,
+                    ..
+                } => {
+                    for directive in directives.iter().filter(|d| is_forwarded(d)) {
+                        for (_, value) in &directive.node.arguments {
+                            collect_variables(&value.node, names);
                         }
                     }
-                    referenced_variables_rec(
-                        &field.selection_set,
-                        variables,
-                        variable_definitions,
-                        variables_ref,
-                        variables_definition_ref,
-                    )
+                    referenced_variables_rec(selection_set, names, key_names)
+                }
+                SelectionRef::RequiredRef(required) => {
+                    for requires in required.requires.iter().copied() {
+                        names.extend(requires.variables());
+                        key_names.extend(requires.variables());
+                    }
                 }
+                SelectionRef::GatewayFieldRef(gateway_field) => {
+                    for directive in gateway_field
+                        .field
+                        .directives
+                        .iter()
+                        .filter(|d| is_forwarded(d))
+                    {
+                        for (_, value) in &directive.node.arguments {
+                            collect_variables(&value.node, names);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
 
-                SelectionRef::InlineFragment { selection_set, .. } => referenced_variables_rec(
-                    selection_set,
-                    variables,
-                    variable_definitions,
-                    variables_ref,
-                    variables_definition_ref,
-                ),
+    let mut names = Vec::new();
+    let mut key_names = Vec::new();
+    referenced_variables_rec(selection_set, &mut names, &mut key_names);
+
+    // The variables of the `@requires` arguments are not inlined, they are
+    // written as is with the key fields.
+    let mut inlined_ref = VariablesRef::default();
+    if let Some(max_size) = inline_max_size {
+        for name in &names {
+            match variables.get(*name) {
+                Some(value) if !key_names.contains(name) && is_inlinable(value, max_size) => {
+                    inlined_ref.variables.insert(name.as_str(), value);
+                }
                 _ => {}
             }
         }
@@ -981,13 +1932,24 @@ fn referenced_variables<'a>(
 
     let mut variables_ref = VariablesRef::default();
     let mut variable_definition_ref = IndexMap::new();
-    referenced_variables_rec(
-        selection_set,
-        variables,
-        variable_definitions,
-        &mut variables_ref,
-        &mut variable_definition_ref,
-    );
+    for name in names {
+        if inlined_ref.variables.contains_key(name.as_str()) {
+            continue;
+        }
+        let definition = match variable_definitions
+            .iter()
+            .find(|definition| definition.node.name.node == *name)
+        {
+            Some(definition) => definition,
+            None => continue,
+        };
+        // A variable that is not provided is still declared, so that the
+        // service applies the default value of the definition.
+        if let Some(value) = variables.get(name) {
+            variables_ref.variables.insert(name.as_str(), value);
+        }
+        variable_definition_ref.insert(name.as_str(), &definition.node);
+    }
     (
         variables_ref,
         VariableDefinitionsRef {
@@ -996,10 +1958,46 @@ fn referenced_variables<'a>(
                 .map(|(_, value)| value)
                 .collect(),
         },
+        inlined_ref,
     )
 }
 
+/// Returns `true` if a variable can be written as a literal of at most
+/// `max_size` bytes.
+fn is_inlinable(value: &ConstValue, max_size: usize) -> bool {
+    matches!(
+        value,
+        ConstValue::Boolean(_)
+            | ConstValue::Number(_)
+            | ConstValue::Enum(_)
+            | ConstValue::String(_)
+    ) && value.to_string().len() <= max_size
+}
+
 #[inline]
 fn is_introspection_field(name: &str) -> bool {
     name == "__type" || name == "__schema"
 }
+
+/// Returns the service resolving the source fields of a gateway field, or
+/// `None` if they are resolved by different services.
+fn gateway_field_service<'a>(
+    parent_type: &'a MetaType,
+    gateway_field: &GatewayField,
+    current_service: &'a str,
+) -> Option<&'a str> {
+    let mut services = gateway_field.resolver.sources().iter().map(|source| {
+        parent_type
+            .fields
+            .get(source)
+            .and_then(|field| field.service.as_deref())
+            .or_else(|| parent_type.owner.as_deref())
+            .unwrap_or(current_service)
+    });
+    let service = services.next().unwrap_or(current_service);
+    if services.all(|other| other == service) {
+        Some(service)
+    } else {
+        None
+    }
+}
@@ -4,13 +4,21 @@ mod builder;
 mod plan;
 mod request;
 mod response;
+mod signature;
 mod types;
+mod visualize;
 
-pub use builder::PlanBuilder;
+pub use builder::{InlineVariables, PlanBuilder, PlanLimits};
+pub use graphgate_validation::{ScalarValidator, ValidationLimits};
 pub use plan::{
-    FetchNode, FlattenNode, IntrospectionDirective, IntrospectionField, IntrospectionNode,
-    IntrospectionSelectionSet, ParallelNode, PathSegment, PlanNode, ResponsePath, RootNode,
-    SequenceNode, SubscribeNode,
+    DeferNode, DeferredNode, FetchNode, FlattenNode, IntrospectionDirective, IntrospectionField,
+    IntrospectionNode, IntrospectionSelectionSet, ParallelNode, PathSegment, PlanNode, PlanStats,
+    ResponsePath, RootNode, SequenceNode, StreamNode, SubscribeNode,
 };
 pub use request::Request;
-pub use response::{ErrorPath, Response, ServerError};
+pub use response::{
+    BodySizes, ErrorCode, ErrorPath, Response, ServerError, EXTENSION_CODE, EXTENSION_HTTP,
+    EXTENSION_SERVICE_NAME,
+};
+pub use signature::{normalized_document, operation_signature};
+pub use visualize::GraphFormat;
@@ -1,3 +1,4 @@
+use std::collections::HashSet;
 use std::fmt::{Debug, Display, Formatter, Result as FmtResult};
 use std::ops::{Deref, DerefMut};
 
@@ -16,6 +17,7 @@ pub enum PlanNode<'a> {
     Introspection(IntrospectionNode),
     Fetch(FetchNode<'a>),
     Flatten(FlattenNode<'a>),
+    Defer(DeferNode<'a>),
 }
 
 impl<'a> PlanNode<'a> {
@@ -26,15 +28,74 @@ impl<'a> PlanNode<'a> {
             _ => self,
         }
     }
+
+    /// Collects the statistics of this node, returns the number of sequential
+    /// flatten rounds.
+    fn collect_stats(&self, stats: &mut PlanStats<'a>) -> usize {
+        match self {
+            PlanNode::Sequence(node) => node
+                .nodes
+                .iter()
+                .map(|node| node.collect_stats(stats))
+                .sum(),
+            PlanNode::Parallel(node) => node
+                .nodes
+                .iter()
+                .map(|node| node.collect_stats(stats))
+                .max()
+                .unwrap_or_default(),
+            PlanNode::Introspection(_) => 0,
+            PlanNode::Fetch(node) => {
+                stats.fetch_nodes += 1;
+                stats.services.insert(node.service);
+                0
+            }
+            PlanNode::Flatten(node) => {
+                stats.fetch_nodes += 1;
+                stats.services.insert(node.service);
+                1
+            }
+            PlanNode::Defer(node) => {
+                node.primary.collect_stats(stats)
+                    + node
+                        .deferred
+                        .iter()
+                        .map(|deferred| deferred.node.collect_stats(stats))
+                        .sum::<usize>()
+            }
+        }
+    }
+}
+
+/// Statistics of a query plan.
+#[derive(Debug, Default)]
+pub struct PlanStats<'a> {
+    /// Number of requests sent to the services.
+    pub fetch_nodes: usize,
+
+    /// Number of sequential entity fetch rounds.
+    pub flatten_rounds: usize,
+
+    /// Services touched by the plan.
+    pub services: HashSet<&'a str>,
 }
 
 #[derive(Debug, Clone, Hash, Eq, PartialEq)]
 pub struct PathSegment<'a> {
     pub name: &'a str,
-    pub is_list: bool,
+    /// Number of nested lists of the field type, `2` for `[[Entity]]` and
+    /// `0` if the field isn't a list.
+    pub list_depth: usize,
     pub possible_type: Option<&'a str>,
 }
 
+impl<'a> PathSegment<'a> {
+    #[inline]
+    pub fn is_list(&self) -> bool {
+        self.list_depth > 0
+    }
+}
+
 #[derive(Clone, Default, Hash, Eq, PartialEq)]
 pub struct ResponsePath<'a>(Vec<PathSegment<'a>>);
 
@@ -44,11 +105,13 @@ impl<'a> Debug for ResponsePath<'a> {
             if idx > 0 {
                 write!(f, ".")?;
             }
-            if segment.is_list {
-                write!(f, "[{}]", segment.name)?;
-            } else {
-                write!(f, "{}", segment.name)?;
-            }
+            write!(
+                f,
+                "{}{}{}",
+                "[".repeat(segment.list_depth),
+                segment.name,
+                "]".repeat(segment.list_depth)
+            )?;
             if let Some(possible_type) = segment.possible_type {
                 write!(f, "({})", possible_type)?;
             }
@@ -141,7 +204,15 @@ pub struct FetchNode<'a> {
 
 impl<'a> FetchNode<'a> {
     pub fn to_request(&self) -> Request {
-        Request::new(self.query.to_string()).variables(self.variables.to_variables())
+        fetch_request(&self.query).variables(self.variables.to_variables())
+    }
+}
+
+fn fetch_request(query: &FetchQuery<'_>) -> Request {
+    let request = Request::new(query.to_string());
+    match &query.operation_name {
+        Some(operation_name) => request.operation(operation_name.clone()),
+        None => request,
     }
 }
 
@@ -158,12 +229,51 @@ pub struct FlattenNode<'a> {
 
 impl<'a> FlattenNode<'a> {
     pub fn to_request(&self, representations: Variables) -> Request {
-        Request::new(self.query.to_string())
+        fetch_request(&self.query)
             .variables(representations)
             .extend_variables(self.variables.to_variables())
     }
 }
 
+/// A query with fragments marked with `@defer` or lists marked with
+/// `@stream`.
+///
+/// The deferred fragments are fetched once the primary node is executed, and
+/// delivered as subsequent payloads to the clients accepting incremental
+/// responses. The other clients receive a single response.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeferNode<'a> {
+    pub primary: Box<PlanNode<'a>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub deferred: Vec<DeferredNode<'a>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub streams: Vec<StreamNode<'a>>,
+}
+
+/// A fragment marked with `@defer`, fetched for the objects at the path.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeferredNode<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    pub path: ResponsePath<'a>,
+    pub node: PlanNode<'a>,
+}
+
+/// A list marked with `@stream`.
+///
+/// The services return the whole list, the items after the first
+/// `initial_count` ones are delivered after the initial payload.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamNode<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    pub path: ResponsePath<'a>,
+    pub initial_count: usize,
+}
+
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct SubscribeNode<'a> {
@@ -178,3 +288,25 @@ pub enum RootNode<'a> {
     Subscribe(SubscribeNode<'a>),
     Query(PlanNode<'a>),
 }
+
+impl<'a> RootNode<'a> {
+    /// Returns the statistics of this plan.
+    pub fn stats(&self) -> PlanStats<'a> {
+        let mut stats = PlanStats::default();
+        match self {
+            RootNode::Query(node) => {
+                stats.flatten_rounds = node.collect_stats(&mut stats);
+            }
+            RootNode::Subscribe(node) => {
+                for fetch_node in &node.subscribe_nodes {
+                    stats.fetch_nodes += 1;
+                    stats.services.insert(fetch_node.service);
+                }
+                if let Some(flatten_node) = &node.flatten_node {
+                    stats.flatten_rounds = flatten_node.collect_stats(&mut stats);
+                }
+            }
+        }
+        stats
+    }
+}
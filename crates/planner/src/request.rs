@@ -1,12 +1,18 @@
+use std::collections::HashMap;
+
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use value::{ConstValue, Variables};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Request {
     pub query: String,
+    #[serde(rename = "operationName", alias = "operation", default)]
     pub operation: Option<String>,
     #[serde(skip_serializing_if = "variables_is_empty", default)]
     pub variables: Variables,
+    #[serde(skip_serializing_if = "HashMap::is_empty", default)]
+    pub extensions: HashMap<String, ConstValue>,
 }
 
 impl Request {
@@ -15,6 +21,7 @@ impl Request {
             query: query.into(),
             operation: None,
             variables: Default::default(),
+            extensions: Default::default(),
         }
     }
 
@@ -35,6 +42,29 @@ impl Request {
         }
         self
     }
+
+    pub fn extensions(self, extensions: HashMap<String, ConstValue>) -> Self {
+        Self { extensions, ..self }
+    }
+
+    /// Sets an extension, such as `persistedQuery`, replacing the previous value.
+    pub fn extension(mut self, name: impl Into<String>, value: impl Into<ConstValue>) -> Self {
+        self.extensions.insert(name.into(), value.into());
+        self
+    }
+
+    /// Returns the value of an extension.
+    pub fn get_extension(&self, name: &str) -> Option<&ConstValue> {
+        self.extensions.get(name)
+    }
+
+    /// Returns the value of an extension deserialized as `T`, `None` if the
+    /// extension is missing or has another type.
+    pub fn extension_as<T: DeserializeOwned>(&self, name: &str) -> Option<T> {
+        self.extensions
+            .get(name)
+            .and_then(|value| value::from_value(value.clone()).ok())
+    }
 }
 
 #[inline]
@@ -1,17 +1,86 @@
 use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+use std::time::Duration;
 
+use indexmap::IndexMap;
 use parser::Pos;
-use serde::{Deserialize, Serialize};
-use value::ConstValue;
+use serde::{Deserialize, Deserializer, Serialize};
+use value::{ConstValue, Name};
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Extension key of the error code.
+pub const EXTENSION_CODE: &str = "code";
+
+/// Extension key of the name of the service that caused the error.
+pub const EXTENSION_SERVICE_NAME: &str = "serviceName";
+
+/// Extension key of the HTTP details of the error, such as `{"status": 503}`.
+pub const EXTENSION_HTTP: &str = "http";
+
+/// Categories of the errors created by the gateway, used as the `code` extension.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ErrorCode {
+    /// The query could not be parsed.
+    ParseFailed,
+    /// The query is not valid against the composed schema.
+    ValidationFailed,
+    /// The query plan exceeds the plan limits.
+    PlanLimitExceeded,
+    /// The query cannot be planned, such as a field that cannot be fetched
+    /// from the service resolving its parent.
+    PlanFailed,
+    /// A request to a service failed, or its response could not be read.
+    FetchError,
+    /// A service returned an error that does not follow the GraphQL specification.
+    MalformedError,
+    /// The schema has not been composed yet.
+    NotReady,
+    /// The execution of the operation exceeded its timeout.
+    Timeout,
+    /// A subscription was sent over HTTP instead of a WebSocket.
+    SubscriptionRequiresWebSocket,
+    InternalServerError,
+    Maintenance,
+    ReadOnly,
+    Disabled,
+    /// Parsing, validating or planning the operation exceeded its time budget.
+    BudgetExceeded,
+}
+
+impl ErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::ParseFailed => "GRAPHQL_PARSE_FAILED",
+            ErrorCode::ValidationFailed => "GRAPHQL_VALIDATION_FAILED",
+            ErrorCode::PlanLimitExceeded => "PLAN_LIMIT_EXCEEDED",
+            ErrorCode::PlanFailed => "QUERY_PLAN_FAILED",
+            ErrorCode::FetchError => "FETCH_ERROR",
+            ErrorCode::MalformedError => "MALFORMED_ERROR",
+            ErrorCode::NotReady => "NOT_READY",
+            ErrorCode::Timeout => "TIMEOUT",
+            ErrorCode::SubscriptionRequiresWebSocket => "SUBSCRIPTION_REQUIRES_WEBSOCKET",
+            ErrorCode::InternalServerError => "INTERNAL_SERVER_ERROR",
+            ErrorCode::Maintenance => "MAINTENANCE",
+            ErrorCode::ReadOnly => "READ_ONLY",
+            ErrorCode::Disabled => "DISABLED",
+            ErrorCode::BudgetExceeded => "BUDGET_EXCEEDED",
+        }
+    }
+}
+
+impl Display for ErrorCode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum ErrorPath {
     Name(String),
     Index(usize),
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ServerError {
     pub message: String,
 
@@ -34,13 +103,145 @@ impl ServerError {
             extensions: Default::default(),
         }
     }
+
+    /// Converts an error returned by a service.
+    ///
+    /// Plain strings are used as the message. Other values without a string
+    /// `message` are kept in the `originalError` extension, with the
+    /// `MALFORMED_ERROR` code.
+    fn from_value(value: ConstValue) -> Self {
+        let mut object = match value {
+            ConstValue::String(message) => return ServerError::new(message),
+            ConstValue::Object(object)
+                if matches!(object.get("message"), Some(ConstValue::String(_))) =>
+            {
+                object
+            }
+            value => return ServerError::malformed(value),
+        };
+
+        let message = match object.remove("message") {
+            Some(ConstValue::String(message)) => message,
+            _ => unreachable!(),
+        };
+        let path = match object.remove("path") {
+            Some(ConstValue::List(path)) => path,
+            _ => Vec::new(),
+        };
+        let locations = object
+            .remove("locations")
+            .and_then(|locations| value::from_value(locations).ok())
+            .unwrap_or_default();
+        let extensions = match object.remove("extensions") {
+            Some(ConstValue::Object(extensions)) => extensions
+                .into_iter()
+                .map(|(name, value)| (name.to_string(), value))
+                .collect(),
+            _ => Default::default(),
+        };
+        ServerError {
+            message,
+            path,
+            locations,
+            extensions,
+        }
+    }
+
+    /// Returns the error of an operation whose parsing, validation or
+    /// planning exceeded its time budget, the phase is in the `phase`
+    /// extension.
+    pub fn budget_exceeded(phase: &str, budget: Duration) -> Self {
+        ServerError::new(format!(
+            "Operation exceeded the {} time budget of {}ms.",
+            phase,
+            budget.as_millis()
+        ))
+        .with_code(ErrorCode::BudgetExceeded)
+        .with_extension("phase", ConstValue::String(phase.to_string()))
+    }
+
+    fn malformed(value: ConstValue) -> Self {
+        ServerError::new("Malformed error returned by the service.")
+            .with_code(ErrorCode::MalformedError)
+            .with_extension("originalError", value)
+    }
+
+    /// Sets the `code` extension, usually an [`ErrorCode`].
+    pub fn with_code(self, code: impl Display) -> Self {
+        self.with_extension(EXTENSION_CODE, ConstValue::String(code.to_string()))
+    }
+
+    /// Sets the `serviceName` extension.
+    pub fn with_service_name(self, service: impl Into<String>) -> Self {
+        self.with_extension(EXTENSION_SERVICE_NAME, ConstValue::String(service.into()))
+    }
+
+    /// Sets the `http` extension to `{"status": status}`.
+    pub fn with_http_status(self, status: u16) -> Self {
+        let mut http = IndexMap::new();
+        http.insert(Name::new("status"), ConstValue::Number(status.into()));
+        self.with_extension(EXTENSION_HTTP, ConstValue::Object(http))
+    }
+
+    pub fn with_extension(mut self, name: impl Into<String>, value: impl Into<ConstValue>) -> Self {
+        self.extensions.insert(name.into(), value.into());
+        self
+    }
+
+    pub fn with_path(mut self, path: Vec<ConstValue>) -> Self {
+        self.path = path;
+        self
+    }
+
+    pub fn with_locations(mut self, locations: Vec<Pos>) -> Self {
+        self.locations = locations;
+        self
+    }
+
+    /// Returns the `code` extension.
+    pub fn code(&self) -> Option<&str> {
+        match self.extensions.get(EXTENSION_CODE) {
+            Some(ConstValue::String(code)) => Some(code),
+            _ => None,
+        }
+    }
+
+    /// Returns the `serviceName` extension.
+    pub fn service_name(&self) -> Option<&str> {
+        match self.extensions.get(EXTENSION_SERVICE_NAME) {
+            Some(ConstValue::String(service)) => Some(service),
+            _ => None,
+        }
+    }
+
+    /// Returns `true` if the error did not follow the GraphQL specification.
+    pub fn is_malformed(&self) -> bool {
+        self.code() == Some(ErrorCode::MalformedError.as_str())
+    }
+}
+
+/// Deserializes the errors of a response, accepting a single error and
+/// errors that do not follow the GraphQL specification.
+fn deserialize_errors<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<ServerError>, D::Error> {
+    Ok(match ConstValue::deserialize(deserializer)? {
+        ConstValue::Null => Vec::new(),
+        ConstValue::List(errors) => errors.into_iter().map(ServerError::from_value).collect(),
+        error => vec![ServerError::from_value(error)],
+    })
 }
 
-#[derive(Debug, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct Response {
+    #[serde(default)]
     pub data: ConstValue,
 
-    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    #[serde(
+        skip_serializing_if = "Vec::is_empty",
+        default,
+        deserialize_with = "deserialize_errors"
+    )]
     pub errors: Vec<ServerError>,
 
     #[serde(skip_serializing_if = "HashMap::is_empty", default)]
@@ -48,4 +249,28 @@ pub struct Response {
 
     #[serde(skip_serializing)]
     pub headers: Option<HashMap<String, Vec<String>>>,
+
+    /// Sizes of the bodies exchanged with the service that sent this response.
+    #[serde(skip)]
+    pub body_sizes: Option<BodySizes>,
+}
+
+/// Sizes in bytes of the request and response bodies of a fetch.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct BodySizes {
+    pub request: usize,
+    pub response: usize,
+}
+
+impl Response {
+    /// Creates a response without data.
+    pub fn from_errors(errors: Vec<ServerError>) -> Self {
+        Self {
+            data: ConstValue::Null,
+            errors,
+            extensions: Default::default(),
+            headers: Default::default(),
+            body_sizes: None,
+        }
+    }
 }
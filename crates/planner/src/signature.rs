@@ -0,0 +1,244 @@
+use std::collections::BTreeSet;
+use std::fmt::{Result as FmtResult, Write};
+
+use parser::types::{
+    Directive, ExecutableDocument, FragmentDefinition, OperationDefinition, Selection, SelectionSet,
+};
+use parser::Positioned;
+use value::{Name, Value};
+
+/// Returns a value with the literals replaced by placeholders, `0` for the
+/// numbers, `""` for the strings and empty lists and objects.
+///
+/// The variables, booleans, enums and nulls are kept.
+pub(crate) fn hide_literals(value: &Value) -> Value {
+    match value {
+        Value::Number(_) => Value::Number(0.into()),
+        Value::String(_) | Value::Binary(_) => Value::String(String::new()),
+        Value::List(_) => Value::List(Vec::new()),
+        Value::Object(_) => Value::Object(Default::default()),
+        _ => value.clone(),
+    }
+}
+
+/// Returns the signature of the executed operation of a document, with the
+/// literals replaced by placeholders so that it can be logged without the
+/// data embedded in the query.
+///
+/// Like Apollo operation signatures, the aliases and the unused fragments are
+/// removed, the fragments are sorted by name and the whitespace is minimized.
+/// Returns `None` if the operation is not found.
+pub fn operation_signature(
+    document: &ExecutableDocument,
+    operation_name: Option<&str>,
+) -> Option<String> {
+    let (name, operation) = match operation_name {
+        Some(operation_name) => document
+            .operations
+            .iter()
+            .find(|(name, _)| name.map(Name::as_str) == Some(operation_name))?,
+        None => {
+            let mut operations = document.operations.iter();
+            let operation = operations.next()?;
+            if operations.next().is_some() {
+                return None;
+            }
+            operation
+        }
+    };
+
+    let mut fragments = BTreeSet::new();
+    collect_fragments(document, &operation.node.selection_set.node, &mut fragments);
+
+    let mut signature = String::new();
+    write_operation(
+        &mut signature,
+        name.map(Name::as_str),
+        &operation.node,
+        true,
+    )
+    .ok()?;
+    for fragment_name in fragments {
+        if let Some(fragment) = document.fragments.get(fragment_name) {
+            write!(signature, " ").ok()?;
+            write_fragment(&mut signature, fragment_name, &fragment.node, true).ok()?;
+        }
+    }
+    Some(signature)
+}
+
+/// Returns the whole document printed with the whitespace and the comments
+/// removed, keeping the aliases, the literals and every operation and
+/// fragment, so that two documents have the same text only if they parse to
+/// the same document. The operations and the fragments are sorted by name.
+pub fn normalized_document(document: &ExecutableDocument) -> String {
+    let mut operations = document.operations.iter().collect::<Vec<_>>();
+    operations.sort_by(|(a, _), (b, _)| a.cmp(b));
+    let mut fragments = document.fragments.iter().collect::<Vec<_>>();
+    fragments.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    let mut normalized = String::new();
+    for (idx, (name, operation)) in operations.into_iter().enumerate() {
+        if idx > 0 {
+            normalized.push(' ');
+        }
+        let _ = write_operation(
+            &mut normalized,
+            name.map(Name::as_str),
+            &operation.node,
+            false,
+        );
+    }
+    for (name, fragment) in fragments {
+        normalized.push(' ');
+        let _ = write_fragment(&mut normalized, name, &fragment.node, false);
+    }
+    normalized
+}
+
+fn collect_fragments<'a>(
+    document: &'a ExecutableDocument,
+    selection_set: &'a SelectionSet,
+    fragments: &mut BTreeSet<&'a Name>,
+) {
+    for selection in &selection_set.items {
+        match &selection.node {
+            Selection::Field(field) => {
+                collect_fragments(document, &field.node.selection_set.node, fragments)
+            }
+            Selection::FragmentSpread(fragment_spread) => {
+                let fragment_name = &fragment_spread.node.fragment_name.node;
+                if let Some(fragment) = document.fragments.get(fragment_name) {
+                    if fragments.insert(fragment_name) {
+                        collect_fragments(document, &fragment.node.selection_set.node, fragments);
+                    }
+                }
+            }
+            Selection::InlineFragment(inline_fragment) => collect_fragments(
+                document,
+                &inline_fragment.node.selection_set.node,
+                fragments,
+            ),
+        }
+    }
+}
+
+fn write_value(w: &mut String, value: &Value, hide: bool) -> FmtResult {
+    if hide {
+        write!(w, "{}", hide_literals(value))
+    } else {
+        write!(w, "{}", value)
+    }
+}
+
+fn write_fragment(
+    w: &mut String,
+    name: &Name,
+    fragment: &FragmentDefinition,
+    hide: bool,
+) -> FmtResult {
+    write!(
+        w,
+        "fragment {} on {}",
+        name, fragment.type_condition.node.on.node
+    )?;
+    write_directives(w, &fragment.directives, hide)?;
+    write_selection_set(w, &fragment.selection_set.node, hide)
+}
+
+fn write_operation(
+    w: &mut String,
+    name: Option<&str>,
+    operation: &OperationDefinition,
+    hide: bool,
+) -> FmtResult {
+    write!(w, "{}", operation.ty)?;
+    if let Some(name) = name {
+        write!(w, " {}", name)?;
+    }
+    if !operation.variable_definitions.is_empty() {
+        write!(w, "(")?;
+        for (idx, variable_definition) in operation.variable_definitions.iter().enumerate() {
+            if idx > 0 {
+                write!(w, ",")?;
+            }
+            write!(
+                w,
+                "${}:{}",
+                variable_definition.node.name.node, variable_definition.node.var_type.node
+            )?;
+            if let Some(default_value) = &variable_definition.node.default_value {
+                write!(w, "=")?;
+                write_value(w, &default_value.node.clone().into_value(), hide)?;
+            }
+        }
+        write!(w, ")")?;
+    }
+    write_directives(w, &operation.directives, hide)?;
+    write_selection_set(w, &operation.selection_set.node, hide)
+}
+
+fn write_arguments(
+    w: &mut String,
+    arguments: &[(Positioned<Name>, Positioned<Value>)],
+    hide: bool,
+) -> FmtResult {
+    if arguments.is_empty() {
+        return Ok(());
+    }
+    write!(w, "(")?;
+    for (idx, (name, value)) in arguments.iter().enumerate() {
+        if idx > 0 {
+            write!(w, ",")?;
+        }
+        write!(w, "{}:", name.node)?;
+        write_value(w, &value.node, hide)?;
+    }
+    write!(w, ")")
+}
+
+fn write_directives(w: &mut String, directives: &[Positioned<Directive>], hide: bool) -> FmtResult {
+    for directive in directives {
+        write!(w, "@{}", directive.node.name.node)?;
+        write_arguments(w, &directive.node.arguments, hide)?;
+    }
+    Ok(())
+}
+
+/// Writes a selection set, the aliases are removed when the literals are
+/// hidden.
+fn write_selection_set(w: &mut String, selection_set: &SelectionSet, hide: bool) -> FmtResult {
+    if selection_set.items.is_empty() {
+        return Ok(());
+    }
+    write!(w, "{{")?;
+    for (idx, selection) in selection_set.items.iter().enumerate() {
+        if idx > 0 {
+            write!(w, " ")?;
+        }
+        match &selection.node {
+            Selection::Field(field) => {
+                if let (Some(alias), false) = (&field.node.alias, hide) {
+                    write!(w, "{}:", alias.node)?;
+                }
+                write!(w, "{}", field.node.name.node)?;
+                write_arguments(w, &field.node.arguments, hide)?;
+                write_directives(w, &field.node.directives, hide)?;
+                write_selection_set(w, &field.node.selection_set.node, hide)?;
+            }
+            Selection::FragmentSpread(fragment_spread) => {
+                write!(w, "...{}", fragment_spread.node.fragment_name.node)?;
+                write_directives(w, &fragment_spread.node.directives, hide)?;
+            }
+            Selection::InlineFragment(inline_fragment) => {
+                write!(w, "...")?;
+                if let Some(type_condition) = &inline_fragment.node.type_condition {
+                    write!(w, "on {}", type_condition.node.on.node)?;
+                }
+                write_directives(w, &inline_fragment.node.directives, hide)?;
+                write_selection_set(w, &inline_fragment.node.selection_set.node, hide)?;
+            }
+        }
+    }
+    write!(w, "}}")
+}
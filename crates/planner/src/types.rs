@@ -1,3 +1,4 @@
+use std::borrow::Cow;
 use std::fmt::{Display, Formatter, Result as FmtResult};
 
 use graphgate_schema::{KeyFields, MetaType};
@@ -9,27 +10,68 @@ use serde::{Serialize, Serializer};
 use value::{ConstValue, Name, Value, Variables};
 
 use crate::plan::ResponsePath;
+use crate::signature::hide_literals;
 
 #[derive(Debug)]
 pub struct FieldRef<'a> {
     pub field: &'a Field,
+    /// The directives of the field forwarded to the service, without the
+    /// directives evaluated by the gateway.
+    pub directives: Vec<&'a Positioned<Directive>>,
     pub selection_set: SelectionRefSet<'a>,
 }
 
+impl<'a> FieldRef<'a> {
+    /// Returns `true` if both fields are the same selection, with the same
+    /// response key, arguments and directives.
+    fn can_merge(&self, other: &FieldRef<'_>) -> bool {
+        self.field.response_key().node == other.field.response_key().node
+            && self.field.name.node == other.field.name.node
+            && self.field.arguments.len() == other.field.arguments.len()
+            && self
+                .field
+                .arguments
+                .iter()
+                .all(|(name, value)| other.field.get_argument(&name.node) == Some(value))
+            && self.directives.len() == other.directives.len()
+            && self
+                .directives
+                .iter()
+                .zip(&other.directives)
+                .all(|(directive, other)| {
+                    directive.node.name.node == other.node.name.node
+                        && directive.node.arguments == other.node.arguments
+                })
+    }
+}
+
 #[derive(Debug)]
 pub struct RequiredRef<'a> {
     pub prefix: usize,
     pub fields: &'a KeyFields,
-    pub requires: Option<&'a KeyFields>,
+    /// The `@requires` fields of the fields fetched with these keys.
+    pub requires: Vec<&'a KeyFields>,
+}
+
+/// A field resolved by the gateway, fetched as its source fields aliased with
+/// `__gateway{id}_{index}_{response key}`, or `__typename` if it has none.
+#[derive(Debug)]
+pub struct GatewayFieldRef<'a> {
+    pub id: usize,
+    pub field: &'a Field,
+    pub sources: &'a [Name],
 }
 
 #[derive(Debug)]
 pub enum SelectionRef<'a> {
     FieldRef(FieldRef<'a>),
-    IntrospectionTypename,
+    /// The `__typename` field, with its alias if any.
+    IntrospectionTypename(Option<&'a str>),
     RequiredRef(RequiredRef<'a>),
+    GatewayFieldRef(GatewayFieldRef<'a>),
     InlineFragment {
         type_condition: Option<&'a str>,
+        directives: &'a [Positioned<Directive>],
         selection_set: SelectionRefSet<'a>,
     },
 }
@@ -37,43 +79,142 @@ pub enum SelectionRef<'a> {
 #[derive(Default, Debug)]
 pub struct SelectionRefSet<'a>(pub Vec<SelectionRef<'a>>);
 
+impl<'a> SelectionRefSet<'a> {
+    /// Adds a selection, merging it into a previous selection of the same
+    /// field or keys, such as one selected by another fragment, so that the
+    /// query sent to the service selects every field once.
+    pub fn push(&mut self, selection: SelectionRef<'a>) {
+        match selection {
+            SelectionRef::FieldRef(field) => {
+                match self.0.iter_mut().find_map(|selection| match selection {
+                    SelectionRef::FieldRef(prev) if prev.can_merge(&field) => Some(prev),
+                    _ => None,
+                }) {
+                    Some(prev) => prev.selection_set.extend(field.selection_set),
+                    None => self.0.push(SelectionRef::FieldRef(field)),
+                }
+            }
+            SelectionRef::IntrospectionTypename(alias) => {
+                if !self.0.iter().any(|selection| {
+                    matches!(selection, SelectionRef::IntrospectionTypename(prev) if *prev == alias)
+                }) {
+                    self.0.push(SelectionRef::IntrospectionTypename(alias));
+                }
+            }
+            SelectionRef::RequiredRef(required) => {
+                match self.0.iter_mut().find_map(|selection| match selection {
+                    SelectionRef::RequiredRef(prev) if prev.prefix == required.prefix => Some(prev),
+                    _ => None,
+                }) {
+                    Some(prev) => {
+                        for requires in required.requires {
+                            if !prev
+                                .requires
+                                .iter()
+                                .any(|prev| std::ptr::eq(*prev, requires))
+                            {
+                                prev.requires.push(requires);
+                            }
+                        }
+                    }
+                    None => self.0.push(SelectionRef::RequiredRef(required)),
+                }
+            }
+            SelectionRef::InlineFragment {
+                type_condition,
+                directives,
+                selection_set,
+            } if directives.is_empty() => {
+                match self.0.iter_mut().find_map(|selection| match selection {
+                    SelectionRef::InlineFragment {
+                        type_condition: prev_type_condition,
+                        directives: prev_directives,
+                        selection_set: prev_selection_set,
+                    } if *prev_type_condition == type_condition && prev_directives.is_empty() => {
+                        Some(prev_selection_set)
+                    }
+                    _ => None,
+                }) {
+                    Some(prev) => prev.extend(selection_set),
+                    None => self.0.push(SelectionRef::InlineFragment {
+                        type_condition,
+                        directives,
+                        selection_set,
+                    }),
+                }
+            }
+            selection => self.0.push(selection),
+        }
+    }
+
+    fn extend(&mut self, selection_set: SelectionRefSet<'a>) {
+        for selection in selection_set.0 {
+            self.push(selection);
+        }
+    }
+}
+
 impl<'a> Display for SelectionRefSet<'a> {
     fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
-        stringify_selection_ref_set_rec(f, self)
+        stringify_selection_ref_set_rec(f, self, &VariablesRef::default())
     }
 }
 
 #[derive(Debug)]
 pub struct FetchQuery<'a> {
     pub entity_type: Option<&'a str>,
+    pub operation_name: Option<String>,
+    /// The type of the operation sent to the service, always `query` for the
+    /// entity fetches whatever the type of the client operation.
     pub operation_type: OperationType,
     pub variable_definitions: VariableDefinitionsRef<'a>,
+    /// Variables written as literals in the query instead of being declared.
+    pub inlined_variables: VariablesRef<'a>,
     pub selection_set: SelectionRefSet<'a>,
 }
 
+impl<'a> FetchQuery<'a> {
+    /// Returns the query with the literals replaced by placeholders, to be
+    /// logged without the data embedded in the query.
+    pub fn signature(&self) -> String {
+        format!("{:#}", self)
+    }
+}
+
+/// The alternate flag (`{:#}`) replaces the literals with placeholders.
 impl<'a> Display for FetchQuery<'a> {
     fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
         match self.entity_type {
             Some(entity_type) => {
+                write!(f, "{}", self.operation_type)?;
+                if let Some(operation_name) = &self.operation_name {
+                    write!(f, " {}", operation_name)?;
+                }
+                write!(f, "($representations:[_Any!]!")?;
+                if !self.variable_definitions.variables.is_empty() {
+                    write!(f, ", ")?;
+                    Display::fmt(&self.variable_definitions, f)?;
+                }
                 write!(
                     f,
-                    "query($representations:[_Any!]!{}{}) {{ _entities(representations:$representations) {{ ... on {} {} }} }}",
-                    if self.variable_definitions.variables.is_empty() {
-                        ""
-                    } else {
-                        ", "
-                    },
-                    self.variable_definitions,
-                    entity_type,
-                    self.selection_set
-                )
+                    ") {{ _entities(representations:$representations) {{ ... on {} ",
+                    entity_type
+                )?;
+                stringify_selection_ref_set_rec(f, &self.selection_set, &self.inlined_variables)?;
+                write!(f, " }} }}")
             }
             None => {
                 write!(f, "{}", self.operation_type)?;
+                if let Some(operation_name) = &self.operation_name {
+                    write!(f, " {}", operation_name)?;
+                }
                 if !self.variable_definitions.variables.is_empty() {
-                    write!(f, "({})", self.variable_definitions)?;
+                    write!(f, "(")?;
+                    Display::fmt(&self.variable_definitions, f)?;
+                    write!(f, ")")?;
                 }
-                write!(f, "\n{}", self.selection_set)
+                writeln!(f)?;
+                stringify_selection_ref_set_rec(f, &self.selection_set, &self.inlined_variables)
             }
         }
     }
@@ -88,39 +229,109 @@ impl<'a> Serialize for FetchQuery<'a> {
     }
 }
 
+/// Returns a value with the inlined variables replaced by their values.
+fn inline_variables(value: &Value, inlined: &VariablesRef<'_>) -> Value {
+    match value {
+        Value::Variable(name) => match inlined.variables.get(name.as_str()) {
+            Some(inlined) => ConstValue::clone(inlined).into_value(),
+            None => value.clone(),
+        },
+        Value::List(values) => Value::List(
+            values
+                .iter()
+                .map(|value| inline_variables(value, inlined))
+                .collect(),
+        ),
+        Value::Object(object) => Value::Object(
+            object
+                .iter()
+                .map(|(name, value)| (name.clone(), inline_variables(value, inlined)))
+                .collect(),
+        ),
+        _ => value.clone(),
+    }
+}
+
 fn stringify_argument(
     f: &mut Formatter<'_>,
     arguments: &[(Positioned<Name>, Positioned<Value>)],
+    inlined: &VariablesRef<'_>,
 ) -> FmtResult {
     write!(f, "(")?;
     for (idx, (name, value)) in arguments.iter().enumerate() {
         if idx > 0 {
             write!(f, ", ")?;
         }
-        write!(f, "{}: {}", name.node, value.node)?;
+        let value = if inlined.is_empty() {
+            Cow::Borrowed(&value.node)
+        } else {
+            Cow::Owned(inline_variables(&value.node, inlined))
+        };
+        if f.alternate() {
+            write!(f, "{}: {}", name.node, hide_literals(&value))?;
+        } else {
+            write!(f, "{}: {}", name.node, value)?;
+        }
     }
     write!(f, ")")
 }
 
-fn stringify_directive(f: &mut Formatter<'_>, directive: &Directive) -> FmtResult {
+fn stringify_directive(
+    f: &mut Formatter<'_>,
+    directive: &Directive,
+    inlined: &VariablesRef<'_>,
+) -> FmtResult {
     write!(f, "@{}", directive.name.node.as_str())?;
     if !directive.arguments.is_empty() {
-        stringify_argument(f, &directive.arguments)?;
+        stringify_argument(f, &directive.arguments, inlined)?;
     }
     Ok(())
 }
 
-fn stringify_directives(f: &mut Formatter<'_>, directives: &[Positioned<Directive>]) -> FmtResult {
-    for (idx, directive) in directives.iter().enumerate() {
+/// Returns `false` for the `@defer` and `@stream` directives, they are
+/// evaluated by the gateway and never sent to the services.
+pub(crate) fn is_forwarded(directive: &Positioned<Directive>) -> bool {
+    !matches!(directive.node.name.node.as_str(), "defer" | "stream")
+}
+
+fn stringify_directives<'b>(
+    f: &mut Formatter<'_>,
+    directives: impl IntoIterator<Item = &'b Positioned<Directive>>,
+    inlined: &VariablesRef<'_>,
+) -> FmtResult {
+    for (idx, directive) in directives
+        .into_iter()
+        .filter(|directive| is_forwarded(directive))
+        .enumerate()
+    {
         if idx > 0 {
             write!(f, " ")?;
         }
-        stringify_directive(f, &directive.node)?;
+        stringify_directive(f, &directive.node, inlined)?;
     }
     Ok(())
 }
 
 fn stringify_key_fields(f: &mut Formatter<'_>, prefix: usize, fields: &KeyFields) -> FmtResult {
+    fn stringify_key_field_arguments(
+        f: &mut Formatter<'_>,
+        fields: &KeyFields,
+        field_name: &str,
+    ) -> FmtResult {
+        let arguments = fields.arguments(field_name);
+        if arguments.is_empty() {
+            return Ok(());
+        }
+        write!(f, "(")?;
+        for (idx, (name, value)) in arguments.iter().enumerate() {
+            if idx > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}: {}", name, value)?;
+        }
+        write!(f, ")")
+    }
+
     fn stringify_key_fields_no_prefix(f: &mut Formatter<'_>, fields: &KeyFields) -> FmtResult {
         if fields.is_empty() {
             return Ok(());
@@ -129,16 +340,18 @@ fn stringify_key_fields(f: &mut Formatter<'_>, prefix: usize, fields: &KeyFields
         for (idx, (field_name, children)) in fields.iter().enumerate() {
             if idx > 0 {
                 write!(f, " ")?;
-                write!(f, "{}", field_name)?;
-                stringify_key_fields_no_prefix(f, children)?;
             }
+            write!(f, "{}", field_name)?;
+            stringify_key_field_arguments(f, fields, field_name)?;
+            stringify_key_fields_no_prefix(f, children)?;
         }
         write!(f, "}}")
     }
 
     for (field_name, children) in fields.iter() {
         write!(f, " __key{}_{}:{}", prefix, field_name, field_name)?;
-        stringify_key_fields_no_prefix(f, &children)?;
+        stringify_key_field_arguments(f, fields, field_name)?;
+        stringify_key_fields_no_prefix(f, children)?;
     }
     Ok(())
 }
@@ -146,6 +359,7 @@ fn stringify_key_fields(f: &mut Formatter<'_>, prefix: usize, fields: &KeyFields
 fn stringify_selection_ref_set_rec(
     f: &mut Formatter<'_>,
     selection_set: &SelectionRefSet<'_>,
+    inlined: &VariablesRef<'_>,
 ) -> FmtResult {
     write!(f, "{{ ")?;
     for (idx, selection) in selection_set.0.iter().enumerate() {
@@ -160,36 +374,69 @@ fn stringify_selection_ref_set_rec(
                 }
                 write!(f, "{}", field.field.name.node)?;
                 if !field.field.arguments.is_empty() {
-                    stringify_argument(f, &field.field.arguments)?;
+                    stringify_argument(f, &field.field.arguments, inlined)?;
                 }
-                if !field.field.directives.is_empty() {
+                if field
+                    .directives
+                    .iter()
+                    .any(|directive| is_forwarded(directive))
+                {
                     write!(f, " ")?;
-                    stringify_directives(f, &field.field.directives)?;
+                    stringify_directives(f, field.directives.iter().copied(), inlined)?;
                 }
                 if !field.selection_set.0.is_empty() {
                     write!(f, " ")?;
-                    stringify_selection_ref_set_rec(f, &field.selection_set)?;
+                    stringify_selection_ref_set_rec(f, &field.selection_set, inlined)?;
                 }
             }
-            SelectionRef::IntrospectionTypename => {
+            SelectionRef::IntrospectionTypename(alias) => {
+                if let Some(alias) = alias {
+                    write!(f, "{}:", alias)?;
+                }
                 write!(f, "__typename")?;
             }
             SelectionRef::RequiredRef(require_ref) => {
                 write!(f, "__key{}___typename:__typename", require_ref.prefix,)?;
                 stringify_key_fields(f, require_ref.prefix, &require_ref.fields)?;
-                if let Some(requires) = require_ref.requires {
-                    stringify_key_fields(f, require_ref.prefix, &requires)?;
+                for requires in &require_ref.requires {
+                    stringify_key_fields(f, require_ref.prefix, requires)?;
+                }
+            }
+            SelectionRef::GatewayFieldRef(gateway_field) => {
+                let response_key = &gateway_field.field.response_key().node;
+                let sources = match gateway_field.sources {
+                    [] => vec!["__typename"],
+                    sources => sources.iter().map(Name::as_str).collect(),
+                };
+                for (idx, source) in sources.iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(
+                        f,
+                        "__gateway{}_{}_{}:{}",
+                        gateway_field.id, idx, response_key, source
+                    )?;
+                    if gateway_field.field.directives.iter().any(is_forwarded) {
+                        write!(f, " ")?;
+                        stringify_directives(f, &gateway_field.field.directives, inlined)?;
+                    }
                 }
             }
             SelectionRef::InlineFragment {
                 type_condition,
+                directives,
                 selection_set,
             } => {
                 match type_condition {
                     Some(type_condition) => write!(f, "... on {} ", type_condition)?,
                     None => write!(f, "... ")?,
                 }
-                stringify_selection_ref_set_rec(f, selection_set)?;
+                if directives.iter().any(is_forwarded) {
+                    stringify_directives(f, directives.iter(), inlined)?;
+                    write!(f, " ")?;
+                }
+                stringify_selection_ref_set_rec(f, selection_set, inlined)?;
             }
         }
     }
@@ -307,7 +554,15 @@ impl<'a> Display for VariableDefinitionsRef<'a> {
                 variable_definition.name.node, variable_definition.var_type.node
             )?;
             if let Some(default_value) = &variable_definition.default_value {
-                write!(f, " = {}", default_value.node)?;
+                if f.alternate() {
+                    write!(
+                        f,
+                        " = {}",
+                        hide_literals(&default_value.node.clone().into_value())
+                    )?;
+                } else {
+                    write!(f, " = {}", default_value.node)?;
+                }
             }
         }
         Ok(())
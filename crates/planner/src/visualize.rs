@@ -0,0 +1,153 @@
+use std::fmt::Write;
+use std::str::FromStr;
+
+use crate::plan::{PlanNode, RootNode};
+
+/// Text format of a query plan graph.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum GraphFormat {
+    /// [Graphviz DOT](https://graphviz.org/doc/info/lang.html)
+    Dot,
+    /// [Mermaid flowchart](https://mermaid-js.github.io/mermaid/#/flowchart)
+    Mermaid,
+}
+
+impl FromStr for GraphFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dot" => Ok(GraphFormat::Dot),
+            "mermaid" => Ok(GraphFormat::Mermaid),
+            _ => Err(format!("Unknown graph format '{}'.", s)),
+        }
+    }
+}
+
+struct GraphWriter {
+    format: GraphFormat,
+    output: String,
+    next_id: usize,
+}
+
+impl GraphWriter {
+    fn new(format: GraphFormat) -> Self {
+        let output = match format {
+            GraphFormat::Dot => "digraph QueryPlan {\n".to_string(),
+            GraphFormat::Mermaid => "flowchart TD\n".to_string(),
+        };
+        Self {
+            format,
+            output,
+            next_id: 0,
+        }
+    }
+
+    /// Escapes a label to be written between double quotes, the labels can
+    /// contain client input such as the labels of `@defer`.
+    fn escape(&self, label: &str) -> String {
+        let label = label.replace(&['\r', '\n'][..], " ");
+        match self.format {
+            GraphFormat::Dot => label.replace('\\', "\\\\").replace('"', "\\\""),
+            GraphFormat::Mermaid => label.replace('"', "#quot;").replace('|', "#124;"),
+        }
+    }
+
+    fn node(&mut self, label: &str) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        let label = self.escape(label);
+        let line = match self.format {
+            GraphFormat::Dot => format!("    n{} [label=\"{}\"];", id, label),
+            GraphFormat::Mermaid => format!("    n{}[\"{}\"]", id, label),
+        };
+        writeln!(self.output, "{}", line).unwrap();
+        id
+    }
+
+    fn edge(&mut self, from: usize, to: usize, label: Option<&str>) {
+        let label = label.map(|label| self.escape(label));
+        let line = match (self.format, label) {
+            (GraphFormat::Dot, Some(label)) => {
+                format!("    n{} -> n{} [label=\"{}\"];", from, to, label)
+            }
+            (GraphFormat::Dot, None) => format!("    n{} -> n{};", from, to),
+            (GraphFormat::Mermaid, Some(label)) => {
+                format!("    n{} -->|\"{}\"| n{}", from, label, to)
+            }
+            (GraphFormat::Mermaid, None) => format!("    n{} --> n{}", from, to),
+        };
+        writeln!(self.output, "{}", line).unwrap();
+    }
+
+    fn finish(mut self) -> String {
+        if self.format == GraphFormat::Dot {
+            self.output.push_str("}\n");
+        }
+        self.output
+    }
+
+    fn write_plan_node(&mut self, node: &PlanNode<'_>) -> usize {
+        match node {
+            PlanNode::Sequence(sequence) => {
+                let id = self.node("Sequence");
+                for (idx, child) in sequence.nodes.iter().enumerate() {
+                    let child_id = self.write_plan_node(child);
+                    self.edge(id, child_id, Some(&(idx + 1).to_string()));
+                }
+                id
+            }
+            PlanNode::Parallel(parallel) => {
+                let id = self.node("Parallel");
+                for child in &parallel.nodes {
+                    let child_id = self.write_plan_node(child);
+                    self.edge(id, child_id, None);
+                }
+                id
+            }
+            PlanNode::Introspection(_) => self.node("Introspection"),
+            PlanNode::Fetch(fetch) => self.node(&format!("Fetch [{}]", fetch.service)),
+            PlanNode::Flatten(flatten) => {
+                self.node(&format!("Flatten [{}] {}", flatten.service, flatten.path))
+            }
+            PlanNode::Defer(defer) => {
+                let id = self.node("Defer");
+                let primary_id = self.write_plan_node(&defer.primary);
+                self.edge(id, primary_id, Some("primary"));
+                for deferred in &defer.deferred {
+                    let child_id = self.write_plan_node(&deferred.node);
+                    let label = match &deferred.label {
+                        Some(label) => format!("deferred {}", label),
+                        None => "deferred".to_string(),
+                    };
+                    self.edge(id, child_id, Some(&label));
+                }
+                id
+            }
+        }
+    }
+}
+
+impl<'a> RootNode<'a> {
+    /// Renders this plan as a graph of its nodes, services and dependencies.
+    pub fn to_graph(&self, format: GraphFormat) -> String {
+        let mut writer = GraphWriter::new(format);
+        match self {
+            RootNode::Query(node) => {
+                writer.write_plan_node(node);
+            }
+            RootNode::Subscribe(subscribe) => {
+                let id = writer.node("Subscribe");
+                for fetch in &subscribe.subscribe_nodes {
+                    let child_id = writer.node(&format!("Fetch [{}]", fetch.service));
+                    writer.edge(id, child_id, None);
+                }
+                if let Some(flatten_node) = &subscribe.flatten_node {
+                    let child_id = writer.write_plan_node(flatten_node);
+                    writer.edge(id, child_id, Some("push"));
+                }
+            }
+        }
+        writer.finish()
+    }
+}
@@ -1,8 +1,14 @@
 use std::fs;
+use std::sync::Arc;
+use std::time::Duration;
 
 use globset::GlobBuilder;
-use graphgate_planner::PlanBuilder;
-use graphgate_schema::ComposedSchema;
+use graphgate_planner::{
+    normalized_document, operation_signature, ErrorCode, GraphFormat, InlineVariables, PlanBuilder,
+    PlanLimits, PlanNode, Request, Response, RootNode, ValidationLimits,
+};
+use graphgate_schema::{ComposedSchema, ShapingDirective};
+use value::ConstValue;
 
 #[test]
 fn test() {
@@ -50,3 +56,859 @@ fn test() {
         }
     }
 }
+
+#[test]
+fn plan_limits() {
+    let schema = ComposedSchema::parse(include_str!("test.graphql")).unwrap();
+    let query = "{ me { id reviews { body author { username } } } }";
+
+    let document = parser::parse_query(query).unwrap();
+    let builder = PlanBuilder::new(&schema, document);
+    let node = builder.plan().unwrap();
+    let stats = node.stats();
+    assert_eq!(stats.fetch_nodes, 3);
+    assert_eq!(stats.flatten_rounds, 2);
+    assert_eq!(stats.services.len(), 2);
+
+    let document = parser::parse_query(query).unwrap();
+    let builder = PlanBuilder::new(&schema, document).limits(PlanLimits {
+        max_flatten_rounds: Some(2),
+        ..PlanLimits::default()
+    });
+    assert!(builder.plan().is_ok());
+
+    let document = parser::parse_query(query).unwrap();
+    let builder = PlanBuilder::new(&schema, document).limits(PlanLimits {
+        max_fetch_nodes: Some(2),
+        max_flatten_rounds: Some(1),
+        ..PlanLimits::default()
+    });
+    let response = builder.plan().unwrap_err();
+    assert_eq!(response.errors.len(), 2);
+    assert_eq!(
+        response.errors[0].code(),
+        Some(ErrorCode::PlanLimitExceeded.as_str())
+    );
+}
+
+#[test]
+fn time_budgets() {
+    let schema = ComposedSchema::parse(include_str!("test.graphql")).unwrap();
+    let query = "{ me { id reviews { body author { username } } } }";
+
+    let document = parser::parse_query(query).unwrap();
+    let builder = PlanBuilder::new(&schema, document).limits(PlanLimits {
+        validation: ValidationLimits {
+            max_duration: Some(Duration::from_secs(60)),
+            ..ValidationLimits::default()
+        },
+        max_planning_duration: Some(Duration::from_secs(60)),
+        ..PlanLimits::default()
+    });
+    assert!(builder.plan().is_ok());
+
+    for (limits, phase) in [
+        (
+            PlanLimits {
+                validation: ValidationLimits {
+                    max_duration: Some(Duration::ZERO),
+                    ..ValidationLimits::default()
+                },
+                ..PlanLimits::default()
+            },
+            "validation",
+        ),
+        (
+            PlanLimits {
+                max_planning_duration: Some(Duration::ZERO),
+                ..PlanLimits::default()
+            },
+            "planning",
+        ),
+    ] {
+        let document = parser::parse_query(query).unwrap();
+        let builder = PlanBuilder::new(&schema, document).limits(limits);
+        let response = builder.plan().unwrap_err();
+        assert_eq!(response.errors.len(), 1);
+        assert_eq!(
+            response.errors[0].code(),
+            Some(ErrorCode::BudgetExceeded.as_str())
+        );
+        assert_eq!(
+            response.errors[0].extensions.get("phase"),
+            Some(&ConstValue::String(phase.to_string()))
+        );
+    }
+}
+
+#[test]
+fn plan_graph() {
+    let schema = ComposedSchema::parse(include_str!("test.graphql")).unwrap();
+    let document = parser::parse_query("{ me { id reviews { body } } }").unwrap();
+    let builder = PlanBuilder::new(&schema, document);
+    let node = builder.plan().unwrap();
+
+    assert_eq!(
+        node.to_graph(GraphFormat::Dot),
+        r#"digraph QueryPlan {
+    n0 [label="Sequence"];
+    n1 [label="Fetch [accounts]"];
+    n0 -> n1 [label="1"];
+    n2 [label="Flatten [reviews] me"];
+    n0 -> n2 [label="2"];
+}
+"#
+    );
+    assert_eq!(
+        node.to_graph(GraphFormat::Mermaid),
+        r#"flowchart TD
+    n0["Sequence"]
+    n1["Fetch [accounts]"]
+    n0 -->|"1"| n1
+    n2["Flatten [reviews] me"]
+    n0 -->|"2"| n2
+"#
+    );
+
+    // The labels of `@defer` are escaped.
+    let document =
+        parser::parse_query(r#"{ me { id ... @defer(label: "a\"|b\nc") { reviews { body } } } }"#)
+            .unwrap();
+    let node = PlanBuilder::new(&schema, document).plan().unwrap();
+    assert!(node
+        .to_graph(GraphFormat::Dot)
+        .contains(r#"[label="deferred a\"|b c"];"#));
+    assert!(node
+        .to_graph(GraphFormat::Mermaid)
+        .contains(r#"-->|"deferred a#quot;#124;b c"|"#));
+}
+
+#[test]
+fn nested_variables() {
+    let schema = ComposedSchema::parse(include_str!("test.graphql")).unwrap();
+    let plan = |query: &str, variables: serde_json::Value| {
+        let document = parser::parse_query(query).unwrap();
+        let builder = PlanBuilder::new(&schema, document)
+            .variables(serde_json::from_value(variables).unwrap());
+        serde_json::to_value(&builder.plan().unwrap()).unwrap()
+    };
+
+    let node = plan(
+        r#"query($upc: String!, $min: Int) {
+            searchProducts(filters: [{ upcs: [$upc], price: { min: $min } }]) { upc }
+        }"#,
+        serde_json::json!({ "upc": "top-1", "min": 10 }),
+    );
+    assert_eq!(
+        node["variables"],
+        serde_json::json!({ "upc": "top-1", "min": 10 })
+    );
+    let query = node["query"].as_str().unwrap();
+    assert!(query.starts_with("query($upc: String!, $min: Int)"));
+
+    let node = plan(
+        r#"query($upc: String!, $min: Int = 5) {
+            searchProducts(filters: [{ upcs: [$upc], price: { min: $min } }]) { upc }
+        }"#,
+        serde_json::json!({ "upc": "top-1" }),
+    );
+    assert_eq!(node["variables"], serde_json::json!({ "upc": "top-1" }));
+    let query = node["query"].as_str().unwrap();
+    assert!(query.starts_with("query($upc: String!, $min: Int = 5)"));
+
+    let node = plan(
+        r#"query($skip: Boolean!) { me { id username @skip(if: $skip) } }"#,
+        serde_json::json!({ "skip": true }),
+    );
+    assert_eq!(node["variables"], serde_json::json!({ "skip": true }));
+    let query = node["query"].as_str().unwrap();
+    assert!(query.starts_with("query($skip: Boolean!)"));
+}
+
+#[test]
+fn inline_variables() {
+    let schema = ComposedSchema::parse(include_str!("test.graphql")).unwrap();
+    let plan = |services: &[&str]| {
+        let document = parser::parse_query(
+            r#"query($upc: String!, $min: Int, $max: Int = 100) {
+                searchProducts(filters: [{ upcs: [$upc], price: { min: $min, max: $max } }]) { upc }
+            }"#,
+        )
+        .unwrap();
+        let builder = PlanBuilder::new(&schema, document)
+            .variables(
+                serde_json::from_value(serde_json::json!({ "upc": "top-1-long", "min": 10 }))
+                    .unwrap(),
+            )
+            .inline_variables(Arc::new(InlineVariables {
+                services: services.iter().map(ToString::to_string).collect(),
+                max_size: 8,
+            }));
+        serde_json::to_value(&builder.plan().unwrap()).unwrap()
+    };
+
+    let node = plan(&["products"]);
+    assert_eq!(
+        node["variables"],
+        serde_json::json!({ "upc": "top-1-long" })
+    );
+    let query = node["query"].as_str().unwrap();
+    assert!(query.starts_with("query($upc: String!, $max: Int = 100)"));
+    assert!(query.contains("min: 10"));
+
+    let node = plan(&["accounts"]);
+    assert_eq!(
+        node["variables"],
+        serde_json::json!({ "upc": "top-1-long", "min": 10 })
+    );
+    let query = node["query"].as_str().unwrap();
+    assert!(query.starts_with("query($upc: String!, $min: Int, $max: Int = 100)"));
+}
+
+#[test]
+fn fragment_directive_variables() {
+    let schema = ComposedSchema::parse(include_str!("test.graphql")).unwrap();
+    let plan = |query: &str, variables: serde_json::Value| {
+        let document = parser::parse_query(query).unwrap();
+        let builder = PlanBuilder::new(&schema, document)
+            .variables(serde_json::from_value(variables).unwrap());
+        serde_json::to_value(&builder.plan().unwrap()).unwrap()
+    };
+
+    let node = plan(
+        r#"query($flag: Boolean!) { me { id ... @include(if: $flag) { username } } }"#,
+        serde_json::json!({ "flag": true }),
+    );
+    assert_eq!(node["variables"], serde_json::json!({ "flag": true }));
+    let query = node["query"].as_str().unwrap();
+    assert!(query.starts_with("query($flag: Boolean!)"));
+    assert!(query.contains("... @include(if: $flag) { username }"));
+
+    let node = plan(
+        r#"query($flag: Boolean!) { me { id ...Names @skip(if: $flag) } }
+        fragment Names on User { username }"#,
+        serde_json::json!({ "flag": false }),
+    );
+    assert_eq!(node["variables"], serde_json::json!({ "flag": false }));
+    let query = node["query"].as_str().unwrap();
+    assert!(query.contains("... @skip(if: $flag) { username }"));
+
+    let node = plan(
+        r#"query($flag: Boolean!) { ... @include(if: $flag) { me { id } } }"#,
+        serde_json::json!({ "flag": true }),
+    );
+    assert_eq!(node["variables"], serde_json::json!({ "flag": true }));
+    let query = node["query"].as_str().unwrap();
+    assert!(query.contains("... @include(if: $flag) { me { id } }"));
+}
+
+#[test]
+fn supergraph() {
+    let schema = ComposedSchema::from_supergraph(
+        parser::parse_schema(include_str!("supergraph.graphql")).unwrap(),
+    )
+    .unwrap();
+    let user = schema.get_type_by_name("User").unwrap();
+    assert_eq!(user.owner.as_deref(), Some("accounts"));
+    assert_eq!(user.keys.len(), 2);
+    assert_eq!(
+        user.fields.get("reviews").unwrap().service.as_deref(),
+        Some("reviews")
+    );
+    assert_eq!(user.fields.get("username").unwrap().service, None);
+
+    let document = parser::parse_query("{ me { id reviews { body } } }").unwrap();
+    let builder = PlanBuilder::new(&schema, document);
+    let node = builder.plan().unwrap();
+    assert_eq!(
+        node.to_graph(GraphFormat::Mermaid),
+        r#"flowchart TD
+    n0["Sequence"]
+    n1["Fetch [accounts]"]
+    n0 -->|"1"| n1
+    n2["Flatten [reviews] me"]
+    n0 -->|"2"| n2
+"#
+    );
+}
+
+#[test]
+fn malformed_errors() {
+    let resp: Response = serde_json::from_str(
+        r#"{"errors": ["not found", {"code": 1}, {"message": "denied", "path": "a", "locations": 1}]}"#,
+    )
+    .unwrap();
+    assert_eq!(resp.data, ConstValue::Null);
+    assert_eq!(resp.errors.len(), 3);
+    assert_eq!(resp.errors[0].message, "not found");
+    assert!(!resp.errors[0].is_malformed());
+    assert!(resp.errors[1].is_malformed());
+    assert_eq!(resp.errors[2].message, "denied");
+    assert!(resp.errors[2].path.is_empty());
+    assert!(resp.errors[2].locations.is_empty());
+
+    let resp: Response = serde_json::from_str(r#"{"data": null, "errors": "failed"}"#).unwrap();
+    assert_eq!(resp.errors.len(), 1);
+    assert_eq!(resp.errors[0].message, "failed");
+}
+
+#[test]
+fn fetch_operation_names() {
+    let schema = ComposedSchema::parse(include_str!("test.graphql")).unwrap();
+    let document =
+        parser::parse_query("query MyQuery { me { id reviews { body author { username } } } }")
+            .unwrap();
+    let node = PlanBuilder::new(&schema, document).plan().unwrap();
+    let node = serde_json::to_value(&node).unwrap();
+
+    let nodes = node["nodes"].as_array().unwrap();
+    assert!(nodes[0]["query"]
+        .as_str()
+        .unwrap()
+        .starts_with("query MyQuery__accounts__0\n"));
+    assert!(nodes[1]["query"]
+        .as_str()
+        .unwrap()
+        .starts_with("query MyQuery__reviews__0($representations:[_Any!]!)"));
+    assert!(nodes[2]["query"]
+        .as_str()
+        .unwrap()
+        .starts_with("query MyQuery__accounts__1($representations:[_Any!]!)"));
+}
+
+#[test]
+fn deterministic_key_prefixes() {
+    let schema = ComposedSchema::parse(include_str!("test.graphql")).unwrap();
+    let prefixes = |query: &str| {
+        let document = parser::parse_query(query).unwrap();
+        let node = PlanBuilder::new(&schema, document).plan().unwrap();
+        let node = serde_json::to_value(&node).unwrap();
+        let mut prefixes = node["nodes"][1]["nodes"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|node| {
+                (
+                    node["path"].as_str().unwrap().to_string(),
+                    node["prefix"].as_u64().unwrap(),
+                )
+            })
+            .collect::<Vec<_>>();
+        prefixes.sort();
+        prefixes
+    };
+
+    let a = prefixes("{ a: me { reviews { body } } b: me { reviews { body } } }");
+    let b = prefixes("{ b: me { reviews { body } } a: me { reviews { body } } }");
+    assert_eq!(a.len(), 2);
+    assert_ne!(a[0].1, a[1].1);
+    assert_eq!(a, b);
+}
+
+#[test]
+fn request_extensions() {
+    #[derive(Debug, serde::Deserialize, PartialEq)]
+    #[serde(rename_all = "camelCase")]
+    struct PersistedQuery {
+        version: i32,
+        sha256_hash: String,
+    }
+
+    let request: Request = serde_json::from_value(serde_json::json!({
+        "query": "{ me { id } }",
+        "operationName": "Me",
+        "extensions": {
+            "persistedQuery": { "version": 1, "sha256Hash": "abc" },
+        },
+    }))
+    .unwrap();
+    assert_eq!(request.operation.as_deref(), Some("Me"));
+    assert_eq!(
+        request.extension_as::<PersistedQuery>("persistedQuery"),
+        Some(PersistedQuery {
+            version: 1,
+            sha256_hash: "abc".to_string(),
+        })
+    );
+    assert_eq!(request.extension_as::<String>("persistedQuery"), None);
+
+    let request = request.extension("trace", true);
+    assert_eq!(
+        request.get_extension("trace"),
+        Some(&ConstValue::Boolean(true))
+    );
+    let value = serde_json::to_value(&request).unwrap();
+    assert_eq!(value["extensions"]["trace"], serde_json::json!(true));
+}
+
+#[test]
+fn unreachable_field() {
+    let schema = ComposedSchema::parse(
+        r#"
+        type Query {
+            product: Product @resolve(service: "products")
+        }
+
+        type Product @owner(service: "products") {
+            upc: String!
+            stock: Int @resolve(service: "inventory")
+        }
+        "#,
+    )
+    .unwrap();
+    let document = parser::parse_query("{ product { upc s: stock } }").unwrap();
+    let response = PlanBuilder::new(&schema, document).plan().unwrap_err();
+
+    assert_eq!(response.errors.len(), 1);
+    let error = &response.errors[0];
+    assert_eq!(error.code(), Some(ErrorCode::PlanFailed.as_str()));
+    assert_eq!(
+        error.path,
+        vec![
+            ConstValue::String("product".to_string()),
+            ConstValue::String("s".to_string()),
+        ]
+    );
+    assert!(error.message.contains("\"Product.stock\""));
+    assert!(error.message.contains("no keys are defined"));
+}
+
+#[test]
+fn nested_list_paths() {
+    let schema = ComposedSchema::parse(
+        r#"
+        type Query {
+            grid: [[Product!]]! @resolve(service: "products")
+        }
+
+        type Product
+        @owner(service: "products")
+        @key(fields: "upc" service: "products")
+        @key(fields: "upc" service: "inventory")
+        {
+            upc: String!
+            stock: Int @resolve(service: "inventory")
+        }
+        "#,
+    )
+    .unwrap();
+    let document = parser::parse_query("{ grid { upc stock } }").unwrap();
+    let node = serde_json::to_value(&PlanBuilder::new(&schema, document).plan().unwrap()).unwrap();
+
+    assert_eq!(node["nodes"][1]["type"], "flatten");
+    assert_eq!(node["nodes"][1]["path"], "[[grid]]");
+}
+
+#[test]
+fn strict_planning() {
+    let schema = ComposedSchema::parse(
+        r#"
+        type Query {
+            product: Product @resolve(service: "products")
+            version: String
+        }
+
+        type Product @owner(service: "products") {
+            upc: String!
+        }
+        "#,
+    )
+    .unwrap();
+    let query = "{ product { upc } v: version }";
+
+    let document = parser::parse_query(query).unwrap();
+    assert!(PlanBuilder::new(&schema, document).plan().is_ok());
+
+    let document = parser::parse_query(query).unwrap();
+    let response = PlanBuilder::new(&schema, document)
+        .strict(true)
+        .plan()
+        .unwrap_err();
+    assert_eq!(response.errors.len(), 1);
+    let error = &response.errors[0];
+    assert_eq!(error.code(), Some(ErrorCode::PlanFailed.as_str()));
+    assert_eq!(error.path, vec![ConstValue::String("v".to_string())]);
+    assert_eq!(error.locations.len(), 1);
+    assert!(error.message.contains("\"Query.version\""));
+}
+
+#[test]
+fn multiple_operations() {
+    let schema = ComposedSchema::parse(include_str!("test.graphql")).unwrap();
+    let query = r#"
+        query A { me { id } }
+        query B { me { username } }
+        subscription C { users { id } }
+    "#;
+
+    let document = parser::parse_query(query).unwrap();
+    let node = PlanBuilder::new(&schema, document)
+        .operation_name("C")
+        .plan()
+        .unwrap();
+    assert_eq!(
+        serde_json::to_value(&node).unwrap()["type"],
+        serde_json::json!("subscribe")
+    );
+
+    for operation_name in [None, Some("D")].iter() {
+        let document = parser::parse_query(query).unwrap();
+        let mut builder = PlanBuilder::new(&schema, document);
+        if let Some(operation_name) = operation_name {
+            builder = builder.operation_name(*operation_name);
+        }
+        let response = builder.plan().unwrap_err();
+        assert_eq!(response.errors.len(), 1);
+        assert_eq!(
+            response.errors[0].code(),
+            Some(ErrorCode::ValidationFailed.as_str())
+        );
+    }
+}
+
+#[test]
+fn introspection_variable_defaults() {
+    let schema = ComposedSchema::parse(include_str!("test.graphql")).unwrap();
+    let document =
+        parser::parse_query(r#"query($name: String! = "User") { __type(name: $name) { name } }"#)
+            .unwrap();
+    let node = PlanBuilder::new(&schema, document).plan().unwrap();
+    assert!(serde_json::to_string(&node).unwrap().contains("\"User\""));
+}
+
+#[test]
+fn requires_arguments() {
+    let schema = ComposedSchema::parse(
+        r#"
+        type Query {
+            topProducts(currency: String!): [Product!]! @resolve(service: "products")
+        }
+
+        type Product
+        @owner(service: "products")
+        @key(fields: "upc" service: "shipping")
+        {
+            upc: String!
+            price(currency: String!): Int!
+            dimensions: Dimensions!
+            shippingEstimate: Int @resolve(service: "shipping") @requires(fields: "price(currency: $currency) dimensions { size weight }")
+            insurance: Int @resolve(service: "shipping") @requires(fields: "dimensions { weight }")
+        }
+
+        type Dimensions {
+            size: Int!
+            weight: Int!
+        }
+        "#,
+    )
+    .unwrap();
+    let document = parser::parse_query(
+        r#"query($currency: String!) {
+            topProducts(currency: $currency) { upc shippingEstimate insurance }
+        }"#,
+    )
+    .unwrap();
+    let node = PlanBuilder::new(&schema, document)
+        .variables(serde_json::from_value(serde_json::json!({ "currency": "EUR" })).unwrap())
+        .plan()
+        .unwrap();
+    let node = serde_json::to_value(&node).unwrap();
+
+    let fetch = &node["nodes"][0];
+    let prefix = node["nodes"][1]["prefix"].as_u64().unwrap();
+    assert_eq!(fetch["variables"], serde_json::json!({ "currency": "EUR" }));
+    let query = fetch["query"].as_str().unwrap();
+    assert!(query.starts_with("query($currency: String!)"));
+    assert!(query.contains(&format!(
+        "__key{}_price:price(currency: $currency) __key{}_dimensions:dimensions{{size weight}} __key{}_dimensions:dimensions{{weight}}",
+        prefix, prefix, prefix
+    )));
+    assert!(node["nodes"][1]["query"]
+        .as_str()
+        .unwrap()
+        .contains("... on Product { shippingEstimate insurance }"));
+
+    let document =
+        parser::parse_query(r#"{ topProducts(currency: "EUR") { upc shippingEstimate } }"#)
+            .unwrap();
+    let response = PlanBuilder::new(&schema, document).plan().unwrap_err();
+    assert_eq!(response.errors.len(), 1);
+    assert_eq!(
+        response.errors[0].message,
+        "Field \"Product.shippingEstimate\" requires variable \"$currency\", which is not defined by the operation."
+    );
+    assert_eq!(
+        response.errors[0].code(),
+        Some(ErrorCode::PlanFailed.as_str())
+    );
+}
+
+/// Plans the documents derived from the test cases by removing a line or
+/// truncating them, none of them must panic.
+#[test]
+fn mutated_documents() {
+    let schema = ComposedSchema::parse(include_str!("test.graphql")).unwrap();
+
+    for entry in fs::read_dir("./tests").unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some("txt") {
+            continue;
+        }
+
+        let data = fs::read_to_string(&path).unwrap();
+        for graphql in data.split("---").step_by(3) {
+            let lines = graphql.lines().collect::<Vec<_>>();
+            let mut documents = (0..lines.len())
+                .map(|index| {
+                    let mut lines = lines.clone();
+                    lines.remove(index);
+                    lines.join("\n")
+                })
+                .collect::<Vec<_>>();
+            documents.extend(
+                graphql
+                    .char_indices()
+                    .map(|(index, _)| graphql[..index].to_string()),
+            );
+
+            for document in documents {
+                if let Ok(document) = parser::parse_query(&document) {
+                    let _ = PlanBuilder::new(&schema, document).plan();
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn operation_signatures() {
+    let document = parser::parse_query(
+        r#"
+        query A($filters: [ProductFilter!] = [{ upcs: ["1"] }]) {
+            me { username: id ...UserFields }
+            searchProducts(filters: $filters) { upc }
+            user(id: "secret") { id @include(if: true) }
+        }
+        query B { myName }
+        fragment UserFields on User { reviews { body } }
+        fragment Unused on User { id }
+        "#,
+    )
+    .unwrap();
+    assert_eq!(
+        operation_signature(&document, Some("A")).as_deref(),
+        Some("query A($filters:[ProductFilter!]=[]){me{id ...UserFields} searchProducts(filters:$filters){upc} user(id:\"\"){id@include(if:true)}} fragment UserFields on User{reviews{body}}")
+    );
+    assert_eq!(
+        operation_signature(&document, Some("B")).as_deref(),
+        Some("query B{myName}")
+    );
+    assert_eq!(operation_signature(&document, None), None);
+
+    let schema = ComposedSchema::parse(include_str!("test.graphql")).unwrap();
+    let document = parser::parse_query(r#"{ user(id: "secret") { id } }"#).unwrap();
+    let node = PlanBuilder::new(&schema, document).plan().unwrap();
+    let fetch = match &node {
+        RootNode::Query(PlanNode::Fetch(fetch)) => fetch,
+        _ => panic!("expected a fetch node"),
+    };
+    assert!(fetch.query.to_string().contains(r#"user(id: "secret")"#));
+    assert!(fetch.query.signature().contains(r#"user(id: "")"#));
+}
+
+#[test]
+fn typename_aliases() {
+    let schema = ComposedSchema::parse(include_str!("test.graphql")).unwrap();
+    let document = parser::parse_query("{ me { kind: __typename id } }").unwrap();
+    let node = PlanBuilder::new(&schema, document).plan().unwrap();
+    let fetch = match &node {
+        RootNode::Query(PlanNode::Fetch(fetch)) => fetch,
+        _ => panic!("expected a fetch node"),
+    };
+    assert!(fetch.query.to_string().contains("kind:__typename"));
+}
+
+#[test]
+fn normalized_documents() {
+    let document = parser::parse_query(
+        r#"
+        # comment
+        fragment UserFields on User { reviews(first: 2) { body } }
+        query B { myName }
+        query A {
+            me { username: id ...UserFields }
+            user(id: "secret  value") { id @include(if: true) }
+        }
+        "#,
+    )
+    .unwrap();
+    assert_eq!(
+        normalized_document(&document),
+        "query A{me{username:id ...UserFields} user(id:\"secret  value\"){id@include(if:true)}} query B{myName} fragment UserFields on User{reviews(first:2){body}}"
+    );
+    assert_ne!(
+        normalized_document(&parser::parse_query("{ a # b\n }").unwrap()),
+        normalized_document(&parser::parse_query("{ a #\n b }").unwrap())
+    );
+}
+
+#[test]
+fn gateway_fields() {
+    let mut schema = ComposedSchema::parse(include_str!("test.graphql")).unwrap();
+    schema
+        .extend_gateway_fields(
+            parser::parse_schema(
+                r#"
+                extend type User {
+                    login: String @gateway(from: "username")
+                    label: String @gateway(concat: ["id", "username"], separator: "-")
+                    apiVersion: String @gateway(value: "v2")
+                }
+                "#,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+
+    let document =
+        parser::parse_query("{ me { login version: apiVersion reviews { author { label } } } }")
+            .unwrap();
+    let node = PlanBuilder::new(&schema, document).plan().unwrap();
+    let node = serde_json::to_value(&node).unwrap();
+    let nodes = node["nodes"].as_array().unwrap();
+    let accounts = nodes[0]["query"].as_str().unwrap();
+    assert!(accounts.contains("__gateway0_0_login:username"));
+    assert!(accounts.contains("__gateway2_0_version:__typename"));
+    let entities = nodes[2]["query"].as_str().unwrap();
+    assert_eq!(nodes[2]["service"], "accounts");
+    assert!(entities.contains("__gateway1_0_label:id __gateway1_1_label:username"));
+
+    let invalid =
+        parser::parse_schema(r#"extend type User { nickname: String @gateway(from: "reviews") }"#)
+            .unwrap();
+    assert!(schema.extend_gateway_fields(invalid).is_err());
+}
+
+#[test]
+fn shaping_directives() {
+    let mut schema = ComposedSchema::parse(include_str!("test.graphql")).unwrap();
+    let query = r#"query($n: Int!) { topProducts @first(n: $n) @skip(if: false) { upc } }"#;
+    let plan = |schema: &ComposedSchema| {
+        let document = parser::parse_query(query).unwrap();
+        PlanBuilder::new(schema, document)
+            .variables(serde_json::from_value(serde_json::json!({ "n": 2 })).unwrap())
+            .plan()
+            .map(|node| serde_json::to_value(&node).unwrap())
+    };
+
+    assert!(plan(&schema).is_err());
+
+    schema
+        .extend_shaping_directives(&[ShapingDirective::First])
+        .unwrap();
+    let node = plan(&schema).unwrap();
+    assert_eq!(
+        node["query"],
+        "query\n{ topProducts @skip(if: false) { upc } }"
+    );
+    assert!(node.get("variables").is_none());
+}
+
+#[test]
+fn entity_fetch_operation_type() {
+    fn collect_queries(
+        node: &serde_json::Value,
+        fetches: &mut Vec<String>,
+        flattens: &mut Vec<String>,
+    ) {
+        match node {
+            serde_json::Value::Object(object) => {
+                if let Some(query) = object.get("query").and_then(|query| query.as_str()) {
+                    if object.contains_key("path") {
+                        flattens.push(query.to_string());
+                    } else {
+                        fetches.push(query.to_string());
+                    }
+                }
+                object
+                    .values()
+                    .for_each(|value| collect_queries(value, fetches, flattens));
+            }
+            serde_json::Value::Array(values) => values
+                .iter()
+                .for_each(|value| collect_queries(value, fetches, flattens)),
+            _ => {}
+        }
+    }
+
+    let schema = ComposedSchema::parse(include_str!("test.graphql")).unwrap();
+    for (query, operation_type) in [
+        ("{ me { id reviews { body } } }", "query"),
+        (
+            r#"mutation { createUser(username: "a") { id reviews { body } } }"#,
+            "mutation",
+        ),
+        (
+            "subscription { users { id reviews { body } } }",
+            "subscription",
+        ),
+    ] {
+        let document = parser::parse_query(query).unwrap();
+        let node =
+            serde_json::to_value(&PlanBuilder::new(&schema, document).plan().unwrap()).unwrap();
+        let mut fetches = Vec::new();
+        let mut flattens = Vec::new();
+        collect_queries(&node, &mut fetches, &mut flattens);
+
+        assert_eq!(fetches.len(), 1, "{}", query);
+        assert!(fetches[0].starts_with(operation_type), "{}", fetches[0]);
+        assert_eq!(flattens.len(), 1, "{}", query);
+        assert!(
+            flattens[0].starts_with("query($representations:[_Any!]!)"),
+            "{}",
+            flattens[0]
+        );
+    }
+}
+
+#[test]
+fn defer_and_stream() {
+    let schema = ComposedSchema::parse(include_str!("test.graphql")).unwrap();
+    let document = parser::parse_query(
+        r#"{
+            me {
+                id
+                ... @defer(label: "details") { username reviews { body } }
+            }
+            topProducts @stream(initialCount: 2) { upc }
+        }"#,
+    )
+    .unwrap();
+    let plan = PlanBuilder::new(&schema, document).plan().unwrap();
+    let defer = match &plan {
+        RootNode::Query(PlanNode::Defer(defer)) => defer,
+        _ => panic!("Not a deferred plan."),
+    };
+
+    let primary = serde_json::to_string(&defer.primary).unwrap();
+    assert!(!primary.contains("username"), "{}", primary);
+    assert!(!primary.contains("@defer"), "{}", primary);
+    assert!(!primary.contains("@stream"), "{}", primary);
+
+    assert_eq!(defer.deferred.len(), 1);
+    assert_eq!(defer.deferred[0].label.as_deref(), Some("details"));
+    assert_eq!(defer.deferred[0].path.to_string(), "me");
+    let deferred = serde_json::to_string(&defer.deferred[0].node).unwrap();
+    assert!(deferred.contains("username"), "{}", deferred);
+    assert!(deferred.contains("reviews"), "{}", deferred);
+
+    assert_eq!(defer.streams.len(), 1);
+    assert_eq!(defer.streams[0].path.to_string(), "[topProducts]");
+    assert_eq!(defer.streams[0].initial_count, 2);
+
+    // A disabled `@defer` is planned as a regular fragment.
+    let document =
+        parser::parse_query(r#"{ me { id ... @defer(if: false) { username } } }"#).unwrap();
+    let plan = PlanBuilder::new(&schema, document).plan().unwrap();
+    assert!(!matches!(plan, RootNode::Query(PlanNode::Defer(_))));
+}
@@ -1,5 +1,6 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::ops::Deref;
+use std::sync::Arc;
 
 use indexmap::{IndexMap, IndexSet};
 use parser::types::{
@@ -8,12 +9,36 @@ use parser::types::{
     SelectionSet, ServiceDocument, Type, TypeDefinition, TypeSystemDefinition, UnionType,
 };
 use parser::{Positioned, Result};
-use value::{ConstValue, Name};
+use value::{ConstValue, Name, Value};
 
+use crate::federation_v2::{is_linked_definition, normalize_federation_v2};
 use crate::type_ext::TypeExt;
-use crate::CombineError;
-
-#[derive(Debug, Eq, PartialEq)]
+use crate::{CombineError, GatewayField, GatewayFieldResolver, ShapingDirective};
+
+/// Fields added to the query type of the services by the federation
+/// specification, they are not part of the composed schema.
+pub const FEDERATION_FIELDS: &[&str] = &["_entities", "_service"];
+
+/// Types added to the services by the federation specification.
+const FEDERATION_TYPES: &[&str] = &["_Any", "_Entity", "_FieldSet", "_Service"];
+
+/// Directives of the federation specification, they are not part of the
+/// composed schema.
+const FEDERATION_DIRECTIVES: &[&str] = &[
+    "external",
+    "extends",
+    "key",
+    "provides",
+    "requires",
+    "shareable",
+    "inaccessible",
+    "override",
+    "link",
+    "composeDirective",
+    "interfaceObject",
+];
+
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub enum Deprecation {
     NoDeprecated,
     Deprecated { reason: Option<String> },
@@ -34,7 +59,7 @@ impl Deprecation {
     }
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub struct MetaField {
     pub description: Option<String>,
     pub name: Name,
@@ -57,25 +82,63 @@ pub enum TypeKind {
     InputObject,
 }
 
-#[derive(Debug, Eq, PartialEq)]
-pub struct KeyFields(IndexMap<Name, KeyFields>);
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct KeyFields {
+    fields: IndexMap<Name, KeyFields>,
+    arguments: IndexMap<Name, Vec<(Name, Value)>>,
+}
 
 impl Deref for KeyFields {
     type Target = IndexMap<Name, KeyFields>;
 
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.fields
+    }
+}
+
+impl KeyFields {
+    /// Returns the arguments of a field, such as `currency: $currency` in
+    /// `@requires(fields: "price(currency: $currency)")`.
+    ///
+    /// The variables refer to the variables of the client operation.
+    pub fn arguments(&self, field: &str) -> &[(Name, Value)] {
+        self.arguments
+            .get(field)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Returns the variables referenced by the arguments of the fields, and
+    /// of their subfields.
+    pub fn variables(&self) -> Vec<&Name> {
+        fn collect<'a>(value: &'a Value, names: &mut Vec<&'a Name>) {
+            match value {
+                Value::Variable(name) => names.push(name),
+                Value::List(values) => values.iter().for_each(|value| collect(value, names)),
+                Value::Object(object) => object.values().for_each(|value| collect(value, names)),
+                _ => {}
+            }
+        }
+
+        let mut names = Vec::new();
+        for (field_name, children) in self.iter() {
+            for (_, value) in self.arguments(field_name) {
+                collect(value, &mut names);
+            }
+            names.extend(children.variables());
+        }
+        names
     }
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub struct MetaEnumValue {
     pub description: Option<String>,
     pub value: Name,
     pub deprecation: Deprecation,
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub struct MetaInputValue {
     pub description: Option<String>,
     pub name: Name,
@@ -83,7 +146,7 @@ pub struct MetaInputValue {
     pub default_value: Option<ConstValue>,
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub struct MetaType {
     pub description: Option<String>,
     pub name: Name,
@@ -156,7 +219,7 @@ impl MetaType {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct MetaDirective {
     pub name: Name,
     pub description: Option<String>,
@@ -164,13 +227,21 @@ pub struct MetaDirective {
     pub arguments: IndexMap<Name, MetaInputValue>,
 }
 
-#[derive(Debug, Default)]
+/// A composed schema.
+///
+/// The types are shared with `Arc`, so cloning the schema is cheap and the
+/// derived schemas, such as the public schema, share the unchanged types.
+#[derive(Debug, Clone, Default)]
 pub struct ComposedSchema {
     pub query_type: Option<Name>,
     pub mutation_type: Option<Name>,
     pub subscription_type: Option<Name>,
-    pub types: IndexMap<Name, MetaType>,
-    pub directives: HashMap<Name, MetaDirective>,
+    pub types: IndexMap<Name, Arc<MetaType>>,
+    pub directives: IndexMap<Name, MetaDirective>,
+    /// The fields resolved by the gateway, also defined in their types.
+    pub gateway_fields: Vec<GatewayField>,
+    /// The directives evaluated by the gateway, also defined in `directives`.
+    pub shaping_directives: Vec<ShapingDirective>,
 }
 
 impl ComposedSchema {
@@ -189,10 +260,15 @@ impl ComposedSchema {
                 TypeSystemDefinition::Type(type_definition) => {
                     composed_schema.types.insert(
                         type_definition.node.name.node.clone(),
-                        convert_type_definition(type_definition.node),
+                        Arc::new(convert_type_definition(type_definition.node)),
+                    );
+                }
+                TypeSystemDefinition::Directive(directive_definition) => {
+                    composed_schema.directives.insert(
+                        directive_definition.node.name.node.clone(),
+                        convert_directive_definition(directive_definition.node),
                     );
                 }
-                TypeSystemDefinition::Directive(_) => {}
             }
         }
 
@@ -200,17 +276,30 @@ impl ComposedSchema {
         composed_schema
     }
 
+    /// Composes the schema from the SDL of the services.
+    ///
+    /// The federation 2 subgraphs, which link the federation specification
+    /// with `extend schema @link(...)`, don't extend the types of an owner:
+    /// the first service defining a type owns it, and the others extend it.
+    /// A field can then be defined by several services if all of them mark it
+    /// `@shareable` or use it in a key, the first definition resolves it
+    /// unless another one has `@override(from: ...)`. The `@inaccessible`
+    /// types and fields are removed from the composed schema.
     pub fn combine(
         federation_sdl: impl IntoIterator<Item = (String, ServiceDocument)>,
     ) -> ::std::result::Result<Self, CombineError> {
         let mut composed_schema = ComposedSchema::default();
         let root_objects = &["Query", "Mutation", "Subscription"];
+        let mut shareable_fields = HashSet::new();
+        let mut overridden_fields = HashMap::new();
+        let mut hidden_types = HashSet::new();
+        let mut hidden_fields = HashSet::new();
 
         for obj in root_objects {
             let name = Name::new(obj);
             composed_schema.types.insert(
                 name.clone(),
-                MetaType {
+                Arc::new(MetaType {
                     description: None,
                     name,
                     kind: TypeKind::Object,
@@ -221,7 +310,7 @@ impl ComposedSchema {
                     possible_types: Default::default(),
                     enum_values: Default::default(),
                     input_fields: Default::default(),
-                },
+                }),
             );
         }
 
@@ -229,42 +318,73 @@ impl ComposedSchema {
         composed_schema.mutation_type = Some(Name::new("Mutation"));
         composed_schema.subscription_type = Some(Name::new("Subscription"));
 
-        for (service, doc) in federation_sdl {
+        for (service, mut doc) in federation_sdl {
+            let is_v2 = normalize_federation_v2(&mut doc);
             for definition in doc.definitions {
                 match definition {
+                    TypeSystemDefinition::Type(type_definition)
+                        if FEDERATION_TYPES.contains(&type_definition.node.name.node.as_str())
+                            || is_linked_definition(&type_definition.node.name.node) => {}
                     TypeSystemDefinition::Type(type_definition) => {
+                        if is_v2 && has_directive(&type_definition.node.directives, "inaccessible")
+                        {
+                            hidden_types.insert(type_definition.node.name.node.to_string());
+                        }
                         if let types::TypeKind::Object(ObjectType { implements, fields }) =
                             type_definition.node.kind
                         {
                             let name = type_definition.node.name.node.clone();
-                            let description = type_definition
-                                .node
-                                .description
-                                .map(|description| description.node);
-                            let is_extend =
-                                type_definition.node.extend || root_objects.contains(&&*name);
-                            let meta_type = composed_schema
-                                .types
-                                .entry(name.clone())
-                                .or_insert_with(|| MetaType {
-                                    description,
-                                    name,
-                                    kind: TypeKind::Object,
-                                    owner: None,
-                                    keys: Default::default(),
-                                    implements: Default::default(),
-                                    fields: Default::default(),
-                                    possible_types: Default::default(),
-                                    enum_values: Default::default(),
-                                    input_fields: Default::default(),
-                                });
-
+                            let mut is_extend = type_definition.node.extend
+                                || root_objects.contains(&&*name)
+                                || (is_v2
+                                    && has_directive(&type_definition.node.directives, "extends"));
+                            let is_shareable = is_v2
+                                && has_directive(&type_definition.node.directives, "shareable");
+                            let meta_type = Arc::make_mut(
+                                composed_schema
+                                    .types
+                                    .entry(name.clone())
+                                    .or_insert_with(|| {
+                                        Arc::new(MetaType {
+                                            description: None,
+                                            name,
+                                            kind: TypeKind::Object,
+                                            owner: None,
+                                            keys: Default::default(),
+                                            implements: Default::default(),
+                                            fields: Default::default(),
+                                            possible_types: Default::default(),
+                                            enum_values: Default::default(),
+                                            input_fields: Default::default(),
+                                        })
+                                    }),
+                            );
+
+                            if is_v2
+                                && meta_type
+                                    .owner
+                                    .as_ref()
+                                    .map_or(false, |owner| owner != &service)
+                            {
+                                is_extend = true;
+                            }
                             if !is_extend {
                                 meta_type.owner = Some(service.clone());
                             };
+                            if meta_type.description.is_none() {
+                                meta_type.description = type_definition
+                                    .node
+                                    .description
+                                    .map(|description| description.node);
+                            }
 
                             for directive in type_definition.node.directives {
-                                if directive.node.name.node.as_str() == "key" {
+                                let resolvable = !matches!(
+                                    get_argument(&directive.node.arguments, "resolvable")
+                                        .map(|value| &value.node),
+                                    Some(ConstValue::Boolean(false))
+                                );
+                                if directive.node.name.node.as_str() == "key" && resolvable {
                                     if let Some(fields) =
                                         get_argument_str(&directive.node.arguments, "fields")
                                     {
@@ -288,7 +408,12 @@ impl ComposedSchema {
                                 .extend(implements.into_iter().map(|implement| implement.node));
 
                             for field in fields {
-                                if is_extend {
+                                if meta_type.name == "Query"
+                                    && FEDERATION_FIELDS.contains(&field.node.name.node.as_str())
+                                {
+                                    continue;
+                                }
+                                if is_extend || is_v2 {
                                     let is_external =
                                         has_directive(&field.node.directives, "external");
                                     if is_external {
@@ -296,36 +421,103 @@ impl ComposedSchema {
                                     }
                                 }
 
-                                if meta_type.fields.contains_key(&field.node.name.node) {
-                                    return Err(CombineError::FieldConflicted {
-                                        type_name: type_definition.node.name.node.to_string(),
-                                        field_name: field.node.name.node.to_string(),
-                                    });
+                                let field_key =
+                                    (meta_type.name.clone(), field.node.name.node.clone());
+                                let overrides = if is_v2 {
+                                    field
+                                        .node
+                                        .directives
+                                        .iter()
+                                        .find(|directive| directive.node.name.node == "override")
+                                        .and_then(|directive| {
+                                            get_argument_str(&directive.node.arguments, "from")
+                                        })
+                                        .map(|from| from.node.to_string())
+                                } else {
+                                    None
+                                };
+                                let is_shareable = is_v2
+                                    && (is_shareable
+                                        || has_directive(&field.node.directives, "shareable")
+                                        || meta_type.keys.get(&service).map_or(false, |keys| {
+                                            keys.iter()
+                                                .any(|key| key.contains_key(&field.node.name.node))
+                                        }));
+                                if is_v2 && has_directive(&field.node.directives, "inaccessible") {
+                                    hidden_fields
+                                        .insert(format!("{}.{}", field_key.0, field_key.1));
+                                }
+
+                                if let Some(existing) = meta_type.fields.get(&field.node.name.node)
+                                {
+                                    let existing_service =
+                                        existing.service.as_ref().or(meta_type.owner.as_ref());
+                                    if overrides.is_some() && overrides.as_ref() == existing_service
+                                    {
+                                        // The definition of this service replaces the existing one.
+                                    } else if overridden_fields.get(&field_key) == Some(&service)
+                                        || (is_shareable && shareable_fields.contains(&field_key))
+                                    {
+                                        continue;
+                                    } else {
+                                        return Err(CombineError::FieldConflicted {
+                                            type_name: type_definition.node.name.node.to_string(),
+                                            field_name: field.node.name.node.to_string(),
+                                        });
+                                    }
                                 }
                                 let mut meta_field = convert_field_definition(field.node);
                                 if is_extend {
                                     meta_field.service = Some(service.clone());
                                 }
+                                if is_shareable {
+                                    shareable_fields.insert(field_key.clone());
+                                }
+                                if let Some(from) = overrides {
+                                    overridden_fields.insert(field_key, from);
+                                }
                                 meta_type.fields.insert(meta_field.name.clone(), meta_field);
                             }
                         } else {
                             let meta_type = convert_type_definition(type_definition.node);
-                            if let Some(meta_type2) = composed_schema.types.get(&meta_type.name) {
-                                if meta_type2 != &meta_type {
-                                    return Err(CombineError::DefinitionConflicted {
-                                        type_name: meta_type.name.to_string(),
-                                    });
+                            match composed_schema.types.get_mut(&meta_type.name) {
+                                Some(meta_type2) => {
+                                    if without_descriptions(meta_type2)
+                                        != without_descriptions(&meta_type)
+                                    {
+                                        return Err(CombineError::DefinitionConflicted {
+                                            type_name: meta_type.name.to_string(),
+                                        });
+                                    }
+                                    merge_descriptions(Arc::make_mut(meta_type2), meta_type);
+                                }
+                                None => {
+                                    composed_schema
+                                        .types
+                                        .insert(meta_type.name.clone(), Arc::new(meta_type));
                                 }
                             }
-                            composed_schema
-                                .types
-                                .insert(meta_type.name.clone(), meta_type);
                         }
                     }
+                    // The schema extensions only link specifications, such as
+                    // `extend schema @link(...)` in the federation 2 subgraphs.
+                    TypeSystemDefinition::Schema(schema_definition)
+                        if schema_definition.node.extend => {}
                     TypeSystemDefinition::Schema(_schema_definition) => {
                         return Err(CombineError::SchemaIsNotAllowed)
                     }
-                    TypeSystemDefinition::Directive(_directive_definition) => {}
+                    TypeSystemDefinition::Directive(directive_definition) => {
+                        let name = &directive_definition.node.name.node;
+                        if !FEDERATION_DIRECTIVES.contains(&name.as_str())
+                            && !is_linked_definition(name)
+                            && !composed_schema.directives.contains_key(name)
+                        {
+                            composed_schema.directives.insert(
+                                name.clone(),
+                                convert_directive_definition(directive_definition.node),
+                            );
+                        }
+                    }
                 }
             }
         }
@@ -344,6 +536,161 @@ impl ComposedSchema {
             }
         }
 
+        check_dependency_cycles(&composed_schema)?;
+        finish_schema(&mut composed_schema);
+        if !hidden_types.is_empty() || !hidden_fields.is_empty() {
+            composed_schema = composed_schema.without(&hidden_types, &hidden_fields);
+        }
+        Ok(composed_schema)
+    }
+
+    /// Creates a schema from a supergraph composed by Apollo tools such as `rover`.
+    ///
+    /// The services are taken from the `join__Graph` enum, the owners, keys and
+    /// resolvers from the `@join__owner`, `@join__type` and `@join__field` directives.
+    pub fn from_supergraph(document: ServiceDocument) -> ::std::result::Result<Self, CombineError> {
+        let mut composed_schema = ComposedSchema::default();
+        let mut graphs = HashMap::new();
+
+        for definition in &document.definitions {
+            if let TypeSystemDefinition::Type(type_definition) = definition {
+                if let types::TypeKind::Enum(EnumType { values }) = &type_definition.node.kind {
+                    if type_definition.node.name.node.as_str() == "join__Graph" {
+                        for value in values {
+                            let name = value
+                                .node
+                                .directives
+                                .iter()
+                                .find(|directive| {
+                                    directive.node.name.node.as_str() == "join__graph"
+                                })
+                                .and_then(|directive| {
+                                    get_argument_str(&directive.node.arguments, "name")
+                                })
+                                .map(|name| name.node.to_string())
+                                .unwrap_or_else(|| value.node.value.node.to_lowercase());
+                            graphs.insert(value.node.value.node.clone(), name);
+                        }
+                    }
+                }
+            }
+        }
+
+        let get_graph =
+            |directive: &ConstDirective| match get_argument(&directive.arguments, "graph")
+                .map(|value| &value.node)
+            {
+                Some(ConstValue::Enum(graph)) => {
+                    graphs
+                        .get(graph)
+                        .cloned()
+                        .ok_or_else(|| CombineError::UnknownGraph {
+                            graph: graph.to_string(),
+                        })
+                }
+                _ => Err(CombineError::UnknownGraph {
+                    graph: String::new(),
+                }),
+            };
+
+        for definition in document.definitions {
+            match definition {
+                TypeSystemDefinition::Schema(schema) => {
+                    convert_schema_definition(&mut composed_schema, schema.node);
+                }
+                TypeSystemDefinition::Type(type_definition) => {
+                    let name = type_definition.node.name.node.as_str();
+                    if name.starts_with("join__") || name.starts_with("core__") {
+                        continue;
+                    }
+
+                    let mut owner = None;
+                    let mut keys = Vec::new();
+                    for directive in &type_definition.node.directives {
+                        match directive.node.name.node.as_str() {
+                            "join__owner" => owner = Some(get_graph(&directive.node)?),
+                            "join__type" => {
+                                let service = get_graph(&directive.node)?;
+                                if let Some(fields) =
+                                    get_argument_str(&directive.node.arguments, "key")
+                                        .and_then(|key| parse_fields(key.node))
+                                {
+                                    keys.push((service, convert_key_fields(fields)));
+                                }
+                            }
+                            _ => {}
+                        }
+                    }
+
+                    let mut join_fields = HashMap::new();
+                    if let types::TypeKind::Object(ObjectType { fields, .. })
+                    | types::TypeKind::Interface(InterfaceType { fields, .. }) =
+                        &type_definition.node.kind
+                    {
+                        for field in fields {
+                            if let Some(directive) =
+                                field.node.directives.iter().find(|directive| {
+                                    directive.node.name.node.as_str() == "join__field"
+                                })
+                            {
+                                join_fields.insert(
+                                    field.node.name.node.clone(),
+                                    (
+                                        Some(get_graph(&directive.node)?),
+                                        get_argument_str(&directive.node.arguments, "requires")
+                                            .and_then(|fields| parse_fields(fields.node))
+                                            .map(convert_key_fields),
+                                        get_argument_str(&directive.node.arguments, "provides")
+                                            .and_then(|fields| parse_fields(fields.node))
+                                            .map(convert_key_fields),
+                                    ),
+                                );
+                            }
+                        }
+                    }
+
+                    let mut meta_type = convert_type_definition(type_definition.node);
+                    meta_type.owner = owner;
+                    for (service, key) in keys {
+                        meta_type.keys.entry(service).or_default().push(key);
+                    }
+                    for (name, (service, requires, provides)) in join_fields {
+                        if let Some(meta_field) = meta_type.fields.get_mut(&name) {
+                            if service != meta_type.owner {
+                                meta_field.service = service;
+                            }
+                            meta_field.requires = requires;
+                            meta_field.provides = provides;
+                        }
+                    }
+                    composed_schema
+                        .types
+                        .insert(meta_type.name.clone(), Arc::new(meta_type));
+                }
+                TypeSystemDefinition::Directive(directive_definition) => {
+                    let name = directive_definition.node.name.node.as_str();
+                    if name.starts_with("join__") || name.starts_with("core__") || name == "core" {
+                        continue;
+                    }
+                    composed_schema.directives.insert(
+                        directive_definition.node.name.node.clone(),
+                        convert_directive_definition(directive_definition.node),
+                    );
+                }
+            }
+        }
+
+        if composed_schema.query_type.is_none() {
+            composed_schema.query_type = Some(Name::new("Query"));
+            if composed_schema.types.contains_key("Mutation") {
+                composed_schema.mutation_type = Some(Name::new("Mutation"));
+            }
+            if composed_schema.types.contains_key("Subscription") {
+                composed_schema.subscription_type = Some(Name::new("Subscription"));
+            }
+        }
+
+        check_dependency_cycles(&composed_schema)?;
         finish_schema(&mut composed_schema);
         Ok(composed_schema)
     }
@@ -372,11 +719,212 @@ impl ComposedSchema {
             BaseType::Named(name) => name.as_str(),
             BaseType::List(ty) => return self.get_type(ty),
         };
-        self.types.get(name)
+        self.types.get(name).map(Arc::as_ref)
+    }
+
+    #[inline]
+    pub fn get_type_by_name(&self, name: &str) -> Option<&MetaType> {
+        self.types.get(name).map(Arc::as_ref)
     }
 
     pub fn concrete_type_by_name(&self, ty: &Type) -> Option<&MetaType> {
-        self.types.get(ty.concrete_typename())
+        self.get_type_by_name(ty.concrete_typename())
+    }
+
+    /// Returns the index and the definition of a field resolved by the gateway.
+    pub fn gateway_field(
+        &self,
+        type_name: &str,
+        field_name: &str,
+    ) -> Option<(usize, &GatewayField)> {
+        self.gateway_fields
+            .iter()
+            .enumerate()
+            .find(|(_, field)| field.type_name == type_name && field.field_name == field_name)
+    }
+
+    /// Adds the fields of the object type extensions of a document, resolved
+    /// by the gateway as described by their `@gateway` directive.
+    ///
+    /// The directive has one of the `value`, `from` and `concat` arguments,
+    /// see [`GatewayField`]. The source fields must be leaf fields of the same
+    /// type without required arguments.
+    pub fn extend_gateway_fields(
+        &mut self,
+        document: ServiceDocument,
+    ) -> ::std::result::Result<(), CombineError> {
+        for definition in document.definitions {
+            let type_definition = match definition {
+                TypeSystemDefinition::Type(type_definition) => type_definition.node,
+                _ => continue,
+            };
+            let type_name = type_definition.name.node;
+            let fields = match type_definition.kind {
+                types::TypeKind::Object(ObjectType { fields, .. }) => fields,
+                _ => continue,
+            };
+            for field in fields {
+                let field = field.node;
+                let field_name = field.name.node.clone();
+                let invalid = |reason: &str| CombineError::InvalidGatewayField {
+                    type_name: type_name.to_string(),
+                    field_name: field_name.to_string(),
+                    reason: reason.to_string(),
+                };
+
+                let ty = self
+                    .types
+                    .get(&type_name)
+                    .ok_or_else(|| invalid("the type is not defined"))?;
+                if ty.kind != TypeKind::Object
+                    || [
+                        Some(self.query_type()),
+                        self.mutation_type(),
+                        self.subscription_type(),
+                    ]
+                    .contains(&Some(type_name.as_str()))
+                {
+                    return Err(invalid("the type is not an object type or is a root type"));
+                }
+                if ty.fields.contains_key(&field_name) {
+                    return Err(invalid("the field is already defined"));
+                }
+                let directive = field
+                    .directives
+                    .iter()
+                    .find(|directive| directive.node.name.node == "gateway")
+                    .ok_or_else(|| invalid("the @gateway directive is missing"))?;
+                let resolver =
+                    convert_gateway_resolver(&directive.node.arguments).ok_or_else(|| {
+                        invalid(
+                            "the @gateway directive requires one of 'value', 'from' and 'concat'",
+                        )
+                    })?;
+                for source in resolver.sources() {
+                    let is_leaf = ty.fields.get(source).map_or(false, |source| {
+                        self.get_type(&source.ty).map_or(false, MetaType::is_leaf)
+                            && source.arguments.values().all(|argument| {
+                                argument.ty.nullable || argument.default_value.is_some()
+                            })
+                    });
+                    if !is_leaf {
+                        return Err(invalid(&format!(
+                            "the source field '{}' is not a leaf field without required arguments",
+                            source
+                        )));
+                    }
+                }
+
+                let meta_field = convert_field_definition(field);
+                Arc::make_mut(self.types.get_mut(&type_name).unwrap())
+                    .fields
+                    .insert(field_name.clone(), meta_field);
+                self.gateway_fields.push(GatewayField {
+                    type_name: type_name.clone(),
+                    field_name,
+                    resolver,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds the definitions of the directives evaluated by the gateway.
+    ///
+    /// A directive can't be added if the services define a directive with
+    /// the same name.
+    pub fn extend_shaping_directives(
+        &mut self,
+        directives: &[ShapingDirective],
+    ) -> ::std::result::Result<(), CombineError> {
+        for directive in directives {
+            if self.shaping_directives.contains(directive) {
+                continue;
+            }
+            let definition = directive.definition();
+            if self.directives.contains_key(&definition.name) {
+                return Err(CombineError::ShapingDirectiveConflicted {
+                    directive: directive.to_string(),
+                });
+            }
+            self.directives.insert(definition.name.clone(), definition);
+            self.shaping_directives.push(*directive);
+        }
+        Ok(())
+    }
+
+    /// Returns `true` if the directive is evaluated by the gateway.
+    pub fn is_shaping_directive(&self, name: &str) -> bool {
+        self.shaping_directives
+            .iter()
+            .any(|directive| directive.name() == name)
+    }
+
+    /// Returns a copy of the schema without the hidden types and fields, such
+    /// as the public part of the graph.
+    ///
+    /// The fields are in the form `Type.field`. The fields and arguments of a
+    /// hidden type are removed too, as well as its membership in unions and
+    /// interfaces. The types without hidden parts are shared with this schema.
+    pub fn without(&self, types: &HashSet<String>, fields: &HashSet<String>) -> ComposedSchema {
+        let is_hidden_field = |type_name: &Name, name: &Name, field: &MetaField| {
+            fields.contains(&format!("{}.{}", type_name, name))
+                || types.contains(field.ty.concrete_typename())
+                || field
+                    .arguments
+                    .values()
+                    .any(|argument| types.contains(argument.ty.concrete_typename()))
+        };
+
+        let mut schema = self.clone();
+        schema
+            .types
+            .retain(|name, _| !types.contains(name.as_str()));
+        for ty in schema.types.values_mut() {
+            let changed = ty
+                .fields
+                .iter()
+                .any(|(name, field)| is_hidden_field(&ty.name, name, field))
+                || ty
+                    .input_fields
+                    .values()
+                    .any(|field| types.contains(field.ty.concrete_typename()))
+                || ty
+                    .possible_types
+                    .iter()
+                    .chain(&ty.implements)
+                    .any(|name| types.contains(name.as_str()));
+            if !changed {
+                continue;
+            }
+
+            let ty = Arc::make_mut(ty);
+            let type_name = ty.name.clone();
+            ty.fields
+                .retain(|name, field| !is_hidden_field(&type_name, name, field));
+            ty.input_fields
+                .retain(|_, field| !types.contains(field.ty.concrete_typename()));
+            ty.possible_types
+                .retain(|name| !types.contains(name.as_str()));
+            ty.implements.retain(|name| !types.contains(name.as_str()));
+        }
+        schema.directives.retain(|_, directive| {
+            directive
+                .arguments
+                .values()
+                .all(|argument| !types.contains(argument.ty.concrete_typename()))
+        });
+        if let Some(mutation_type) = &schema.mutation_type {
+            if !schema.types.contains_key(mutation_type) {
+                schema.mutation_type = None;
+            }
+        }
+        if let Some(subscription_type) = &schema.subscription_type {
+            if !schema.types.contains_key(subscription_type) {
+                schema.subscription_type = None;
+            }
+        }
+        schema
     }
 }
 
@@ -412,6 +960,35 @@ fn parse_fields(fields: &str) -> Option<SelectionSet> {
         })
 }
 
+fn convert_gateway_resolver(
+    arguments: &[(Positioned<Name>, Positioned<ConstValue>)],
+) -> Option<GatewayFieldResolver> {
+    let value = get_argument(arguments, "value");
+    let from = get_argument_str(arguments, "from");
+    let concat = get_argument(arguments, "concat");
+    match (value, from, concat) {
+        (Some(value), None, None) => Some(GatewayFieldResolver::Constant(value.node.clone())),
+        (None, Some(from), None) => Some(GatewayFieldResolver::Rename(Name::new(from.node))),
+        (None, None, Some(concat)) => {
+            let fields = match &concat.node {
+                ConstValue::List(fields) => fields
+                    .iter()
+                    .map(|field| match field {
+                        ConstValue::String(field) => Some(Name::new(field)),
+                        _ => None,
+                    })
+                    .collect::<Option<Vec<_>>>()?,
+                _ => return None,
+            };
+            let separator = get_argument_str(arguments, "separator")
+                .map(|separator| separator.node.to_string())
+                .unwrap_or_default();
+            Some(GatewayFieldResolver::Concat { fields, separator })
+        }
+        _ => None,
+    }
+}
+
 fn convert_schema_definition(
     composed_schema: &mut ComposedSchema,
     schema_definition: SchemaDefinition,
@@ -573,22 +1150,30 @@ fn convert_field_definition(definition: types::FieldDefinition) -> MetaField {
 }
 
 fn convert_key_fields(selection_set: SelectionSet) -> KeyFields {
-    KeyFields(
-        selection_set
-            .items
-            .into_iter()
-            .filter_map(|field| {
-                if let Selection::Field(field) = field.node {
-                    Some((
-                        field.node.name.node,
-                        convert_key_fields(field.node.selection_set.node),
-                    ))
-                } else {
-                    None
-                }
-            })
-            .collect(),
-    )
+    let mut key_fields = KeyFields {
+        fields: IndexMap::new(),
+        arguments: IndexMap::new(),
+    };
+    for selection in selection_set.items {
+        if let Selection::Field(field) = selection.node {
+            let field = field.node;
+            if !field.arguments.is_empty() {
+                key_fields.arguments.insert(
+                    field.name.node.clone(),
+                    field
+                        .arguments
+                        .into_iter()
+                        .map(|(name, value)| (name.node, value.node))
+                        .collect(),
+                );
+            }
+            key_fields.fields.insert(
+                field.name.node,
+                convert_key_fields(field.selection_set.node),
+            );
+        }
+    }
+    key_fields
 }
 
 fn convert_input_value_definition(arg: parser::types::InputValueDefinition) -> MetaInputValue {
@@ -600,7 +1185,9 @@ fn convert_input_value_definition(arg: parser::types::InputValueDefinition) -> M
     }
 }
 
-fn convert_directive_definition(directive_definition: DirectiveDefinition) -> MetaDirective {
+pub(crate) fn convert_directive_definition(
+    directive_definition: DirectiveDefinition,
+) -> MetaDirective {
     MetaDirective {
         name: directive_definition.name.node,
         description: directive_definition
@@ -641,6 +1228,161 @@ fn has_directive(directives: &[Positioned<ConstDirective>], name: &str) -> bool
         .any(|directive| directive.node.name.node.as_str() == name)
 }
 
+/// Returns a copy of a type without the descriptions of the type, its fields,
+/// arguments, enum values and input fields.
+fn without_descriptions(ty: &MetaType) -> MetaType {
+    let mut ty = ty.clone();
+    ty.description = None;
+    for field in ty.fields.values_mut() {
+        field.description = None;
+        for argument in field.arguments.values_mut() {
+            argument.description = None;
+        }
+    }
+    for value in ty.enum_values.values_mut() {
+        value.description = None;
+    }
+    for field in ty.input_fields.values_mut() {
+        field.description = None;
+    }
+    ty
+}
+
+/// Sets the missing descriptions of a type from another definition of the same
+/// type, so that a description given by any service is kept.
+fn merge_descriptions(ty: &mut MetaType, other: MetaType) {
+    fn merge(description: &mut Option<String>, other: Option<String>) {
+        if description.is_none() {
+            *description = other;
+        }
+    }
+
+    merge(&mut ty.description, other.description);
+    for (name, other_field) in other.fields {
+        if let Some(field) = ty.fields.get_mut(&name) {
+            merge(&mut field.description, other_field.description);
+            for (name, other_argument) in other_field.arguments {
+                if let Some(argument) = field.arguments.get_mut(&name) {
+                    merge(&mut argument.description, other_argument.description);
+                }
+            }
+        }
+    }
+    for (name, other_value) in other.enum_values {
+        if let Some(value) = ty.enum_values.get_mut(&name) {
+            merge(&mut value.description, other_value.description);
+        }
+    }
+    for (name, other_field) in other.input_fields {
+        if let Some(field) = ty.input_fields.get_mut(&name) {
+            merge(&mut field.description, other_field.description);
+        }
+    }
+}
+
+type FieldCoordinate<'a> = (&'a str, &'a str);
+
+/// Checks that the fields resolved through entity fetches do not depend on
+/// themselves via `@key` and `@requires`.
+fn check_dependency_cycles(
+    composed_schema: &ComposedSchema,
+) -> ::std::result::Result<(), CombineError> {
+    let mut dependencies: HashMap<FieldCoordinate, Vec<FieldCoordinate>> = HashMap::new();
+
+    for ty in composed_schema.types.values() {
+        for field in ty.fields.values() {
+            let coordinate = (ty.name.as_str(), field.name.as_str());
+            let mut deps = Vec::new();
+
+            if let Some(service) = &field.service {
+                let keys = ty
+                    .keys
+                    .get(service)
+                    .or_else(|| ty.owner.as_ref().and_then(|owner| ty.keys.get(owner)))
+                    .and_then(|keys| keys.first());
+                if let Some(keys) = keys {
+                    collect_key_fields(composed_schema, ty, keys, &mut deps);
+                    deps.retain(|dep| *dep != coordinate);
+                }
+            }
+
+            if let Some(requires) = &field.requires {
+                collect_key_fields(composed_schema, ty, requires, &mut deps);
+            }
+
+            if !deps.is_empty() {
+                dependencies.insert(coordinate, deps);
+            }
+        }
+    }
+
+    let mut visited = HashSet::new();
+    for ty in composed_schema.types.values() {
+        for field in ty.fields.values() {
+            let coordinate = (ty.name.as_str(), field.name.as_str());
+            if let Some(cycle) =
+                find_cycle(coordinate, &dependencies, &mut visited, &mut Vec::new())
+            {
+                return Err(CombineError::DependencyCycle {
+                    cycle: cycle
+                        .iter()
+                        .map(|(type_name, field_name)| format!("{}.{}", type_name, field_name))
+                        .collect::<Vec<_>>()
+                        .join(" -> "),
+                });
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn collect_key_fields<'a>(
+    composed_schema: &'a ComposedSchema,
+    ty: &'a MetaType,
+    fields: &'a KeyFields,
+    deps: &mut Vec<FieldCoordinate<'a>>,
+) {
+    for (name, children) in fields.iter() {
+        deps.push((ty.name.as_str(), name.as_str()));
+        if children.is_empty() {
+            continue;
+        }
+        if let Some(child_type) = ty
+            .fields
+            .get(name)
+            .and_then(|field| composed_schema.concrete_type_by_name(&field.ty))
+        {
+            collect_key_fields(composed_schema, child_type, children, deps);
+        }
+    }
+}
+
+fn find_cycle<'a>(
+    coordinate: FieldCoordinate<'a>,
+    dependencies: &HashMap<FieldCoordinate<'a>, Vec<FieldCoordinate<'a>>>,
+    visited: &mut HashSet<FieldCoordinate<'a>>,
+    path: &mut Vec<FieldCoordinate<'a>>,
+) -> Option<Vec<FieldCoordinate<'a>>> {
+    if let Some(idx) = path.iter().position(|item| *item == coordinate) {
+        let mut cycle = path[idx..].to_vec();
+        cycle.push(coordinate);
+        return Some(cycle);
+    }
+    if !visited.insert(coordinate) {
+        return None;
+    }
+
+    path.push(coordinate);
+    for dep in dependencies.get(&coordinate).into_iter().flatten() {
+        if let Some(cycle) = find_cycle(*dep, dependencies, visited, path) {
+            return Some(cycle);
+        }
+    }
+    path.pop();
+    None
+}
+
 fn finish_schema(composed_schema: &mut ComposedSchema) {
     for definition in parser::parse_schema(include_str!("builtin.graphql"))
         .unwrap()
@@ -652,7 +1394,7 @@ fn finish_schema(composed_schema: &mut ComposedSchema) {
                 let type_definition = convert_type_definition(type_definition.node);
                 composed_schema
                     .types
-                    .insert(type_definition.name.clone(), type_definition);
+                    .insert(type_definition.name.clone(), Arc::new(type_definition));
             }
             TypeSystemDefinition::Directive(directive_definition) => {
                 composed_schema.directives.insert(
@@ -664,13 +1406,17 @@ fn finish_schema(composed_schema: &mut ComposedSchema) {
         }
     }
 
-    if let Some(query_type) = composed_schema.types.get_mut(
-        composed_schema
-            .query_type
-            .as_ref()
-            .map(|name| name.as_str())
-            .unwrap_or("Query"),
-    ) {
+    if let Some(query_type) = composed_schema
+        .types
+        .get_mut(
+            composed_schema
+                .query_type
+                .as_ref()
+                .map(|name| name.as_str())
+                .unwrap_or("Query"),
+        )
+        .map(Arc::make_mut)
+    {
         let name = Name::new("__type");
         query_type.fields.insert(
             name.clone(),
@@ -728,7 +1474,102 @@ fn finish_schema(composed_schema: &mut ComposedSchema) {
     }
     for (name, types) in possible_types {
         if let Some(ty) = composed_schema.types.get_mut(&name) {
-            ty.possible_types = types;
+            Arc::make_mut(ty).possible_types = types;
+        }
+    }
+
+    let types = &composed_schema.types;
+    composed_schema.directives.retain(|_, directive| {
+        directive
+            .arguments
+            .values()
+            .all(|argument| types.contains_key(argument.ty.concrete_typename()))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ACCOUNTS: &str = r#"
+        type Query {
+            me: User
+        }
+
+        type User @key(fields: "id") {
+            id: ID!
+            name: String
+        }
+    "#;
+
+    fn combine(services: &[(&str, &str)]) -> ::std::result::Result<ComposedSchema, CombineError> {
+        ComposedSchema::combine(
+            services
+                .iter()
+                .map(|(name, sdl)| (name.to_string(), parser::parse_schema(sdl).unwrap())),
+        )
+    }
+
+    #[test]
+    fn key_requires_cycle() {
+        // `User.score` is fetched from `reviews` with its key `handle`, which
+        // `inventory` resolves with `@requires(fields: "score")`.
+        let reviews = r#"
+            extend type User @key(fields: "handle") {
+                handle: String @external
+                score: Int
+            }
+        "#;
+        let inventory = r#"
+            extend type User @key(fields: "id") {
+                id: ID! @external
+                score: Int @external
+                handle: String @requires(fields: "score")
+            }
+        "#;
+        match combine(&[
+            ("accounts", ACCOUNTS),
+            ("reviews", reviews),
+            ("inventory", inventory),
+        ]) {
+            Err(CombineError::DependencyCycle { cycle }) => {
+                assert!(
+                    cycle == "User.score -> User.handle -> User.score"
+                        || cycle == "User.handle -> User.score -> User.handle",
+                    "{}",
+                    cycle
+                );
+            }
+            Err(err) => panic!("unexpected error: {}", err),
+            Ok(_) => panic!("the cycle is not detected"),
         }
     }
+
+    #[test]
+    fn requires_chain() {
+        // `User.rank` requires `User.score`, which requires `User.name` of the
+        // owner, without any cycle.
+        let reviews = r#"
+            extend type User @key(fields: "id") {
+                id: ID! @external
+                name: String @external
+                score: Int @requires(fields: "name")
+            }
+        "#;
+        let inventory = r#"
+            extend type User @key(fields: "id") {
+                id: ID! @external
+                score: Int @external
+                rank: Int @requires(fields: "score")
+            }
+        "#;
+        let schema = combine(&[
+            ("accounts", ACCOUNTS),
+            ("reviews", reviews),
+            ("inventory", inventory),
+        ])
+        .unwrap();
+        let user = schema.get_type_by_name("User").unwrap();
+        assert!(user.fields.contains_key("rank"));
+    }
 }
@@ -13,4 +13,20 @@ pub enum CombineError {
         type_name: String,
         field_name: String,
     },
+
+    #[error("Unknown graph '{graph}' in the supergraph.")]
+    UnknownGraph { graph: String },
+
+    #[error("Entity dependency cycle detected: {cycle}.")]
+    DependencyCycle { cycle: String },
+
+    #[error("Gateway field '{type_name}.{field_name}' is invalid: {reason}")]
+    InvalidGatewayField {
+        type_name: String,
+        field_name: String,
+        reason: String,
+    },
+
+    #[error("Shaping directive '{directive}' conflicts with a directive of the services.")]
+    ShapingDirectiveConflicted { directive: String },
 }
@@ -0,0 +1,133 @@
+use std::collections::HashMap;
+
+use parser::types::{
+    ConstDirective, EnumType, InputObjectType, InterfaceType, ObjectType, ServiceDocument,
+    TypeKind, TypeSystemDefinition,
+};
+use parser::Positioned;
+use value::{ConstValue, Name};
+
+/// Directives of the federation 2 specification, by their canonical names.
+const FEDERATION_V2_DIRECTIVES: &[&str] = &[
+    "key",
+    "requires",
+    "provides",
+    "external",
+    "extends",
+    "shareable",
+    "inaccessible",
+    "override",
+    "tag",
+    "composeDirective",
+    "interfaceObject",
+];
+
+fn get_argument<'a>(directive: &'a ConstDirective, name: &str) -> Option<&'a ConstValue> {
+    directive
+        .arguments
+        .iter()
+        .find(|(argument, _)| argument.node == name)
+        .map(|(_, value)| &value.node)
+}
+
+/// Returns `true` if the document is a federation 2 subgraph, which links the
+/// federation specification with `extend schema @link(url: ...)`.
+///
+/// The federation directives imported under another name, or used with their
+/// namespace such as `@federation__key`, are renamed to their canonical names.
+pub(crate) fn normalize_federation_v2(document: &mut ServiceDocument) -> bool {
+    let mut renames = HashMap::new();
+    let mut is_v2 = false;
+
+    let links = document
+        .definitions
+        .iter()
+        .filter_map(|definition| match definition {
+            TypeSystemDefinition::Schema(schema) => Some(&schema.node.directives),
+            _ => None,
+        })
+        .flatten()
+        .filter(|directive| directive.node.name.node == "link");
+    for link in links {
+        match get_argument(&link.node, "url") {
+            Some(ConstValue::String(url)) if url.contains("/federation/v2") => {}
+            _ => continue,
+        }
+        is_v2 = true;
+
+        let namespace = match get_argument(&link.node, "as") {
+            Some(ConstValue::String(namespace)) => namespace.as_str(),
+            _ => "federation",
+        };
+        for name in FEDERATION_V2_DIRECTIVES {
+            renames.insert(format!("{}__{}", namespace, name), name.to_string());
+        }
+
+        let imports = match get_argument(&link.node, "import") {
+            Some(ConstValue::List(imports)) => imports,
+            _ => continue,
+        };
+        for import in imports {
+            let (name, alias) = match import {
+                ConstValue::String(name) => (name, name),
+                ConstValue::Object(import) => match (import.get("name"), import.get("as")) {
+                    (Some(ConstValue::String(name)), Some(ConstValue::String(alias))) => {
+                        (name, alias)
+                    }
+                    (Some(ConstValue::String(name)), None) => (name, name),
+                    _ => continue,
+                },
+                _ => continue,
+            };
+            if let (Some(name), Some(alias)) = (name.strip_prefix('@'), alias.strip_prefix('@')) {
+                if FEDERATION_V2_DIRECTIVES.contains(&name) {
+                    renames.insert(alias.to_string(), name.to_string());
+                }
+            }
+        }
+    }
+    if !is_v2 {
+        return false;
+    }
+
+    let rename = |directives: &mut Vec<Positioned<ConstDirective>>| {
+        for directive in directives {
+            if let Some(name) = renames.get(directive.node.name.node.as_str()) {
+                directive.node.name.node = Name::new(name);
+            }
+        }
+    };
+    for definition in &mut document.definitions {
+        let type_definition = match definition {
+            TypeSystemDefinition::Type(type_definition) => &mut type_definition.node,
+            _ => continue,
+        };
+        rename(&mut type_definition.directives);
+        match &mut type_definition.kind {
+            TypeKind::Object(ObjectType { fields, .. })
+            | TypeKind::Interface(InterfaceType { fields, .. }) => {
+                for field in fields {
+                    rename(&mut field.node.directives);
+                }
+            }
+            TypeKind::Enum(EnumType { values }) => {
+                for value in values {
+                    rename(&mut value.node.directives);
+                }
+            }
+            TypeKind::InputObject(InputObjectType { fields }) => {
+                for field in fields {
+                    rename(&mut field.node.directives);
+                }
+            }
+            _ => {}
+        }
+    }
+    true
+}
+
+/// Returns `true` if a type or directive is defined by the `@link` or the
+/// federation 2 specifications, such as `link__Import`.
+pub(crate) fn is_linked_definition(name: &str) -> bool {
+    name.starts_with("link__") || name.starts_with("federation__")
+}
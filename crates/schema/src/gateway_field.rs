@@ -0,0 +1,71 @@
+use value::{ConstValue, Name};
+
+/// How the gateway resolves a field that no service defines.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GatewayFieldResolver {
+    /// Always the same value.
+    Constant(ConstValue),
+    /// The value of another field of the same object.
+    Rename(Name),
+    /// The values of other fields of the same object joined with a separator,
+    /// the nulls are skipped.
+    Concat {
+        fields: Vec<Name>,
+        separator: String,
+    },
+}
+
+/// A field added to an object type by the gateway, such as a compatibility
+/// shim during a migration.
+///
+/// It is defined in an SDL extension with the `@gateway` directive:
+///
+/// ```graphql
+/// extend type User {
+///     apiVersion: String @gateway(value: "v2")
+///     login: String @gateway(from: "username")
+///     fullName: String @gateway(concat: ["firstName", "lastName"], separator: " ")
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct GatewayField {
+    pub type_name: Name,
+    pub field_name: Name,
+    pub resolver: GatewayFieldResolver,
+}
+
+impl GatewayFieldResolver {
+    /// Returns the fields of the object the value is computed from.
+    pub fn sources(&self) -> &[Name] {
+        match self {
+            GatewayFieldResolver::Constant(_) => &[],
+            GatewayFieldResolver::Rename(field) => std::slice::from_ref(field),
+            GatewayFieldResolver::Concat { fields, .. } => fields,
+        }
+    }
+
+    /// Computes the value from the values of the source fields, in the order
+    /// of [`sources`](Self::sources).
+    pub fn resolve(&self, values: Vec<ConstValue>) -> ConstValue {
+        match self {
+            GatewayFieldResolver::Constant(value) => value.clone(),
+            GatewayFieldResolver::Rename(_) => values.into_iter().next().unwrap_or_default(),
+            GatewayFieldResolver::Concat { separator, .. } => {
+                let parts = values
+                    .into_iter()
+                    .filter_map(|value| match value {
+                        ConstValue::Null => None,
+                        ConstValue::String(s) => Some(s),
+                        ConstValue::Enum(name) => Some(name.to_string()),
+                        value => Some(value.to_string()),
+                    })
+                    .collect::<Vec<_>>();
+                if parts.is_empty() {
+                    ConstValue::Null
+                } else {
+                    ConstValue::String(parts.join(separator))
+                }
+            }
+        }
+    }
+}
@@ -2,13 +2,18 @@
 
 mod composed_schema;
 mod error;
+mod federation_v2;
+mod gateway_field;
+mod shaping_directive;
 mod type_ext;
 mod value_ext;
 
 pub use composed_schema::{
-    ComposedSchema, Deprecation, KeyFields, MetaEnumValue, MetaField, MetaInputValue, MetaType,
-    TypeKind,
+    ComposedSchema, Deprecation, KeyFields, MetaDirective, MetaEnumValue, MetaField,
+    MetaInputValue, MetaType, TypeKind, FEDERATION_FIELDS,
 };
 pub use error::CombineError;
+pub use gateway_field::{GatewayField, GatewayFieldResolver};
+pub use shaping_directive::ShapingDirective;
 pub use type_ext::TypeExt;
 pub use value_ext::ValueExt;
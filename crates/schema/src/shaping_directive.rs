@@ -0,0 +1,78 @@
+use std::fmt::{self, Display, Formatter};
+
+use parser::types::TypeSystemDefinition;
+
+use crate::composed_schema::convert_directive_definition;
+use crate::MetaDirective;
+
+/// A directive evaluated by the gateway on the merged data of a response,
+/// instead of being forwarded to the services.
+///
+/// The directives only apply to the list fields, they are ignored on the
+/// other fields:
+///
+/// ```graphql
+/// {
+///     users @sortBy(field: "name") @first(n: 10) {
+///         name
+///     }
+/// }
+/// ```
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum ShapingDirective {
+    /// `@first(n: Int!)` keeps the first `n` items of the list.
+    First,
+    /// `@sortBy(field: String!, desc: Boolean = false)` sorts the items of the
+    /// list by the value of one of their fields, the items without a
+    /// comparable value are put last.
+    SortBy,
+}
+
+impl ShapingDirective {
+    pub const ALL: &'static [ShapingDirective] =
+        &[ShapingDirective::First, ShapingDirective::SortBy];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ShapingDirective::First => "first",
+            ShapingDirective::SortBy => "sortBy",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|directive| directive.name() == name)
+    }
+
+    pub(crate) fn definition(&self) -> MetaDirective {
+        let sdl = match self {
+            ShapingDirective::First => {
+                r#""Keeps the first `n` items of the list, evaluated by the gateway."
+                directive @first(n: Int!) on FIELD"#
+            }
+            ShapingDirective::SortBy => {
+                r#""Sorts the items of the list by one of their fields, evaluated by the gateway."
+                directive @sortBy(field: String!, desc: Boolean = false) on FIELD"#
+            }
+        };
+        match parser::parse_schema(sdl)
+            .unwrap()
+            .definitions
+            .into_iter()
+            .next()
+        {
+            Some(TypeSystemDefinition::Directive(definition)) => {
+                convert_directive_definition(definition.node)
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl Display for ShapingDirective {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "@{}", self.name())
+    }
+}
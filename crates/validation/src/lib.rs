@@ -6,10 +6,14 @@ mod test_harness;
 
 mod error;
 mod rules;
+mod scalar_validator;
 mod suggestion;
 mod utils;
 mod visitor;
 
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
 use graphgate_schema::ComposedSchema;
 use parser::types::ExecutableDocument;
 use value::Variables;
@@ -17,6 +21,21 @@ use value::Variables;
 use visitor::{visit, Visitor, VisitorContext, VisitorNil};
 
 pub use error::RuleError;
+pub use scalar_validator::ScalarValidator;
+
+/// Limits of the selections in a document.
+#[derive(Debug, Clone, Default)]
+pub struct ValidationLimits {
+    /// Maximum number of aliases of the same field in a selection set.
+    pub max_aliases_per_field: Option<usize>,
+
+    /// Maximum number of identical selections in a selection set.
+    pub max_repeated_selections: Option<usize>,
+
+    /// Maximum time spent validating the document, the remaining selections
+    /// are not visited once it is exceeded.
+    pub max_duration: Option<Duration>,
+}
 
 macro_rules! rules {
     ($($rule:ident),*) => {
@@ -28,8 +47,13 @@ pub fn check_rules(
     composed_schema: &ComposedSchema,
     document: &ExecutableDocument,
     variables: &Variables,
+    limits: &ValidationLimits,
+    scalar_validators: &HashMap<String, ScalarValidator>,
 ) -> Vec<RuleError> {
     let mut ctx = VisitorContext::new(composed_schema, document, variables);
+    ctx.deadline = limits
+        .max_duration
+        .map(|max_duration| Instant::now() + max_duration);
     let mut visitor = rules!(
         ArgumentsOfCorrectType,
         DefaultValuesOfCorrectType,
@@ -47,11 +71,29 @@ pub fn check_rules(
         PossibleFragmentSpreads,
         ProvidedNonNullArguments,
         ScalarLeafs,
+        SubscriptionRootFields,
         UniqueArgumentNames,
         UniqueVariableNames,
         VariablesAreInputTypes,
         VariableInAllowedPosition
-    );
+    )
+    .with(rules::AliasLimits::new(limits))
+    .with(rules::ScalarValues::new(scalar_validators));
+    visit(&mut visitor, &mut ctx, &document);
+    ctx.errors
+}
+
+/// Checks the rules that depend on the values of the variables, for a
+/// document that already passed [`check_rules`] against the same schema.
+pub fn check_variable_rules(
+    composed_schema: &ComposedSchema,
+    document: &ExecutableDocument,
+    variables: &Variables,
+    scalar_validators: &HashMap<String, ScalarValidator>,
+) -> Vec<RuleError> {
+    let mut ctx = VisitorContext::new(composed_schema, document, variables);
+    let mut visitor =
+        rules!(ArgumentsOfCorrectType).with(rules::ScalarValues::new(scalar_validators));
     visit(&mut visitor, &mut ctx, &document);
     ctx.errors
 }
@@ -0,0 +1,220 @@
+use std::collections::HashMap;
+
+use parser::types::{Field, SelectionSet};
+use parser::{Pos, Positioned};
+
+use crate::{ValidationLimits, Visitor, VisitorContext};
+
+#[derive(Default)]
+struct SelectionCounts<'a> {
+    aliases: HashMap<&'a str, (Pos, usize)>,
+    selections: HashMap<(&'a str, &'a str, String), (Pos, usize)>,
+}
+
+#[derive(Default)]
+pub struct AliasLimits<'a> {
+    max_aliases_per_field: Option<usize>,
+    max_repeated_selections: Option<usize>,
+    stack: Vec<SelectionCounts<'a>>,
+}
+
+impl<'a> AliasLimits<'a> {
+    pub fn new(limits: &ValidationLimits) -> Self {
+        Self {
+            max_aliases_per_field: limits.max_aliases_per_field,
+            max_repeated_selections: limits.max_repeated_selections,
+            stack: Vec::new(),
+        }
+    }
+}
+
+impl<'a> Visitor<'a> for AliasLimits<'a> {
+    fn enter_selection_set(
+        &mut self,
+        _ctx: &mut VisitorContext<'a>,
+        _selection_set: &'a Positioned<SelectionSet>,
+    ) {
+        self.stack.push(SelectionCounts::default());
+    }
+
+    fn exit_selection_set(
+        &mut self,
+        ctx: &mut VisitorContext<'a>,
+        _selection_set: &'a Positioned<SelectionSet>,
+    ) {
+        let counts = match self.stack.pop() {
+            Some(counts) => counts,
+            None => return,
+        };
+
+        if let Some(limit) = self.max_aliases_per_field {
+            for (name, (pos, count)) in counts.aliases {
+                if count > limit {
+                    ctx.report_error(
+                        vec![pos],
+                        format!(
+                            "Field \"{}\" is aliased {} times, but the limit is {}.",
+                            name, count, limit
+                        ),
+                    );
+                }
+            }
+        }
+
+        if let Some(limit) = self.max_repeated_selections {
+            for ((response_key, _, _), (pos, count)) in counts.selections {
+                if count > limit {
+                    ctx.report_error(
+                        vec![pos],
+                        format!(
+                            "Selection \"{}\" is repeated {} times, but the limit is {}.",
+                            response_key, count, limit
+                        ),
+                    );
+                }
+            }
+        }
+    }
+
+    fn enter_field(&mut self, _ctx: &mut VisitorContext<'a>, field: &'a Positioned<Field>) {
+        let counts = match self.stack.last_mut() {
+            Some(counts) => counts,
+            None => return,
+        };
+        let name = field.node.name.node.as_str();
+
+        if self.max_aliases_per_field.is_some() && field.node.alias.is_some() {
+            counts.aliases.entry(name).or_insert((field.pos, 0)).1 += 1;
+        }
+
+        if self.max_repeated_selections.is_some() {
+            let arguments = field
+                .node
+                .arguments
+                .iter()
+                .map(|(name, value)| format!("{}:{}", name.node, value.node))
+                .collect::<Vec<_>>()
+                .join(",");
+            counts
+                .selections
+                .entry((field.node.response_key().node.as_str(), name, arguments))
+                .or_insert((field.pos, 0))
+                .1 += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    pub fn factory<'a>() -> AliasLimits<'a> {
+        AliasLimits::new(&ValidationLimits {
+            max_aliases_per_field: Some(2),
+            max_repeated_selections: Some(2),
+            max_duration: None,
+        })
+    }
+
+    #[test]
+    fn aliases_within_limit() {
+        expect_passes_rule!(
+            factory,
+            r#"
+          {
+            dog {
+              a: name
+              b: name
+              nickname
+            }
+          }
+        "#,
+        );
+    }
+
+    #[test]
+    fn aliases_in_different_selection_sets() {
+        expect_passes_rule!(
+            factory,
+            r#"
+          {
+            dog {
+              a: name
+              b: name
+            }
+            cat {
+              a: name
+              b: name
+            }
+          }
+        "#,
+        );
+    }
+
+    #[test]
+    fn too_many_aliases() {
+        expect_fails_rule!(
+            factory,
+            r#"
+          {
+            dog {
+              a: name
+              b: name(surname: true)
+              c: name(surname: false)
+            }
+          }
+        "#,
+        );
+    }
+
+    #[test]
+    fn repeated_selections_within_limit() {
+        expect_passes_rule!(
+            factory,
+            r#"
+          {
+            dog {
+              name
+              name
+              name(surname: true)
+            }
+          }
+        "#,
+        );
+    }
+
+    #[test]
+    fn too_many_repeated_selections() {
+        expect_fails_rule!(
+            factory,
+            r#"
+          {
+            dog {
+              name
+              name
+              name
+            }
+          }
+        "#,
+        );
+    }
+
+    #[test]
+    fn no_limits() {
+        expect_passes_rule!(
+            AliasLimits::default,
+            r#"
+          {
+            dog {
+              a: name
+              b: name
+              c: name
+              name
+              name
+              name
+            }
+          }
+        "#,
+        );
+    }
+}
@@ -1,7 +1,7 @@
 use parser::types::Field;
 use parser::Positioned;
 
-use graphgate_schema::TypeKind;
+use graphgate_schema::{TypeKind, FEDERATION_FIELDS};
 
 use crate::suggestion::make_suggestion;
 use crate::{Visitor, VisitorContext};
@@ -19,6 +19,18 @@ impl<'a> Visitor<'a> for FieldsOnCorrectType {
             }
 
             if !parent_type.fields.contains_key(&field.node.name.node) {
+                if parent_type.name == ctx.schema.query_type()
+                    && FEDERATION_FIELDS.contains(&field.node.name.node.as_str())
+                {
+                    ctx.report_error(
+                        vec![field.pos],
+                        format!(
+                            "Field \"{}\" of the federation specification is not served by the gateway, send it to the services directly.",
+                            field.node.name
+                        ),
+                    );
+                    return;
+                }
                 ctx.report_error(
                     vec![field.pos],
                     format!(
@@ -316,4 +328,18 @@ mod tests {
         "#,
         );
     }
+
+    #[test]
+    fn federation_fields() {
+        expect_fails_rule!(
+            factory,
+            r#"
+          {
+            _entities(representations: [{__typename: "Dog", name: "a"}]) {
+              ... on Dog { name }
+            }
+          }
+        "#,
+        );
+    }
 }
@@ -1,3 +1,4 @@
+mod alias_limits;
 mod arguments_of_correct_type;
 mod default_values_of_correct_type;
 mod fields_on_correct_type;
@@ -14,11 +15,14 @@ mod overlapping_fields_can_be_merged;
 mod possible_fragment_spreads;
 mod provided_non_null_arguments;
 mod scalar_leafs;
+mod scalar_values;
+mod subscription_root_fields;
 mod unique_argument_names;
 mod unique_variable_names;
 mod variables_are_input_types;
 mod variables_in_allowed_position;
 
+pub use alias_limits::AliasLimits;
 pub use arguments_of_correct_type::ArgumentsOfCorrectType;
 pub use default_values_of_correct_type::DefaultValuesOfCorrectType;
 pub use fields_on_correct_type::FieldsOnCorrectType;
@@ -35,6 +39,8 @@ pub use overlapping_fields_can_be_merged::OverlappingFieldsCanBeMerged;
 pub use possible_fragment_spreads::PossibleFragmentSpreads;
 pub use provided_non_null_arguments::ProvidedNonNullArguments;
 pub use scalar_leafs::ScalarLeafs;
+pub use scalar_values::ScalarValues;
+pub use subscription_root_fields::SubscriptionRootFields;
 pub use unique_argument_names::UniqueArgumentNames;
 pub use unique_variable_names::UniqueVariableNames;
 pub use variables_are_input_types::VariablesAreInputTypes;
@@ -28,7 +28,7 @@ impl<'a> Visitor<'a> for PossibleFragmentSpreads<'a> {
             .get(&*fragment_spread.node.fragment_name.node)
         {
             if let Some(current_type) = ctx.current_type() {
-                if let Some(on_type) = ctx.schema.types.get(*fragment_type) {
+                if let Some(on_type) = ctx.schema.get_type_by_name(*fragment_type) {
                     if !current_type.type_overlap(on_type) {
                         ctx.report_error(
                             vec![fragment_spread.pos],
@@ -55,7 +55,7 @@ impl<'a> Visitor<'a> for PossibleFragmentSpreads<'a> {
                 .as_ref()
                 .map(|c| &c.node)
             {
-                if let Some(on_type) = ctx.schema.types.get(fragment_type.node.as_str()) {
+                if let Some(on_type) = ctx.schema.get_type_by_name(fragment_type.node.as_str()) {
                     if !parent_type.type_overlap(&on_type) {
                         ctx.report_error(
                             vec![inline_fragment.pos],
@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+
+use graphgate_schema::TypeKind;
+use parser::types::{BaseType, Type, VariableDefinition};
+use parser::Positioned;
+use value::ConstValue;
+
+use crate::utils::PathNode;
+use crate::{ScalarValidator, Visitor, VisitorContext};
+
+pub struct ScalarValues<'a> {
+    validators: &'a HashMap<String, ScalarValidator>,
+}
+
+impl<'a> ScalarValues<'a> {
+    pub fn new(validators: &'a HashMap<String, ScalarValidator>) -> Self {
+        Self { validators }
+    }
+
+    fn check(
+        &self,
+        ctx: &VisitorContext<'a>,
+        ty: &Type,
+        value: &ConstValue,
+        path_node: PathNode,
+    ) -> Option<String> {
+        match (&ty.base, value) {
+            (BaseType::List(element_ty), ConstValue::List(elements)) => {
+                elements.iter().enumerate().find_map(|(idx, element)| {
+                    self.check(ctx, element_ty, element, path_node.index(idx))
+                })
+            }
+            (BaseType::List(element_ty), _) => self.check(ctx, element_ty, value, path_node),
+            (BaseType::Named(type_name), _) => {
+                let ty = ctx.schema.get_type_by_name(type_name)?;
+                match (&ty.kind, value) {
+                    (TypeKind::Scalar, _) => {
+                        let reason = self.validators.get(type_name.as_str())?.check(value)?;
+                        Some(format!(
+                            "\"{}\", value of type \"{}\" {}",
+                            path_node, type_name, reason
+                        ))
+                    }
+                    (TypeKind::InputObject, ConstValue::Object(values)) => {
+                        ty.input_fields.values().find_map(|field| {
+                            self.check(
+                                ctx,
+                                &field.ty,
+                                values.get(&field.name)?,
+                                path_node.name(field.name.as_str()),
+                            )
+                        })
+                    }
+                    _ => None,
+                }
+            }
+        }
+    }
+}
+
+impl<'a> Visitor<'a> for ScalarValues<'a> {
+    fn enter_variable_definition(
+        &mut self,
+        ctx: &mut VisitorContext<'a>,
+        variable_definition: &'a Positioned<VariableDefinition>,
+    ) {
+        if self.validators.is_empty() {
+            return;
+        }
+        let variables = ctx.variables;
+        let name = &variable_definition.node.name.node;
+        let value = match variables.get(name) {
+            Some(value) => value,
+            None => return,
+        };
+        if let Some(reason) = self.check(
+            ctx,
+            &variable_definition.node.var_type.node,
+            value,
+            PathNode::new(name.as_str()),
+        ) {
+            ctx.report_error(
+                vec![variable_definition.pos],
+                format!("Invalid value for variable {}", reason),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_harness::{expect_fails_rule_, expect_passes_rule_};
+    use indexmap::IndexMap;
+    use regex::Regex;
+    use value::{Name, Variables};
+
+    fn validators() -> HashMap<String, ScalarValidator> {
+        let mut validators = HashMap::new();
+        validators.insert(
+            "String".to_string(),
+            ScalarValidator {
+                pattern: Some(Regex::new("^[a-z]+$").unwrap()),
+                max_length: Some(5),
+                ..Default::default()
+            },
+        );
+        validators.insert(
+            "Int".to_string(),
+            ScalarValidator {
+                min: Some(0.0),
+                max: Some(10.0),
+                ..Default::default()
+            },
+        );
+        validators
+    }
+
+    fn variables(name: &str, value: ConstValue) -> Variables {
+        let mut variables = Variables::default();
+        variables.insert(Name::new(name), value);
+        variables
+    }
+
+    #[test]
+    fn valid_values() {
+        let validators = validators();
+        let doc = parser::parse_query(
+            r#"
+          query Foo($s: String, $i: Int) {
+            complicatedArgs {
+              stringArgField(stringArg: $s)
+              intArgField(intArg: $i)
+            }
+          }
+        "#,
+        )
+        .unwrap();
+        let variables = variables("s", ConstValue::String("abc".to_string()));
+        expect_passes_rule_(&doc, &variables, || ScalarValues::new(&validators));
+    }
+
+    #[test]
+    fn invalid_values() {
+        let validators = validators();
+        let doc = parser::parse_query(
+            r#"
+          query Foo($s: String, $l: [String], $c: ComplexInput) {
+            complicatedArgs {
+              stringArgField(stringArg: $s)
+              stringListArgField(stringListArg: $l)
+              complexArgField(complexArg: $c)
+            }
+          }
+        "#,
+        )
+        .unwrap();
+        for variables in [
+            variables("s", ConstValue::String("abcdef".to_string())),
+            variables("s", ConstValue::String("ABC".to_string())),
+            variables(
+                "l",
+                ConstValue::List(vec![
+                    ConstValue::String("a".to_string()),
+                    ConstValue::String("B".to_string()),
+                ]),
+            ),
+            variables(
+                "c",
+                ConstValue::Object(IndexMap::from([(
+                    Name::new("intField"),
+                    ConstValue::Number((-1).into()),
+                )])),
+            ),
+        ] {
+            expect_fails_rule_(&doc, &variables, || ScalarValues::new(&validators));
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use std::collections::HashSet;
+
+use parser::types::{OperationDefinition, OperationType, Selection, SelectionSet};
+use parser::Positioned;
+use value::Name;
+
+use crate::{Visitor, VisitorContext};
+
+/// The root fields of a subscription must not be introspection fields.
+///
+/// Unlike the specification, several root fields are allowed, since the
+/// gateway subscribes to each service separately.
+#[derive(Default)]
+pub struct SubscriptionRootFields;
+
+impl<'a> Visitor<'a> for SubscriptionRootFields {
+    fn enter_operation_definition(
+        &mut self,
+        ctx: &mut VisitorContext<'a>,
+        name: Option<&'a Name>,
+        operation_definition: &'a Positioned<OperationDefinition>,
+    ) {
+        if operation_definition.node.ty != OperationType::Subscription {
+            return;
+        }
+
+        let mut visited = HashSet::new();
+        check_selection_set(
+            ctx,
+            name,
+            &operation_definition.node.selection_set.node,
+            &mut visited,
+        );
+    }
+}
+
+fn check_selection_set<'a>(
+    ctx: &mut VisitorContext<'a>,
+    name: Option<&'a Name>,
+    selection_set: &'a SelectionSet,
+    visited: &mut HashSet<&'a str>,
+) {
+    for selection in &selection_set.items {
+        match &selection.node {
+            Selection::Field(field) => {
+                if field.node.name.node.starts_with("__") {
+                    let operation = match name {
+                        Some(name) => format!(r#"Subscription "{}""#, name),
+                        None => "Anonymous Subscription".to_string(),
+                    };
+                    ctx.report_error(
+                        vec![field.pos],
+                        format!(
+                            "{} must not select an introspection top level field.",
+                            operation
+                        ),
+                    );
+                }
+            }
+            Selection::FragmentSpread(fragment_spread) => {
+                let fragment_name = fragment_spread.node.fragment_name.node.as_str();
+                if !visited.insert(fragment_name) {
+                    continue;
+                }
+                if let Some(fragment) = ctx.fragment(fragment_name) {
+                    check_selection_set(ctx, name, &fragment.node.selection_set.node, visited);
+                }
+            }
+            Selection::InlineFragment(inline_fragment) => {
+                check_selection_set(ctx, name, &inline_fragment.node.selection_set.node, visited);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    pub fn factory() -> SubscriptionRootFields {
+        SubscriptionRootFields::default()
+    }
+
+    #[test]
+    fn fields_in_fragments() {
+        expect_passes_rule!(
+            factory,
+            r#"
+          subscription {
+            ...NewDog
+            ... on Subscription {
+              newCat { name }
+            }
+          }
+          fragment NewDog on Subscription {
+            newDog { name __typename }
+          }
+        "#,
+        );
+    }
+
+    #[test]
+    fn introspection_field() {
+        expect_fails_rule!(
+            factory,
+            r#"
+          subscription {
+            newDog { name }
+            __typename
+          }
+        "#,
+        );
+    }
+
+    #[test]
+    fn introspection_field_in_fragment() {
+        expect_fails_rule!(
+            factory,
+            r#"
+          subscription Dogs {
+            ...Fields
+          }
+          fragment Fields on Subscription {
+            ... {
+              __schema { queryType { name } }
+            }
+          }
+        "#,
+        );
+    }
+}
@@ -0,0 +1,49 @@
+use regex::Regex;
+use value::ConstValue;
+
+/// Constraints on the values of a custom scalar, checked on the variables of
+/// the operations before they are sent to the services.
+#[derive(Debug, Clone, Default)]
+pub struct ScalarValidator {
+    /// Regular expression the string values must match.
+    pub pattern: Option<Regex>,
+
+    /// Minimum of the numeric values.
+    pub min: Option<f64>,
+
+    /// Maximum of the numeric values.
+    pub max: Option<f64>,
+
+    /// Maximum number of characters of the string values.
+    pub max_length: Option<usize>,
+}
+
+impl ScalarValidator {
+    /// Returns the reason why a value is rejected.
+    pub(crate) fn check(&self, value: &ConstValue) -> Option<String> {
+        match value {
+            ConstValue::String(s) => {
+                if let Some(max_length) = self.max_length {
+                    if s.chars().count() > max_length {
+                        return Some(format!("is longer than {} characters", max_length));
+                    }
+                }
+                if let Some(pattern) = &self.pattern {
+                    if !pattern.is_match(s) {
+                        return Some(format!("does not match the pattern \"{}\"", pattern));
+                    }
+                }
+                None
+            }
+            ConstValue::Number(n) => {
+                let n = n.as_f64()?;
+                match (self.min, self.max) {
+                    (Some(min), _) if n < min => Some(format!("is less than {}", min)),
+                    (_, Some(max)) if n > max => Some(format!("is greater than {}", max)),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+}
@@ -92,7 +92,7 @@ pub fn is_valid_input_value(
                 if matches!(value, ConstValue::Null) {
                     return None;
                 }
-                if let Some(ty) = schema.types.get(type_name) {
+                if let Some(ty) = schema.get_type_by_name(type_name) {
                     match ty.kind {
                         TypeKind::Scalar => {
                             if is_valid_scalar_value(ty.name.as_str(), value) {
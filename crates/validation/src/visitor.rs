@@ -1,4 +1,5 @@
 use std::collections::HashMap;
+use std::time::Instant;
 
 use graphgate_schema::{ComposedSchema, MetaType, TypeKind};
 use parser::types::{
@@ -18,6 +19,9 @@ pub struct VisitorContext<'a> {
     type_stack: Vec<Option<&'a MetaType>>,
     input_type: Vec<Option<&'a Type>>,
     fragments: &'a HashMap<Name, Positioned<FragmentDefinition>>,
+
+    /// The selection sets are no longer visited after the deadline.
+    pub(crate) deadline: Option<Instant>,
 }
 
 impl<'a> VisitorContext<'a> {
@@ -33,6 +37,7 @@ impl<'a> VisitorContext<'a> {
             type_stack: Default::default(),
             input_type: Default::default(),
             fragments: &document.fragments,
+            deadline: None,
         }
     }
 
@@ -470,8 +475,7 @@ pub fn visit<'a, V: Visitor<'a>>(
     for (name, fragment) in &doc.fragments {
         ctx.with_type(
             ctx.schema
-                .types
-                .get(fragment.node.type_condition.node.on.node.as_str()),
+                .get_type_by_name(fragment.node.type_condition.node.on.node.as_str()),
             |ctx| visit_fragment_definition(v, ctx, name, fragment),
         )
     }
@@ -496,7 +500,7 @@ fn visit_operation_definition<'a, V: Visitor<'a>>(
         OperationType::Subscription => ctx.schema.subscription_type(),
     };
     if let Some(root_name) = root_name {
-        ctx.with_type(Some(&ctx.schema.types[root_name]), |ctx| {
+        ctx.with_type(ctx.schema.get_type_by_name(root_name), |ctx| {
             visit_variable_definitions(v, ctx, &operation.node.variable_definitions);
             visit_directives(v, ctx, &operation.node.directives);
             visit_selection_set(v, ctx, &operation.node.selection_set);
@@ -516,6 +520,9 @@ fn visit_selection_set<'a, V: Visitor<'a>>(
     ctx: &mut VisitorContext<'a>,
     selection_set: &'a Positioned<SelectionSet>,
 ) {
+    if matches!(ctx.deadline, Some(deadline) if Instant::now() > deadline) {
+        return;
+    }
     if !selection_set.node.items.is_empty() {
         v.enter_selection_set(ctx, selection_set);
         for selection in &selection_set.node.items {
@@ -556,7 +563,7 @@ fn visit_selection<'a, V: Visitor<'a>>(
                 .as_ref()
                 .map(|c| &c.node)
             {
-                ctx.with_type(ctx.schema.types.get(name.node.as_str()), |ctx| {
+                ctx.with_type(ctx.schema.get_type_by_name(name.node.as_str()), |ctx| {
                     visit_inline_fragment(v, ctx, inline_fragment)
                 });
             } else {
@@ -616,7 +623,7 @@ fn visit_input_value<'a, V: Visitor<'a>>(
             if let Some(expected_ty) = expected_ty {
                 let expected_ty = &expected_ty.base;
                 if let BaseType::Named(expected_ty) = expected_ty {
-                    if let Some(ty) = ctx.schema.types.get(expected_ty.as_str()) {
+                    if let Some(ty) = ctx.schema.get_type_by_name(expected_ty.as_str()) {
                         if ty.kind == TypeKind::InputObject {
                             for (item_key, item_value) in values {
                                 if let Some(input_value) = ty.input_fields.get(item_key.as_str()) {
@@ -0,0 +1,54 @@
+use std::fmt::{self, Display, Formatter};
+
+/// Version, toolchain and features of the running binary.
+pub struct BuildInfo {
+    pub version: &'static str,
+    pub git_sha: &'static str,
+    pub rustc_version: &'static str,
+    pub target: &'static str,
+    pub features: Vec<&'static str>,
+    pub allocator: &'static str,
+}
+
+impl BuildInfo {
+    pub fn current() -> Self {
+        let mut features = Vec::new();
+        if cfg!(feature = "chaos") {
+            features.push("chaos");
+        }
+        if cfg!(feature = "demo") {
+            features.push("demo");
+        }
+        if cfg!(feature = "jemalloc") {
+            features.push("jemalloc");
+        }
+
+        Self {
+            version: env!("CARGO_PKG_VERSION"),
+            git_sha: env!("GRAPHGATE_GIT_SHA"),
+            rustc_version: env!("GRAPHGATE_RUSTC_VERSION"),
+            target: env!("GRAPHGATE_TARGET"),
+            features,
+            allocator: if cfg!(all(feature = "jemalloc", not(target_env = "msvc"))) {
+                "jemalloc"
+            } else {
+                "system"
+            },
+        }
+    }
+}
+
+impl Display for BuildInfo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(f, "graphgate {}", self.version)?;
+        writeln!(f, "git sha:   {}", self.git_sha)?;
+        writeln!(f, "rustc:     {}", self.rustc_version)?;
+        writeln!(f, "target:    {}", self.target)?;
+        if self.features.is_empty() {
+            writeln!(f, "features:  none")?;
+        } else {
+            writeln!(f, "features:  {}", self.features.join(", "))?;
+        }
+        write!(f, "allocator: {}", self.allocator)
+    }
+}
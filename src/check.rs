@@ -0,0 +1,102 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use graphgate_handler::{ComposedSchema, PlanBuilder};
+
+use crate::config::load_supergraph;
+
+/// Validates and plans every operation of the `.graphql` and `.gql` files in a
+/// directory against a supergraph, printing the operations that fail.
+///
+/// Returns an error if any of them fails, so that it can be used in CI to find
+/// the clients broken by a schema change.
+pub async fn check_operations(schema: &str, ops: &str) -> Result<()> {
+    let schema = load_supergraph(schema).await?;
+    let mut files = Vec::new();
+    find_operation_files(Path::new(ops), &mut files)
+        .with_context(|| format!("Failed to read operations directory '{}'.", ops))?;
+    files.sort();
+
+    let mut total = 0;
+    let mut failed = 0;
+    for path in &files {
+        let query = tokio::fs::read_to_string(path)
+            .await
+            .with_context(|| format!("Failed to read operation file '{}'.", path.display()))?;
+        for (operation, errors) in check_document(&schema, &query) {
+            total += 1;
+            if errors.is_empty() {
+                continue;
+            }
+            failed += 1;
+            for error in errors {
+                println!(
+                    "{}: {}: {}",
+                    path.display(),
+                    operation.as_deref().unwrap_or("<anonymous>"),
+                    error
+                );
+            }
+        }
+    }
+
+    println!(
+        "Checked {} operations in {} files, {} failed.",
+        total,
+        files.len(),
+        failed
+    );
+    anyhow::ensure!(failed == 0, "{} operations failed.", failed);
+    Ok(())
+}
+
+fn find_operation_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_operation_files(&path, files)?;
+        } else if matches!(
+            path.extension().and_then(|ext| ext.to_str()),
+            Some("graphql") | Some("gql")
+        ) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Returns the errors of each operation of a document, prefixed with their
+/// position. A parse error is reported as a single anonymous operation.
+fn check_document(schema: &ComposedSchema, query: &str) -> Vec<(Option<String>, Vec<String>)> {
+    let document = match parser::parse_query(query) {
+        Ok(document) => document,
+        Err(err) => return vec![(None, vec![err.to_string()])],
+    };
+    let operation_names = document
+        .operations
+        .iter()
+        .map(|(name, _)| name.map(ToString::to_string))
+        .collect::<Vec<_>>();
+
+    operation_names
+        .into_iter()
+        .map(|operation_name| {
+            let mut builder = PlanBuilder::new(schema, document.clone());
+            if let Some(operation_name) = operation_name.clone() {
+                builder = builder.operation_name(operation_name);
+            }
+            let errors = match builder.plan() {
+                Ok(_) => Vec::new(),
+                Err(response) => response
+                    .errors
+                    .into_iter()
+                    .map(|error| match error.locations.first() {
+                        Some(pos) => format!("{}: {}", pos, error.message),
+                        None => error.message,
+                    })
+                    .collect(),
+            };
+            (operation_name, errors)
+        })
+        .collect()
+}
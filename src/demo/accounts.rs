@@ -0,0 +1,58 @@
+use async_graphql::{EmptyMutation, Object, Schema, SimpleObject, Subscription, ID};
+use futures_util::stream::Stream;
+use tokio::time::Duration;
+
+use super::DemoRng;
+
+#[derive(SimpleObject)]
+struct User {
+    id: ID,
+    username: String,
+}
+
+pub struct Query;
+
+#[Object(extends)]
+impl Query {
+    /// Get the current user.
+    async fn me(&self) -> User {
+        User {
+            id: "1234".into(),
+            username: "Me".to_string(),
+        }
+    }
+
+    #[graphql(entity)]
+    async fn find_user_by_id(&self, id: ID) -> User {
+        let username = if id == "1234" {
+            "Me".to_string()
+        } else {
+            format!("User {:?}", id)
+        };
+        User { id, username }
+    }
+}
+
+pub struct Subscription {
+    rng: DemoRng,
+}
+
+#[Subscription(extends)]
+impl Subscription {
+    async fn users(&self) -> impl Stream<Item = User> {
+        let rng = self.rng.clone();
+        async_stream::stream! {
+            loop {
+                tokio::time::sleep(Duration::from_secs(rng.u64(1..3))).await;
+                yield User { id: "1234".into(), username: "Me".to_string() };
+            }
+        }
+    }
+}
+
+pub(super) fn schema(rng: DemoRng) -> Schema<Query, EmptyMutation, Subscription> {
+    Schema::build(Query, EmptyMutation, Subscription { rng })
+        .extension(async_graphql::extensions::ApolloTracing)
+        .enable_subscription_in_federation()
+        .finish()
+}
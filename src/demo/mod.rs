@@ -0,0 +1,148 @@
+//! Synthetic accounts, products and reviews services for demos and benchmarks.
+
+mod accounts;
+mod products;
+mod reviews;
+
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Context, Result};
+use async_graphql::{EmptyMutation, ObjectType, Schema, ServerError, SubscriptionType};
+use async_graphql_warp::{graphql, graphql_subscription};
+use fastrand::Rng;
+use tokio::time::Duration;
+use warp::{Filter, Reply};
+
+use crate::config::Config;
+
+/// Latency and error profile of the demo services.
+///
+/// The latency jitter, the failing requests and the subscription events are
+/// drawn from a generator seeded with `seed`, so runs are reproducible.
+#[derive(Debug, Clone)]
+pub struct DemoProfile {
+    /// Latency in milliseconds added to every request.
+    pub latency: u64,
+
+    /// Maximum random latency in milliseconds added to `latency`.
+    pub jitter: u64,
+
+    /// Fraction of the requests that fail, from `0.0` to `1.0`.
+    pub error_rate: f64,
+
+    pub seed: u64,
+}
+
+#[derive(Clone)]
+struct DemoRng(Arc<Mutex<Rng>>);
+
+impl DemoRng {
+    fn new(seed: u64) -> Self {
+        Self(Arc::new(Mutex::new(Rng::with_seed(seed))))
+    }
+
+    fn u64(&self, range: std::ops::Range<u64>) -> u64 {
+        self.0.lock().unwrap().u64(range)
+    }
+
+    fn f64(&self) -> f64 {
+        self.0.lock().unwrap().f64()
+    }
+}
+
+/// Binds a demo service and spawns its server.
+fn serve<Query, Subscription>(
+    addr: SocketAddr,
+    schema: Schema<Query, EmptyMutation, Subscription>,
+    profile: DemoProfile,
+    rng: DemoRng,
+) -> Result<()>
+where
+    Query: ObjectType + 'static,
+    Subscription: SubscriptionType + 'static,
+{
+    let routes = graphql(schema.clone())
+        .and(warp::post())
+        .and_then(
+            move |(schema, request): (
+                Schema<Query, EmptyMutation, Subscription>,
+                async_graphql::Request,
+            )| {
+                let profile = profile.clone();
+                let rng = rng.clone();
+                async move {
+                    let latency = profile.latency + rng.u64(0..profile.jitter + 1);
+                    if latency > 0 {
+                        tokio::time::sleep(Duration::from_millis(latency)).await;
+                    }
+                    let response = if rng.f64() < profile.error_rate {
+                        async_graphql::Response::from_errors(vec![ServerError::new(
+                            "Injected error.",
+                            None,
+                        )])
+                    } else {
+                        schema.execute(request).await
+                    };
+                    Ok::<_, Infallible>(warp::reply::json(&response).into_response())
+                }
+            },
+        )
+        .or(graphql_subscription(schema));
+
+    let (_, server) = warp::serve(routes)
+        .try_bind_ephemeral(addr)
+        .with_context(|| format!("Failed to bind demo service to '{}'.", addr))?;
+    tokio::spawn(server);
+    Ok(())
+}
+
+/// Starts the demo services on `base_port` and the next two ports, and returns
+/// the gateway configuration that routes to them.
+pub fn start(bind: &str, base_port: u16, profile: DemoProfile) -> Result<Config> {
+    let rng = DemoRng::new(profile.seed);
+    let accounts_addr = SocketAddr::from(([127, 0, 0, 1], base_port));
+    let products_addr = SocketAddr::from(([127, 0, 0, 1], base_port + 1));
+    let reviews_addr = SocketAddr::from(([127, 0, 0, 1], base_port + 2));
+
+    serve(
+        accounts_addr,
+        accounts::schema(rng.clone()),
+        profile.clone(),
+        rng.clone(),
+    )?;
+    serve(
+        products_addr,
+        products::schema(rng.clone()),
+        profile.clone(),
+        rng.clone(),
+    )?;
+    serve(reviews_addr, reviews::schema(rng.clone()), profile, rng)?;
+    tracing::info!(
+        accounts = %accounts_addr,
+        products = %products_addr,
+        reviews = %reviews_addr,
+        "Demo services listening"
+    );
+
+    toml::from_str(&format!(
+        r#"
+bind = "{}"
+
+[[services]]
+name = "accounts"
+addr = "{}"
+
+[[services]]
+name = "products"
+addr = "{}"
+
+[[services]]
+name = "reviews"
+addr = "{}"
+"#,
+        bind, accounts_addr, products_addr, reviews_addr
+    ))
+    .context("Failed to create demo config.")
+}
@@ -0,0 +1,74 @@
+use async_graphql::{Context, EmptyMutation, Object, Schema, SimpleObject, Subscription};
+use futures_util::stream::Stream;
+use tokio::time::Duration;
+
+use super::DemoRng;
+
+#[derive(SimpleObject, Clone)]
+struct Product {
+    upc: String,
+    name: String,
+    price: i32,
+}
+
+pub struct Query;
+
+#[Object(extends)]
+impl Query {
+    async fn top_products<'a>(&self, ctx: &'a Context<'_>) -> &'a Vec<Product> {
+        ctx.data_unchecked::<Vec<Product>>()
+    }
+
+    #[graphql(entity)]
+    async fn find_product_by_upc<'a>(&self, ctx: &Context<'a>, upc: String) -> Option<&'a Product> {
+        let hats = ctx.data_unchecked::<Vec<Product>>();
+        hats.iter().find(|product| product.upc == upc)
+    }
+}
+
+pub struct Subscription {
+    rng: DemoRng,
+}
+
+#[Subscription(extends)]
+impl Subscription {
+    async fn products(&self) -> impl Stream<Item = Product> {
+        let rng = self.rng.clone();
+        async_stream::stream! {
+            loop {
+                tokio::time::sleep(Duration::from_secs(rng.u64(5..10))).await;
+                yield Product {
+                    upc: "top-1".to_string(),
+                    name: "Trilby".to_string(),
+                    price: 11,
+                };
+            }
+        }
+    }
+}
+
+pub(super) fn schema(rng: DemoRng) -> Schema<Query, EmptyMutation, Subscription> {
+    let hats = vec![
+        Product {
+            upc: "top-1".to_string(),
+            name: "Trilby".to_string(),
+            price: 11,
+        },
+        Product {
+            upc: "top-2".to_string(),
+            name: "Fedora".to_string(),
+            price: 22,
+        },
+        Product {
+            upc: "top-3".to_string(),
+            name: "Boater".to_string(),
+            price: 33,
+        },
+    ];
+
+    Schema::build(Query, EmptyMutation, Subscription { rng })
+        .extension(async_graphql::extensions::ApolloTracing)
+        .enable_subscription_in_federation()
+        .data(hats)
+        .finish()
+}
@@ -0,0 +1,131 @@
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use anyhow::{Context, Result};
+use graphgate_handler::{ComposedSchema, SharedRouteTable};
+use tokio::time::Duration;
+
+use crate::config::Config;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// A subgraph developed locally, written `name=sdl_file@url`.
+#[derive(Debug, Clone)]
+pub struct Subgraph {
+    pub name: String,
+    pub sdl_path: PathBuf,
+    pub url: String,
+}
+
+impl FromStr for Subgraph {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, rest) = s
+            .split_once('=')
+            .with_context(|| format!("Subgraph '{}' is not 'name=sdl_file@url'.", s))?;
+        let (sdl_path, url) = rest
+            .rsplit_once('@')
+            .with_context(|| format!("Subgraph '{}' is not 'name=sdl_file@url'.", s))?;
+        Ok(Self {
+            name: name.to_string(),
+            sdl_path: PathBuf::from(sdl_path),
+            url: url.to_string(),
+        })
+    }
+}
+
+/// Returns the configuration routing the requests to the subgraphs.
+pub fn config(bind: &str, subgraphs: &[Subgraph]) -> Result<Config> {
+    let services = subgraphs
+        .iter()
+        .map(|subgraph| {
+            let (tls, rest) = match subgraph.url.split_once("://") {
+                Some(("http", rest)) => (false, rest),
+                Some(("https", rest)) => (true, rest),
+                _ => anyhow::bail!(
+                    "URL of subgraph '{}' must start with 'http://' or 'https://'.",
+                    subgraph.name
+                ),
+            };
+            let (addr, query_path) = match rest.find('/') {
+                Some(idx) => (&rest[..idx], Some(&rest[idx..])),
+                None => (rest, None),
+            };
+            Ok(serde_json::json!({
+                "name": subgraph.name,
+                "addr": addr,
+                "tls": tls,
+                "query_path": query_path,
+            }))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    serde_json::from_value(serde_json::json!({
+        "bind": bind,
+        "services": services,
+        "query_plan_extension": true,
+    }))
+    .context("Failed to create dev config.")
+}
+
+async fn compose(subgraphs: &[Subgraph]) -> Result<ComposedSchema> {
+    let mut documents = Vec::with_capacity(subgraphs.len());
+    for subgraph in subgraphs {
+        let sdl = tokio::fs::read_to_string(&subgraph.sdl_path)
+            .await
+            .with_context(|| {
+                format!("Failed to load SDL file '{}'.", subgraph.sdl_path.display())
+            })?;
+        let document = parser::parse_schema(sdl)
+            .with_context(|| format!("Invalid SDL of subgraph '{}'.", subgraph.name))?;
+        documents.push((subgraph.name.clone(), document));
+    }
+    Ok(ComposedSchema::combine(documents)?)
+}
+
+async fn modified(subgraphs: &[Subgraph]) -> Vec<Option<SystemTime>> {
+    let mut modified = Vec::with_capacity(subgraphs.len());
+    for subgraph in subgraphs {
+        modified.push(
+            tokio::fs::metadata(&subgraph.sdl_path)
+                .await
+                .and_then(|metadata| metadata.modified())
+                .ok(),
+        );
+    }
+    modified
+}
+
+/// Composes the schema from the SDL files of the subgraphs, then composes it
+/// again and swaps it whenever one of them changes.
+///
+/// A schema that fails to compose is reported and the previous one is kept.
+pub async fn watch(subgraphs: Vec<Subgraph>, shared_route_table: SharedRouteTable) -> Result<()> {
+    shared_route_table
+        .set_static_schema(Arc::new(compose(&subgraphs).await?))
+        .await;
+    let mut prev_modified = modified(&subgraphs).await;
+
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(POLL_INTERVAL).await;
+            let current = modified(&subgraphs).await;
+            if current == prev_modified {
+                continue;
+            }
+            prev_modified = current;
+            match compose(&subgraphs).await {
+                Ok(schema) => {
+                    shared_route_table.set_static_schema(Arc::new(schema)).await;
+                    tracing::info!("Schema recomposed.");
+                }
+                Err(err) => {
+                    tracing::error!(error = %err, "Failed to recompose schema.")
+                }
+            }
+        }
+    });
+    Ok(())
+}
@@ -0,0 +1,40 @@
+use anyhow::{Context, Result};
+use graphgate_handler::{GraphFormat, PlanBuilder};
+use value::Variables;
+
+use crate::config::load_supergraph;
+use crate::options::ExplainOptions;
+
+/// Plans an operation against a supergraph and prints the graph of its query
+/// plan.
+pub async fn explain(options: &ExplainOptions) -> Result<()> {
+    let schema = load_supergraph(&options.schema).await?;
+    let query = tokio::fs::read_to_string(&options.query)
+        .await
+        .with_context(|| format!("Failed to read operation file '{}'.", options.query))?;
+    let document = parser::parse_query(&query)
+        .with_context(|| format!("Invalid operation file '{}'.", options.query))?;
+    let variables: Variables = match &options.variables {
+        Some(variables) => serde_json::from_str(variables).context("Invalid variables.")?,
+        None => Default::default(),
+    };
+    let format = options
+        .format
+        .parse::<GraphFormat>()
+        .map_err(anyhow::Error::msg)?;
+
+    let mut builder = PlanBuilder::new(&schema, document).variables(variables);
+    if let Some(operation_name) = &options.operation_name {
+        builder = builder.operation_name(operation_name);
+    }
+    let plan = builder.plan().map_err(|response| {
+        let errors = response
+            .errors
+            .into_iter()
+            .map(|error| error.message)
+            .collect::<Vec<_>>();
+        anyhow::anyhow!("Failed to plan the operation: {}", errors.join(" "))
+    })?;
+    print!("{}", plan.to_graph(format));
+    Ok(())
+}
@@ -12,6 +12,10 @@ const ANNOTATIONS_QUERY_PATH: &str = "graphgate.org/queryPath";
 const ANNOTATIONS_SUBSCRIBE_PATH: &str = "graphgate.org/subscribePath";
 const ANNOTATIONS_INTROSPECTION_PATH: &str = "graphgate.org/introspectionPath";
 const ANNOTATIONS_WEBSOCKET_PATH: &str = "graphgate.org/websocketPath";
+const ANNOTATIONS_SUBSCRIPTION_CALLBACK: &str = "graphgate.org/subscriptionCallback";
+const ANNOTATIONS_HTTP_VERSION: &str = "graphgate.org/httpVersion";
+const ANNOTATIONS_ENCODING: &str = "graphgate.org/encoding";
+const ANNOTATIONS_WEBSOCKET_PROTOCOL: &str = "graphgate.org/websocketProtocol";
 
 fn get_label_value<'a>(meta: &'a ObjectMeta, name: &str) -> Option<&'a str> {
     meta.labels
@@ -83,6 +87,45 @@ pub async fn find_graphql_services(gateway_name: &str) -> Result<ServiceRouteTab
                     get_annotation_value(&service.metadata, ANNOTATIONS_INTROSPECTION_PATH);
                 let websocket_path =
                     get_annotation_value(&service.metadata, ANNOTATIONS_WEBSOCKET_PATH);
+                let subscription_callback =
+                    get_annotation_value(&service.metadata, ANNOTATIONS_SUBSCRIPTION_CALLBACK)
+                        .is_some();
+                let http_version = match get_annotation_value(
+                    &service.metadata,
+                    ANNOTATIONS_HTTP_VERSION,
+                )
+                .map(str::parse)
+                .transpose()
+                {
+                    Ok(http_version) => http_version.unwrap_or_default(),
+                    Err(err) => {
+                        tracing::warn!(service = %service_name, error = %err, "Invalid HTTP version.");
+                        Default::default()
+                    }
+                };
+                let encoding = match get_annotation_value(&service.metadata, ANNOTATIONS_ENCODING)
+                    .map(str::parse)
+                    .transpose()
+                {
+                    Ok(encoding) => encoding.unwrap_or_default(),
+                    Err(err) => {
+                        tracing::warn!(service = %service_name, error = %err, "Invalid body encoding.");
+                        Default::default()
+                    }
+                };
+                let websocket_protocol = match get_annotation_value(
+                    &service.metadata,
+                    ANNOTATIONS_WEBSOCKET_PROTOCOL,
+                )
+                .map(str::parse)
+                .transpose()
+                {
+                    Ok(websocket_protocol) => websocket_protocol,
+                    Err(err) => {
+                        tracing::warn!(service = %service_name, error = %err, "Invalid websocket protocol.");
+                        None
+                    }
+                };
                 route_table.insert(
                     service_name.to_string(),
                     ServiceRoute {
@@ -92,6 +135,14 @@ pub async fn find_graphql_services(gateway_name: &str) -> Result<ServiceRouteTab
                         subscribe_path: subscribe_path.map(ToString::to_string),
                         introspection_path: introspection_path.map(ToString::to_string),
                         websocket_path: websocket_path.map(ToString::to_string),
+                        subscription_callback,
+                        http_version,
+                        response_limits: Default::default(),
+                        encoding,
+                        websocket_protocol,
+                        oauth2: None,
+                        aws_sigv4: None,
+                        maintenance_windows: Vec::new(),
                     },
                 );
             }
@@ -0,0 +1,64 @@
+use std::net::{SocketAddr, TcpListener};
+
+use anyhow::{Context, Result};
+use listenfd::ListenFd;
+use socket2::{Domain, Protocol, Socket, Type};
+
+const BACKLOG: i32 = 1024;
+
+/// Listening sockets of the gateway.
+///
+/// The sockets passed by systemd socket activation are used by their index,
+/// `0` for the gateway and `1` for the admin API. The other addresses are
+/// bound by the process, with `SO_REUSEPORT` if requested so that a new
+/// process can listen on the same address before the old one exits.
+pub struct Listeners {
+    fds: ListenFd,
+    reuse_port: bool,
+}
+
+pub const GATEWAY: usize = 0;
+pub const ADMIN: usize = 1;
+
+impl Listeners {
+    pub fn from_env(reuse_port: bool) -> Self {
+        Self {
+            fds: ListenFd::from_env(),
+            reuse_port,
+        }
+    }
+
+    /// Returns the socket passed by systemd at an index, or binds the address.
+    pub fn take(&mut self, idx: usize, addr: SocketAddr) -> Result<TcpListener> {
+        if let Some(listener) = self
+            .fds
+            .take_tcp_listener(idx)
+            .context("Invalid socket passed by systemd.")?
+        {
+            tracing::info!(
+                addr = %listener.local_addr()?,
+                "Using the socket passed by systemd."
+            );
+            listener.set_nonblocking(true)?;
+            return Ok(listener);
+        }
+        self.bind(addr)
+            .with_context(|| format!("Failed to bind '{}'.", addr))
+    }
+
+    fn bind(&self, addr: SocketAddr) -> Result<TcpListener> {
+        let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+        #[cfg(unix)]
+        socket.set_reuse_address(true)?;
+        if self.reuse_port {
+            #[cfg(unix)]
+            socket.set_reuse_port(true)?;
+            #[cfg(not(unix))]
+            anyhow::bail!("SO_REUSEPORT is only supported on Unix.");
+        }
+        socket.set_nonblocking(true)?;
+        socket.bind(&addr.into())?;
+        socket.listen(BACKLOG)?;
+        Ok(socket.into())
+    }
+}
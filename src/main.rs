@@ -1,39 +1,70 @@
 #![forbid(unsafe_code)]
 
+mod build_info;
+mod check;
 mod config;
+#[cfg(feature = "demo")]
+mod demo;
+mod dev;
+mod explain;
 mod k8s;
+mod listener;
+mod openmetrics;
 mod options;
 
+use std::convert::Infallible;
+use std::future::Future;
 use std::net::SocketAddr;
 use std::sync::Arc;
 
 use anyhow::{Context, Result};
-use futures_util::FutureExt;
-use graphgate_handler::handler::HandlerConfig;
-use graphgate_handler::{handler, SharedRouteTable};
+use futures_util::future;
+use graphgate_handler::handler::{ClientConnection, HandlerConfig};
+use graphgate_handler::{
+    admin, begin_shutdown, drain, grafana_dashboard, handler, shutdown_started, Authenticator,
+    CallbackRegistry, InjectRule, RuntimeSwitches, SecretResolvers, ShapingDirective,
+    SharedRouteTable, TenantRouteTables, LATENCY_BUCKETS,
+};
 use opentelemetry::global;
 use opentelemetry::global::GlobalTracerProvider;
 use opentelemetry::trace::noop::NoopTracerProvider;
 use opentelemetry_prometheus::PrometheusExporter;
 use prometheus::{Encoder, TextEncoder};
 use structopt::StructOpt;
+use tokio::net::{TcpListener, TcpStream};
 use tokio::signal;
 use tokio::time::Duration;
+use tokio_stream::wrappers::TcpListenerStream;
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
 use tracing_subscriber::{fmt, EnvFilter};
+use warp::filters::BoxedFilter;
+use warp::http::header::CONTENT_TYPE;
 use warp::http::Response as HttpResponse;
-use warp::hyper::StatusCode;
+use warp::hyper::server::accept;
+use warp::hyper::service::{make_service_fn, service_fn, Service};
+use warp::hyper::{Body, Request as HyperRequest, Server, StatusCode};
+use warp::reply::Response;
 use warp::{Filter, Rejection, Reply};
 
-use config::Config;
+use config::{
+    create_inject_rules, load_config, load_redacted_config, load_runtime_config, load_supergraph,
+    Config, CorsConfig, OperationConfig, ResponseRuleConfig, ScalarConfig, ValidationCacheConfig,
+    VariableConfig,
+};
+use listener::Listeners;
 use options::Options;
 
-// Use Jemalloc only for musl-64 bits platforms
-#[cfg(all(target_env = "musl", target_pointer_width = "64"))]
+// Jemalloc is not available for MSVC targets, the release builds of musl-64 bits
+// platforms enable it with the `jemalloc` feature.
+#[cfg(all(feature = "jemalloc", not(target_env = "msvc")))]
 #[global_allocator]
 static ALLOC: jemallocator::Jemalloc = jemallocator::Jemalloc;
 
+/// Default time in seconds to wait for the subscriptions to complete on
+/// shutdown.
+const DEFAULT_DRAIN_TIMEOUT: u64 = 30;
+
 fn init_tracing() {
     tracing_subscriber::registry()
         .with(fmt::layer().compact().with_target(false))
@@ -88,49 +119,454 @@ fn init_tracer(config: &Config) -> Result<GlobalTracerProvider> {
     Ok(uninstall)
 }
 
+/// Serves the gateway routes until the signal completes.
+///
+/// Unlike `warp::serve`, the connection of each request is added to its
+/// extensions, so that the handlers know the negotiated HTTP version, and
+/// can choose how to stream the responses. Only the GraphQL routes need it.
+///
+/// HTTP/2 server push is not used, hyper doesn't expose the push promises of
+/// the server, and most browsers no longer accept them.
+async fn serve<S>(service: S, listener: TcpListener, signal: impl Future<Output = ()>)
+where
+    S: Service<HyperRequest<Body>, Response = HttpResponse<Body>, Error = Infallible>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send + 'static,
+{
+    let make_service = make_service_fn(move |stream: &TcpStream| {
+        let service = service.clone();
+        let remote_addr = stream.peer_addr().ok();
+        future::ok::<_, Infallible>(service_fn(move |mut req: HyperRequest<Body>| {
+            let connection = ClientConnection {
+                remote_addr,
+                version: req.version(),
+            };
+            req.extensions_mut().insert(connection);
+            service.clone().call(req)
+        }))
+    });
+    let server = Server::builder(accept::from_stream(TcpListenerStream::new(listener)))
+        .serve(make_service)
+        .with_graceful_shutdown(signal);
+    if let Err(err) = server.await {
+        tracing::error!(error = %err, "Server error.");
+    }
+}
+
+/// Waits for `SIGTERM` or `Ctrl-C`, then stops accepting connections and
+/// drains the WebSocket connections.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::SignalKind;
+
+        match signal::unix::signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                tokio::select! {
+                    _ = sigterm.recv() => {}
+                    _ = signal::ctrl_c() => {}
+                }
+            }
+            Err(err) => {
+                tracing::error!(error = %err, "Failed to listen for SIGTERM.");
+                let _ = signal::ctrl_c().await;
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = signal::ctrl_c().await;
+
+    tracing::info!("Shutting down.");
+    begin_shutdown();
+}
+
+/// Applies a CORS policy to the routes, if any.
+fn with_cors<F, T>(filter: F, cors: Option<warp::cors::Builder>) -> BoxedFilter<(Response,)>
+where
+    F: Filter<Extract = (T,), Error = Rejection> + Clone + Send + Sync + 'static,
+    T: Reply,
+{
+    let filter = filter.map(|reply: T| reply.into_response()).boxed();
+    match cors {
+        Some(cors) => filter
+            .with(cors)
+            .map(|reply| Reply::into_response(reply))
+            .boxed(),
+        None => filter,
+    }
+}
+
 pub fn metrics(
     exporter: PrometheusExporter,
 ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
-    warp::path!("metrics").and(warp::get()).map({
-        move || {
-            let mut buffer = Vec::new();
-            let encoder = TextEncoder::new();
-            let metric_families = exporter.registry().gather();
-            if let Err(err) = encoder.encode(&metric_families, &mut buffer) {
-                return HttpResponse::builder()
-                    .status(StatusCode::INTERNAL_SERVER_ERROR)
-                    .body(err.to_string().into_bytes())
-                    .unwrap();
+    warp::path!("metrics")
+        .and(warp::get())
+        .and(warp::header::optional::<String>("accept"))
+        .map({
+            move |accept: Option<String>| {
+                let metric_families = exporter.registry().gather();
+                if accept.map_or(false, |accept| {
+                    accept.contains("application/openmetrics-text")
+                }) {
+                    return HttpResponse::builder()
+                        .status(StatusCode::OK)
+                        .header(CONTENT_TYPE, openmetrics::CONTENT_TYPE)
+                        .body(openmetrics::encode(&metric_families).into_bytes())
+                        .unwrap();
+                }
+
+                let mut buffer = Vec::new();
+                let encoder = TextEncoder::new();
+                if let Err(err) = encoder.encode(&metric_families, &mut buffer) {
+                    return HttpResponse::builder()
+                        .status(StatusCode::INTERNAL_SERVER_ERROR)
+                        .body(err.to_string().into_bytes())
+                        .unwrap();
+                }
+                HttpResponse::builder()
+                    .status(StatusCode::OK)
+                    .body(buffer)
+                    .unwrap()
             }
-            HttpResponse::builder()
-                .status(StatusCode::OK)
-                .body(buffer)
-                .unwrap()
+        })
+}
+
+async fn configure_route_table(
+    config: &Config,
+    shared_route_table: &mut SharedRouteTable,
+    inject_rules: Vec<InjectRule>,
+    switches: &RuntimeSwitches,
+    callbacks: Option<&Arc<CallbackRegistry>>,
+) -> Result<()> {
+    if let Some(schema_update) = &config.schema_update {
+        shared_route_table
+            .set_schema_update_options(schema_update.to_options())
+            .await;
+    }
+    if let Some(plan_limits) = &config.plan_limits {
+        shared_route_table.set_plan_limits(plan_limits.to_limits());
+    }
+    shared_route_table.set_validation_cache(
+        config
+            .validation_cache
+            .as_ref()
+            .map(ValidationCacheConfig::to_options),
+    );
+    shared_route_table.set_strict_planning(config.strict_planning);
+    shared_route_table.set_strict_responses(config.strict_responses);
+    shared_route_table.set_query_plan_extension(config.query_plan_extension);
+    shared_route_table.set_response_rules(
+        config
+            .response_rules
+            .iter()
+            .map(ResponseRuleConfig::to_rule)
+            .collect::<Result<_>>()
+            .context("Invalid response rule.")?,
+    );
+    if let Some(path) = &config.gateway_fields {
+        let sdl = tokio::fs::read_to_string(path)
+            .await
+            .with_context(|| format!("Failed to load gateway fields file '{}'.", path))?;
+        let document = parser::parse_schema(sdl)
+            .with_context(|| format!("Failed to parse gateway fields file '{}'.", path))?;
+        shared_route_table.set_gateway_fields(Some(document));
+    }
+    shared_route_table.set_shaping_directives(
+        config
+            .shaping_directives
+            .iter()
+            .map(|name| {
+                ShapingDirective::from_name(name)
+                    .with_context(|| format!("Unknown shaping directive '{}'.", name))
+            })
+            .collect::<Result<_>>()?,
+    );
+    if let Some(websocket_protocols) = &config.websocket_protocols {
+        anyhow::ensure!(
+            !websocket_protocols.is_empty(),
+            "At least one websocket protocol must be enabled."
+        );
+        shared_route_table.set_websocket_protocols(websocket_protocols.clone());
+    }
+    shared_route_table.set_scalar_validators(
+        config
+            .scalars
+            .iter()
+            .map(ScalarConfig::to_validator)
+            .collect::<Result<_>>()?,
+    );
+    shared_route_table.set_operation_policies(
+        config
+            .operations
+            .iter()
+            .map(OperationConfig::to_policy)
+            .collect(),
+    );
+    shared_route_table.set_audit(config.audit.as_ref().map(|audit| audit.to_options()));
+    shared_route_table.set_token_refresh(
+        config
+            .websocket_token_refresh
+            .as_ref()
+            .map(|token_refresh| token_refresh.to_options()),
+    );
+    shared_route_table.set_record(config.record.as_ref().map(|record| record.to_options()));
+    shared_route_table.set_slo(config.slo.as_ref().map(|slo| slo.to_options()));
+    shared_route_table.set_mirror(
+        config
+            .mirror
+            .as_ref()
+            .map(|mirror| mirror.to_options())
+            .transpose()
+            .context("Invalid mirror config.")?,
+    );
+    if let Some(differential) = &config.differential {
+        let schema = match &differential.supergraph {
+            Some(path) => Some(Arc::new(load_supergraph(path).await?)),
+            None => None,
+        };
+        shared_route_table.set_differential(Some(
+            differential
+                .to_options(schema)
+                .context("Invalid differential config.")?,
+        ));
+    }
+    shared_route_table.set_slow_query_log(
+        config
+            .slow_query_log
+            .as_ref()
+            .map(|slow_query_log| slow_query_log.to_options()),
+    );
+    #[cfg(feature = "chaos")]
+    shared_route_table.set_chaos(config.chaos.as_ref().map(|chaos| chaos.to_options()));
+    shared_route_table.set_receive_headers(config.receive_headers.clone());
+    shared_route_table.set_forward_extensions(config.forward_extensions.clone());
+    shared_route_table.set_response_headers(config.response_headers.clone());
+    shared_route_table.set_error_responses(config.create_error_responses()?);
+    shared_route_table.set_inline_variables(config.create_inline_variables(&config.services));
+    shared_route_table.set_inject_rules(inject_rules);
+    shared_route_table.set_computed_variables(
+        config
+            .variables
+            .iter()
+            .map(VariableConfig::to_variable)
+            .collect::<Result<_>>()
+            .context("Invalid computed variable.")?,
+    );
+    shared_route_table.set_switches(switches.clone());
+    shared_route_table.set_event_sources(config.create_event_sources());
+    shared_route_table.set_callbacks(callbacks.cloned());
+    shared_route_table.set_public_schema(
+        config
+            .public_schema
+            .as_ref()
+            .map(|public_schema| public_schema.to_options())
+            .transpose()
+            .context("Invalid public schema config.")?,
+    );
+    Ok(())
+}
+
+/// Resolves the secrets of the config file again every interval.
+///
+/// The inject rules, the route tables of the services of the config file and
+/// the secret of the HMAC tokens are updated. The secret references of the
+/// other fields are rejected when the config file is loaded.
+async fn refresh_secrets(
+    path: String,
+    resolvers: Arc<SecretResolvers>,
+    interval: Duration,
+    shared_route_table: SharedRouteTable,
+    tenants: Option<Arc<TenantRouteTables>>,
+    auth: Option<Arc<Authenticator>>,
+) {
+    loop {
+        tokio::time::sleep(interval).await;
+
+        let res = async {
+            let config = load_config(&path, &resolvers).await?;
+            let inject_rules =
+                create_inject_rules(&config.inject).context("Invalid inject rule.")?;
+            if let (Some(tenancy), Some(tenants)) = (&config.tenancy, &tenants) {
+                for (name, tenant_route_table) in tenants.iter() {
+                    if let Some(tenant) = tenancy.tenants.iter().find(|tenant| tenant.name == name)
+                    {
+                        tenant_route_table
+                            .set_inject_rules(tenant.create_inject_rules(&inject_rules)?);
+                        tenant_route_table.set_route_table(tenant.create_route_table());
+                    }
+                }
+            } else if !config.services.is_empty() {
+                shared_route_table.set_route_table(config.create_route_table());
+            }
+            shared_route_table.set_inject_rules(inject_rules);
+            if let (Some(auth_config), Some(auth)) = (&config.auth, &auth) {
+                auth.set_secret(auth_config.secret.clone());
+            }
+            Ok::<_, anyhow::Error>(())
         }
-    })
+        .await;
+
+        match res {
+            Ok(()) => tracing::debug!("Secrets refreshed."),
+            Err(err) => tracing::error!(error = %err, "Failed to refresh secrets."),
+        }
+    }
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
+fn main() -> Result<()> {
     let options: Options = Options::from_args();
+    if options.print_build_info {
+        println!("{}", build_info::BuildInfo::current());
+        return Ok(());
+    }
+    if options.print_grafana_dashboard {
+        println!("{}", serde_json::to_string_pretty(&grafana_dashboard())?);
+        return Ok(());
+    }
+    if let Some(options::Command::CheckOperations(check_options)) = &options.command {
+        return tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?
+            .block_on(check::check_operations(
+                &check_options.schema,
+                &check_options.ops,
+            ));
+    }
+    if let Some(options::Command::Explain(explain_options)) = &options.command {
+        return tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?
+            .block_on(explain::explain(explain_options));
+    }
+    load_runtime_config(&options.config)?
+        .build_runtime()?
+        .block_on(run(options))
+}
+
+async fn run(options: Options) -> Result<()> {
     init_tracing();
 
-    let config = toml::from_str::<Config>(
-        &std::fs::read_to_string(&options.config)
-            .with_context(|| format!("Failed to load config file '{}'.", options.config))?,
-    )
-    .with_context(|| format!("Failed to parse config file '{}'.", options.config))?;
+    let secret_resolvers = Arc::new(SecretResolvers::from_env());
+    if options.print_config {
+        let config = load_redacted_config(&options.config, &secret_resolvers).await?;
+        println!("{}", config.to_string_pretty(&options.print_config_format)?);
+        return Ok(());
+    }
+    let config = match &options.command {
+        #[cfg(feature = "demo")]
+        Some(options::Command::Demo(demo_options)) => demo::start(
+            &demo_options.bind,
+            demo_options.base_port,
+            demo::DemoProfile {
+                latency: demo_options.latency,
+                jitter: demo_options.jitter,
+                error_rate: demo_options.error_rate,
+                seed: demo_options.seed,
+            },
+        )?,
+        Some(options::Command::Dev(dev_options)) => {
+            dev::config(&dev_options.bind, &dev_options.subgraphs)?
+        }
+        _ => load_config(&options.config, &secret_resolvers).await?,
+    };
+    config.log_summary();
     let _uninstall = init_tracer(&config)?;
-    let exporter = opentelemetry_prometheus::exporter().init();
+    let exporter = opentelemetry_prometheus::exporter()
+        .with_default_histogram_boundaries(LATENCY_BUCKETS.to_vec())
+        .init();
+
+    let switches = RuntimeSwitches::default();
+    if let Some(read_only) = &config.read_only {
+        switches.set_read_only(read_only.clone());
+    }
+    if let Some(disabled) = &config.disabled {
+        switches.set_disabled(disabled.clone());
+    }
+    if let Some(maintenance) = &config.maintenance {
+        switches.set_maintenance(maintenance.clone());
+    }
+
+    if let Some(upstream_pool) = &config.upstream_pool {
+        upstream_pool
+            .to_options()
+            .apply()
+            .context("Invalid upstream pool config.")?;
+    }
+    if let Some(upstream_tls) = &config.upstream_tls {
+        upstream_tls
+            .to_options()
+            .watch()
+            .await
+            .context("Invalid upstream TLS files.")?;
+    }
 
+    let callbacks = config
+        .subscription_callback
+        .as_ref()
+        .map(|callback| CallbackRegistry::new(callback.to_options()));
+
+    let inject_rules = create_inject_rules(&config.inject).context("Invalid inject rule.")?;
     let mut shared_route_table = SharedRouteTable::default();
-    if !config.services.is_empty() {
+    configure_route_table(
+        &config,
+        &mut shared_route_table,
+        inject_rules.clone(),
+        &switches,
+        callbacks.as_ref(),
+    )
+    .await?;
+
+    let supergraph = match &config.supergraph {
+        Some(path) => {
+            let schema = Arc::new(load_supergraph(path).await?);
+            shared_route_table.set_static_schema(schema.clone()).await;
+            Some(schema)
+        }
+        None => None,
+    };
+    if let Some(options::Command::Dev(dev_options)) = &options.command {
+        dev::watch(dev_options.subgraphs.clone(), shared_route_table.clone()).await?;
+    }
+
+    let tenants = match &config.tenancy {
+        Some(tenancy) => {
+            let mut tenants = TenantRouteTables::new(&tenancy.header);
+            for tenant in &tenancy.tenants {
+                let tenant_inject_rules = tenant.create_inject_rules(&inject_rules)?;
+                let mut tenant_route_table = SharedRouteTable::default();
+                configure_route_table(
+                    &config,
+                    &mut tenant_route_table,
+                    tenant_inject_rules,
+                    &switches,
+                    callbacks.as_ref(),
+                )
+                .await?;
+                if let Some(schema) = &supergraph {
+                    tenant_route_table.set_static_schema(schema.clone()).await;
+                }
+                tenant_route_table
+                    .set_inline_variables(config.create_inline_variables(&tenant.services));
+                tenant_route_table.set_route_table(tenant.create_route_table());
+                tenants.insert(tenant.name.clone(), tenant_route_table);
+            }
+            Some(Arc::new(tenants))
+        }
+        None => None,
+    };
+
+    if let Some(tenancy) = &config.tenancy {
+        tracing::info!(
+            header = %tenancy.header,
+            "Route tables of the tenants in the configuration file."
+        );
+    } else if !config.services.is_empty() {
         tracing::info!("Route table in the configuration file.");
         shared_route_table.set_route_table(config.create_route_table());
-        shared_route_table.set_receive_headers(config.receive_headers);
     } else if std::env::var("KUBERNETES_SERVICE_HOST").is_ok() {
         tracing::info!("Route table within the current namespace in Kubernetes cluster.");
-        shared_route_table.set_receive_headers(config.receive_headers);
         tokio::spawn(update_route_table_in_k8s(
             shared_route_table.clone(),
             config.gateway_name.clone(),
@@ -140,69 +576,116 @@ async fn main() -> Result<()> {
         return Ok(());
     }
 
-    let handler_config = HandlerConfig {
-        shared_route_table,
-        forward_headers: Arc::new(config.forward_headers),
-    };
-
-    let cors = if let Some(cors_config) = config.cors {
-        let warp_cors = warp::cors();
-
-        let origins_vec = cors_config.allow_origins.unwrap_or_default();
-
-        let origins: Vec<&str> = origins_vec.iter().map(|s| s as &str).collect();
-
-        let headers_vec = cors_config.allow_headers.unwrap_or_default();
-
-        let headers: Vec<&str> = headers_vec.iter().map(|s| s as &str).collect();
+    let mut listeners = Listeners::from_env(
+        config
+            .shutdown
+            .as_ref()
+            .map_or(false, |shutdown| shutdown.reuse_port),
+    );
+    tokio::spawn(shutdown_signal());
 
-        let allow_credentials = cors_config.allow_credentials.unwrap_or(false);
+    if let Some(admin_config) = &config.admin {
+        let admin_addr: SocketAddr = admin_config.bind.parse().context(format!(
+            "Failed to parse admin bind addr '{}'",
+            admin_config.bind
+        ))?;
+        let listener =
+            tokio::net::TcpListener::from_std(listeners.take(listener::ADMIN, admin_addr)?)?;
+        let addr = listener.local_addr()?;
+        let server = warp::serve(admin::admin(switches.clone(), shared_route_table.clone()))
+            .serve_incoming_with_graceful_shutdown(
+                TcpListenerStream::new(listener),
+                shutdown_started(),
+            );
+        tracing::info!(addr = %addr, "Admin API listening");
+        tokio::spawn(server);
+    }
 
-        let allow_methods_vec = cors_config.allow_methods.unwrap_or_default();
+    let access = config
+        .access
+        .as_ref()
+        .map(|access| access.to_options())
+        .transpose()
+        .context("Invalid access config.")?
+        .unwrap_or_default();
+    let auth = config
+        .auth
+        .as_ref()
+        .map(|auth| auth.to_options())
+        .transpose()
+        .context("Invalid auth config.")?
+        .map(Authenticator::new);
 
-        let methods: Vec<&str> = allow_methods_vec.iter().map(|s| s as &str).collect();
+    if let Some(refresh_interval) = config
+        .secrets
+        .as_ref()
+        .and_then(|secrets| secrets.refresh_interval)
+    {
+        tokio::spawn(refresh_secrets(
+            options.config.clone(),
+            secret_resolvers,
+            Duration::from_secs(refresh_interval),
+            shared_route_table.clone(),
+            tenants.clone(),
+            auth.clone(),
+        ));
+    }
 
-        let cors_setup = warp_cors
-            .allow_headers(headers)
-            .allow_origins(origins)
-            .allow_methods(methods)
-            .allow_credentials(allow_credentials);
+    let handler_config = HandlerConfig {
+        shared_route_table,
+        forward_headers: Arc::new(config.forward_headers),
+        proxy_headers: Arc::new(
+            config
+                .proxy_headers
+                .as_ref()
+                .map(|proxy_headers| proxy_headers.to_options())
+                .unwrap_or_default(),
+        ),
+        access: Arc::new(access),
+        auth,
+        tenants,
+    };
 
-        if let Some(true) = cors_config.allow_any_origin {
-            Some(cors_setup.allow_any_origin())
-        } else {
-            Some(cors_setup)
-        }
-    } else {
-        None
+    let cors = config.cors.as_ref().map(CorsConfig::to_cors);
+    let playground_cors = match &config.playground_cors {
+        Some(playground_cors) => Some(playground_cors.to_cors()),
+        None => cors.clone(),
     };
 
     let graphql = warp::path::end().and(
         handler::graphql_request(handler_config.clone())
-            .or(handler::graphql_websocket(handler_config.clone()))
-            .or(handler::graphql_playground()),
+            .or(handler::graphql_websocket(handler_config.clone())),
     );
+    let playground = warp::path::end().and(handler::graphql_playground());
+    let callback = handler::graphql_callback(callbacks);
     let health = warp::path!("health").map(|| warp::reply::json(&"healthy"));
 
     let bind_addr: SocketAddr = config
         .bind
         .parse()
         .context(format!("Failed to parse bind addr '{}'", config.bind))?;
-    if let Some(warp_cors) = cors {
-        let routes = graphql.or(health).or(metrics(exporter)).with(warp_cors);
-        let (addr, server) = warp::serve(routes)
-            .bind_with_graceful_shutdown(bind_addr, signal::ctrl_c().map(|_| ()));
-        tracing::info!(addr = %addr, "Listening");
-        server.await;
-        tracing::info!("Server shutdown");
-    } else {
-        let routes = graphql.or(health).or(metrics(exporter));
-        let (addr, server) = warp::serve(routes)
-            .bind_with_graceful_shutdown(bind_addr, signal::ctrl_c().map(|_| ()));
-        tracing::info!(addr = %addr, "Listening");
-        server.await;
-        tracing::info!("Server shutdown");
+    let routes = with_cors(graphql.or(callback).or(health).or(metrics(exporter)), cors)
+        .or(with_cors(playground, playground_cors));
+    let listener =
+        tokio::net::TcpListener::from_std(listeners.take(listener::GATEWAY, bind_addr)?)?;
+    let addr = listener.local_addr()?;
+    tracing::info!(addr = %addr, "Listening");
+    serve(warp::service(routes), listener, shutdown_started()).await;
+
+    // The upgraded WebSocket connections outlive the server.
+    let drain_timeout = config
+        .shutdown
+        .as_ref()
+        .and_then(|shutdown| shutdown.drain_timeout)
+        .unwrap_or(DEFAULT_DRAIN_TIMEOUT);
+    let closed = drain(Duration::from_secs(drain_timeout)).await;
+    if closed > 0 {
+        tracing::warn!(
+            connections = closed,
+            "Closed the WebSocket connections whose subscriptions did not complete."
+        );
     }
+    tracing::info!("Server shutdown");
 
     Ok(())
 }
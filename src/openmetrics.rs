@@ -0,0 +1,134 @@
+use std::fmt::Write;
+
+use graphgate_handler::exemplar;
+use prometheus::proto::{LabelPair, MetricFamily, MetricType};
+
+pub const CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn format_labels(labels: &[LabelPair], extra: Option<(&str, String)>) -> String {
+    let labels = labels
+        .iter()
+        .map(|label| format!("{}=\"{}\"", label.get_name(), escape(label.get_value())))
+        .chain(extra.map(|(name, value)| format!("{}=\"{}\"", name, escape(&value))))
+        .collect::<Vec<_>>();
+    if labels.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", labels.join(","))
+    }
+}
+
+fn format_bound(bound: f64) -> String {
+    if bound.is_infinite() {
+        "+Inf".to_string()
+    } else {
+        format!("{:?}", bound)
+    }
+}
+
+/// Encodes the metric families in the OpenMetrics text format, with the
+/// exemplars of the latency histograms.
+pub fn encode(metric_families: &[MetricFamily]) -> String {
+    let mut output = String::new();
+
+    for family in metric_families {
+        let name = family.get_name();
+        let (kind, family_name) = match family.get_field_type() {
+            MetricType::COUNTER => ("counter", name.strip_suffix("_total").unwrap_or(name)),
+            MetricType::GAUGE => ("gauge", name),
+            MetricType::HISTOGRAM => ("histogram", name),
+            MetricType::SUMMARY => ("summary", name),
+            MetricType::UNTYPED => ("unknown", name),
+        };
+        let _ = writeln!(output, "# TYPE {} {}", family_name, kind);
+        if !family.get_help().is_empty() {
+            let _ = writeln!(
+                output,
+                "# HELP {} {}",
+                family_name,
+                escape(family.get_help())
+            );
+        }
+
+        for metric in family.get_metric() {
+            let labels = metric.get_label();
+            match family.get_field_type() {
+                MetricType::COUNTER => {
+                    let _ = writeln!(
+                        output,
+                        "{}_total{} {}",
+                        family_name,
+                        format_labels(labels, None),
+                        metric.get_counter().get_value()
+                    );
+                }
+                MetricType::GAUGE => {
+                    let _ = writeln!(
+                        output,
+                        "{}{} {}",
+                        name,
+                        format_labels(labels, None),
+                        metric.get_gauge().get_value()
+                    );
+                }
+                MetricType::HISTOGRAM => {
+                    let histogram = metric.get_histogram();
+                    let key = labels
+                        .iter()
+                        .map(|label| (label.get_name().to_string(), label.get_value().to_string()))
+                        .collect::<Vec<_>>();
+                    let mut buckets = histogram
+                        .get_bucket()
+                        .iter()
+                        .map(|bucket| (bucket.get_upper_bound(), bucket.get_cumulative_count()))
+                        .collect::<Vec<_>>();
+                    if buckets.last().map(|(bound, _)| bound.is_finite()) != Some(false) {
+                        buckets.push((f64::INFINITY, histogram.get_sample_count()));
+                    }
+                    for (bound, count) in buckets {
+                        let _ = write!(
+                            output,
+                            "{}_bucket{} {}",
+                            name,
+                            format_labels(labels, Some(("le", format_bound(bound)))),
+                            count
+                        );
+                        if let Some(exemplar) = exemplar(name, &key, bound) {
+                            let _ = write!(
+                                output,
+                                " # {{trace_id=\"{}\"}} {} {}",
+                                exemplar.trace_id, exemplar.value, exemplar.timestamp
+                            );
+                        }
+                        output.push('\n');
+                    }
+                    let _ = writeln!(
+                        output,
+                        "{}_sum{} {}",
+                        name,
+                        format_labels(labels, None),
+                        histogram.get_sample_sum()
+                    );
+                    let _ = writeln!(
+                        output,
+                        "{}_count{} {}",
+                        name,
+                        format_labels(labels, None),
+                        histogram.get_sample_count()
+                    );
+                }
+                MetricType::SUMMARY | MetricType::UNTYPED => {}
+            }
+        }
+    }
+
+    output.push_str("# EOF\n");
+    output
+}
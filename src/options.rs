@@ -1,8 +1,119 @@
 use structopt::StructOpt;
 
+use crate::dev::Subgraph;
+
 #[derive(StructOpt)]
 pub struct Options {
     /// Path of the config file
     #[structopt(default_value = "config.toml")]
     pub config: String,
+
+    /// Print the version, git sha, rustc version and enabled features, then exit
+    #[structopt(long)]
+    pub print_build_info: bool,
+
+    /// Print a Grafana dashboard of the gateway metrics as JSON, then exit
+    #[structopt(long)]
+    pub print_grafana_dashboard: bool,
+
+    /// Print the configuration with the defaults applied, then exit.
+    ///
+    /// The secret references and the credentials are printed as `<redacted>`.
+    #[structopt(long)]
+    pub print_config: bool,
+
+    /// Format of the printed configuration
+    #[structopt(long, default_value = "toml", possible_values = &["toml", "json"])]
+    pub print_config_format: String,
+
+    #[structopt(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(StructOpt)]
+pub enum Command {
+    /// Run the gateway with built-in accounts, products and reviews services
+    #[cfg(feature = "demo")]
+    Demo(DemoOptions),
+
+    /// Validate and plan a directory of operations against a supergraph, then exit
+    CheckOperations(CheckOperationsOptions),
+
+    /// Run the gateway on local subgraphs, recomposing the schema whenever their SDL files change
+    Dev(DevOptions),
+
+    /// Plan an operation against a supergraph and print the graph of its query plan, then exit
+    Explain(ExplainOptions),
+}
+
+#[derive(StructOpt)]
+pub struct DevOptions {
+    /// Bind address of the gateway
+    #[structopt(long, default_value = "127.0.0.1:8000")]
+    pub bind: String,
+
+    /// Subgraph as `name=sdl_file@url`, for example `accounts=./accounts.graphql@http://localhost:4001`
+    #[structopt(long = "subgraph", required = true, number_of_values = 1)]
+    pub subgraphs: Vec<Subgraph>,
+}
+
+#[derive(StructOpt)]
+pub struct CheckOperationsOptions {
+    /// Path of the supergraph file
+    #[structopt(long)]
+    pub schema: String,
+
+    /// Directory of the `.graphql` and `.gql` operation files, searched recursively
+    #[structopt(long)]
+    pub ops: String,
+}
+
+#[derive(StructOpt)]
+pub struct ExplainOptions {
+    /// Path of the supergraph file
+    #[structopt(long)]
+    pub schema: String,
+
+    /// Name of the operation to plan, required if the file has several operations
+    #[structopt(long)]
+    pub operation_name: Option<String>,
+
+    /// Variables of the operation as a JSON object
+    #[structopt(long)]
+    pub variables: Option<String>,
+
+    /// Format of the graph
+    #[structopt(long, default_value = "mermaid", possible_values = &["dot", "mermaid"])]
+    pub format: String,
+
+    /// Path of the operation file
+    pub query: String,
+}
+
+#[cfg(feature = "demo")]
+#[derive(StructOpt)]
+pub struct DemoOptions {
+    /// Bind address of the gateway
+    #[structopt(long, default_value = "0.0.0.0:8000")]
+    pub bind: String,
+
+    /// Port of the accounts service, the products and reviews services use the next two ports
+    #[structopt(long, default_value = "8001")]
+    pub base_port: u16,
+
+    /// Latency in milliseconds added to every service request
+    #[structopt(long, default_value = "0")]
+    pub latency: u64,
+
+    /// Maximum random latency in milliseconds added to every service request
+    #[structopt(long, default_value = "0")]
+    pub jitter: u64,
+
+    /// Fraction of the service requests that fail, from 0.0 to 1.0
+    #[structopt(long, default_value = "0")]
+    pub error_rate: f64,
+
+    /// Seed of the random latency, errors and subscription events
+    #[structopt(long, default_value = "0")]
+    pub seed: u64,
 }